@@ -0,0 +1,217 @@
+//! Proxies and reply types for the D-Bus API of bootkitd.
+//!
+//! Methods that take and return JSON strings keep doing so in the proxies,
+//! [`parse`] turns the replies into the types in [`types`].
+
+use std::fmt::Display;
+
+use futures_util::{
+    future::{ready, select, Either},
+    Stream, StreamExt,
+};
+use serde::de::DeserializeOwned;
+
+pub mod observer;
+pub mod proxy;
+pub mod types;
+
+pub use observer::ObserverProxy;
+pub use proxy::{
+    BootEntryProxy, ConfigProxy, EntryProxy, InfoProxy, JobProxy, SnapshotProxy, StatusProxy,
+};
+use types::FileChange;
+
+/// Bits of the Capabilities property of the Info interface. At most one of the
+/// boot loader bits is set
+pub mod capabilities {
+    pub const GRUB2: u64 = 1 << 0;
+    pub const SYSTEMD_BOOT: u64 = 1 << 1;
+    pub const REFIND: u64 = 1 << 2;
+    pub const EXTLINUX: u64 = 1 << 3;
+    pub const LIMINE: u64 = 1 << 4;
+    /// Snapshot interface has the history of the config
+    pub const SNAPSHOTS: u64 = 1 << 5;
+    /// SetNextEntry can set the entry that's booted once
+    pub const NEXT_ENTRY: u64 = 1 << 6;
+    /// Mutating methods are authorized with polkit
+    pub const POLKIT: u64 = 1 << 7;
+}
+
+/// Names of the errors the methods fail with, next to the standard
+/// org.freedesktop.DBus.Error ones
+pub mod error_names {
+    pub const PARSE_FAILED: &str = "org.opensuse.bootkit.Error.ParseFailed";
+    pub const INVALID_VALUES: &str = "org.opensuse.bootkit.Error.InvalidValues";
+    /// Config was changed by someone else, reload it and try again
+    pub const CONFLICT: &str = "org.opensuse.bootkit.Error.Conflict";
+    pub const NOT_AUTHORIZED: &str = "org.opensuse.bootkit.Error.NotAuthorized";
+    /// Too many expensive calls in a short time, try again later
+    pub const RATE_LIMITED: &str = "org.opensuse.bootkit.Error.RateLimited";
+    pub const FAILED: &str = "org.opensuse.bootkit.Error.Failed";
+}
+
+/// Stable codes sent after the message in the bootkitd errors, for localizing
+/// the messages and handling specific failures. Also in the JSON of the
+/// diagnostics and in the ErrorCode of failed jobs
+pub mod error_codes {
+    pub const GRUB_PARSE: &str = "ERR_GRUB_PARSE";
+    pub const VALIDATION: &str = "ERR_VALIDATION";
+    pub const CONFLICT: &str = "ERR_CONFLICT";
+    pub const NOT_AUTHORIZED: &str = "ERR_NOT_AUTHORIZED";
+    pub const RATE_LIMITED: &str = "ERR_RATE_LIMITED";
+    pub const JSON: &str = "ERR_JSON";
+    pub const IO: &str = "ERR_IO";
+    pub const DATABASE: &str = "ERR_DATABASE";
+    pub const DBUS: &str = "ERR_DBUS";
+    pub const SCRIPT: &str = "ERR_SCRIPT";
+    pub const FAILED: &str = "ERR_FAILED";
+}
+
+#[derive(Debug)]
+pub enum Error {
+    DBus(zbus::Error),
+    /// Reply wasn't the JSON that was expected
+    Json(serde_json::Error),
+    /// Job failed with this error
+    JobFailed(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::DBus(err) => write!(f, "D-Bus error: {err}"),
+            Error::Json(err) => write!(f, "Malformed reply: {err}"),
+            Error::JobFailed(err) => write!(f, "Job failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// D-Bus name of the error the method failed with, like [`error_names::CONFLICT`]
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Error::DBus(zbus::Error::MethodError(name, _, _)) => Some(name.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Code of the bootkitd error the method failed with, like
+    /// [`error_codes::CONFLICT`]. None for the standard D-Bus errors
+    pub fn code(&self) -> Option<String> {
+        match self {
+            Error::DBus(zbus::Error::MethodError(_, _, reply)) => reply
+                .body()
+                .deserialize::<(String, String)>()
+                .ok()
+                .map(|(_, code)| code),
+            _ => None,
+        }
+    }
+}
+
+impl From<zbus::Error> for Error {
+    fn from(value: zbus::Error) -> Self {
+        Self::DBus(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Parse the JSON reply of a method, like the [`types::WriteReply`] of the writes
+pub fn parse<T: DeserializeOwned>(reply: &str) -> Result<T> {
+    Ok(serde_json::from_str(reply)?)
+}
+
+/// Wait until `job` is done, returning its output. The signals are subscribed
+/// before the status is checked so a job that finishes in between isn't missed
+pub async fn wait_for_job(job: &JobProxy<'_>) -> Result<String> {
+    let mut finished = job.receive_finished().await?;
+    let mut failed = job.receive_failed().await?;
+    match job.status().await?.as_str() {
+        "finished" => return Ok(job.output().await?),
+        "failed" => return Err(Error::JobFailed(job.error().await?)),
+        _ => {}
+    }
+
+    match select(finished.next(), failed.next()).await {
+        Either::Left((Some(signal), _)) => Ok(signal.args()?.output().to_string()),
+        Either::Right((Some(signal), _)) => {
+            Err(Error::JobFailed(signal.args()?.error().to_string()))
+        }
+        _ => Err(Error::JobFailed(
+            "Job was removed before it finished".into(),
+        )),
+    }
+}
+
+/// Regenerate grub.cfg in the background and wait until it's done. Returns the
+/// captured output of grub2-mkconfig as JSON
+pub async fn regenerate_config(config: &ConfigProxy<'_>) -> Result<String> {
+    let job = config.start_regenerate_config().await?;
+    wait_for_job(&job).await
+}
+
+/// FileChangedDetailed signals of `config`
+pub async fn file_changes(
+    config: &ConfigProxy<'_>,
+) -> Result<impl Stream<Item = FileChange> + Unpin> {
+    let signals = config.receive_file_changed_detailed().await?;
+    Ok(signals.filter_map(|signal| {
+        let change = signal.args().ok().map(|args| FileChange {
+            file: args.file().to_string(),
+            keys: args.keys().clone(),
+        });
+        ready(change)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{ChangesPreview, KeyChange, WriteReply};
+
+    #[test]
+    fn test_parse_replies() {
+        let reply: WriteReply = parse(
+            r#"{"status":"ok","queue_position":1,"estimated_wait_ms":200,"warnings":["hidden"]}"#,
+        )
+        .unwrap();
+        assert_eq!(reply.queue_position, 1);
+        assert_eq!(reply.warnings, vec!["hidden"]);
+
+        let reply: WriteReply =
+            parse(r#"{"status":"ok","queue_position":0,"estimated_wait_ms":0}"#).unwrap();
+        assert!(reply.warnings.is_empty());
+
+        let preview: ChangesPreview = parse(
+            r##"{"path":"/etc/default/grub","diff":null,"key_changes":[
+                {"change":"changed","key":"GRUB_TIMEOUT","old":"5","new":"8"},
+                {"change":"line_added","line":"# comment"}
+            ],"invalid_values":{},"warnings":[]}"##,
+        )
+        .unwrap();
+        assert_eq!(
+            preview.key_changes,
+            vec![
+                KeyChange::Changed {
+                    key: "GRUB_TIMEOUT".into(),
+                    old: "5".into(),
+                    new: "8".into()
+                },
+                KeyChange::LineAdded {
+                    line: "# comment".into()
+                },
+            ]
+        );
+
+        assert!(matches!(parse::<WriteReply>("ok"), Err(Error::Json(_))));
+    }
+}
@@ -0,0 +1,46 @@
+//! Proxy of the observer object, separate from [`crate::proxy`] since its
+//! signals have the same names as the ones of the admin interfaces
+
+use std::collections::BTreeMap;
+
+use zbus::proxy;
+
+use crate::types::EntryInfo;
+
+/// Read only interface that unprivileged clients can use
+#[proxy(
+    interface = "org.opensuse.bootkit.Observer",
+    default_service = "org.opensuse.bootkit",
+    default_path = "/org/opensuse/bootkit/observer"
+)]
+pub trait Observer {
+    fn get_default_entry(&self) -> zbus::Result<String>;
+
+    fn get_entry_list(&self) -> zbus::Result<Vec<EntryInfo>>;
+
+    fn get_config_values(&self) -> zbus::Result<BTreeMap<String, String>>;
+
+    fn get_pending_changes(&self) -> zbus::Result<String>;
+
+    /// Receive KeyChanged when any of `keys` changes
+    fn watch_keys(&self, keys: &[&str]) -> zbus::Result<()>;
+
+    /// Empty `keys` stops watching all of them
+    fn unwatch_keys(&self, keys: &[&str]) -> zbus::Result<()>;
+
+    /// Sent only for the keys of WatchKeys
+    #[zbus(signal)]
+    fn key_changed(&self, key: &str, old: &str, new: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn file_changed(&self) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn file_changed_detailed(&self, file: &str, keys: Vec<String>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn boot_entries_changed(&self, added: Vec<String>, removed: Vec<String>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn default_entry_changed(&self, old: &str, new: &str) -> zbus::Result<()>;
+}
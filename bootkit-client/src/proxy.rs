@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, HashMap};
+
+use zbus::proxy;
+
+use crate::types::EntryInfo;
+
+#[proxy(
+    interface = "org.opensuse.bootkit.Info",
+    default_service = "org.opensuse.bootkit",
+    default_path = "/org/opensuse/bootkit"
+)]
+pub trait Info {
+    fn get_version(&self) -> zbus::Result<String>;
+
+    /// Version of the D-Bus API, bumped on breaking changes
+    #[zbus(property)]
+    fn api_version(&self) -> zbus::Result<u32>;
+
+    /// Bitmask of the [`crate::capabilities`]
+    #[zbus(property)]
+    fn capabilities(&self) -> zbus::Result<u64>;
+
+    /// Like `grub2-efi`, `grub2-bios` or `systemd-boot`, empty if none was detected
+    #[zbus(property)]
+    fn bootloader_type(&self) -> zbus::Result<String>;
+
+    /// `uefi` or `bios`
+    #[zbus(property)]
+    fn firmware_mode(&self) -> zbus::Result<String>;
+
+    /// JSON of the detected boot loader
+    #[zbus(property)]
+    fn bootloader(&self) -> zbus::Result<String>;
+}
+
+#[proxy(
+    interface = "org.opensuse.bootkit.Config",
+    default_service = "org.opensuse.bootkit",
+    default_path = "/org/opensuse/bootkit"
+)]
+pub trait Config {
+    #[zbus(property)]
+    fn default_entry(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn timeout(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn kernel_cmdline(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn bootloader_type(&self) -> zbus::Result<String>;
+
+    fn get_config(&self) -> zbus::Result<String>;
+
+    fn save_config(&self, data: &str) -> zbus::Result<String>;
+
+    /// JSON of [`crate::types::AppliedState`]
+    fn apply_state(&self, data: &str) -> zbus::Result<String>;
+
+    /// JSON of [`crate::types::ValidationReport`]
+    fn validate_config(&self, data: &str) -> zbus::Result<String>;
+
+    /// JSON of [`crate::types::ChangesPreview`]
+    fn preview_changes(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_config_values(&self) -> zbus::Result<BTreeMap<String, String>>;
+
+    fn save_config_values(&self, values: &BTreeMap<String, String>) -> zbus::Result<Vec<String>>;
+
+    /// Value of `key` and whether grub.cfg needs to be regenerated for it
+    fn get_config_value(&self, key: &str) -> zbus::Result<(String, bool)>;
+
+    /// Whether grub.cfg was regenerated and the warnings about the value
+    fn set_config_value(&self, key: &str, value: &str) -> zbus::Result<(bool, Vec<String>)>;
+
+    fn set_config_values(&self, changes: &BTreeMap<String, String>) -> zbus::Result<Vec<String>>;
+
+    /// Token of the edit and the hash of the config files it began with
+    fn begin_edit(&self) -> zbus::Result<(String, String)>;
+
+    fn stage(&self, token: &str, key: &str, value: &str) -> zbus::Result<()>;
+
+    fn commit(&self, token: &str) -> zbus::Result<Vec<String>>;
+
+    fn cancel(&self, token: &str) -> zbus::Result<()>;
+
+    fn get_key_schema(&self) -> zbus::Result<String>;
+
+    fn get_menu_protection(&self) -> zbus::Result<String>;
+
+    fn set_menu_protection(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_gfx_modes(&self) -> zbus::Result<String>;
+
+    fn get_cmdline(&self) -> zbus::Result<String>;
+
+    fn get_kernel_cmdline(&self) -> zbus::Result<String>;
+
+    fn set_cmdline_param(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_resume(&self) -> zbus::Result<String>;
+
+    fn set_resume(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_themes(&self) -> zbus::Result<String>;
+
+    fn set_theme(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_os_prober(&self) -> zbus::Result<String>;
+
+    fn set_os_prober(&self, data: &str) -> zbus::Result<String>;
+
+    fn set_timeout(&self, data: &str) -> zbus::Result<String>;
+
+    fn set_timeout_style(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_pending_changes(&self) -> zbus::Result<String>;
+
+    fn regenerate_config(&self) -> zbus::Result<String>;
+
+    /// Job object that regenerates grub.cfg in the background
+    #[zbus(object = "Job")]
+    fn start_regenerate_config(&self);
+
+    fn remove_key(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_write_queue(&self) -> zbus::Result<String>;
+
+    /// Postpone the idle shutdown announced with ShuttingDown
+    fn keep_alive(&self) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn file_changed(&self) -> zbus::Result<()>;
+
+    /// `file` is `grub`, `grubenv` or `grub.cfg`
+    #[zbus(signal)]
+    fn file_changed_detailed(&self, file: &str, keys: Vec<String>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn shutting_down(&self, grace_ms: u64) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.opensuse.bootkit.BootEntry",
+    default_service = "org.opensuse.bootkit",
+    default_path = "/org/opensuse/bootkit"
+)]
+pub trait BootEntry {
+    fn get_entries(&self) -> zbus::Result<String>;
+
+    fn get_entry_list(&self) -> zbus::Result<Vec<EntryInfo>>;
+
+    fn get_entries_sorted(&self, data: &str) -> zbus::Result<String>;
+
+    fn set_default_entry(&self, data: &str) -> zbus::Result<String>;
+
+    fn set_next_entry(&self, data: &str) -> zbus::Result<String>;
+
+    /// Boot `entry` once and reboot right away
+    fn reboot_to_entry(&self, entry: &str) -> zbus::Result<String>;
+
+    fn get_firmware_entries(&self) -> zbus::Result<String>;
+
+    fn set_firmware_boot_next(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_mok_status(&self) -> zbus::Result<String>;
+
+    fn get_boot_status(&self) -> zbus::Result<String>;
+
+    fn mark_boot_successful(&self) -> zbus::Result<String>;
+
+    fn get_custom_entries(&self) -> zbus::Result<String>;
+
+    fn add_custom_entry(&self, data: &str) -> zbus::Result<String>;
+
+    fn remove_custom_entry(&self, data: &str) -> zbus::Result<String>;
+
+    fn get_boot_timeline(&self) -> zbus::Result<String>;
+
+    /// JSON list of [`crate::types::InstalledKernel`]
+    fn list_kernels(&self) -> zbus::Result<String>;
+
+    /// JSON of [`crate::types::KernelCleanup`]
+    fn remove_old_kernels(&self, keep: u32, dry_run: bool) -> zbus::Result<String>;
+
+    fn set_entry_cmdline(&self, data: &str) -> zbus::Result<String>;
+
+    fn set_entry_devicetree(&self, data: &str) -> zbus::Result<String>;
+
+    /// Empty string means that no default entry was set
+    #[zbus(signal)]
+    fn default_entry_changed(&self, old: &str, new: &str) -> zbus::Result<()>;
+
+    /// Titles of the entries that were added and removed
+    #[zbus(signal)]
+    fn boot_entries_changed(&self, added: Vec<String>, removed: Vec<String>) -> zbus::Result<()>;
+}
+
+#[proxy(
+    interface = "org.opensuse.bootkit.Snapshot",
+    default_service = "org.opensuse.bootkit",
+    default_path = "/org/opensuse/bootkit"
+)]
+pub trait Snapshot {
+    fn list(&self) -> zbus::Result<String>;
+
+    /// Page of the list, empty filters match every snapshot
+    fn query(
+        &self,
+        offset: u32,
+        limit: u32,
+        origin: &str,
+        since: &str,
+        before: &str,
+    ) -> zbus::Result<String>;
+
+    fn get(&self, id: i64) -> zbus::Result<String>;
+
+    fn get_by_tag(&self, tag: &str) -> zbus::Result<String>;
+
+    /// Latest snapshot taken while the root was the snapper snapshot `number`
+    fn get_by_snapper_snapshot(&self, number: i64) -> zbus::Result<String>;
+
+    /// Config snapshot ids by snapper snapshot number
+    fn snapper_snapshots(&self) -> zbus::Result<HashMap<i64, Vec<i64>>>;
+
+    /// JSON with the number of checked snapshots, the unhashed ids and the corrupted ones
+    fn verify_snapshots(&self) -> zbus::Result<String>;
+
+    /// Snapshot of the current config with a unique `tag`, returns its id
+    fn create(&self, tag: &str, comment: &str) -> zbus::Result<i64>;
+
+    /// JSON of [`crate::types::SnapshotDiff`], id 0 is the current config
+    fn diff(&self, from: i64, to: i64) -> zbus::Result<String>;
+
+    fn restore(&self, id: i64) -> zbus::Result<String>;
+
+    /// Same as restore, but grub.cfg is left pending unless `regenerate` is set
+    fn restore_with_options(&self, id: i64, regenerate: bool) -> zbus::Result<String>;
+
+    fn delete(&self, id: i64) -> zbus::Result<()>;
+}
+
+/// Health of the daemon
+#[proxy(
+    interface = "org.opensuse.bootkit.Status",
+    default_service = "org.opensuse.bootkit",
+    default_path = "/org/opensuse/bootkit"
+)]
+pub trait Status {
+    #[zbus(property)]
+    fn backend(&self) -> zbus::Result<String>;
+
+    /// Watched directories and whether their watch still works
+    #[zbus(property)]
+    fn watched_paths(&self) -> zbus::Result<HashMap<String, bool>>;
+
+    #[zbus(property)]
+    fn database_available(&self) -> zbus::Result<bool>;
+
+    /// Empty if no method call has failed
+    #[zbus(property)]
+    fn last_error(&self) -> zbus::Result<String>;
+
+    /// Seconds since the daemon started
+    #[zbus(property)]
+    fn uptime(&self) -> zbus::Result<u64>;
+}
+
+/// Long running write under /org/opensuse/bootkit/jobs
+#[proxy(
+    interface = "org.opensuse.bootkit.Job",
+    default_service = "org.opensuse.bootkit"
+)]
+pub trait Job {
+    #[zbus(property)]
+    fn name(&self) -> zbus::Result<String>;
+
+    /// `running`, `finished` or `failed`
+    #[zbus(property)]
+    fn status(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn log(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(property)]
+    fn output(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn error(&self) -> zbus::Result<String>;
+
+    /// Stable code of the error, one of [`crate::error_codes`]
+    #[zbus(property)]
+    fn error_code(&self) -> zbus::Result<String>;
+
+    #[zbus(signal)]
+    fn progress(&self, line: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn finished(&self, output: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    fn failed(&self, error: &str) -> zbus::Result<()>;
+}
+
+/// Boot entry object under /org/opensuse/bootkit/entries
+#[proxy(
+    interface = "org.opensuse.bootkit.Entry",
+    default_service = "org.opensuse.bootkit"
+)]
+pub trait Entry {
+    #[zbus(property)]
+    fn title(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn id(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn kernel(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn is_default(&self) -> zbus::Result<bool>;
+}
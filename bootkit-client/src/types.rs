@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use zbus::zvariant::Type;
+
+/// Reply of the methods that write the boot configuration
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct WriteReply {
+    pub status: String,
+    /// How many writes were ahead in the queue
+    pub queue_position: usize,
+    pub estimated_wait_ms: u64,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// Boot entry returned by GetEntryList. Missing values are empty strings
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Type)]
+pub struct EntryInfo {
+    pub id: String,
+    pub title: String,
+    /// Titles of the submenus and the entry separated by `>`
+    pub path: String,
+    pub os: String,
+    pub kernel: String,
+    pub version: String,
+    pub initrds: Vec<String>,
+    pub classes: Vec<String>,
+    pub is_default: bool,
+    /// Entry is booted once on the next boot instead of the default one
+    pub is_next: bool,
+}
+
+/// Change of a single key or other line of a config file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum KeyChange {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        old: String,
+        new: String,
+    },
+    LineAdded {
+        line: String,
+    },
+    LineRemoved {
+        line: String,
+    },
+}
+
+/// What SaveConfig would change on disk, returned by PreviewChanges
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ChangesPreview {
+    pub path: String,
+    /// Unified diff of the file, none if nothing would change
+    pub diff: Option<String>,
+    pub key_changes: Vec<KeyChange>,
+    /// Values that SaveConfig would reject, and why
+    pub invalid_values: BTreeMap<String, String>,
+    pub warnings: Vec<String>,
+}
+
+/// Reply of ApplyState
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AppliedState {
+    /// False when the system was already in the desired state
+    pub changed: bool,
+    pub key_changes: Vec<KeyChange>,
+    pub default_entry_changed: bool,
+    /// Set when the keys were written but saving the default entry failed
+    #[serde(default)]
+    pub default_entry_error: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Problem found by ValidateConfig
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Diagnostic {
+    /// `error` or `warning`
+    pub severity: String,
+    /// `parse`, `value` or `script`
+    pub stage: String,
+    /// One of [`crate::error_codes`]
+    pub code: String,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+/// Reply of ValidateConfig
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ValidationReport {
+    /// True if there are no errors, there can still be warnings
+    pub valid: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Changes between two snapshots, returned by Snapshot Diff
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SnapshotDiff {
+    pub from: i64,
+    pub to: i64,
+    pub diff: Option<String>,
+    pub key_changes: Vec<KeyChange>,
+}
+
+/// Kernel image in /boot, returned by ListKernels
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct InstalledKernel {
+    pub version: String,
+    pub image: String,
+    /// Empty if the kernel has no initrd
+    pub initrds: Vec<String>,
+    pub has_modules: bool,
+    pub running: bool,
+    /// Ids of the boot entries that boot the kernel, empty for orphaned kernels
+    pub entries: Vec<String>,
+}
+
+/// Reply of RemoveOldKernels
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct KernelCleanup {
+    pub dry_run: bool,
+    /// Kernels that were removed, or would be without `dry_run`
+    pub removed: Vec<InstalledKernel>,
+    /// Kernels that belong to a package, only the package manager removes them
+    pub packaged: Vec<KernelPackage>,
+    /// Files that couldn't be removed and grub.cfg if it couldn't be regenerated
+    pub failed: Vec<CleanupFailure>,
+    pub regenerated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct KernelPackage {
+    pub version: String,
+    pub package: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CleanupFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// FileChangedDetailed signal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    /// `grub`, `grubenv` or `grub.cfg`
+    pub file: String,
+    /// Keys whose values changed, grub.cfg has none
+    pub keys: Vec<String>,
+}
@@ -0,0 +1,4 @@
+fn main() {
+    // sqlx::migrate! embeds the migrations, so new ones have to trigger a rebuild
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,70 @@
+-- Schema of the databases created before the migrations were versioned. The
+-- tables may already exist in those, see Database::upgrade_unversioned
+
+CREATE TABLE IF NOT EXISTS grub2_snapshot (
+    -- Auto incrementing snapshot id
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- /etc/default/grub config, or its delta against delta_base
+    grub_config TEXT NOT NULL,
+    -- selected kernel that's booted to, if it's actually specified
+    selected_kernel TEXT,
+    -- when snapshot was created
+    created DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    -- D-Bus caller that made the change, null if it's not known
+    caller_uid INTEGER,
+    caller_pid INTEGER,
+    caller_process TEXT,
+    caller_unit TEXT,
+    -- why the snapshot was taken: initial, save, external,
+    -- pre-restore or pre-save
+    origin TEXT,
+    -- host the snapshot was taken on, the database can be copied between machines
+    hostname TEXT,
+    -- optional comment given by the client that saved the config
+    comment TEXT,
+    -- snapshot with the full config that grub_config is a delta of, null if
+    -- grub_config is the full config
+    delta_base INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS selected_snapshot (
+    -- Id of selected grub2 snapshot, null if none is selected.
+    -- If none is selected, it implies that latest snapshot is being used.
+    grub2_snapshot_id INTEGER
+);
+
+-- The database always has a single value that defaults to null
+-- so it's fine to set it as such when the DB is defined
+INSERT INTO selected_snapshot (grub2_snapshot_id)
+SELECT NULL WHERE NOT EXISTS (SELECT 1 FROM selected_snapshot);
+
+CREATE TABLE IF NOT EXISTS boot_timeline (
+    -- boot id of the kernel, same as the journal boot id
+    boot_id TEXT PRIMARY KEY NOT NULL,
+    -- BOOT_IMAGE from the kernel command line, if grub set it
+    boot_image TEXT,
+    -- kernel version parsed from the boot image
+    kernel_version TEXT,
+    -- saved_entry in grubenv when the boot was recorded
+    saved_entry TEXT,
+    -- when the system was booted
+    boot_time DATETIME NOT NULL,
+    -- when the boot was recorded
+    created DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mkconfig_run (
+    -- Auto incrementing run id
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- Snapshot the grub.cfg was generated from, null if the generation failed
+    grub2_snapshot_id INTEGER,
+    -- Command line that was run
+    command TEXT NOT NULL,
+    -- Exit code of the command, null if it was killed by a signal
+    exit_status INTEGER,
+    -- Output of the command
+    stdout TEXT NOT NULL,
+    stderr TEXT NOT NULL,
+    -- when the command was run
+    created DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
@@ -0,0 +1,2 @@
+-- grubenv as it was when the snapshot was taken, none if it couldn't be read
+ALTER TABLE grub2_snapshot ADD COLUMN grubenv TEXT;
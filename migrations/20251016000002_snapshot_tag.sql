@@ -0,0 +1,4 @@
+-- Name given by the client to a snapshot it created, so it can be looked up
+-- later. Only some snapshots have a tag, sqlite allows any number of NULLs
+ALTER TABLE grub2_snapshot ADD COLUMN tag TEXT;
+CREATE UNIQUE INDEX grub2_snapshot_tag ON grub2_snapshot (tag);
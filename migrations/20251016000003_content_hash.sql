@@ -0,0 +1,3 @@
+-- SHA-256 of the full grub config and grubenv of the snapshot, see
+-- grub2::content_hash. Snapshots taken before it was stored don't have one
+ALTER TABLE grub2_snapshot ADD COLUMN content_hash TEXT;
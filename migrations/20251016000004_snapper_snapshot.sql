@@ -0,0 +1,4 @@
+-- snapper snapshot the root filesystem was mounted from when the config snapshot
+-- was taken, so the config can be found after a root filesystem rollback
+ALTER TABLE grub2_snapshot ADD COLUMN snapper_snapshot INTEGER;
+CREATE INDEX grub2_snapshot_snapper ON grub2_snapshot (snapper_snapshot);
@@ -0,0 +1,3 @@
+-- SHA-256 of grub.cfg right after a successful run, tells if grub.cfg was
+-- generated by something else since
+ALTER TABLE mkconfig_run ADD COLUMN grub_cfg_hash TEXT;
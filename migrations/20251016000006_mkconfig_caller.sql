@@ -0,0 +1,5 @@
+-- D-Bus caller that started the grub2-mkconfig run, null if it's not known
+ALTER TABLE mkconfig_run ADD COLUMN caller_uid INTEGER;
+ALTER TABLE mkconfig_run ADD COLUMN caller_pid INTEGER;
+ALTER TABLE mkconfig_run ADD COLUMN caller_process TEXT;
+ALTER TABLE mkconfig_run ADD COLUMN caller_unit TEXT;
@@ -9,6 +9,7 @@ if [[ ! -e "tmp/grub" ]]; then
     cp test_data/grub.cfg tmp
     cp test_data/grub_full tmp/grub
     cp test_data/grubenv_empty tmp/grubenv
+    cp -r test_data/loader tmp
 
     scripts/setup_local_db.sh
 fi
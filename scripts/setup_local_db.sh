@@ -4,10 +4,12 @@ set -e
 cd $(dirname "$0")
 cd ..
 
+# migrations are applied in the order of their versions, remove tmp/bootkit.db
+# to apply the new ones
 if [[ ! -e tmp/bootkit.db ]]; then
     mkdir -p tmp
     touch tmp/bootkit.db
-    for db_file in $(find db -type f -name '*.sql'); do
+    for db_file in $(find migrations -type f -name '*.sql' | sort); do
         sqlite3 tmp/bootkit.db < "$db_file"
     done
 fi
@@ -0,0 +1,407 @@
+use std::{cmp::Ordering, collections::BTreeMap, fs::read_dir, path::Path};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    config::{
+        layout::GrubLayout, ESP_EFI_DIR, EXTLINUX_CONF_PATH, LIMINE_CONF_PATH, REFIND_CONF_PATH,
+        SDBOOT_LOADER_CONF_PATH,
+    },
+    dctx,
+    errors::{DError, DResult},
+    extlinux::Extlinux,
+    grub2::{version::compare_versions, EntrySort},
+    limine::Limine,
+    refind::Refind,
+    sdboot::{efi_var, SdBoot},
+    system::efivars::FirmwareBoot,
+};
+
+/// Variable systemd-boot and other boot loaders implementing the Boot Loader
+/// Interface set to their name and version, like `systemd-boot 256.4`
+const LOADER_INFO_VAR: &str = "LoaderInfo";
+
+/// Boot loader whose configuration is managed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootloaderKind {
+    /// Boot loader detected from the EFI variables, the EFI system partition
+    /// and the installed configs
+    #[default]
+    Auto,
+    Grub2,
+    SystemdBoot,
+    Refind,
+    /// extlinux.conf of U-Boot
+    Extlinux,
+    Limine,
+}
+
+/// How the managed boot loader was chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DetectionSource {
+    /// Set with `--bootloader`
+    Option,
+    /// `LoaderInfo` EFI variable set by the boot loader that started the system
+    LoaderInfo,
+    /// EFI binary of the firmware boot entry the system was started from
+    BootCurrent,
+    /// Boot loader binary on the EFI system partition together with its config
+    Esp,
+    /// Only the config of the boot loader is installed
+    Config,
+    /// Nothing was found, grub2 is used
+    Fallback,
+}
+
+/// Boot loader that manages the system and why it was chosen
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BootloaderDetection {
+    pub kind: BootloaderKind,
+    pub source: DetectionSource,
+    /// What was found, like the value of `LoaderInfo` or the path of the config
+    pub evidence: Option<String>,
+}
+
+impl BootloaderDetection {
+    fn new(kind: BootloaderKind, source: DetectionSource, evidence: Option<String>) -> Self {
+        Self {
+            kind,
+            source,
+            evidence,
+        }
+    }
+}
+
+/// Boot loader from the name of a boot loader or the path of its EFI binary.
+/// shim is assumed to load grub2 since that's what distributions ship it with
+fn kind_from_name(name: &str) -> Option<BootloaderKind> {
+    let name = name.to_ascii_lowercase();
+    [
+        ("systemd-boot", BootloaderKind::SystemdBoot),
+        ("refind", BootloaderKind::Refind),
+        ("limine", BootloaderKind::Limine),
+        ("grub", BootloaderKind::Grub2),
+        ("shim", BootloaderKind::Grub2),
+    ]
+    .into_iter()
+    .find(|(pattern, _)| name.contains(pattern))
+    .map(|(_, kind)| kind)
+}
+
+/// EFI binary of a boot loader in the `dir` subdirectory of the EFI directory
+fn esp_binary(efi_dir: &Path, dir: &str) -> Option<String> {
+    let mut binaries: Vec<String> = read_dir(efi_dir.join(dir))
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("efi"))
+        })
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    binaries.sort();
+    binaries.into_iter().next()
+}
+
+impl BootloaderKind {
+    /// Config file that has to exist for the boot loader to be managed
+    pub fn config_path(self) -> String {
+        match self {
+            Self::Auto | Self::Grub2 => GrubLayout::get().cfg_path.clone(),
+            Self::SystemdBoot => SDBOOT_LOADER_CONF_PATH.into(),
+            Self::Refind => REFIND_CONF_PATH.into(),
+            Self::Extlinux => EXTLINUX_CONF_PATH.into(),
+            Self::Limine => LIMINE_CONF_PATH.into(),
+        }
+    }
+
+    /// Resolve `Auto` to the boot loader that manages the system.
+    ///
+    /// The boot loader that started the system is preferred since a system can
+    /// have configs of several boot loaders left around. It's known from
+    /// `LoaderInfo` if the boot loader sets it, or from the firmware entry that
+    /// was booted. Without those the boot loaders installed on the EFI system
+    /// partition and the configs are probed, grub2 first.
+    pub fn detect(self) -> BootloaderDetection {
+        let detection = self.detect_with(
+            efi_var(LOADER_INFO_VAR),
+            FirmwareBoot::read().ok(),
+            Path::new(ESP_EFI_DIR),
+        );
+        log::info!(
+            "Using {:?} boot loader, detected from {:?}: {}",
+            detection.kind,
+            detection.source,
+            detection.evidence.as_deref().unwrap_or("-")
+        );
+        if !Path::new(&detection.kind.config_path()).is_file() {
+            log::warn!(
+                "Config of the {:?} boot loader is missing from {}",
+                detection.kind,
+                detection.kind.config_path()
+            );
+        }
+        detection
+    }
+
+    fn detect_with(
+        self,
+        loader_info: Option<String>,
+        firmware: Option<FirmwareBoot>,
+        efi_dir: &Path,
+    ) -> BootloaderDetection {
+        if self != Self::Auto {
+            return BootloaderDetection::new(self, DetectionSource::Option, None);
+        }
+
+        if let Some(kind) = loader_info.as_deref().and_then(kind_from_name) {
+            return BootloaderDetection::new(kind, DetectionSource::LoaderInfo, loader_info);
+        }
+
+        let booted_file = firmware.as_ref().and_then(|firmware| {
+            firmware
+                .find(firmware.current?)
+                .and_then(|entry| entry.file_path.clone())
+        });
+        if let Some(kind) = booted_file.as_deref().and_then(kind_from_name) {
+            return BootloaderDetection::new(kind, DetectionSource::BootCurrent, booted_file);
+        }
+
+        // extlinux.conf is read by U-Boot which isn't on the EFI system partition
+        let esp_dirs = [
+            (Self::SystemdBoot, "systemd"),
+            (Self::Refind, "refind"),
+            (Self::Limine, "limine"),
+        ];
+        let installed = esp_dirs.into_iter().find_map(|(kind, dir)| {
+            let binary = esp_binary(efi_dir, dir)?;
+            Path::new(&kind.config_path())
+                .is_file()
+                .then_some((kind, binary))
+        });
+        if let Some((kind, binary)) = installed {
+            return BootloaderDetection::new(kind, DetectionSource::Esp, Some(binary));
+        }
+
+        let config = [
+            Self::Grub2,
+            Self::SystemdBoot,
+            Self::Refind,
+            Self::Extlinux,
+            Self::Limine,
+        ]
+        .into_iter()
+        .map(|kind| (kind, kind.config_path()))
+        .find(|(_, path)| Path::new(path).is_file());
+        match config {
+            Some((kind, path)) => {
+                BootloaderDetection::new(kind, DetectionSource::Config, Some(path))
+            }
+            None => BootloaderDetection::new(Self::Grub2, DetectionSource::Fallback, None),
+        }
+    }
+
+    /// Read the config of the boot loader. grub2 has its own handling since
+    /// grub.cfg is generated from /etc/default/grub instead of edited directly
+    pub fn open(self) -> DResult<Option<Box<dyn Bootloader>>> {
+        Ok(match self {
+            Self::Auto | Self::Grub2 => None,
+            Self::SystemdBoot => Some(Box::new(SdBoot::read()?)),
+            Self::Refind => Some(Box::new(Refind::read()?)),
+            Self::Extlinux => Some(Box::new(Extlinux::read()?)),
+            Self::Limine => Some(Box::new(Limine::read()?)),
+        })
+    }
+}
+
+/// Boot entry of a boot loader other than grub2
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoaderEntry {
+    /// Unique name of the entry, the title if the boot loader has no ids
+    pub id: String,
+    pub title: String,
+    pub kernel: Option<String>,
+    pub initrds: Vec<String>,
+    /// Kernel command line
+    pub options: Option<String>,
+    pub version: Option<String>,
+    /// Device tree blob the boot loader loads for the kernel
+    pub devicetree: Option<String>,
+    /// Directory the boot loader picks the device tree of the board from
+    pub devicetree_dir: Option<String>,
+    /// Menu classes of the entry, from the `grub_class` lines of BLS entries
+    pub classes: Vec<String>,
+}
+
+/// Boot loader whose config files are edited directly, unlike grub2 that has
+/// grub.cfg generated from /etc/default/grub.
+///
+/// Changes are made in memory and written with `write`.
+pub trait Bootloader: Send {
+    /// Name of the boot loader used in the messages
+    fn name(&self) -> &'static str;
+
+    /// Path of the main config file
+    fn config_path(&self) -> &str;
+
+    /// Effective values of the global keys in the main config file
+    fn values(&self) -> BTreeMap<String, String>;
+
+    /// Raw lines of the main config file
+    fn lines(&self) -> Vec<String>;
+
+    /// Check `value` of `key` before it's set
+    fn validate(&self, key: &str, value: &str) -> Result<(), String>;
+
+    fn set_value(&mut self, key: &str, value: &str);
+
+    /// Remove `key` from the main config file. Returns false if it wasn't set
+    fn remove_value(&mut self, key: &str) -> bool;
+
+    /// Entries in the same order as the boot menu shows them
+    fn entries(&self) -> Vec<LoaderEntry>;
+
+    /// Key that selects the default entry
+    fn default_key(&self) -> &'static str;
+
+    /// Value that selects the default entry, none if the boot loader picks it
+    fn default_entry(&self) -> Option<String> {
+        self.values().get(self.default_key()).cloned()
+    }
+
+    /// Value of `default_key` that selects `entry`, none if the entry can't
+    /// be the default
+    fn default_value(&self, entry: &LoaderEntry) -> Option<String>;
+
+    /// Entry that is booted by default
+    fn selected(&self) -> Option<LoaderEntry>;
+
+    /// Entry that is booted once on the next boot
+    fn next_entry(&self) -> Option<String> {
+        None
+    }
+
+    /// Replace the kernel command line of the entry with `id`
+    fn set_entry_cmdline(&mut self, id: &str, cmdline: &str) -> DResult<()>;
+
+    /// Set the device tree file or directory of the entry with `id`.
+    /// None removes the setting
+    fn set_entry_devicetree(
+        &mut self,
+        _id: &str,
+        _devicetree: Option<&str>,
+        _devicetree_dir: Option<&str>,
+    ) -> DResult<()> {
+        Err(DError::generic(
+            dctx!(),
+            format!("{} doesn't support setting the device tree", self.name()),
+        ))
+    }
+
+    /// Write the changed config files
+    fn write(&self) -> DResult<()>;
+
+    /// Entry by its id, title or position in the menu starting from 0 like
+    /// the grub2 index paths
+    fn find(&self, entry: &str) -> Option<LoaderEntry> {
+        let entries = self.entries();
+        let index = entry.parse::<usize>().ok();
+        entries
+            .iter()
+            .find(|candidate| candidate.id == entry || candidate.title == entry)
+            .or_else(|| entries.get(index?))
+            .cloned()
+    }
+}
+
+/// Sort the entries by kernel version, newest first. Menu order is kept as is
+pub fn sort_entries(entries: &mut [LoaderEntry], sort: EntrySort) {
+    if sort == EntrySort::Menu {
+        return;
+    }
+
+    entries.sort_by(|a, b| match (&a.version, &b.version) {
+        (Some(version_a), Some(version_b)) => compare_versions(version_b, version_a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::efivars::FirmwareEntry;
+
+    #[test]
+    fn test_detect() {
+        let efi_dir = Path::new("test_data/missing");
+        let detection =
+            BootloaderKind::Refind.detect_with(Some("systemd-boot 256.4".into()), None, efi_dir);
+        assert_eq!(detection.kind, BootloaderKind::Refind);
+        assert_eq!(detection.source, DetectionSource::Option);
+
+        let detection =
+            BootloaderKind::Auto.detect_with(Some("systemd-boot 256.4".into()), None, efi_dir);
+        assert_eq!(detection.kind, BootloaderKind::SystemdBoot);
+        assert_eq!(detection.source, DetectionSource::LoaderInfo);
+        assert_eq!(detection.evidence.as_deref(), Some("systemd-boot 256.4"));
+
+        let entry = |number, file_path: &str| FirmwareEntry {
+            number,
+            description: String::new(),
+            active: true,
+            partition_uuid: None,
+            file_path: Some(file_path.into()),
+        };
+        let firmware = FirmwareBoot {
+            current: Some(2),
+            entries: vec![
+                entry(1, "\\EFI\\opensuse\\shim.efi"),
+                entry(2, "\\EFI\\refind\\refind_x64.efi"),
+            ],
+            ..Default::default()
+        };
+        let detection = BootloaderKind::Auto.detect_with(None, Some(firmware), efi_dir);
+        assert_eq!(detection.kind, BootloaderKind::Refind);
+        assert_eq!(detection.source, DetectionSource::BootCurrent);
+
+        // booting from the fallback path doesn't tell the boot loader
+        assert_eq!(kind_from_name("\\EFI\\BOOT\\BOOTX64.EFI"), None);
+        assert_eq!(
+            kind_from_name("\\EFI\\fedora\\shimx64.efi"),
+            Some(BootloaderKind::Grub2)
+        );
+        assert_eq!(kind_from_name("Limine 8.6.0"), Some(BootloaderKind::Limine));
+    }
+
+    #[test]
+    fn test_sort_entries() {
+        let entry = |id: &str, version: Option<&str>| LoaderEntry {
+            id: id.into(),
+            title: id.into(),
+            kernel: None,
+            initrds: Vec::new(),
+            options: None,
+            version: version.map(str::to_string),
+            devicetree: None,
+            devicetree_dir: None,
+            classes: Vec::new(),
+        };
+        let mut entries = vec![
+            entry("windows", None),
+            entry("old", Some("6.11.4")),
+            entry("new", Some("6.12.5")),
+        ];
+
+        sort_entries(&mut entries, EntrySort::Menu);
+        assert_eq!(entries[0].id, "windows");
+
+        sort_entries(&mut entries, EntrySort::Version);
+        let ids: Vec<&str> = entries.iter().map(|entry| entry.id.as_str()).collect();
+        assert_eq!(ids, vec!["new", "old", "windows"]);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fs::{canonicalize, read_dir},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// Directories probed for grub.cfg and grubenv, in order of preference
+#[cfg(not(feature = "dev"))]
+const GRUB_BOOT_DIRS: &[&str] = &["/boot/grub2", "/boot/grub"];
+#[cfg(feature = "dev")]
+const GRUB_BOOT_DIRS: &[&str] = &["tmp"];
+
+/// EFI system partition directory that has a subdirectory per distribution
+#[cfg(not(feature = "dev"))]
+const GRUB_EFI_DIR: &str = "/boot/efi/EFI";
+#[cfg(feature = "dev")]
+const GRUB_EFI_DIR: &str = "tmp/EFI";
+
+static LAYOUT: OnceLock<GrubLayout> = OnceLock::new();
+
+/// Locations of the grub files generated under /boot.
+///
+/// openSUSE and Fedora use /boot/grub2, Debian based distributions /boot/grub
+/// and some EFI setups keep grub.cfg in the EFI system partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrubLayout {
+    /// Path to the generated grub.cfg
+    pub cfg_path: String,
+    /// Path to the grub environment block with the symlinks resolved
+    pub env_path: String,
+    /// Command used to generate grub.cfg
+    pub mkconfig: &'static str,
+    /// Command used to check the syntax of grub.cfg
+    pub script_check: &'static str,
+    /// Prefix of the grub tools, `grub2` or `grub`
+    pub tool_prefix: &'static str,
+}
+
+impl GrubLayout {
+    fn from_dir(dir: &Path) -> Self {
+        let (mkconfig, script_check, tool_prefix) =
+            if dir.file_name().is_some_and(|name| name == "grub") {
+                ("grub-mkconfig", "grub-script-check", "grub")
+            } else {
+                ("grub2-mkconfig", "grub2-script-check", "grub2")
+            };
+
+        Self {
+            cfg_path: dir.join("grub.cfg").to_string_lossy().to_string(),
+            env_path: dir.join("grubenv").to_string_lossy().to_string(),
+            mkconfig,
+            script_check,
+            tool_prefix,
+        }
+    }
+
+    /// Directory that has grub.cfg
+    pub fn boot_dir(&self) -> &Path {
+        Path::new(&self.cfg_path).parent().unwrap_or(Path::new("/"))
+    }
+
+    /// Directory where the grub themes are installed, next to grub.cfg
+    pub fn themes_dir(&self) -> PathBuf {
+        self.boot_dir().join("themes")
+    }
+
+    /// Name of a grub tool for this layout, e.g. `set-default` -> `grub2-set-default`
+    pub fn tool(&self, name: &str) -> String {
+        format!("{}-{name}", self.tool_prefix)
+    }
+
+    /// Directories under the EFI directory that contain grub.cfg, sorted by name
+    fn efi_dirs(efi_dir: &str) -> Vec<PathBuf> {
+        let Ok(dir) = read_dir(efi_dir) else {
+            return Vec::new();
+        };
+
+        let mut dirs: Vec<PathBuf> = dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.join("grub.cfg").is_file())
+            .collect();
+        dirs.sort();
+        dirs
+    }
+
+    /// grubenv next to grub.cfg or in the EFI directories. The symlinks are resolved
+    /// so writing and watching grubenv doesn't replace the symlink itself.
+    /// Defaults to grubenv next to grub.cfg if it doesn't exist anywhere
+    fn find_env(dir: &Path, efi_dir: &str) -> Option<PathBuf> {
+        std::iter::once(dir.to_path_buf())
+            .chain(Self::efi_dirs(efi_dir))
+            .map(|dir| dir.join("grubenv"))
+            .find_map(|path| canonicalize(path).ok())
+    }
+
+    fn probe(boot_dirs: &[&str], efi_dir: &str) -> Option<Self> {
+        let dir = boot_dirs
+            .iter()
+            .map(PathBuf::from)
+            .find(|dir| dir.join("grub.cfg").is_file())
+            .or_else(|| Self::efi_dirs(efi_dir).into_iter().next())?;
+
+        let mut layout = Self::from_dir(&dir);
+        if let Some(env_path) = Self::find_env(&dir, efi_dir) {
+            layout.env_path = env_path.to_string_lossy().to_string();
+        }
+        Some(layout)
+    }
+
+    /// Detect the layout on first call and reuse it afterwards
+    pub fn get() -> &'static Self {
+        LAYOUT.get_or_init(|| {
+            if let Some(layout) = Self::probe(GRUB_BOOT_DIRS, GRUB_EFI_DIR) {
+                log::info!(
+                    "Using grub config from {} and grubenv from {}",
+                    layout.cfg_path,
+                    layout.env_path
+                );
+                layout
+            } else {
+                let layout = Self::from_dir(Path::new(GRUB_BOOT_DIRS[0]));
+                log::warn!(
+                    "Couldn't find grub.cfg from known locations, defaulting to {}",
+                    layout.cfg_path
+                );
+                layout
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_probe() {
+        let efi_env = canonicalize("test_data/EFI/fedora/grubenv")
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        // test_data has no grubenv so the one in the EFI directory is used
+        let layout = GrubLayout::probe(&["test_data/missing", "test_data"], "test_data/EFI");
+        assert_eq!(
+            layout,
+            Some(GrubLayout {
+                cfg_path: "test_data/grub.cfg".into(),
+                env_path: efi_env.clone(),
+                mkconfig: "grub2-mkconfig",
+                script_check: "grub2-script-check",
+                tool_prefix: "grub2",
+            })
+        );
+
+        let layout = GrubLayout::probe(&["test_data/missing"], "test_data/EFI").unwrap();
+        assert_eq!(layout.cfg_path, "test_data/EFI/fedora/grub.cfg");
+        assert_eq!(layout.env_path, efi_env);
+
+        // grubenv symlink is resolved
+        let layout = GrubLayout::probe(&["test_data/boot/grub2"], "test_data/missing").unwrap();
+        assert_eq!(layout.cfg_path, "test_data/boot/grub2/grub.cfg");
+        assert_eq!(layout.env_path, efi_env);
+
+        let layout = GrubLayout::probe(&["test_data"], "test_data/missing").unwrap();
+        assert_eq!(layout.env_path, "test_data/grubenv");
+
+        let layout = GrubLayout::from_dir(Path::new("/boot/grub"));
+        assert_eq!(layout.mkconfig, "grub-mkconfig");
+        assert_eq!(layout.tool("set-default"), "grub-set-default");
+        assert_eq!(layout.themes_dir(), Path::new("/boot/grub/themes"));
+        assert_eq!(
+            GrubLayout::probe(&["test_data/missing"], "test_data/missing"),
+            None
+        );
+    }
+}
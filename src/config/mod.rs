@@ -2,8 +2,14 @@ use std::str::FromStr;
 
 use clap::Parser;
 
-use crate::config::time::TimeConfig;
+use crate::{
+    bootloader::BootloaderKind,
+    config::time::TimeConfig,
+    grub2::{editenv::EnvBackend, EntrySort, ParseMode},
+    system::ostree::KargsBackend,
+};
 
+pub mod layout;
 mod time;
 
 /// Log levels that are idententical to `tracing::Level` but includes
@@ -117,9 +123,47 @@ pub struct ConfigArgs {
     /// Allow program to idle indefinitely. Overrides idle_time argument
     #[arg(long, default_value_t = false)]
     allow_idle: bool,
+
+    /// Keep lines in the grub config that aren't key=value pairs as they are
+    /// instead of refusing to read the file. Clients are warned about those lines.
+    #[arg(long, default_value_t = false)]
+    lenient_parse: bool,
+
+    /// How grubenv is modified. "auto" uses grub2-set-default, grub2-reboot and
+    /// grub2-editenv if grubenv is on btrfs or zfs, otherwise grubenv is written directly
+    #[arg(long, value_enum, default_value_t = EnvBackend::Auto)]
+    pub grubenv_backend: EnvBackend,
+
+    /// How kernel arguments are changed. "auto" uses rpm-ostree or ostree on systems
+    /// booted from an ostree deployment, where the grub config can't be edited directly
+    #[arg(long, value_enum, default_value_t = KargsBackend::Auto)]
+    pub kargs_backend: KargsBackend,
+
+    /// Order of the boot entries returned by GetEntries. "version" lists the newest
+    /// kernel first with its recovery entries right after it
+    #[arg(long, value_enum, default_value_t = EntrySort::Menu)]
+    pub entry_sort: EntrySort,
+
+    /// Boot loader to manage. "auto" detects the boot loader that started the
+    /// system from the EFI variables, the EFI system partition and the installed configs
+    #[arg(long, value_enum, default_value_t = BootloaderKind::Auto)]
+    pub bootloader: BootloaderKind,
+
+    /// SQLite database of the snapshots and the boot history. The file and its
+    /// directory are created if they don't exist
+    #[arg(long, default_value = DATABASE_PATH)]
+    pub database: String,
 }
 
 impl ConfigArgs {
+    pub fn parse_mode(&self) -> ParseMode {
+        if self.lenient_parse {
+            ParseMode::Lenient
+        } else {
+            ParseMode::Strict
+        }
+    }
+
     /// Configured allowed idle time in milliseconds, or none if
     /// program is allowed to idle indefinitely
     pub fn allowed_idle_time(&self) -> Option<u64> {
@@ -146,15 +190,85 @@ pub const GRUB_ROOT_PATH: &str = "/etc/default";
 pub const GRUB_ROOT_PATH: &str = "tmp";
 
 #[cfg(not(feature = "dev"))]
-pub const GRUB_ENV_PATH: &str = "/boot/grub2/grubenv";
+pub const GRUB_DROPIN_PATH: &str = "/etc/default/grub.d";
+#[cfg(feature = "dev")]
+pub const GRUB_DROPIN_PATH: &str = "tmp/grub.d";
+
+/// Scripts grub2-mkconfig runs to generate grub.cfg
+#[cfg(not(feature = "dev"))]
+pub const GRUB_SCRIPTS_PATH: &str = "/etc/grub.d";
+#[cfg(feature = "dev")]
+pub const GRUB_SCRIPTS_PATH: &str = "tmp/etc/grub.d";
+
+#[cfg(not(feature = "dev"))]
+pub const BLS_ENTRIES_PATH: &str = "/boot/loader/entries";
+#[cfg(feature = "dev")]
+pub const BLS_ENTRIES_PATH: &str = "tmp/loader/entries";
+
+/// Kernel images and initrds installed by the kernel packages
+#[cfg(not(feature = "dev"))]
+pub const BOOT_PATH: &str = "/boot";
+#[cfg(feature = "dev")]
+pub const BOOT_PATH: &str = "tmp/boot";
+
+/// Modules of every installed kernel are in a directory named by the version
+#[cfg(not(feature = "dev"))]
+pub const MODULES_PATH: &str = "/usr/lib/modules";
+#[cfg(feature = "dev")]
+pub const MODULES_PATH: &str = "tmp/modules";
+
+/// EFI directory of the EFI system partition where the boot loaders are installed
+#[cfg(not(feature = "dev"))]
+pub const ESP_EFI_DIR: &str = "/boot/efi/EFI";
+#[cfg(feature = "dev")]
+pub const ESP_EFI_DIR: &str = "tmp/EFI";
+
+#[cfg(not(feature = "dev"))]
+pub const SDBOOT_LOADER_CONF_PATH: &str = "/boot/efi/loader/loader.conf";
+#[cfg(feature = "dev")]
+pub const SDBOOT_LOADER_CONF_PATH: &str = "tmp/efi/loader/loader.conf";
+
+#[cfg(not(feature = "dev"))]
+pub const REFIND_CONF_PATH: &str = "/boot/efi/EFI/refind/refind.conf";
+#[cfg(feature = "dev")]
+pub const REFIND_CONF_PATH: &str = "tmp/EFI/refind/refind.conf";
+
+#[cfg(not(feature = "dev"))]
+pub const EXTLINUX_CONF_PATH: &str = "/boot/extlinux/extlinux.conf";
+#[cfg(feature = "dev")]
+pub const EXTLINUX_CONF_PATH: &str = "tmp/extlinux/extlinux.conf";
+
+/// limine.conf on an EFI system partition mounted to /boot
+#[cfg(not(feature = "dev"))]
+pub const LIMINE_CONF_PATH: &str = "/boot/limine.conf";
+#[cfg(feature = "dev")]
+pub const LIMINE_CONF_PATH: &str = "tmp/limine.conf";
+
+/// Exists only when the machine was booted with UEFI
+#[cfg(not(feature = "dev"))]
+pub const FIRMWARE_EFI_PATH: &str = "/sys/firmware/efi";
+#[cfg(feature = "dev")]
+pub const FIRMWARE_EFI_PATH: &str = "tmp/firmware/efi";
+
+/// EFI variables of the firmware boot manager, systemd-boot and shim
+#[cfg(not(feature = "dev"))]
+pub const EFIVARS_PATH: &str = "/sys/firmware/efi/efivars";
+#[cfg(feature = "dev")]
+pub const EFIVARS_PATH: &str = "tmp/efivars";
+
+/// BootNext that bootkit set during the current boot, /run is emptied on every boot
+#[cfg(not(feature = "dev"))]
+pub const BOOT_NEXT_MARKER_PATH: &str = "/run/bootkit/boot-next";
 #[cfg(feature = "dev")]
-pub const GRUB_ENV_PATH: &str = "tmp/grubenv";
+pub const BOOT_NEXT_MARKER_PATH: &str = "tmp/run/boot-next";
 
+/// Private directory for the configs that ValidateConfig checks
 #[cfg(not(feature = "dev"))]
-pub const GRUB_CFG_PATH: &str = "/boot/grub2/grub.cfg";
+pub const VALIDATE_DIR: &str = "/run/bootkit/validate";
 #[cfg(feature = "dev")]
-pub const GRUB_CFG_PATH: &str = "tmp/grub.cfg";
+pub const VALIDATE_DIR: &str = "tmp/run/validate";
 
+/// Default of the --database argument
 #[cfg(not(feature = "dev"))]
 pub const DATABASE_PATH: &str = "/var/lib/bootkit/bootkit.db";
 #[cfg(feature = "dev")]
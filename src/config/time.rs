@@ -91,7 +91,7 @@ mod tests {
         let time = TimeConfig::from_str("1 S").unwrap();
         assert_eq!(time.milliseconds, 1_000);
         let time = TimeConfig::from_str("1123 sec").unwrap();
-        assert_eq!(time.milliseconds, 1123_000);
+        assert_eq!(time.milliseconds, 1_123_000);
         let time = TimeConfig::from_str("99     sec").unwrap();
         assert_eq!(time.milliseconds, 99_000);
     }
@@ -0,0 +1,19 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[allow(dead_code)]
+pub struct BootTimeline {
+    /// boot id of the kernel, same as the journal boot id
+    pub boot_id: String,
+    /// BOOT_IMAGE from the kernel command line, if grub set it
+    pub boot_image: Option<String>,
+    /// kernel version parsed from the boot image
+    pub kernel_version: Option<String>,
+    /// saved_entry in grubenv when the boot was recorded
+    pub saved_entry: Option<String>,
+    /// when the system was booted
+    pub boot_time: NaiveDateTime,
+    /// when the boot was recorded
+    pub created: NaiveDateTime,
+}
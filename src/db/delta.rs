@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use similar::{DiffOp, TextDiff};
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+/// Deltas stored against the same baseline before the next snapshot is stored
+/// in full again
+pub const MAX_DELTAS_PER_BASE: i64 = 16;
+
+/// Line level edit of the baseline, lines keep their line endings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DeltaOp {
+    /// Keep the next lines of the baseline
+    Copy(usize),
+    /// Drop the next lines of the baseline
+    Skip(usize),
+    Insert(Vec<String>),
+}
+
+/// Delta that turns `base` into `new`, as JSON for the grub_config column
+pub fn encode(base: &str, new: &str) -> DResult<String> {
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let diff = TextDiff::from_lines(base, new);
+    let mut ops = Vec::new();
+    for op in diff.ops() {
+        match *op {
+            DiffOp::Equal { len, .. } => ops.push(DeltaOp::Copy(len)),
+            DiffOp::Delete { old_len, .. } => ops.push(DeltaOp::Skip(old_len)),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => ops.push(insert(&new_lines[new_index..new_index + new_len])),
+            DiffOp::Replace {
+                old_len,
+                new_index,
+                new_len,
+                ..
+            } => {
+                ops.push(DeltaOp::Skip(old_len));
+                ops.push(insert(&new_lines[new_index..new_index + new_len]));
+            }
+        }
+    }
+    serde_json::to_string(&ops).ctx(dctx!(), "Cannot serialize snapshot delta")
+}
+
+fn insert(lines: &[&str]) -> DeltaOp {
+    DeltaOp::Insert(lines.iter().map(|line| line.to_string()).collect())
+}
+
+/// Content of a snapshot stored as `delta` against `base`
+pub fn apply(base: &str, delta: &str) -> DResult<String> {
+    let ops: Vec<DeltaOp> =
+        serde_json::from_str(delta).ctx(dctx!(), "Malformed snapshot delta in the database")?;
+    let mut base_lines = base.split_inclusive('\n');
+    let mut content = String::with_capacity(base.len());
+    for op in ops {
+        match op {
+            DeltaOp::Copy(len) => {
+                for _ in 0..len {
+                    let line = base_lines.next().ok_or_else(|| {
+                        DError::generic(dctx!(), "Snapshot delta is longer than its baseline")
+                    })?;
+                    content.push_str(line);
+                }
+            }
+            DeltaOp::Skip(len) => {
+                if base_lines.by_ref().take(len).count() != len {
+                    return Err(DError::generic(
+                        dctx!(),
+                        "Snapshot delta is longer than its baseline",
+                    ));
+                }
+            }
+            DeltaOp::Insert(lines) => lines.iter().for_each(|line| content.push_str(line)),
+        }
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let base = "GRUB_TIMEOUT=8\nGRUB_DEFAULT=saved\nGRUB_CMDLINE_LINUX=\"quiet\"\n";
+        let configs = [
+            base,
+            "GRUB_TIMEOUT=5\nGRUB_DEFAULT=saved\nGRUB_CMDLINE_LINUX=\"quiet\"\n",
+            "GRUB_DEFAULT=saved\n",
+            "# comment\nGRUB_TIMEOUT=8\nGRUB_DEFAULT=saved\nGRUB_CMDLINE_LINUX=\"quiet splash\"\nGRUB_TERMINAL=console\n",
+            // no newline at the end
+            "GRUB_TIMEOUT=8\nGRUB_DEFAULT=saved",
+            "",
+        ];
+        for config in configs {
+            let delta = encode(base, config).unwrap();
+            assert_eq!(apply(base, &delta).unwrap(), config);
+        }
+
+        // a single changed key is much smaller than the whole file
+        let delta = encode(base, configs[1]).unwrap();
+        assert!(!delta.contains("GRUB_CMDLINE_LINUX"));
+
+        assert!(apply("", &encode(base, configs[1]).unwrap()).is_err());
+        assert!(apply(base, "not json").is_err());
+    }
+}
@@ -1,5 +1,11 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    dctx,
+    errors::{DError, DResult},
+};
 
 #[derive(Debug, Serialize)]
 #[allow(dead_code)]
@@ -12,4 +18,230 @@ pub struct Grub2Snapshot {
     pub selected_kernel: Option<String>,
     /// when snapshot was created
     pub created: NaiveDateTime,
+    /// D-Bus caller that made the change, none if it's not known
+    pub caller_uid: Option<i64>,
+    pub caller_pid: Option<i64>,
+    pub caller_process: Option<String>,
+    pub caller_unit: Option<String>,
+    /// [`SnapshotOrigin`] as a string, none for the snapshots taken before it was recorded
+    pub origin: Option<String>,
+    pub hostname: Option<String>,
+    /// Comment given by the client that saved the config
+    pub comment: Option<String>,
+    /// grubenv when the snapshot was taken, none if it couldn't be read
+    pub grubenv: Option<String>,
+    /// Unique name of a snapshot created by a client, see [`SnapshotOrigin::Manual`]
+    pub tag: Option<String>,
+    /// [`content_hash`] when the snapshot was taken
+    pub content_hash: Option<String>,
+    /// snapper snapshot the root filesystem was mounted from, none without snapper
+    pub snapper_snapshot: Option<i64>,
+    /// Snapshot that `grub_config` is a delta of in the database. Snapshots are
+    /// always returned with the full config so this is none outside of the database
+    #[serde(skip)]
+    pub delta_base: Option<i64>,
+}
+
+impl Grub2Snapshot {
+    /// Check that the full config and grubenv still match the stored hash.
+    /// Snapshots that don't have a hash can't be checked and always pass
+    pub fn verify(&self) -> DResult<()> {
+        let Some(hash) = &self.content_hash else {
+            return Ok(());
+        };
+        if *hash != content_hash(&self.grub_config, self.grubenv.as_deref()) {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "Snapshot {} is corrupted, its content doesn't match its hash",
+                    self.id
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Hex encoded SHA-256 of the grub config and grubenv of a snapshot
+pub fn content_hash(grub_config: &str, grubenv: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(grub_config.as_bytes());
+    if let Some(grubenv) = grubenv {
+        // keep the config and grubenv apart so moving bytes between them changes the hash
+        hasher.update([0]);
+        hasher.update(grubenv.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Result of checking a snapshot in the database against its hash
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotIntegrity {
+    Intact,
+    /// Snapshot doesn't have a hash to check against
+    Unhashed,
+    /// Why the snapshot can't be trusted
+    Corrupted(String),
+}
+
+/// Why a snapshot was taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotOrigin {
+    /// First snapshot of the config when the database is created
+    Initial,
+    /// Config was saved through the D-Bus API
+    Save,
+    /// Config was modified by something else than bootkit
+    External,
+    /// Config as it was before a snapshot was restored over it
+    PreRestore,
+    /// Config as it was before it was saved, when it wasn't the latest snapshot
+    PreSave,
+    /// Snapshot of the current config requested by a client, usually with a tag
+    Manual,
+}
+
+impl SnapshotOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotOrigin::Initial => "initial",
+            SnapshotOrigin::Save => "save",
+            SnapshotOrigin::External => "external",
+            SnapshotOrigin::PreRestore => "pre-restore",
+            SnapshotOrigin::PreSave => "pre-save",
+            SnapshotOrigin::Manual => "manual",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        [
+            SnapshotOrigin::Initial,
+            SnapshotOrigin::Save,
+            SnapshotOrigin::External,
+            SnapshotOrigin::PreRestore,
+            SnapshotOrigin::PreSave,
+            SnapshotOrigin::Manual,
+        ]
+        .into_iter()
+        .find(|origin| origin.as_str() == value)
+    }
+}
+
+/// Which snapshots are listed, unset fields match every snapshot
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SnapshotFilter {
+    pub origin: Option<SnapshotOrigin>,
+    /// Snapshots created at or after this time
+    pub since: Option<NaiveDateTime>,
+    /// Snapshots created before this time
+    pub before: Option<NaiveDateTime>,
+}
+
+impl SnapshotFilter {
+    /// Filter from the D-Bus arguments where an empty string is unset. Times are
+    /// in UTC like the creation times, either as a date or a date and a time
+    pub fn new(origin: &str, since: &str, before: &str) -> DResult<Self> {
+        let origin = if origin.is_empty() {
+            None
+        } else {
+            Some(SnapshotOrigin::parse(origin).ok_or_else(|| {
+                DError::generic(dctx!(), format!("Unknown snapshot origin '{origin}'"))
+            })?)
+        };
+
+        Ok(Self {
+            origin,
+            since: parse_time(since)?,
+            before: parse_time(before)?,
+        })
+    }
+}
+
+fn parse_time(value: &str) -> DResult<Option<NaiveDateTime>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(Some(time));
+        }
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| DError::generic(dctx!(), format!("Invalid snapshot time '{value}'")))?;
+    Ok(Some(date.and_time(Default::default())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(grub_config: &str, grubenv: Option<&str>) -> Grub2Snapshot {
+        Grub2Snapshot {
+            id: 1,
+            grub_config: grub_config.into(),
+            selected_kernel: None,
+            created: NaiveDateTime::default(),
+            caller_uid: None,
+            caller_pid: None,
+            caller_process: None,
+            caller_unit: None,
+            origin: None,
+            hostname: None,
+            comment: None,
+            grubenv: grubenv.map(str::to_string),
+            tag: None,
+            content_hash: None,
+            snapper_snapshot: None,
+            delta_base: None,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_filter() {
+        assert_eq!(
+            SnapshotFilter::new("", "", "").unwrap(),
+            SnapshotFilter::default()
+        );
+
+        let filter = SnapshotFilter::new("pre-save", "2025-10-16", "2025-10-17 08:30:00").unwrap();
+        assert_eq!(filter.origin, Some(SnapshotOrigin::PreSave));
+        assert_eq!(filter.since.unwrap().to_string(), "2025-10-16 00:00:00");
+        assert_eq!(filter.before.unwrap().to_string(), "2025-10-17 08:30:00");
+        assert_eq!(
+            SnapshotFilter::new("", "2025-10-16T08:30:00", "")
+                .unwrap()
+                .since
+                .unwrap()
+                .to_string(),
+            "2025-10-16 08:30:00"
+        );
+
+        assert!(SnapshotFilter::new("unknown", "", "").is_err());
+        assert!(SnapshotFilter::new("", "yesterday", "").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_verify() {
+        let config = "GRUB_TIMEOUT=8\n";
+        let grubenv = "saved_entry=0\n";
+        assert_eq!(
+            content_hash("", None),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_ne!(content_hash(config, None), content_hash(config, Some("")));
+
+        let mut unhashed = snapshot(config, Some(grubenv));
+        assert!(unhashed.verify().is_ok());
+
+        unhashed.content_hash = Some(content_hash(config, Some(grubenv)));
+        let mut intact = unhashed;
+        assert!(intact.verify().is_ok());
+
+        intact.grub_config = "GRUB_TIMEOUT=0\n".into();
+        assert!(intact.verify().is_err());
+
+        let mut env_changed = snapshot(config, Some("saved_entry=1\n"));
+        env_changed.content_hash = Some(content_hash(config, Some(grubenv)));
+        assert!(env_changed.verify().is_err());
+    }
 }
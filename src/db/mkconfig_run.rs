@@ -0,0 +1,28 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[allow(dead_code)]
+pub struct MkconfigRun {
+    /// Auto incrementing run id
+    pub id: i64,
+    /// Snapshot the grub.cfg was generated from, null if the generation failed
+    pub grub2_snapshot_id: Option<i64>,
+    /// Command line that was run
+    pub command: String,
+    /// Exit code of the command, null if it was killed by a signal
+    pub exit_status: Option<i64>,
+    /// Output of the command
+    pub stdout: String,
+    pub stderr: String,
+    /// when the command was run
+    pub created: NaiveDateTime,
+    /// [`super::grub2::content_hash`] of grub.cfg after a successful run, null
+    /// for the failed runs and the ones recorded before the hash was stored
+    pub grub_cfg_hash: Option<String>,
+    /// D-Bus caller that started the run, none if it's not known
+    pub caller_uid: Option<i64>,
+    pub caller_pid: Option<i64>,
+    pub caller_process: Option<String>,
+    pub caller_unit: Option<String>,
+}
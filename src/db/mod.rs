@@ -1,142 +1,376 @@
-use std::{fs::File, path::Path};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    fs::{create_dir_all, read_to_string},
+    path::Path,
+    time::Duration,
+};
 
-use sqlx::{sqlite::SqlitePoolOptions, Error, Pool, Sqlite};
+use sqlx::{
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePoolOptions},
+    Error, Pool, Sqlite, Transaction,
+};
 
 use crate::{
-    config::{DATABASE_PATH, GRUB_FILE_PATH},
-    db::{grub2::Grub2Snapshot, selected_snapshot::SelectedSnapshot},
+    config::{layout::GrubLayout, GRUB_FILE_PATH},
+    db::{
+        boot_timeline::BootTimeline,
+        grub2::{content_hash, Grub2Snapshot, SnapshotFilter, SnapshotIntegrity, SnapshotOrigin},
+        mkconfig_run::MkconfigRun,
+        selected_snapshot::SelectedSnapshot,
+    },
     dctx,
-    errors::{DRes, DResult},
-    grub2::{GrubBootEntries, GrubFile},
+    errors::{DError, DRes, DResult},
+    grub2::{GrubBootEntries, GrubFile, ParseMode},
+    system::{caller::Caller, hostname, process::CommandOutput, snapper, CurrentBoot},
 };
 
+pub mod boot_timeline;
+mod delta;
 pub mod grub2;
+pub mod mkconfig_run;
 pub mod selected_snapshot;
 
+/// How long a query waits for another connection to release its lock before
+/// failing with SQLITE_BUSY
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Versioned schema changes from migrations/, applied in order on startup. Schema
+/// changes go to new migration files, the applied ones must not be edited
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Columns that were added to grub2_snapshot before the migrations were versioned,
+/// with the column that tells if they are there
+const UNVERSIONED_COLUMNS: &[(&str, &str)] = &[
+    ("caller_uid", GRUB2_CALLER_COLUMNS),
+    ("origin", GRUB2_METADATA_COLUMNS),
+    ("delta_base", GRUB2_DELTA_COLUMN),
+];
+
+const GRUB2_CALLER_COLUMNS: &str = "
+ALTER TABLE grub2_snapshot ADD COLUMN caller_uid INTEGER;
+ALTER TABLE grub2_snapshot ADD COLUMN caller_pid INTEGER;
+ALTER TABLE grub2_snapshot ADD COLUMN caller_process TEXT;
+ALTER TABLE grub2_snapshot ADD COLUMN caller_unit TEXT;
+";
+
+const GRUB2_METADATA_COLUMNS: &str = "
+ALTER TABLE grub2_snapshot ADD COLUMN origin TEXT;
+ALTER TABLE grub2_snapshot ADD COLUMN hostname TEXT;
+ALTER TABLE grub2_snapshot ADD COLUMN comment TEXT;
+";
+
+const GRUB2_DELTA_COLUMN: &str = "ALTER TABLE grub2_snapshot ADD COLUMN delta_base INTEGER;";
+
+/// Takes the write lock right away so the rows the transaction reads can't be
+/// changed by another writer before it commits
+const BEGIN_WRITE: &str = "BEGIN IMMEDIATE";
+
 #[derive(Clone)]
 pub struct Database {
     pool: Pool<Sqlite>,
 }
 
 impl Database {
-    pub async fn new() -> DResult<Self> {
-        if !Path::new(DATABASE_PATH).exists() {
-            log::debug!("Database file in was not found. Creating it in path {DATABASE_PATH}");
-            File::create(DATABASE_PATH).ctx(
-                dctx!(),
-                format!("Cannot create database in path: {DATABASE_PATH}"),
-            )?;
+    /// Open the database at `path`, creating it if it doesn't exist
+    pub async fn new(path: &str) -> DResult<Self> {
+        if let Some(dir) = Path::new(path).parent() {
+            if !dir.as_os_str().is_empty() && !dir.exists() {
+                log::debug!("Creating the database directory {dir:?}");
+                create_dir_all(dir).ctx(
+                    dctx!(),
+                    format!("Cannot create the database directory {dir:?}"),
+                )?;
+            }
         }
 
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            // readers don't block the writer, D-Bus calls and the file events
+            // use the database at the same time
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT);
+
         // should this failure be fatal or should the snapshot features
         // just be disabled?
         let pool = SqlitePoolOptions::new()
             .max_connections(10)
-            .connect(DATABASE_PATH)
+            .connect_with(options)
             .await
             .ctx(
                 dctx!(),
-                format!("Cannot initialize SQLite database in path: {DATABASE_PATH}"),
+                format!("Cannot initialize SQLite database in path: {path}"),
             )?;
 
+        log::info!("Opened database at {path}");
         Ok(Self { pool })
     }
 
-    pub async fn initialize(&self) -> DResult<()> {
-        let grub_table = sqlx::query!(
-            "SELECT name FROM sqlite_master WHERE type='table' AND name='grub2_snapshot'"
-        )
-        .fetch_one(&self.pool)
-        .await;
-
-        if let Err(Error::RowNotFound) = grub_table {
-            log::debug!("grub2_snapshot table not found from database, creating it");
-            sqlx::query(include_str!("../../db/grub2.sql"))
-                .execute(&self.pool)
-                .await
-                .ctx(dctx!(), "Cannot initialize grub2_snapshots")?;
-        }
+    pub async fn initialize(&self, parse_mode: ParseMode) -> DResult<()> {
+        self.upgrade_unversioned().await?;
+        MIGRATOR
+            .run(&self.pool)
+            .await
+            .map_err(Error::from)
+            .ctx(dctx!(), "Cannot run the database migrations")?;
 
         let snapshot_count = sqlx::query!("SELECT COUNT(*) as count FROM grub2_snapshot")
             .fetch_one(&self.pool)
             .await
             .ctx(dctx!(), "Cannot get count from grub2_snapshot")?;
 
-        if snapshot_count.count == 0 {
+        // systemd-boot systems have no grub config to take the snapshot of
+        if snapshot_count.count == 0 && !Path::new(GRUB_FILE_PATH).exists() {
+            log::debug!("{GRUB_FILE_PATH} doesn't exist, not setting the first grub2_snapshot");
+        } else if snapshot_count.count == 0 {
             log::debug!("grub2_snapshot table is empty. Setting first entry to grub2_snapshots");
-            let grub = GrubFile::from_file(GRUB_FILE_PATH)?;
-            if cfg!(feature = "dev") {
-                log::debug!("Setting initial snapshot without selected kernel");
-                self.save_grub2(&grub, None::<&str>).await?;
-            } else {
-                let entry = GrubBootEntries::new()?;
-                self.save_grub2(&grub, entry.selected()).await?;
-            }
+            let grub = GrubFile::from_file(GRUB_FILE_PATH, parse_mode)?;
+            // the default entry is stored with the config so restoring the snapshot
+            // restores it too. grub.cfg may not be generated yet on a new install
+            let selected_kernel = match GrubBootEntries::new() {
+                Ok(entries) => entries.selected().map(str::to_string),
+                Err(err) => {
+                    log::warn!(
+                        "Setting initial snapshot without selected kernel: {}",
+                        err.error()
+                    );
+                    None
+                }
+            };
+            self.save_grub2(
+                &grub,
+                selected_kernel,
+                None,
+                SnapshotOrigin::Initial,
+                None,
+                None,
+            )
+            .await?;
         }
 
-        let grub_table = sqlx::query!(
-            "SELECT name FROM sqlite_master WHERE type='table' AND name='selected_snapshot'"
-        )
-        .fetch_one(&self.pool)
-        .await;
+        log::info!("Initialised database");
+        Ok(())
+    }
 
-        if let Err(Error::RowNotFound) = grub_table {
-            log::debug!("selected_snapshot table not found from database, creating it");
-            sqlx::query(include_str!("../../db/selected_snapshot.sql"))
-                .execute(&self.pool)
-                .await
-                .ctx(dctx!(), "Cannot initialize selected_snapshots table")?;
+    /// Databases from before the migrations were versioned have the tables, but
+    /// they may miss the columns that were added later. The columns are added so
+    /// that the initial migration, which only creates the missing tables, leaves
+    /// the database with the same schema as a new one
+    async fn upgrade_unversioned(&self) -> DResult<()> {
+        if self.has_table("_sqlx_migrations").await? || !self.has_table("grub2_snapshot").await? {
+            return Ok(());
         }
 
-        log::info!("Initialised database at {DATABASE_PATH}");
+        log::info!("Upgrading database from before the versioned migrations");
+        for (column, columns_sql) in UNVERSIONED_COLUMNS {
+            let existing =
+                sqlx::query("SELECT name FROM pragma_table_info('grub2_snapshot') WHERE name=(?)")
+                    .bind(column)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .ctx(dctx!(), "Cannot get the columns of grub2_snapshot")?;
+
+            if existing.is_none() {
+                log::debug!("Adding columns of {column} to grub2_snapshot table");
+                sqlx::query(columns_sql)
+                    .execute(&self.pool)
+                    .await
+                    .ctx(dctx!(), format!("Cannot add {column} to grub2_snapshot"))?;
+            }
+        }
         Ok(())
     }
 
+    async fn has_table(&self, name: &str) -> DResult<bool> {
+        let table = sqlx::query("SELECT name FROM sqlite_master WHERE type='table' AND name=(?)")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+            .ctx(dctx!(), format!("Cannot look up table {name}"))?;
+        Ok(table.is_some())
+    }
+
+    /// Insert a snapshot of `grub`, `tag` is unique among the snapshots
     pub async fn save_grub2<K: Into<String>>(
         &self,
         grub: &GrubFile,
         selected_kernel: Option<K>,
-    ) -> DResult<()> {
+        caller: Option<&Caller>,
+        origin: SnapshotOrigin,
+        comment: Option<&str>,
+        tag: Option<&str>,
+    ) -> DResult<i64> {
         let selected_kernel: Option<String> = selected_kernel.map(K::into);
-        let grub_file = grub.as_string();
+        let config = grub.as_string();
+        let caller = caller.cloned().unwrap_or_default();
+        let origin = origin.as_str();
+        let hostname = hostname();
+        let grubenv = read_to_string(&GrubLayout::get().env_path).ok();
+        let content_hash = content_hash(&config, grubenv.as_deref());
+        let snapper_snapshot = snapper::root_snapshot();
 
-        sqlx::query!(
-            "INSERT INTO grub2_snapshot (grub_config, selected_kernel) VALUES (?, ?)",
+        // the baseline can't be removed between choosing it and inserting the delta
+        let mut tx = self.begin_write().await?;
+        let (grub_file, delta_base) = stored_config(&mut tx, &config).await?;
+        let result = sqlx::query!(
+            "INSERT INTO grub2_snapshot (grub_config, selected_kernel, caller_uid, caller_pid, caller_process, caller_unit, origin, hostname, comment, tag, delta_base, grubenv, content_hash, snapper_snapshot) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             grub_file,
             selected_kernel,
+            caller.uid,
+            caller.pid,
+            caller.process,
+            caller.unit,
+            origin,
+            hostname,
+            comment,
+            tag,
+            delta_base,
+            grubenv,
+            content_hash,
+            snapper_snapshot,
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
         .ctx(dctx!(), "Cannot insert new entry to grub2_snapshot table")?;
+        tx.commit()
+            .await
+            .ctx(dctx!(), "Cannot commit the new grub2_snapshot entry")?;
 
         log::debug!("New grub2 config snapshot inserted to grub2_snapshot table");
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Start a transaction that reads rows and writes based on them
+    async fn begin_write(&self) -> DResult<Transaction<'static, Sqlite>> {
+        self.pool
+            .begin_with(BEGIN_WRITE)
+            .await
+            .ctx(dctx!(), "Cannot start a database transaction")
+    }
+
+    /// Replace the deltas in `snapshots` with the full configs. The baselines
+    /// that aren't in `snapshots` are fetched from the database
+    async fn expand(&self, snapshots: &mut [Grub2Snapshot]) -> DResult<()> {
+        let mut conn = self
+            .pool
+            .acquire()
+            .await
+            .ctx(dctx!(), "Cannot get a database connection")?;
+        expand(&mut conn, snapshots).await
+    }
+
+    /// Record a grub2-mkconfig run with the caller that started it, optionally tied
+    /// to the snapshot it was generated from. Runs with a snapshot generated the
+    /// current grub.cfg, so its hash is kept
+    pub async fn save_mkconfig_run(
+        &self,
+        grub2_snapshot_id: Option<i64>,
+        output: &CommandOutput,
+    ) -> DResult<()> {
+        let grub_cfg_hash = grub2_snapshot_id
+            .and_then(|_| read_to_string(&GrubLayout::get().cfg_path).ok())
+            .map(|grub_cfg| content_hash(&grub_cfg, None));
+        let caller = Caller::current().unwrap_or_default();
+        sqlx::query!(
+            "INSERT INTO mkconfig_run (grub2_snapshot_id, command, exit_status, stdout, stderr, grub_cfg_hash, caller_uid, caller_pid, caller_process, caller_unit) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            grub2_snapshot_id,
+            output.command,
+            output.exit_status,
+            output.stdout,
+            output.stderr,
+            grub_cfg_hash,
+            caller.uid,
+            caller.pid,
+            caller.process,
+            caller.unit,
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot insert new entry to mkconfig_run table")?;
+
+        log::debug!("New mkconfig run inserted to mkconfig_run table");
         Ok(())
     }
 
+    pub async fn mkconfig_runs(&self) -> DResult<Vec<MkconfigRun>> {
+        let runs = sqlx::query_as!(MkconfigRun, "SELECT * FROM mkconfig_run ORDER BY id DESC",)
+            .fetch_all(&self.pool)
+            .await
+            .ctx(dctx!(), "Cannot fetch runs from mkconfig_run table")?;
+
+        Ok(runs)
+    }
+
     pub async fn remove_grub2(&self, grub_id: i64) -> DResult<()> {
+        // no delta can be added against the snapshot while it's being removed
+        let mut tx = self.begin_write().await?;
+        // the deltas of the snapshot are stored in full before their baseline is gone
+        let mut dependents = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT * FROM grub2_snapshot WHERE delta_base=(?)",
+            grub_id
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .ctx(
+            dctx!(),
+            format!("Cannot fetch the deltas of snapshot {grub_id}"),
+        )?;
+        expand(&mut tx, &mut dependents).await?;
+
+        for dependent in &dependents {
+            sqlx::query!(
+                "UPDATE grub2_snapshot SET grub_config=(?), delta_base=NULL WHERE id=(?)",
+                dependent.grub_config,
+                dependent.id
+            )
+            .execute(&mut *tx)
+            .await
+            .ctx(
+                dctx!(),
+                format!("Cannot store snapshot {} in full", dependent.id),
+            )?;
+        }
         sqlx::query!("DELETE FROM grub2_snapshot WHERE id=(?)", grub_id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await
             .ctx(dctx!(), "Cannot remove snapshot with id {grub_id}")?;
+        tx.commit().await.ctx(
+            dctx!(),
+            format!("Cannot commit the removal of snapshot {grub_id}"),
+        )?;
 
         log::debug!("Grub2 snapshot with id {grub_id} was removed");
         Ok(())
     }
 
-    pub async fn latest_grub2(&self) -> DResult<Grub2Snapshot> {
+    /// Can the database be queried
+    pub async fn is_available(&self) -> bool {
+        sqlx::query("SELECT 1").execute(&self.pool).await.is_ok()
+    }
+
+    /// Newest snapshot, none if no snapshot has been taken yet
+    pub async fn latest_grub2(&self) -> DResult<Option<Grub2Snapshot>> {
         let snapshot = sqlx::query_as!(
             Grub2Snapshot,
             "SELECT * FROM grub2_snapshot ORDER BY id DESC LIMIT 1",
         )
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
         .await
         .ctx(dctx!(), "Cannot fetch snapshot from grub2_snapshot table")?;
+        let Some(snapshot) = snapshot else {
+            return Ok(None);
+        };
 
-        Ok(snapshot)
+        let mut snapshots = [snapshot];
+        self.expand(&mut snapshots).await?;
+        let [snapshot] = snapshots;
+        Ok(Some(snapshot))
     }
 
     pub async fn grub2_snapshots(&self) -> DResult<Vec<Grub2Snapshot>> {
-        let snapshots = sqlx::query_as!(
+        let mut snapshots = sqlx::query_as!(
             Grub2Snapshot,
             "SELECT * FROM grub2_snapshot ORDER BY id DESC",
         )
@@ -144,9 +378,50 @@ impl Database {
         .await
         .ctx(dctx!(), "Cannot fetch snapshot from grub2_snapshot table")?;
 
+        self.expand(&mut snapshots).await?;
         Ok(snapshots)
     }
 
+    /// At most `limit` snapshots that match `filter` from the newest to the oldest,
+    /// skipping the first `offset`, and the number of snapshots that match.
+    /// Limit of 0 returns all the snapshots after `offset`
+    pub async fn grub2_snapshot_page(
+        &self,
+        filter: &SnapshotFilter,
+        offset: u32,
+        limit: u32,
+    ) -> DResult<(Vec<Grub2Snapshot>, i64)> {
+        let origin = filter.origin.map(|origin| origin.as_str());
+        // negative limit is no limit in sqlite
+        let limit = if limit == 0 { -1 } else { i64::from(limit) };
+
+        let total = sqlx::query!(
+            "SELECT COUNT(*) as count FROM grub2_snapshot WHERE (?1 IS NULL OR origin=?1) AND (?2 IS NULL OR created>=?2) AND (?3 IS NULL OR created<?3)",
+            origin,
+            filter.since,
+            filter.before,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot count the snapshots in grub2_snapshot table")?;
+
+        let mut snapshots = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT * FROM grub2_snapshot WHERE (?1 IS NULL OR origin=?1) AND (?2 IS NULL OR created>=?2) AND (?3 IS NULL OR created<?3) ORDER BY id DESC LIMIT ?4 OFFSET ?5",
+            origin,
+            filter.since,
+            filter.before,
+            limit,
+            offset,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch snapshot from grub2_snapshot table")?;
+
+        self.expand(&mut snapshots).await?;
+        Ok((snapshots, total.count))
+    }
+
     pub async fn grub2_snapshot(&self, id: i64) -> DResult<Grub2Snapshot> {
         let snapshots = sqlx::query_as!(
             Grub2Snapshot,
@@ -160,7 +435,103 @@ impl Database {
             "Cannot fetch snapshot with id '{id}' from grub2_snapshot table",
         )?;
 
-        Ok(snapshots)
+        let mut snapshots = [snapshots];
+        self.expand(&mut snapshots).await?;
+        let [snapshot] = snapshots;
+        Ok(snapshot)
+    }
+
+    /// Check every snapshot against its content hash, from the oldest to the newest.
+    /// A delta that can't be applied to its baseline is corrupted as well
+    pub async fn verify_grub2_snapshots(&self) -> DResult<Vec<(i64, SnapshotIntegrity)>> {
+        let snapshots = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT * FROM grub2_snapshot ORDER BY id ASC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch snapshot from grub2_snapshot table")?;
+
+        let mut results = Vec::with_capacity(snapshots.len());
+        for snapshot in snapshots {
+            let id = snapshot.id;
+            let mut snapshots = [snapshot];
+            let integrity = match self.expand(&mut snapshots).await {
+                Err(err) => SnapshotIntegrity::Corrupted(err.error().to_string()),
+                Ok(()) => match snapshots[0].verify() {
+                    Err(err) => SnapshotIntegrity::Corrupted(err.error().to_string()),
+                    Ok(()) if snapshots[0].content_hash.is_none() => SnapshotIntegrity::Unhashed,
+                    Ok(()) => SnapshotIntegrity::Intact,
+                },
+            };
+            results.push((id, integrity));
+        }
+        Ok(results)
+    }
+
+    /// Snapshot that's tagged with `tag`, if there's one
+    pub async fn grub2_snapshot_by_tag(&self, tag: &str) -> DResult<Option<Grub2Snapshot>> {
+        let snapshot = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT * FROM grub2_snapshot WHERE tag=(?)",
+            tag
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .ctx(
+            dctx!(),
+            format!("Cannot fetch snapshot with tag '{tag}' from grub2_snapshot table"),
+        )?;
+
+        let Some(snapshot) = snapshot else {
+            return Ok(None);
+        };
+        let mut snapshots = [snapshot];
+        self.expand(&mut snapshots).await?;
+        let [snapshot] = snapshots;
+        Ok(Some(snapshot))
+    }
+
+    /// Latest config snapshot taken while the root filesystem was mounted from
+    /// the snapper snapshot `number`, if there's one
+    pub async fn grub2_snapshot_by_snapper(&self, number: i64) -> DResult<Option<Grub2Snapshot>> {
+        let snapshot = sqlx::query_as!(
+            Grub2Snapshot,
+            "SELECT * FROM grub2_snapshot WHERE snapper_snapshot=(?) ORDER BY id DESC LIMIT 1",
+            number
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .ctx(
+            dctx!(),
+            format!("Cannot fetch snapshot of snapper snapshot {number} from grub2_snapshot table"),
+        )?;
+
+        let Some(snapshot) = snapshot else {
+            return Ok(None);
+        };
+        let mut snapshots = [snapshot];
+        self.expand(&mut snapshots).await?;
+        let [snapshot] = snapshots;
+        Ok(Some(snapshot))
+    }
+
+    /// Config snapshot ids by the snapper snapshot they were taken in, newest first
+    pub async fn snapper_snapshot_map(&self) -> DResult<BTreeMap<i64, Vec<i64>>> {
+        let rows = sqlx::query!(
+            "SELECT id, snapper_snapshot FROM grub2_snapshot WHERE snapper_snapshot IS NOT NULL ORDER BY id DESC"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch the snapper snapshots from grub2_snapshot table")?;
+
+        let mut map: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+        for row in rows {
+            if let Some(number) = row.snapper_snapshot {
+                map.entry(number).or_default().push(row.id);
+            }
+        }
+        Ok(map)
     }
 
     pub async fn selected_snapshot(&self) -> DResult<SelectedSnapshot> {
@@ -175,6 +546,45 @@ impl Database {
         Ok(snapshot)
     }
 
+    /// Record the current boot. Boots that are already recorded are ignored
+    pub async fn record_boot<S: Into<String>>(
+        &self,
+        boot: &CurrentBoot,
+        saved_entry: Option<S>,
+    ) -> DResult<()> {
+        let saved_entry: Option<String> = saved_entry.map(S::into);
+        let kernel_version = boot.kernel_version();
+
+        let result = sqlx::query!(
+            "INSERT OR IGNORE INTO boot_timeline (boot_id, boot_image, kernel_version, saved_entry, boot_time) VALUES (?, ?, ?, ?, ?)",
+            boot.boot_id,
+            boot.boot_image,
+            kernel_version,
+            saved_entry,
+            boot.boot_time,
+        )
+        .execute(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot insert new entry to boot_timeline table")?;
+
+        if result.rows_affected() > 0 {
+            log::debug!("Recorded boot {} to boot_timeline table", boot.boot_id);
+        }
+        Ok(())
+    }
+
+    pub async fn boot_timeline(&self) -> DResult<Vec<BootTimeline>> {
+        let boots = sqlx::query_as!(
+            BootTimeline,
+            "SELECT * FROM boot_timeline ORDER BY boot_time DESC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .ctx(dctx!(), "Cannot fetch boots from boot_timeline table")?;
+
+        Ok(boots)
+    }
+
     pub async fn set_selected_snapshot(&self, id: Option<i64>) -> DResult<()> {
         sqlx::query!("UPDATE selected_snapshot SET grub2_snapshot_id=(?)", id)
             .execute(&self.pool)
@@ -184,3 +594,78 @@ impl Database {
         Ok(())
     }
 }
+
+/// `config` as it's stored in grub2_snapshot with its delta_base. Configs are
+/// stored as deltas against the latest full config when that's at most half
+/// the size, and in full again after MAX_DELTAS_PER_BASE deltas
+async fn stored_config(
+    conn: &mut SqliteConnection,
+    config: &str,
+) -> DResult<(String, Option<i64>)> {
+    let base = sqlx::query!(
+        "SELECT id, grub_config FROM grub2_snapshot WHERE delta_base IS NULL ORDER BY id DESC LIMIT 1"
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .ctx(dctx!(), "Cannot fetch the delta baseline from grub2_snapshot table")?;
+    let Some(base) = base else {
+        return Ok((config.to_string(), None));
+    };
+
+    let deltas = sqlx::query!(
+        "SELECT COUNT(*) as count FROM grub2_snapshot WHERE delta_base=(?)",
+        base.id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .ctx(dctx!(), "Cannot count the deltas in grub2_snapshot table")?;
+    if deltas.count >= delta::MAX_DELTAS_PER_BASE {
+        log::debug!("Storing the snapshot in full after {} deltas", deltas.count);
+        return Ok((config.to_string(), None));
+    }
+
+    let delta = delta::encode(&base.grub_config, config)?;
+    if delta.len() * 2 > config.len() {
+        return Ok((config.to_string(), None));
+    }
+    Ok((delta, Some(base.id)))
+}
+
+/// Replace the deltas in `snapshots` with the full configs. The baselines
+/// that aren't in `snapshots` are fetched from the database
+async fn expand(conn: &mut SqliteConnection, snapshots: &mut [Grub2Snapshot]) -> DResult<()> {
+    let mut bases: HashMap<i64, String> = snapshots
+        .iter()
+        .filter(|snapshot| snapshot.delta_base.is_none())
+        .map(|snapshot| (snapshot.id, snapshot.grub_config.clone()))
+        .collect();
+    for snapshot in snapshots {
+        let Some(base_id) = snapshot.delta_base.take() else {
+            continue;
+        };
+        let base = match bases.entry(base_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let base = sqlx::query!(
+                    "SELECT grub_config FROM grub2_snapshot WHERE id=(?) AND delta_base IS NULL",
+                    base_id
+                )
+                .fetch_optional(&mut *conn)
+                .await
+                .ctx(
+                    dctx!(),
+                    "Cannot fetch the delta baseline from grub2_snapshot table",
+                )?
+                .ok_or_else(|| {
+                    DError::generic(
+                        dctx!(),
+                        format!("Baseline {base_id} of snapshot {} is missing", snapshot.id),
+                    )
+                })?;
+                entry.insert(base.grub_config)
+            }
+        };
+        snapshot.grub_config = delta::apply(base, &snapshot.grub_config)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use crate::{bootloader::BootloaderKind, system::FirmwareMode};
+
+/// Version of the D-Bus API, bumped on breaking changes. The interfaces aren't
+/// versioned, so a method that changes is added next to the old one with a name
+/// that says what's new, like RestoreWithOptions, and the existing clients keep working
+pub const API_VERSION: u32 = 1;
+
+/// Bits of the Capabilities property, mirrored in bootkit-client. At most one of
+/// the boot loader bits is set
+pub const CAP_GRUB2: u64 = 1 << 0;
+pub const CAP_SYSTEMD_BOOT: u64 = 1 << 1;
+pub const CAP_REFIND: u64 = 1 << 2;
+pub const CAP_EXTLINUX: u64 = 1 << 3;
+pub const CAP_LIMINE: u64 = 1 << 4;
+/// Snapshot interface has the history of the config
+pub const CAP_SNAPSHOTS: u64 = 1 << 5;
+/// SetNextEntry can set the entry that's booted once
+pub const CAP_NEXT_ENTRY: u64 = 1 << 6;
+/// Mutating methods are authorized with polkit
+pub const CAP_POLKIT: u64 = 1 << 7;
+
+/// Capabilities of the daemon managing `bootloader`
+pub fn capabilities(bootloader: BootloaderKind, polkit: bool) -> u64 {
+    let loader = match bootloader {
+        BootloaderKind::Auto => 0,
+        BootloaderKind::Grub2 => CAP_GRUB2 | CAP_SNAPSHOTS | CAP_NEXT_ENTRY,
+        BootloaderKind::SystemdBoot => CAP_SYSTEMD_BOOT | CAP_NEXT_ENTRY,
+        BootloaderKind::Refind => CAP_REFIND,
+        BootloaderKind::Extlinux => CAP_EXTLINUX,
+        BootloaderKind::Limine => CAP_LIMINE,
+    };
+    if polkit {
+        loader | CAP_POLKIT
+    } else {
+        loader
+    }
+}
+
+/// Boot loader with the platform it's installed for when it supports both, like
+/// `grub2-efi` or `systemd-boot`. Empty if no boot loader was detected
+pub fn bootloader_type(bootloader: BootloaderKind, firmware: FirmwareMode) -> &'static str {
+    match (bootloader, firmware) {
+        (BootloaderKind::Auto, _) => "",
+        (BootloaderKind::Grub2, FirmwareMode::Uefi) => "grub2-efi",
+        (BootloaderKind::Grub2, FirmwareMode::Bios) => "grub2-bios",
+        (BootloaderKind::SystemdBoot, _) => "systemd-boot",
+        (BootloaderKind::Refind, _) => "refind",
+        (BootloaderKind::Extlinux, _) => "extlinux",
+        (BootloaderKind::Limine, FirmwareMode::Uefi) => "limine-efi",
+        (BootloaderKind::Limine, FirmwareMode::Bios) => "limine-bios",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        assert_eq!(
+            capabilities(BootloaderKind::Grub2, true),
+            CAP_GRUB2 | CAP_SNAPSHOTS | CAP_NEXT_ENTRY | CAP_POLKIT
+        );
+        assert_eq!(capabilities(BootloaderKind::Extlinux, false), CAP_EXTLINUX);
+        assert_eq!(
+            capabilities(BootloaderKind::SystemdBoot, false) & CAP_NEXT_ENTRY,
+            CAP_NEXT_ENTRY
+        );
+    }
+
+    #[test]
+    fn test_bootloader_type() {
+        assert_eq!(
+            bootloader_type(BootloaderKind::Grub2, FirmwareMode::Bios),
+            "grub2-bios"
+        );
+        assert_eq!(
+            bootloader_type(BootloaderKind::Grub2, FirmwareMode::Uefi),
+            "grub2-efi"
+        );
+        assert_eq!(
+            bootloader_type(BootloaderKind::SystemdBoot, FirmwareMode::Uefi),
+            "systemd-boot"
+        );
+        assert_eq!(
+            bootloader_type(BootloaderKind::Auto, FirmwareMode::Bios),
+            ""
+        );
+    }
+}
@@ -1,87 +1,1187 @@
-use zbus::{connection::Builder, fdo, interface, object_server::SignalEmitter, Connection};
+use std::collections::BTreeMap;
 
-use crate::{config::ConfigArgs, db::Database, dbus::handler::DbusHandler};
+use zbus::{
+    connection::Builder, fdo, interface, message::Header, object_server::SignalEmitter,
+    zvariant::OwnedObjectPath, Connection,
+};
 
-struct BootKitInfo {}
+use crate::{
+    config::ConfigArgs,
+    db::Database,
+    dbus::{
+        api::{bootloader_type, capabilities, API_VERSION},
+        edit::EditOwner,
+        entries::{EntryChanges, EntryObjects},
+        handler::{ConfigProperties, DbusHandler, EntryInfo},
+        job::JobList,
+        logind::{check_reboot, reboot},
+        observer::{BootKitObserver, OBSERVER_PATH},
+        polkit::{Polkit, MODIFY_CONFIG, REBOOT, REMOVE_KERNELS, SET_BOOT_ENTRY},
+        ratelimit::RateLimiter,
+        status::BootKitStatus,
+    },
+    dctx,
+    errors::{BusError, DError, DResult},
+    system::{caller::Caller, FirmwareMode},
+};
+
+struct BootKitInfo {
+    handler: DbusHandler,
+    polkit: Polkit,
+    firmware: FirmwareMode,
+}
 
 #[interface(name = "org.opensuse.bootkit.Info")]
 impl BootKitInfo {
-    async fn get_version(&self) -> Result<String, fdo::Error> {
+    async fn get_version(&self) -> Result<String, BusError> {
         log::debug!("Calling org.opensuse.bootkit.Info GetVersion");
         Ok(env!("CARGO_PKG_VERSION").into())
     }
+
+    /// Version of the D-Bus API, bumped on breaking changes
+    #[zbus(property)]
+    async fn api_version(&self) -> u32 {
+        API_VERSION
+    }
+
+    /// Bitmask of the managed boot loader and the optional features, the bits are
+    /// defined in src/dbus/api.rs
+    #[zbus(property)]
+    async fn capabilities(&self) -> u64 {
+        capabilities(self.handler.bootloader_kind(), self.polkit.enabled())
+    }
+
+    /// Managed boot loader and the platform it's installed for, like `grub2-efi`,
+    /// `grub2-bios` or `systemd-boot`
+    #[zbus(property)]
+    async fn bootloader_type(&self) -> String {
+        bootloader_type(self.handler.bootloader_kind(), self.firmware).into()
+    }
+
+    /// `uefi` or `bios`, depending on how the machine was booted
+    #[zbus(property)]
+    async fn firmware_mode(&self) -> String {
+        self.firmware.as_str().into()
+    }
+
+    /// Boot loader detected at startup as JSON with the kind, the source of the
+    /// detection and what was found
+    #[zbus(property)]
+    async fn bootloader(&self) -> Result<String, fdo::Error> {
+        log::debug!("Getting org.opensuse.bootkit.Info Bootloader");
+        let data = self.handler.get_bootloader_json()?;
+        Ok(data)
+    }
 }
 
 pub struct BootKitSnapshots {
     handler: DbusHandler,
+    /// Verifying hashes every snapshot in the database
+    verify_limit: RateLimiter,
+    polkit: Polkit,
 }
 
 #[interface(name = "org.opensuse.bootkit.Snapshot")]
 impl BootKitSnapshots {
-    async fn get_snapshots(&self) -> Result<String, fdo::Error> {
+    /// Snapshots of the grub config from the newest to the oldest, and the selected one.
+    /// Snapshots that don't match their hash have `corrupted` set
+    async fn list(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot List");
+        let data = self.handler.get_snapshots_json().await?;
+        Ok(data)
+    }
+
+    /// At most `limit` snapshots from the newest to the oldest after skipping `offset`
+    /// of them, in the same format as List with the total number of matches. Limit 0
+    /// returns everything after `offset`. Empty `origin`, `since` and `before` don't
+    /// filter, the times are UTC dates or "YYYY-MM-DD HH:MM:SS"
+    async fn query(
+        &self,
+        offset: u32,
+        limit: u32,
+        origin: &str,
+        since: &str,
+        before: &str,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot Query");
+        let data = self
+            .handler
+            .query_snapshots_json(offset, limit, origin, since, before)
+            .await?;
+        Ok(data)
+    }
+
+    /// Snapshot with `id`, its diff against the current config and its latest
+    /// grub2-mkconfig run
+    async fn get(&self, id: i64) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot Get");
+        let data = self.handler.get_snapshot_json(id).await?;
+        Ok(data)
+    }
+
+    /// Latest snapshot taken while the root filesystem was mounted from the snapper
+    /// snapshot `number`, in the same format as Get. After rolling back the root
+    /// filesystem this is the config that matches it
+    async fn get_by_snapper_snapshot(&self, number: i64) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot GetBySnapperSnapshot");
+        let data = self.handler.get_snapshot_by_snapper_json(number).await?;
+        Ok(data)
+    }
+
+    /// Config snapshot ids from the newest to the oldest by the snapper snapshot the
+    /// root filesystem was mounted from. Empty if snapper isn't used
+    async fn snapper_snapshots(&self) -> Result<BTreeMap<i64, Vec<i64>>, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot SnapperSnapshots");
+        let data = self.handler.snapper_snapshot_map().await?;
+        Ok(data)
+    }
+
+    /// Check every snapshot against the hash of its content. Returns the number of
+    /// checked snapshots, the ones without a hash and the corrupted ones
+    async fn verify_snapshots(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot VerifySnapshots");
+        self.verify_limit.check(connection, &header).await?;
+        let data = self.handler.verify_snapshots_json().await?;
+        Ok(data)
+    }
+
+    /// Snapshot tagged with `tag`, in the same format as Get
+    async fn get_by_tag(&self, tag: &str) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot GetByTag");
+        let data = self.handler.get_snapshot_by_tag_json(tag).await?;
+        Ok(data)
+    }
+
+    /// Save the current config as a snapshot tagged with `tag`, like "before
+    /// enabling mitigations=off". Tags are unique. Empty `comment` is not stored.
+    /// Returns the id of the new snapshot
+    async fn create(
+        &self,
+        tag: &str,
+        comment: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<i64, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot Create");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let id = caller
+            .scope(self.handler.create_tagged_snapshot(tag, comment))
+            .await?;
+        Ok(id)
+    }
+
+    /// Key and line level changes from the snapshot `from` to the snapshot `to`.
+    /// Id 0 is the current /etc/default/grub
+    async fn diff(&self, from: i64, to: i64) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot Diff");
+        let data = self.handler.snapshot_diff_json(from, to).await?;
+        Ok(data)
+    }
+
+    /// Write the snapshot with `id` to the system and regenerate grub.cfg from it
+    async fn restore(
+        &self,
+        id: i64,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot Restore");
+        self.restore_with_options(id, true, header, connection)
+            .await
+    }
+
+    /// Restore with the option to leave grub.cfg pending. The current config is
+    /// kept as a pre-restore snapshot and grub.cfg is regenerated only if
+    /// `regenerate` is set. Restore keeps its signature for the existing clients
+    async fn restore_with_options(
+        &self,
+        id: i64,
+        regenerate: bool,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot RestoreWithOptions");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller
+            .scope(self.handler.restore_snapshot(id, regenerate))
+            .await?;
+        Ok(data)
+    }
+
+    /// Remove the snapshot with `id`. The selected snapshot can't be removed
+    async fn delete(
+        &self,
+        id: i64,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<(), BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Snapshot Delete");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        caller.scope(self.handler.delete_snapshot(id)).await?;
+        Ok(())
+    }
+
+    /// Deprecated, use List
+    async fn get_snapshots(&self) -> Result<String, BusError> {
         log::debug!("Calling org.opensuse.bootkit.Snapshot GetSnapshots");
         let data = self.handler.get_snapshots_json().await?;
         Ok(data)
     }
 
-    async fn remove_snapshot(&self, data: &str) -> Result<String, fdo::Error> {
+    /// Deprecated, use Delete
+    async fn remove_snapshot(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
         log::debug!("Calling org.opensuse.bootkit.Snapshot RemoveSnapshot");
-        let data = self.handler.remove_snapshot(data).await?;
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.remove_snapshot(data)).await?;
         Ok(data)
     }
 
-    async fn select_snapshot(&self, data: &str) -> Result<String, fdo::Error> {
+    /// Deprecated, use Restore
+    async fn select_snapshot(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
         log::debug!("Calling org.opensuse.bootkit.Snapshot SelectSnapshot");
-        let data = self.handler.select_snapshot(data).await?;
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.select_snapshot(data)).await?;
         Ok(data)
     }
 }
 
 pub struct BootKitConfig {
     handler: DbusHandler,
+    jobs: JobList,
+    regenerate_limit: RateLimiter,
+    validate_limit: RateLimiter,
+    polkit: Polkit,
 }
 
 #[interface(name = "org.opensuse.bootkit.Config")]
 impl BootKitConfig {
-    async fn get_config(&self) -> Result<String, fdo::Error> {
+    /// Default entry as the boot loader config names it, saved_entry when grub2
+    /// uses GRUB_DEFAULT=saved
+    #[zbus(property)]
+    async fn default_entry(&self) -> String {
+        self.handler.config_properties().default_entry
+    }
+
+    #[zbus(property)]
+    async fn timeout(&self) -> String {
+        self.handler.config_properties().timeout
+    }
+
+    /// Command line of the normal boot entries
+    #[zbus(property)]
+    async fn kernel_cmdline(&self) -> String {
+        self.handler.config_properties().kernel_cmdline
+    }
+
+    #[zbus(property)]
+    async fn bootloader_type(&self) -> String {
+        self.handler.bootloader_type()
+    }
+
+    /// Deprecated, use GetConfigValues
+    async fn get_config(&self) -> Result<String, BusError> {
         log::debug!("Calling org.opensuse.bootkit.Config GetConfig");
         let data = self.handler.get_grub2_config_json().await?;
         Ok(data)
     }
 
-    async fn save_config(&self, data: &str) -> Result<String, fdo::Error> {
+    /// Deprecated, use SaveConfigValues
+    async fn save_config(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
         log::debug!("Calling org.opensuse.bootkit.Config SaveConfig");
-        let data = self.handler.save_grub2_config(data).await?;
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.save_grub2_config(data)).await?;
+        Ok(data)
+    }
+
+    /// Reconcile the config to the complete desired state in `data`, returns what
+    /// was changed as JSON. Applying the same state twice changes nothing. If the
+    /// keys were written but the default entry couldn't be saved, the result has
+    /// `default_entry_error` set instead of failing the call
+    async fn apply_state(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config ApplyState");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.apply_state_json(data)).await?;
+        Ok(data)
+    }
+
+    /// Validate `data` in the SaveConfig format like saving it would, without
+    /// writing anything. Returns the diagnostics as JSON. Editors can call this
+    /// as the user types, so it's only limited to keep a runaway client in check
+    async fn validate_config(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config ValidateConfig");
+        self.validate_limit.check(connection, &header).await?;
+        let data = self.handler.validate_config_json(data).await?;
+        Ok(data)
+    }
+
+    /// Diff of what SaveConfig would write with the same `data`, nothing is written
+    async fn preview_changes(&self, data: &str) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config PreviewChanges");
+        let data = self.handler.preview_changes_json(data)?;
+        Ok(data)
+    }
+
+    async fn get_config_values(&self) -> Result<BTreeMap<String, String>, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetConfigValues");
+        let data = self.handler.get_config_values()?;
+        Ok(data)
+    }
+
+    /// Replace the keys of /etc/default/grub. Keys that a drop-in overrides are
+    /// rejected, SetConfigValues changes them where they're defined.
+    /// Returns the warnings about the saved config
+    async fn save_config_values(
+        &self,
+        values: BTreeMap<String, String>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<Vec<String>, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SaveConfigValues");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller
+            .scope(self.handler.save_config_values(values))
+            .await?;
+        Ok(data)
+    }
+
+    /// Value of a single key and whether grub.cfg has to be regenerated
+    /// before it takes effect
+    #[zbus(out_args("value", "needs_regeneration"))]
+    async fn get_config_value(&self, key: &str) -> Result<(String, bool), BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetConfigValue");
+        let data = self.handler.get_config_value(key).await?;
+        Ok(data)
+    }
+
+    /// Set a single key, grub.cfg is regenerated when the value changes
+    #[zbus(out_args("regenerated", "warnings"))]
+    async fn set_config_value(
+        &self,
+        key: &str,
+        value: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<(bool, Vec<String>), BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetConfigValue");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller
+            .scope(self.handler.set_config_value(key, value))
+            .await?;
+        Ok(data)
+    }
+
+    /// Set the given keys in a single write, the other keys are not touched.
+    /// Nothing is changed if any of the values is invalid. Returns the warnings
+    /// about the changed keys
+    async fn set_config_values(
+        &self,
+        changes: BTreeMap<String, String>,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<Vec<String>, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetConfigValues");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller
+            .scope(self.handler.set_config_values(changes))
+            .await?;
+        Ok(data)
+    }
+
+    /// Begin an edit that is staged over time and committed in a single write.
+    /// Returns the token of the edit and the hash of the config it's based on
+    #[zbus(out_args("token", "hash"))]
+    async fn begin_edit(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<(String, String), BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config BeginEdit");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = self.handler.begin_edit(edit_owner(&header, &caller)?)?;
+        Ok(data)
+    }
+
+    async fn stage(
+        &self,
+        token: &str,
+        key: &str,
+        value: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<(), BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config Stage");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let owner = edit_owner(&header, &caller)?;
+        self.handler.stage_edit(&owner, token, key, value)?;
+        Ok(())
+    }
+
+    /// Write the staged changes. Fails without writing anything if the config
+    /// was changed after BeginEdit. Returns the warnings about the changed keys
+    async fn commit(
+        &self,
+        token: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<Vec<String>, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config Commit");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let owner = edit_owner(&header, &caller)?;
+        let data = caller
+            .scope(self.handler.commit_edit(&owner, token))
+            .await?;
+        Ok(data)
+    }
+
+    async fn cancel(
+        &self,
+        token: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<(), BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config Cancel");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let owner = edit_owner(&header, &caller)?;
+        self.handler.cancel_edit(&owner, token)?;
+        Ok(())
+    }
+
+    async fn get_key_schema(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetKeySchema");
+        let data = self.handler.get_key_schema_json()?;
+        Ok(data)
+    }
+
+    async fn get_menu_protection(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetMenuProtection");
+        let data = self.handler.get_menu_protection_json()?;
+        Ok(data)
+    }
+
+    async fn set_menu_protection(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetMenuProtection");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_menu_protection(data)).await?;
+        Ok(data)
+    }
+
+    async fn get_gfx_modes(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetGfxModes");
+        let data = self.handler.get_gfx_modes_json()?;
+        Ok(data)
+    }
+
+    async fn get_cmdline(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetCmdline");
+        let data = self.handler.get_cmdline_json().await?;
+        Ok(data)
+    }
+
+    async fn get_kernel_cmdline(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetKernelCmdline");
+        let data = self.handler.get_kernel_cmdline_json()?;
+        Ok(data)
+    }
+
+    async fn set_cmdline_param(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetCmdlineParam");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_cmdline_param(data)).await?;
+        Ok(data)
+    }
+
+    async fn get_resume(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetResume");
+        let data = self.handler.get_resume_json().await?;
+        Ok(data)
+    }
+
+    async fn set_resume(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetResume");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_resume(data)).await?;
+        Ok(data)
+    }
+
+    async fn get_themes(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetThemes");
+        let data = self.handler.get_themes_json().await?;
+        Ok(data)
+    }
+
+    async fn set_theme(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetTheme");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_theme(data)).await?;
         Ok(data)
     }
 
+    async fn get_os_prober(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetOsProber");
+        let data = self.handler.get_os_prober_json()?;
+        Ok(data)
+    }
+
+    async fn set_os_prober(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetOsProber");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_os_prober(data)).await?;
+        Ok(data)
+    }
+
+    async fn set_timeout(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetTimeout");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_timeout(data)).await?;
+        Ok(data)
+    }
+
+    async fn set_timeout_style(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config SetTimeoutStyle");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_timeout_style(data)).await?;
+        Ok(data)
+    }
+
+    async fn get_pending_changes(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetPendingChanges");
+        let data = self.handler.get_pending_changes_json().await?;
+        Ok(data)
+    }
+
+    /// Blocks until grub.cfg is regenerated, StartRegenerateConfig reports
+    /// the progress through a job object instead
+    async fn regenerate_config(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config RegenerateConfig");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        self.regenerate_limit.check(connection, &header).await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.regenerate_config()).await?;
+        Ok(data)
+    }
+
+    /// Regenerate grub.cfg in the background. Returns the path of the
+    /// org.opensuse.bootkit.Job object that reports the progress and the result
+    async fn start_regenerate_config(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<OwnedObjectPath, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config StartRegenerateConfig");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        self.regenerate_limit.check(connection, &header).await?;
+        self.handler.check_grub_editable()?;
+        let caller = caller(connection, &header).await;
+        let path = caller
+            .scope(
+                self.jobs
+                    .start_regenerate_config(connection, self.handler.clone()),
+            )
+            .await?;
+        Ok(path)
+    }
+
+    async fn remove_key(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config RemoveKey");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.remove_key(data)).await?;
+        Ok(data)
+    }
+
+    async fn get_write_queue(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Config GetWriteQueue");
+        let data = self.handler.get_write_queue_json()?;
+        Ok(data)
+    }
+
+    /// Postpone the idle shutdown after the ShuttingDown signal. Any other method
+    /// call postpones it as well
+    async fn keep_alive(&self) {
+        log::debug!("Calling org.opensuse.bootkit.Config KeepAlive");
+    }
+
     /// Signal for grub file being changed, provided by zbus macro
     #[zbus(signal)]
     async fn file_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// Signal for `file` being changed with the keys whose values changed, provided
+    /// by zbus macro. `file` is `grub`, `grubenv` or `grub.cfg`, which has no keys
+    #[zbus(signal)]
+    async fn file_changed_detailed(
+        emitter: &SignalEmitter<'_>,
+        file: &str,
+        keys: Vec<String>,
+    ) -> zbus::Result<()>;
+
+    /// Signal that the program stops after `grace_ms` milliseconds unless a client
+    /// calls KeepAlive, provided by zbus macro
+    #[zbus(signal)]
+    async fn shutting_down(emitter: &SignalEmitter<'_>, grace_ms: u64) -> zbus::Result<()>;
+}
+
+impl BootKitConfig {
+    pub fn properties(&self) -> ConfigProperties {
+        self.handler.config_properties()
+    }
+
+    /// Take a snapshot of /etc/default/grub after it was modified outside of bootkit.
+    /// Failures are only logged since the file can be in the middle of being edited
+    pub async fn snapshot_external_change(&self) {
+        match self.handler.snapshot_external_change().await {
+            Ok(true) => log::info!("Took a snapshot of the externally modified grub config"),
+            Ok(false) => {}
+            Err(err) => log::warn!(
+                "Cannot snapshot the externally modified grub config: {}",
+                err.error()
+            ),
+        }
+    }
+
+    /// Writes done through the D-Bus methods signal FileChanged once after they're done
+    pub fn write_in_progress(&self) -> bool {
+        self.handler.write_in_progress()
+    }
+
+    /// Drop the state kept for a client that disconnected from the bus
+    pub fn forget_client(&self, name: &str) {
+        self.handler.forget_edits(name);
+    }
+
+    /// Config file of a boot loader other than grub2, none for grub2
+    pub fn loader_config_path(&self) -> Option<String> {
+        self.handler.loader_config_path()
+    }
+
+    /// Emit PropertiesChanged for the properties whose values differ from `last`
+    /// and update `last` to the current values
+    pub async fn emit_properties_changed(
+        &self,
+        emitter: &SignalEmitter<'_>,
+        last: &mut ConfigProperties,
+    ) -> zbus::Result<()> {
+        let current = self.properties();
+        if current.default_entry != last.default_entry {
+            self.default_entry_changed(emitter).await?;
+        }
+        if current.timeout != last.timeout {
+            self.timeout_changed(emitter).await?;
+        }
+        if current.kernel_cmdline != last.kernel_cmdline {
+            self.kernel_cmdline_changed(emitter).await?;
+        }
+        *last = current;
+        Ok(())
+    }
 }
 
 pub struct BootEntry {
     handler: DbusHandler,
+    entries: EntryObjects,
+    /// Listing the entries parses the whole grub.cfg
+    entries_limit: RateLimiter,
+    polkit: Polkit,
 }
 
 #[interface(name = "org.opensuse.bootkit.BootEntry")]
 impl BootEntry {
-    async fn get_entries(&self) -> Result<String, fdo::Error> {
+    /// Deprecated, use GetEntryList
+    async fn get_entries(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
         log::debug!("Calling org.opensuse.bootkit.BootEntry GetEntries");
+        self.entries_limit.check(connection, &header).await?;
         let data = self.handler.get_grub2_boot_entries_json().await?;
         Ok(data)
     }
+
+    /// Same entries as GetEntries as D-Bus structs, with the default and the
+    /// next boot entries flagged
+    async fn get_entry_list(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<Vec<EntryInfo>, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetEntryList");
+        self.entries_limit.check(connection, &header).await?;
+        let data = self.handler.get_entry_list().await?;
+        Ok(data)
+    }
+
+    async fn get_entries_sorted(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetEntriesSorted");
+        self.entries_limit.check(connection, &header).await?;
+        let data = self.handler.get_sorted_boot_entries_json(data).await?;
+        Ok(data)
+    }
+
+    async fn set_default_entry(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetDefaultEntry");
+        self.polkit
+            .authorize(connection, &header, SET_BOOT_ENTRY)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_default_entry(data)).await?;
+        Ok(data)
+    }
+
+    async fn set_next_entry(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetNextEntry");
+        self.polkit
+            .authorize(connection, &header, SET_BOOT_ENTRY)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_next_entry(data)).await?;
+        Ok(data)
+    }
+
+    /// Boot `entry` once and reboot right away. Needs the authorization to reboot
+    /// on top of the one to set the boot entry, and fails without setting the
+    /// entry if logind can't reboot or an inhibitor blocks it
+    async fn reboot_to_entry(
+        &self,
+        entry: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry RebootToEntry");
+        self.polkit
+            .authorize(connection, &header, SET_BOOT_ENTRY)
+            .await?;
+        self.polkit.authorize(connection, &header, REBOOT).await?;
+        check_reboot(connection).await?;
+        let caller = caller(connection, &header).await;
+        let data = caller
+            .clone()
+            .scope(self.handler.set_next_entry_to(Some(entry)))
+            .await?;
+
+        if let Err(err) = reboot(connection).await {
+            // a later reboot shouldn't pick up the entry that was meant for this one
+            log::warn!("Cannot reboot into '{entry}', clearing the one-shot entry: {err}");
+            caller.scope(self.handler.set_next_entry_to(None)).await?;
+            return Err(err.into());
+        }
+        Ok(data)
+    }
+
+    async fn get_firmware_entries(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetFirmwareEntries");
+        let data = self.handler.get_firmware_entries_json()?;
+        Ok(data)
+    }
+
+    async fn set_firmware_boot_next(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetFirmwareBootNext");
+        self.polkit
+            .authorize(connection, &header, SET_BOOT_ENTRY)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller
+            .scope(self.handler.set_firmware_boot_next(data))
+            .await?;
+        Ok(data)
+    }
+
+    async fn get_mok_status(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetMokStatus");
+        let data = self.handler.get_mok_status_json()?;
+        Ok(data)
+    }
+
+    async fn get_boot_status(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetBootStatus");
+        let data = self.handler.get_boot_status_json().await?;
+        Ok(data)
+    }
+
+    async fn mark_boot_successful(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry MarkBootSuccessful");
+        self.polkit
+            .authorize(connection, &header, SET_BOOT_ENTRY)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.mark_boot_successful()).await?;
+        Ok(data)
+    }
+
+    async fn get_custom_entries(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetCustomEntries");
+        let data = self.handler.get_custom_entries_json()?;
+        Ok(data)
+    }
+
+    async fn add_custom_entry(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry AddCustomEntry");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.add_custom_entry(data)).await?;
+        Ok(data)
+    }
+
+    async fn remove_custom_entry(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry RemoveCustomEntry");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.remove_custom_entry(data)).await?;
+        Ok(data)
+    }
+
+    async fn get_boot_timeline(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry GetBootTimeline");
+        let data = self.handler.get_boot_timeline_json().await?;
+        Ok(data)
+    }
+
+    /// Kernels installed in /boot as JSON, with their initrds, whether their
+    /// modules are installed and the ids of the boot entries that boot them
+    async fn list_kernels(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry ListKernels");
+        let data = self.handler.list_kernels_json()?;
+        Ok(data)
+    }
+
+    /// Remove the kernels that aren't running or booted by any entry, keeping at
+    /// least the `keep` newest ones, and regenerate grub.cfg. Kernels that belong
+    /// to a package are only listed. grub2 only. A dry run only lists the kernels
+    /// that would be removed and needs no authorization
+    async fn remove_old_kernels(
+        &self,
+        keep: u32,
+        dry_run: bool,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry RemoveOldKernels");
+        if !dry_run {
+            self.polkit
+                .authorize(connection, &header, REMOVE_KERNELS)
+                .await?;
+        }
+        let caller = caller(connection, &header).await;
+        let data = caller
+            .scope(self.handler.remove_old_kernels_json(keep, dry_run))
+            .await?;
+        Ok(data)
+    }
+
+    async fn set_entry_cmdline(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetEntryCmdline");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller.scope(self.handler.set_entry_cmdline(data)).await?;
+        Ok(data)
+    }
+
+    async fn set_entry_devicetree(
+        &self,
+        data: &str,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.BootEntry SetEntryDevicetree");
+        self.polkit
+            .authorize(connection, &header, MODIFY_CONFIG)
+            .await?;
+        let caller = caller(connection, &header).await;
+        let data = caller
+            .scope(self.handler.set_entry_devicetree(data))
+            .await?;
+        Ok(data)
+    }
+
+    /// Signal for saved_entry in grubenv being changed, provided by zbus macro.
+    ///
+    /// Empty string means that no default entry was set.
+    #[zbus(signal)]
+    async fn default_entry_changed(
+        emitter: &SignalEmitter<'_>,
+        old: &str,
+        new: &str,
+    ) -> zbus::Result<()>;
+
+    /// Signal for boot entries being added or removed, like when grub.cfg is
+    /// regenerated after a kernel update, with the titles of the entries.
+    /// Provided by zbus macro
+    #[zbus(signal)]
+    async fn boot_entries_changed(
+        emitter: &SignalEmitter<'_>,
+        added: Vec<String>,
+        removed: Vec<String>,
+    ) -> zbus::Result<()>;
+}
+
+impl BootEntry {
+    /// Publish the boot entries as objects under /org/opensuse/bootkit/entries.
+    /// Entries that can't be read are only logged, the objects are kept as they were
+    pub async fn sync_entries(&self, connection: &Connection) -> zbus::Result<EntryChanges> {
+        match self.handler.entry_properties() {
+            Ok(entries) => self.entries.sync(connection, entries).await,
+            Err(err) => {
+                log::warn!("Cannot publish boot entries: {}", err.error().as_string());
+                Ok(EntryChanges::default())
+            }
+        }
+    }
+}
+
+/// Identity of the peer that sent the message, the parts that can't be found out are left empty
+async fn caller(connection: &Connection, header: &Header<'_>) -> Caller {
+    let Some(sender) = header.sender() else {
+        return Caller::default();
+    };
+    let credentials = match fdo::DBusProxy::new(connection).await {
+        Ok(proxy) => proxy
+            .get_connection_credentials(sender.clone().into())
+            .await
+            .map_err(zbus::Error::from),
+        Err(err) => Err(err),
+    };
+
+    let caller = match credentials {
+        Ok(credentials) => Caller::new(credentials.unix_user_id(), credentials.process_id()),
+        Err(err) => {
+            log::debug!("Cannot get the credentials of {sender}: {err}");
+            Caller::default()
+        }
+    };
+    if let Some(member) = header.member() {
+        log::info!("{member} called by {caller}");
+    }
+    caller
 }
 
+/// Client calling an edit method, edits are bound to the client that began them
+fn edit_owner(header: &Header<'_>, caller: &Caller) -> DResult<EditOwner> {
+    let Some(sender) = header.sender() else {
+        return Err(DError::generic(dctx!(), "Edits need a sender on the bus"));
+    };
+    Ok(EditOwner {
+        name: sender.to_string(),
+        uid: caller.uid,
+    })
+}
+
+/// Well-known name of the daemon, the D-Bus service file activates it on demand
+pub const BUS_NAME: &str = "org.opensuse.bootkit";
+
 pub async fn create_connection(args: &ConfigArgs, db: &Database) -> zbus::Result<Connection> {
-    let handler = DbusHandler::new(db.clone());
+    let handler = DbusHandler::new(db.clone(), args);
+    let polkit = Polkit::new(args.session);
+    let info = BootKitInfo {
+        handler: handler.clone(),
+        polkit,
+        firmware: FirmwareMode::detect(),
+    };
     let config = BootKitConfig {
         handler: handler.clone(),
+        jobs: JobList::new(),
+        regenerate_limit: RateLimiter::new("RegenerateConfig", 3, 0.1),
+        validate_limit: RateLimiter::new("ValidateConfig", 30, 10.0),
+        polkit,
     };
     let snapshots = BootKitSnapshots {
         handler: handler.clone(),
+        verify_limit: RateLimiter::new("VerifySnapshots", 2, 0.05),
+        polkit,
+    };
+    let status = BootKitStatus::new(handler.clone());
+    let observer = BootKitObserver::new(handler.clone());
+    let bootentry = BootEntry {
+        handler,
+        entries: EntryObjects::new(),
+        entries_limit: RateLimiter::new("GetEntries", 20, 5.0),
+        polkit,
     };
-    let bootentry = BootEntry { handler };
 
     let (connection, contype) = if args.session {
         (Builder::session()?, "session")
@@ -90,11 +1190,15 @@ pub async fn create_connection(args: &ConfigArgs, db: &Database) -> zbus::Result
     };
 
     let connection = connection
-        .name("org.opensuse.bootkit")?
-        .serve_at("/org/opensuse/bootkit", BootKitInfo {})?
+        .name(BUS_NAME)?
+        .serve_at("/org/opensuse/bootkit", info)?
         .serve_at("/org/opensuse/bootkit", config)?
         .serve_at("/org/opensuse/bootkit", bootentry)?
         .serve_at("/org/opensuse/bootkit", snapshots)?
+        .serve_at("/org/opensuse/bootkit", status)?
+        .serve_at(OBSERVER_PATH, observer)?
+        // announces the boot entry and job objects under it
+        .serve_at("/org/opensuse/bootkit", fdo::ObjectManager)?
         .build()
         .await?;
 
@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{read, read_to_string},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    db::grub2,
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+/// Random UUID from the kernel, used for the edit tokens
+const UUID_PATH: &str = "/proc/sys/kernel/random/uuid";
+/// Edits that can be open at the same time
+const MAX_EDITS: usize = 16;
+/// Edits that haven't been touched for this long are dropped
+const EDIT_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Client that began an edit, only it can stage, commit or cancel the edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditOwner {
+    /// Unique bus name of the client
+    pub name: String,
+    pub uid: Option<u32>,
+}
+
+/// Changes a client has staged since BeginEdit
+#[derive(Debug)]
+struct Edit {
+    owner: EditOwner,
+    /// Hash of the config files when the edit began
+    hash: String,
+    changes: BTreeMap<String, String>,
+    /// When the edit began or was last staged to
+    updated: Instant,
+}
+
+/// Edits that clients stage over time and commit in a single write. The commit
+/// is rejected if the config files changed after the edit began.
+#[derive(Clone, Default)]
+pub struct EditSessions {
+    edits: Arc<Mutex<HashMap<String, Edit>>>,
+}
+
+impl EditSessions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start an edit of the config files whose contents hash to `hash` for `owner`.
+    /// Returns the token that the edit is referred with
+    pub fn begin(&self, owner: EditOwner, hash: String) -> DResult<String> {
+        let mut edits = self.edits.lock().expect("edit sessions lock is poisoned");
+        expire(&mut edits, Instant::now());
+        if edits.len() >= MAX_EDITS {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Too many open edits, commit or cancel one of the {MAX_EDITS} first"),
+            ));
+        }
+
+        let token = read_to_string(UUID_PATH)
+            .ctx(dctx!(), format!("Cannot read {UUID_PATH}"))?
+            .trim()
+            .to_string();
+        edits.insert(
+            token.clone(),
+            Edit {
+                owner,
+                hash,
+                changes: BTreeMap::new(),
+                updated: Instant::now(),
+            },
+        );
+        Ok(token)
+    }
+
+    /// Stage `key` to be set to `value` when the edit is committed
+    pub fn stage(&self, owner: &EditOwner, token: &str, key: &str, value: &str) -> DResult<()> {
+        let mut edits = self.edits.lock().expect("edit sessions lock is poisoned");
+        let now = Instant::now();
+        expire(&mut edits, now);
+        let edit = edits
+            .get_mut(token)
+            .filter(|edit| edit.owner == *owner)
+            .ok_or_else(|| unknown_edit(token))?;
+        edit.changes.insert(key.into(), value.into());
+        edit.updated = now;
+        Ok(())
+    }
+
+    /// End the edit, returning the hash it began with and the staged changes
+    pub fn finish(
+        &self,
+        owner: &EditOwner,
+        token: &str,
+    ) -> DResult<(String, BTreeMap<String, String>)> {
+        let mut edits = self.edits.lock().expect("edit sessions lock is poisoned");
+        expire(&mut edits, Instant::now());
+        if edits.get(token).is_none_or(|edit| edit.owner != *owner) {
+            return Err(unknown_edit(token));
+        }
+        let edit = edits.remove(token).ok_or_else(|| unknown_edit(token))?;
+        Ok((edit.hash, edit.changes))
+    }
+
+    /// Drop the edits of the client with the unique bus name `name`, it has disconnected
+    pub fn forget(&self, name: &str) {
+        let mut edits = self.edits.lock().expect("edit sessions lock is poisoned");
+        edits.retain(|token, edit| {
+            let keep = edit.owner.name != name;
+            if !keep {
+                log::debug!("Dropping edit {token} of disconnected client {name}");
+            }
+            keep
+        });
+    }
+}
+
+/// Drop the edits that haven't been touched in [`EDIT_TTL`] at `now`
+fn expire(edits: &mut HashMap<String, Edit>, now: Instant) {
+    edits.retain(|token, edit| {
+        let keep = now.saturating_duration_since(edit.updated) < EDIT_TTL;
+        if !keep {
+            log::debug!("Edit {token} of {} expired", edit.owner.name);
+        }
+        keep
+    });
+}
+
+/// Edits of other clients are reported the same as missing ones so their
+/// tokens can't be probed
+fn unknown_edit(token: &str) -> DError {
+    DError::generic(dctx!(), format!("Edit '{token}' is not open"))
+}
+
+/// Hash of the paths and the contents of `paths`. Missing files are part of the hash
+/// so creating one changes it. A single file is hashed the same way as the snapshots
+/// so the hash of the main config matches the snapshot of it
+pub fn content_hash(paths: &[&Path]) -> String {
+    let hashes: Vec<Option<String>> = paths
+        .iter()
+        .map(|path| {
+            read(path)
+                .ok()
+                .map(|contents| grub2::content_hash(&String::from_utf8_lossy(&contents), None))
+        })
+        .collect();
+    if let [Some(hash)] = hashes.as_slice() {
+        return hash.clone();
+    }
+
+    let listing: String = paths
+        .iter()
+        .zip(&hashes)
+        .map(|(path, hash)| format!("{} {}\n", path.display(), hash.as_deref().unwrap_or("-")))
+        .collect();
+    grub2::content_hash(&listing, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_hash() {
+        let simple = Path::new("test_data/grub_simple");
+        let full = Path::new("test_data/grub_full");
+        assert_eq!(content_hash(&[simple]), content_hash(&[simple]));
+        assert_ne!(content_hash(&[simple]), content_hash(&[full]));
+        assert_ne!(content_hash(&[simple]), content_hash(&[simple, full]));
+        assert_ne!(
+            content_hash(&[simple]),
+            content_hash(&[simple, Path::new("test_data/missing")])
+        );
+        assert_eq!(
+            content_hash(&[simple]),
+            grub2::content_hash(&read_to_string(simple).unwrap(), None)
+        );
+    }
+
+    fn owner(name: &str, uid: u32) -> EditOwner {
+        EditOwner {
+            name: name.into(),
+            uid: Some(uid),
+        }
+    }
+
+    #[test]
+    fn test_edit_sessions() {
+        let sessions = EditSessions::new();
+        let admin = owner(":1.10", 0);
+        let token = sessions.begin(admin.clone(), "hash".into()).unwrap();
+        sessions.stage(&admin, &token, "GRUB_TIMEOUT", "5").unwrap();
+        sessions
+            .stage(&admin, &token, "GRUB_TIMEOUT", "10")
+            .unwrap();
+        assert!(sessions
+            .stage(&admin, "missing", "GRUB_TIMEOUT", "5")
+            .is_err());
+
+        // other clients can't touch the edit
+        let other = owner(":1.11", 1000);
+        assert!(sessions.stage(&other, &token, "GRUB_TIMEOUT", "0").is_err());
+        assert!(sessions.finish(&other, &token).is_err());
+        assert!(sessions
+            .stage(&owner(":1.10", 1000), &token, "GRUB_TIMEOUT", "0")
+            .is_err());
+
+        let (hash, changes) = sessions.finish(&admin, &token).unwrap();
+        assert_eq!(hash, "hash");
+        assert_eq!(
+            changes,
+            BTreeMap::from([("GRUB_TIMEOUT".to_string(), "10".to_string())])
+        );
+        assert!(sessions.finish(&admin, &token).is_err());
+    }
+
+    #[test]
+    fn test_edit_sessions_dropped() {
+        let sessions = EditSessions::new();
+        let admin = owner(":1.10", 0);
+        let token = sessions.begin(admin.clone(), "hash".into()).unwrap();
+        sessions.forget(":1.10");
+        assert!(sessions.finish(&admin, &token).is_err());
+
+        // abandoned edits don't block new ones forever
+        for _ in 0..MAX_EDITS {
+            sessions.begin(admin.clone(), "hash".into()).unwrap();
+        }
+        assert!(sessions.begin(admin.clone(), "hash".into()).is_err());
+        let mut edits = sessions.edits.lock().unwrap();
+        expire(&mut edits, Instant::now() + EDIT_TTL);
+        assert!(edits.is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use tokio::sync::Mutex;
+use zbus::{interface, Connection};
+
+const ENTRIES_PATH: &str = "/org/opensuse/bootkit/entries";
+
+/// Properties of a boot entry object. D-Bus has no optional values so missing
+/// values are empty strings
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryProperties {
+    pub title: String,
+    pub id: String,
+    pub kernel: String,
+    pub is_default: bool,
+}
+
+/// Titles of the entries that were added and removed between two syncs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl EntryChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Boot entry served as its own object under /org/opensuse/bootkit/entries
+pub struct EntryObject {
+    properties: EntryProperties,
+}
+
+#[interface(name = "org.opensuse.bootkit.Entry")]
+impl EntryObject {
+    #[zbus(property)]
+    async fn title(&self) -> String {
+        self.properties.title.clone()
+    }
+
+    #[zbus(property)]
+    async fn id(&self) -> String {
+        self.properties.id.clone()
+    }
+
+    /// Kernel image the entry boots
+    #[zbus(property)]
+    async fn kernel(&self) -> String {
+        self.properties.kernel.clone()
+    }
+
+    /// Is the entry booted by default
+    #[zbus(property)]
+    async fn is_default(&self) -> bool {
+        self.properties.is_default
+    }
+}
+
+/// Entry objects that the ObjectManager at /org/opensuse/bootkit announces.
+/// The object paths are derived from the entry ids so an entry keeps its
+/// object when the entries before it change
+#[derive(Clone, Default)]
+pub struct EntryObjects {
+    published: Arc<Mutex<BTreeMap<String, EntryProperties>>>,
+}
+
+impl EntryObjects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add, update and remove the entry objects so they match `entries`.
+    /// Returns the titles of the added and removed entries
+    pub async fn sync(
+        &self,
+        connection: &Connection,
+        entries: Vec<EntryProperties>,
+    ) -> zbus::Result<EntryChanges> {
+        let mut published = self.published.lock().await;
+        let old: Vec<EntryProperties> = published.values().cloned().collect();
+        let changes = entry_changes(&old, &entries);
+        let entries: BTreeMap<String, EntryProperties> =
+            entry_paths(&entries).into_iter().zip(entries).collect();
+        let server = connection.object_server();
+
+        for (path, properties) in &entries {
+            let Some(old) = published.get(path) else {
+                server
+                    .at(
+                        path.as_str(),
+                        EntryObject {
+                            properties: properties.clone(),
+                        },
+                    )
+                    .await?;
+                continue;
+            };
+            if old == properties {
+                continue;
+            }
+
+            let entry = server.interface::<_, EntryObject>(path.as_str()).await?;
+            entry.get_mut().await.properties = properties.clone();
+            let state = entry.get().await;
+            let emitter = entry.signal_emitter();
+            if old.title != properties.title {
+                state.title_changed(emitter).await?;
+            }
+            if old.id != properties.id {
+                state.id_changed(emitter).await?;
+            }
+            if old.kernel != properties.kernel {
+                state.kernel_changed(emitter).await?;
+            }
+            if old.is_default != properties.is_default {
+                state.is_default_changed(emitter).await?;
+            }
+        }
+
+        for path in published.keys().filter(|path| !entries.contains_key(*path)) {
+            server.remove::<EntryObject, _>(path.as_str()).await?;
+        }
+
+        if *published != entries {
+            log::debug!("Published {} boot entry objects", entries.len());
+        }
+        *published = entries;
+        Ok(changes)
+    }
+}
+
+/// Titles that are in `new` but not in `old` and the other way around. Entries
+/// often share titles, like the recovery entries, so the titles are counted
+fn entry_changes(old: &[EntryProperties], new: &[EntryProperties]) -> EntryChanges {
+    let mut counts: BTreeMap<&str, isize> = BTreeMap::new();
+    for entry in new {
+        *counts.entry(&entry.title).or_default() += 1;
+    }
+    for entry in old {
+        *counts.entry(&entry.title).or_default() -= 1;
+    }
+
+    let mut changes = EntryChanges::default();
+    for (title, count) in counts {
+        let titles = if count > 0 {
+            &mut changes.added
+        } else {
+            &mut changes.removed
+        };
+        titles.extend(std::iter::repeat_n(title.to_string(), count.unsigned_abs()));
+    }
+    changes
+}
+
+/// Object paths of the entries. The path is the escaped id, or the title when the
+/// entry has no id. Entries sharing an id get a `__2`, `__3`... suffix in order,
+/// escaping never produces two underscores in a row so they can't collide
+fn entry_paths(entries: &[EntryProperties]) -> Vec<String> {
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+    entries
+        .iter()
+        .map(|entry| {
+            let name = if entry.id.is_empty() {
+                &entry.title
+            } else {
+                &entry.id
+            };
+            let name = escape_path(name);
+            let count = seen.entry(name.clone()).or_default();
+            *count += 1;
+            match *count {
+                1 => format!("{ENTRIES_PATH}/{name}"),
+                n => format!("{ENTRIES_PATH}/{name}__{n}"),
+            }
+        })
+        .collect()
+}
+
+/// Escape `name` into a single object path element like systemd does, bytes
+/// other than ASCII letters and digits are written as `_` and two hex digits
+fn escape_path(name: &str) -> String {
+    if name.is_empty() {
+        return "_".into();
+    }
+    name.bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (byte as char).to_string(),
+            _ => format!("_{byte:02x}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(titles: &[&str]) -> Vec<EntryProperties> {
+        titles
+            .iter()
+            .map(|title| EntryProperties {
+                title: title.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_entry_paths() {
+        let mut entries = entries(&["openSUSE", "Recovery", "Recovery", ""]);
+        entries[0].id = "gnulinux-6.18-advanced-1a2b".into();
+        assert_eq!(
+            entry_paths(&entries),
+            vec![
+                "/org/opensuse/bootkit/entries/gnulinux_2d6_2e18_2dadvanced_2d1a2b",
+                "/org/opensuse/bootkit/entries/Recovery",
+                "/org/opensuse/bootkit/entries/Recovery__2",
+                "/org/opensuse/bootkit/entries/_",
+            ]
+        );
+        for path in entry_paths(&entries) {
+            zbus::zvariant::ObjectPath::try_from(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_entry_changes() {
+        let old = entries(&["openSUSE 6.17", "Recovery", "openSUSE 6.9", "Recovery"]);
+        let new = entries(&["openSUSE 6.18", "Recovery", "openSUSE 6.17", "Recovery"]);
+        assert_eq!(
+            entry_changes(&old, &new),
+            EntryChanges {
+                added: vec!["openSUSE 6.18".into()],
+                removed: vec!["openSUSE 6.9".into()],
+            }
+        );
+        assert!(entry_changes(&new, &new).is_empty());
+
+        let changes = entry_changes(&old, &entries(&["openSUSE 6.17"]));
+        assert!(changes.added.is_empty());
+        assert_eq!(
+            changes.removed,
+            vec!["Recovery", "Recovery", "openSUSE 6.9"]
+        );
+    }
+}
@@ -1,29 +1,381 @@
-use std::{fs::File, io::Write, process::Command};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{read_to_string, remove_file, rename},
+    path::Path,
+};
 
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use similar::TextDiff;
+use tokio::sync::mpsc::UnboundedSender;
+use zbus::zvariant::Type;
 
 use crate::{
-    config::GRUB_FILE_PATH,
-    db::{grub2::Grub2Snapshot, selected_snapshot::SelectedSnapshot, Database},
+    bootloader::{sort_entries, Bootloader, BootloaderDetection, BootloaderKind, LoaderEntry},
+    config::{layout::GrubLayout, ConfigArgs, BLS_ENTRIES_PATH, GRUB_FILE_PATH, GRUB_SCRIPTS_PATH},
+    db::{
+        grub2::{Grub2Snapshot, SnapshotFilter, SnapshotIntegrity, SnapshotOrigin},
+        mkconfig_run::MkconfigRun,
+        selected_snapshot::SelectedSnapshot,
+        Database,
+    },
+    dbus::{
+        edit::{content_hash, EditOwner, EditSessions},
+        entries::EntryProperties,
+        queue::WriteQueue,
+    },
     dctx,
-    errors::{DError, DErrorType, DRes, DResult},
-    grub2::{GrubBootEntries, GrubFile, GrubLine},
+    errors::{codes, DError, DErrorType, DRes, DResult},
+    grub2::{
+        check::{check_script, config_syntax_error},
+        cmdline::{CmdLine, ParamDiff},
+        custom::{CustomEntry, CustomFile, EntryTemplate, CUSTOM_CFG, CUSTOM_SCRIPT},
+        dropin::GrubConfig,
+        editenv::{EnvBackend, EnvEditor},
+        env::GrubEnv,
+        gfxmode::{gfx_modes, DRM_PATH},
+        key_error,
+        osprober::{OsProberStatus, OS_PROBER_PATH},
+        schema::{self, KEY_SCHEMA},
+        stale::{key_differences, modified_after, StaleStatus},
+        themes::{themes, validate_theme, GrubTheme},
+        users::{
+            hash_password, is_valid_user, read_users_script, remove_users_script,
+            restore_users_script, write_users_script, MenuProtection,
+        },
+        value_changes, EntrySort, GrubBootEntries, GrubBootEntry, GrubFile, GrubLine, KeyChange,
+        ParseMode, RemoveMode,
+    },
+    sdboot::{bootctl_set_entry, SdBoot, SdBootEntries, ENTRY_DEFAULT_VAR, ENTRY_ONESHOT_VAR},
+    system::{
+        caller::Caller,
+        efivars::{set_boot_next, FirmwareBoot},
+        kernels::{installed_kernels, package_owner, removable_kernels, InstalledKernel},
+        mok::MokStatus,
+        ostree::{deployment_kargs, set_kargs, KargsBackend},
+        process::{self, CommandOutput},
+        swap::{memory_kib, resume_params, ResumeParams},
+        write_file_atomic, KERNEL_CMDLINE_PATH,
+    },
 };
 
+/// Complete desired state for ApplyState. Parts that are left out aren't touched
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DesiredState {
+    /// Values of the keys, null means that the key must not be set
+    #[serde(default)]
+    keys: BTreeMap<String, Option<String>>,
+    /// Title, full path, id or index path of the entry
+    default_entry: Option<String>,
+    timeout: Option<i64>,
+    cmdline: Option<DesiredCmdline>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DesiredCmdline {
+    /// Which command line to reconcile, defaults to GRUB_CMDLINE_LINUX_DEFAULT
+    #[serde(default = "default_cmdline_key")]
+    cmdline: String,
+    /// Parameters like `quiet` or `splash=silent` that have to be set
+    #[serde(default)]
+    present: Vec<String>,
+    /// Names of the parameters that must not be set
+    #[serde(default)]
+    absent: Vec<String>,
+}
+
+/// What ApplyState changed, nothing when the system was already in the state
+#[derive(Debug, Clone, Serialize)]
+struct AppliedState {
+    changed: bool,
+    key_changes: Vec<KeyChange>,
+    default_entry_changed: bool,
+    /// Set when the keys were written but saving the default entry failed
+    default_entry_error: Option<String>,
+    warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Error,
+    Warning,
+}
+
+/// Problem that ValidateConfig found in the config
+#[derive(Debug, Clone, Serialize)]
+struct Diagnostic {
+    severity: Severity,
+    /// Step of the validation that found the problem: `parse`, `value` or `script`
+    stage: &'static str,
+    /// Same code as the method would fail with, like `ERR_VALIDATION`
+    code: &'static str,
+    /// Key the problem is about, none for the whole config
+    key: Option<String>,
+    message: String,
+}
+
+impl Diagnostic {
+    fn error(stage: &'static str, key: Option<String>, message: String) -> Self {
+        let code = match stage {
+            "parse" => codes::JSON,
+            "script" => codes::SCRIPT,
+            _ => codes::VALIDATION,
+        };
+        Self {
+            severity: Severity::Error,
+            stage,
+            code,
+            key,
+            message,
+        }
+    }
+}
+
+/// Reply of ValidateConfig, valid if there are no errors
+#[derive(Debug, Clone, Serialize)]
+struct ValidationReport {
+    valid: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ValidationReport {
+    fn new(diagnostics: Vec<Diagnostic>) -> Self {
+        Self {
+            valid: !diagnostics
+                .iter()
+                .any(|diagnostic| matches!(diagnostic.severity, Severity::Error)),
+            diagnostics,
+        }
+    }
+}
+
+/// Kernels removed by RemoveOldKernels
+#[derive(Debug, Clone, Serialize)]
+struct KernelCleanup {
+    dry_run: bool,
+    /// Kernels that were removed, or would be without `dry_run`
+    removed: Vec<InstalledKernel>,
+    /// Kernels that belong to a package, only the package manager removes them
+    packaged: Vec<KernelPackage>,
+    /// Files that couldn't be removed and grub.cfg if it couldn't be regenerated
+    failed: Vec<CleanupFailure>,
+    /// grub.cfg was regenerated after removing the kernels
+    regenerated: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct KernelPackage {
+    version: String,
+    package: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CleanupFailure {
+    path: String,
+    error: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ConfigData {
     value_map: Value,
     value_list: Value,
     config_diff: Option<Value>,
+    /// Key level changes compared to the selected snapshot
+    #[serde(default)]
+    key_changes: Option<Value>,
     selected_kernel: Option<String>,
+    /// Values after /etc/default/grub.d drop-ins are applied, and the file they come from
+    #[serde(default)]
+    effective_values: Option<Value>,
+    /// Deprecated keys that are set in the config
+    #[serde(default)]
+    deprecated_keys: Option<Value>,
+    /// Replace deprecated keys with their modern equivalents when saving
+    #[serde(default)]
+    migrate_deprecated: bool,
+    /// Problems found when reading the config, like lines that were kept as is
+    /// since they aren't key=value pairs or keys that are defined multiple times
+    #[serde(default)]
+    parse_warnings: Vec<String>,
+    /// Comment stored with the snapshot of the saved config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct BootEntryData {
     entries: Value,
+    /// Ids of the entries in the same order as `entries`, if grub has one
+    entry_ids: Vec<Option<String>>,
+    /// Entries with their metadata in the same order as `entries`
+    details: Vec<BootEntryDetails>,
     selected_kernel: Value,
+    /// Entry that's booted once on the next boot
+    next_entry: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EntrySortData {
+    sort: EntrySort,
+}
+
+/// Structured information about a boot entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BootEntryDetails {
+    title: String,
+    /// Titles of the submenus and the entry separated by `>`
+    path: String,
+    id: Option<String>,
+    /// `--class` values of the menuentry, used to pick an icon
+    classes: Vec<String>,
+    /// OS detected from the classes
+    os: Option<String>,
+    kernel: Option<String>,
+    initrds: Vec<String>,
+    /// Kernel version, used to map the entry to the installed kernel package
+    version: Option<String>,
+    /// Device tree blob loaded for the kernel, set on boot loaders used on ARM boards
+    devicetree: Option<String>,
+    /// Directory the device tree of the board is picked from
+    devicetree_dir: Option<String>,
+    /// Entry is booted by default
+    is_default: bool,
+    /// Entry is booted once on the next boot instead of the default one
+    is_next: bool,
+}
+
+/// Values of the Config D-Bus properties, empty when they can't be read
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigProperties {
+    pub default_entry: String,
+    pub timeout: String,
+    /// Command line of the normal boot entries
+    pub kernel_cmdline: String,
+}
+
+/// Boot entry of the typed D-Bus API. D-Bus has no optional values so missing
+/// values are empty strings
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct EntryInfo {
+    pub id: String,
+    pub title: String,
+    /// Titles of the submenus and the entry separated by `>`
+    pub path: String,
+    pub os: String,
+    pub kernel: String,
+    pub version: String,
+    pub initrds: Vec<String>,
+    pub classes: Vec<String>,
+    pub is_default: bool,
+    /// Entry is booted once on the next boot instead of the default one
+    pub is_next: bool,
+}
+
+impl From<BootEntryDetails> for EntryInfo {
+    fn from(details: BootEntryDetails) -> Self {
+        Self {
+            id: details.id.unwrap_or_default(),
+            title: details.title,
+            path: details.path,
+            os: details.os.unwrap_or_default(),
+            kernel: details.kernel.unwrap_or_default(),
+            version: details.version.unwrap_or_default(),
+            initrds: details.initrds,
+            classes: details.classes,
+            is_default: details.is_default,
+            is_next: details.is_next,
+        }
+    }
+}
+
+impl From<&GrubBootEntry> for BootEntryDetails {
+    fn from(entry: &GrubBootEntry) -> Self {
+        Self {
+            title: entry.entry().to_string(),
+            path: entry.full_path(),
+            id: entry.id_path(),
+            classes: entry.classes().to_vec(),
+            os: entry.os().map(str::to_string),
+            kernel: entry.kernel().map(str::to_string),
+            initrds: entry.initrds().to_vec(),
+            version: entry.version().map(str::to_string),
+            devicetree: None,
+            devicetree_dir: None,
+            is_default: false,
+            is_next: false,
+        }
+    }
+}
+
+impl From<&LoaderEntry> for BootEntryDetails {
+    fn from(entry: &LoaderEntry) -> Self {
+        Self {
+            title: entry.title.clone(),
+            path: entry.title.clone(),
+            id: Some(entry.id.clone()),
+            classes: Vec::new(),
+            os: None,
+            kernel: entry.kernel.clone(),
+            initrds: entry.initrds.clone(),
+            version: entry.version.clone(),
+            devicetree: entry.devicetree.clone(),
+            devicetree_dir: entry.devicetree_dir.clone(),
+            is_default: false,
+            is_next: false,
+        }
+    }
+}
+
+/// Config of a boot loader other than grub2, returned by GetConfig instead of the grub config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LoaderConfigData {
+    /// Effective values of all the keys. Keys missing from the map are removed when saving
+    value_map: BTreeMap<String, String>,
+    /// Raw lines of the config file
+    #[serde(default)]
+    value_list: Vec<String>,
+    /// Id of the entry that is booted by default
+    #[serde(default)]
+    selected_entry: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct DefaultEntryData {
+    /// Title, full path, id or index path like `1>0` of the entry,
+    /// none clears the default entry
+    entry: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct FirmwareBootNextData {
+    /// Number of the `Boot####` entry, none removes BootNext
+    entry: Option<u16>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct NextEntryData {
+    /// Title, full path, id or index path like `1>0` of the entry,
+    /// none clears the one-shot entry
+    entry: Option<String>,
+}
+
+/// What SaveConfig would change on disk, returned by PreviewChanges
+#[derive(Debug, Serialize)]
+struct ChangesPreview {
+    /// Config file that would be written
+    path: String,
+    /// Unified diff of the file, none if nothing would change
+    diff: Option<String>,
+    /// Key level changes from the current config
+    key_changes: Vec<KeyChange>,
+    /// Values that SaveConfig would reject, and why
+    invalid_values: BTreeMap<String, String>,
+    /// Deprecations and migrations that SaveConfig would report
+    warnings: Vec<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -32,6 +384,14 @@ struct Grub2SnapshotData {
     snapshot: Grub2Snapshot,
     /// diff against the current config
     diff: Option<String>,
+    /// Key level changes from the current config to the snapshot
+    key_changes: Option<Vec<KeyChange>>,
+    /// Latest grub2-mkconfig run that generated grub.cfg from this snapshot
+    mkconfig: Option<MkconfigRun>,
+    /// Variables of the grubenv in the snapshot
+    grubenv_values: Option<BTreeMap<String, String>>,
+    /// Why the snapshot doesn't match its hash, the content can't be trusted then
+    corrupted: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -40,6 +400,109 @@ struct SnapshotData {
     selected: SelectedSnapshot,
 }
 
+/// Snapshots returned by Snapshot Query
+#[derive(Debug, Serialize)]
+struct SnapshotPageData {
+    #[serde(flatten)]
+    data: SnapshotData,
+    /// Number of snapshots that match the filter, including the ones outside the page
+    total: i64,
+    offset: u32,
+}
+
+/// Snapshot that doesn't match its hash, returned by VerifySnapshots
+#[derive(Debug, Serialize)]
+struct CorruptedSnapshot {
+    id: i64,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SnapshotVerifyData {
+    /// Number of snapshots in the database
+    checked: usize,
+    /// Snapshots taken before the hashes were stored, these can't be checked
+    unhashed: Vec<i64>,
+    corrupted: Vec<CorruptedSnapshot>,
+}
+
+/// Id that refers to the current /etc/default/grub instead of a snapshot in Snapshot Diff.
+/// Snapshot ids start from 1
+const CURRENT_CONFIG_ID: i64 = 0;
+
+/// Changes between two grub configs, returned by Snapshot Diff
+#[derive(Debug, Serialize)]
+struct SnapshotDiffData {
+    from: i64,
+    to: i64,
+    /// Unified diff, none if the configs are the same
+    diff: Option<String>,
+    /// Key level changes and the changes in the other lines
+    key_changes: Vec<KeyChange>,
+}
+
+impl SnapshotDiffData {
+    /// Diff between two configs given as their snapshot id, label and contents
+    fn new(from: (i64, &str, &str), to: (i64, &str, &str)) -> DResult<Self> {
+        let (from, from_label, from_config) = from;
+        let (to, to_label, to_config) = to;
+        let diff = TextDiff::from_lines(from_config, to_config)
+            .unified_diff()
+            .header(from_label, to_label)
+            .to_string();
+        let key_changes = GrubFile::parse(from_config, ParseMode::Lenient)?
+            .diff(&GrubFile::parse(to_config, ParseMode::Lenient)?);
+
+        Ok(Self {
+            from,
+            to,
+            diff: (!diff.is_empty()).then_some(diff),
+            key_changes,
+        })
+    }
+}
+
+impl Grub2SnapshotData {
+    /// `snapshot` compared to the `current` config that's parsed as `grub`
+    fn new(
+        grub: &GrubFile,
+        current: &str,
+        snapshot: Grub2Snapshot,
+        mkconfig: Option<MkconfigRun>,
+    ) -> Self {
+        let corrupted = snapshot.verify().err().map(|err| {
+            let message = err.error().as_string();
+            log::warn!("{message}");
+            message
+        });
+        let diff = TextDiff::from_lines(current, &snapshot.grub_config)
+            .unified_diff()
+            .to_string();
+        let diff = if diff.trim().is_empty() {
+            None
+        } else {
+            Some(diff)
+        };
+
+        let key_changes = GrubFile::parse(&snapshot.grub_config, ParseMode::Lenient)
+            .ok()
+            .map(|snapshot_grub| grub.diff(&snapshot_grub));
+        let grubenv_values = snapshot
+            .grubenv
+            .as_deref()
+            .map(|grubenv| GrubEnv::new(grubenv).values());
+
+        Self {
+            snapshot,
+            diff,
+            key_changes,
+            mkconfig,
+            grubenv_values,
+            corrupted,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct RemoveSnapshotData {
     snapshot_id: i64,
@@ -50,14 +513,473 @@ struct SelectSnapshotData {
     snapshot_id: i64,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum CmdLineAction {
+    Add,
+    Remove,
+    Set,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CmdLineParamData {
+    /// Which command line to edit, defaults to GRUB_CMDLINE_LINUX_DEFAULT
+    #[serde(default = "default_cmdline_key")]
+    cmdline: String,
+    action: CmdLineAction,
+    param: String,
+    value: Option<String>,
+}
+
+fn default_cmdline_key() -> String {
+    "GRUB_CMDLINE_LINUX_DEFAULT".into()
+}
+
+/// Apply the action of `data` to `cmdline`
+fn edit_cmdline(cmdline: &mut CmdLine, data: &CmdLineParamData) {
+    let value = data.value.as_deref();
+    match data.action {
+        CmdLineAction::Add => {
+            // adding the exact same parameter twice is never useful
+            if cmdline.get(&data.param) == Some(value) {
+                log::debug!("Parameter '{}' is already set", data.param);
+            } else {
+                cmdline.add_param(&data.param, value);
+            }
+        }
+        CmdLineAction::Set => {
+            if !cmdline.contains(&data.param) {
+                log::debug!(
+                    "Parameter '{}' not found from {}, adding it",
+                    data.param,
+                    data.cmdline
+                );
+            }
+            cmdline.set_param(&data.param, value);
+        }
+        CmdLineAction::Remove => {
+            if !cmdline.remove_param(&data.param) {
+                log::debug!("Parameter '{}' not found from {}", data.param, data.cmdline);
+            }
+        }
+    }
+}
+
+/// Changes to the keys of `config` that reach the desired `state`
+fn state_key_changes(config: &GrubConfig, state: &DesiredState) -> Vec<KeyChange> {
+    let mut desired = state.keys.clone();
+    if let Some(timeout) = state.timeout {
+        desired.insert("GRUB_TIMEOUT".into(), Some(timeout.to_string()));
+    }
+    if let Some(cmdline) = &state.cmdline {
+        let current = match desired.get(&cmdline.cmdline) {
+            Some(value) => value.clone().unwrap_or_default(),
+            None => config.value(&cmdline.cmdline).unwrap_or_default().into(),
+        };
+        let mut params = CmdLine::new(&current);
+        if params.reconcile(&cmdline.present, &cmdline.absent) {
+            desired.insert(cmdline.cmdline.clone(), Some(params.to_string()));
+        }
+    }
+
+    let old: BTreeMap<String, String> = desired
+        .keys()
+        .filter_map(|key| Some((key.clone(), config.value(key)?.to_string())))
+        .collect();
+    let new: BTreeMap<String, String> = desired
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect();
+    value_changes(&old, &new)
+}
+
+/// Apply the key changes to `config`. Returns the deprecation warnings of the set keys
+fn apply_key_changes(config: &mut GrubConfig, changes: &[KeyChange]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for change in changes {
+        match change {
+            KeyChange::Added { key, value }
+            | KeyChange::Changed {
+                key, new: value, ..
+            } => {
+                log::debug!("Setting {key} to '{value}'");
+                config.set_key_value(key, value);
+                if let Some(deprecation) = schema::deprecation(key) {
+                    warnings.push(deprecation.message.to_string());
+                }
+            }
+            KeyChange::Removed { key, .. } => {
+                log::debug!("Removing {key}");
+                config.remove_key(key, RemoveMode::Delete);
+            }
+            KeyChange::LineAdded { .. } | KeyChange::LineRemoved { .. } => {}
+        }
+    }
+    warnings
+}
+
+/// Keys of `value_map` that `loader` rejects, and why
+fn loader_invalid_values(
+    loader: &dyn Bootloader,
+    value_map: &BTreeMap<String, String>,
+) -> Vec<(String, String)> {
+    value_map
+        .iter()
+        .filter_map(|(key, value)| {
+            loader
+                .validate(key, value)
+                .err()
+                .map(|reason| (key.clone(), reason))
+        })
+        .collect()
+}
+
+/// Run grub2-mkconfig into `path` and check the generated script. Returns the
+/// output of the run with the result of the checks, `path` is removed if they fail
+async fn generate_grub_cfg(
+    path: &str,
+    progress: Option<UnboundedSender<String>>,
+) -> DResult<(CommandOutput, DResult<()>)> {
+    let layout = GrubLayout::get();
+    let args = ["-o", path];
+    let output = match progress {
+        Some(progress) => process::run_with_progress(layout.mkconfig, &args, progress).await?,
+        None => process::run(layout.mkconfig, &args).await?,
+    };
+
+    let result = match output.check() {
+        Ok(()) => {
+            // grub2-script-check is run synchronously
+            let check_path = path.to_string();
+            tokio::task::spawn_blocking(move || check_script(check_path))
+                .await
+                .ctx(dctx!(), "Checking the generated grub.cfg stopped")?
+        }
+        Err(err) => Err(err),
+    };
+    if result.is_err() {
+        // the temporary file is useless at this point so failing to remove it is fine
+        let _ = remove_file(path);
+    }
+    Ok((output, result))
+}
+
+/// Diagnostics of a SaveConfig payload for grub2: the payload has to parse and the
+/// changed values have to be valid. The parsed file is returned for the syntax check
+/// unless the payload can't be parsed
+fn validate_grub2_config(data: &str) -> (Vec<Diagnostic>, Option<GrubFile>) {
+    let parsed = serde_json::from_str::<ConfigData>(data).and_then(|config| {
+        let lines: Vec<GrubLine> = serde_json::from_value(config.value_list)?;
+        Ok((lines, config.migrate_deprecated))
+    });
+    let (lines, migrate_deprecated) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            return (
+                vec![Diagnostic::error("parse", None, err.to_string())],
+                None,
+            )
+        }
+    };
+
+    let mut grub_file = GrubFile::from_lines(&lines);
+    if migrate_deprecated {
+        grub_file.migrate_deprecated();
+    }
+    let mut diagnostics: Vec<Diagnostic> = grub_file
+        .invalid_changed()
+        .into_iter()
+        .map(|(key, reason)| Diagnostic::error("value", Some(key), reason))
+        .collect();
+    diagnostics.extend(
+        grub_file
+            .deprecated_keys()
+            .into_iter()
+            .map(|deprecation| Diagnostic {
+                severity: Severity::Warning,
+                stage: "value",
+                code: codes::VALIDATION,
+                key: Some(deprecation.key.to_string()),
+                message: deprecation.message.to_string(),
+            }),
+    );
+    (diagnostics, Some(grub_file))
+}
+
+/// Set the keys of `loader` to `value_map` in memory, removing the keys that are
+/// missing from it
+fn edit_loader_values(loader: &mut dyn Bootloader, value_map: &BTreeMap<String, String>) {
+    let path = loader.config_path().to_string();
+    for key in loader.values().keys() {
+        if !value_map.contains_key(key) {
+            log::debug!("Removing {key} from {path}");
+            loader.remove_value(key);
+        }
+    }
+    for (key, value) in value_map {
+        if loader.values().get(key) != Some(value) {
+            log::debug!("Setting {key} to '{value}' in {path}");
+            loader.set_value(key, value);
+        }
+    }
+}
+
+/// Unified diff from `old` to `new` with `path` in the headers, none if they're equal
+fn unified_diff(old: &str, new: &str, path: &str) -> Option<String> {
+    let diff = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(path, path)
+        .to_string();
+    (!diff.is_empty()).then_some(diff)
+}
+
+#[derive(Debug, Serialize)]
+struct ThemesData {
+    themes: Vec<GrubTheme>,
+    /// Effective GRUB_THEME value
+    current: Option<String>,
+    /// Why the current GRUB_THEME doesn't work, if it doesn't
+    current_error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct MenuProtectionData {
+    enabled: bool,
+    /// Superuser that can edit the entries, defaults to root
+    user: Option<String>,
+    /// Plain text password, only used when enabling the protection
+    password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AddCustomEntryData {
+    title: String,
+    #[serde(flatten)]
+    template: EntryTemplate,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RemoveCustomEntryData {
+    title: String,
+    /// File of the entry, needed only if both files have an entry with the same title
+    source: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct OsProberData {
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TimeoutData {
+    /// Seconds to wait before booting the default entry
+    timeout: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TimeoutStyleData {
+    /// `menu`, `countdown` or `hidden`
+    style: String,
+}
+
+#[derive(Debug, Serialize)]
+struct KernelCmdlineStatus {
+    /// Command line the default entry boots with after the next reboot
+    configured: String,
+    /// Command line of the running kernel
+    running: String,
+    differences: Vec<ParamDiff>,
+    /// Configured parameters are not in use until the next reboot
+    reboot_needed: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ResumeStatus {
+    /// Parameters for the current swap, none if they can't be detected
+    detected: Option<ResumeParams>,
+    /// Why the parameters couldn't be detected
+    detection_error: Option<String>,
+    /// `resume=` in GRUB_CMDLINE_LINUX_DEFAULT
+    resume: Option<String>,
+    /// `resume_offset=` in GRUB_CMDLINE_LINUX_DEFAULT
+    resume_offset: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ResumeData {
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SetThemeData {
+    /// Name or theme.txt path of the theme, none disables the theme
+    theme: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RemoveKeyData {
+    key: String,
+    /// Delete the line (default) or comment it out
+    #[serde(default)]
+    mode: RemoveMode,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct EntryCmdlineData {
+    /// Title or id of the boot entry
+    entry: String,
+    cmdline: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct EntryDevicetreeData {
+    /// Title or id of the boot entry
+    entry: String,
+    /// Device tree blob to load, none removes it
+    #[serde(default)]
+    devicetree: Option<String>,
+    /// Directory to pick the device tree of the board from, none removes it
+    #[serde(default)]
+    devicetree_dir: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct DbusHandler {
     db: Database,
+    /// All the writes to boot configuration files go through this queue
+    queue: WriteQueue,
+    /// How the grub config files are read
+    parse_mode: ParseMode,
+    /// How grubenv is modified
+    env_backend: EnvBackend,
+    /// Default order of the boot entries
+    entry_sort: EntrySort,
+    /// Boot loader whose configuration is managed
+    bootloader: BootloaderKind,
+    /// How kernel arguments are changed, through ostree on image based systems
+    kargs_backend: KargsBackend,
+    /// How the boot loader was detected at startup
+    detection: BootloaderDetection,
+    /// Edits that are staged with BeginEdit and Stage
+    edits: EditSessions,
 }
 
+/// Resume parameters are only needed by the normal boot entries, not recovery
+const RESUME_CMDLINE: &str = "GRUB_CMDLINE_LINUX_DEFAULT";
+const HIDDEN_MENU_WARNING: &str =
+    "The menu is hidden with a timeout of 0 so it's only shown by holding Shift or pressing Esc";
+
 impl DbusHandler {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(db: Database, args: &ConfigArgs) -> Self {
+        let detection = args.bootloader.detect();
+        Self {
+            db,
+            queue: WriteQueue::new(),
+            parse_mode: args.parse_mode(),
+            env_backend: args.grubenv_backend,
+            entry_sort: args.entry_sort,
+            bootloader: detection.kind,
+            kargs_backend: args.kargs_backend.detect(),
+            detection,
+            edits: EditSessions::new(),
+        }
+    }
+
+    /// Is bootkit itself writing to the boot configuration right now
+    pub fn write_in_progress(&self) -> bool {
+        self.queue.is_writing()
+    }
+
+    /// Boot loader the daemon manages
+    pub fn bootloader_kind(&self) -> BootloaderKind {
+        self.bootloader
+    }
+
+    pub async fn database_available(&self) -> bool {
+        self.db.is_available().await
+    }
+
+    /// Name of the managed boot loader, like `grub2` or `systemd-boot`
+    pub fn bootloader_type(&self) -> String {
+        self.bootloader
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Current values of the Config D-Bus properties
+    pub fn config_properties(&self) -> ConfigProperties {
+        let properties = if self.bootloader == BootloaderKind::Grub2 {
+            self.grub_properties()
+        } else {
+            self.loader_properties()
+        };
+        properties.unwrap_or_else(|err| {
+            log::debug!("Cannot read the config properties: {}", err.error());
+            ConfigProperties::default()
+        })
+    }
+
+    fn grub_properties(&self) -> DResult<ConfigProperties> {
+        let config = GrubConfig::read(self.parse_mode)?;
+        let mut properties = grub_config_properties(&config);
+        if properties.default_entry == "saved" {
+            properties.default_entry = GrubEnv::from_file(&GrubLayout::get().env_path)
+                .ok()
+                .and_then(|env| env.saved_entry().map(str::to_string))
+                .unwrap_or_default();
+        }
+        if self.kargs_backend.is_ostree() {
+            properties.kernel_cmdline = deployment_kargs()?.to_string();
+        }
+        Ok(properties)
+    }
+
+    fn loader_properties(&self) -> DResult<ConfigProperties> {
+        let loader = self.loader()?;
+        let selected = loader.selected();
+        Ok(ConfigProperties {
+            default_entry: selected
+                .as_ref()
+                .map(|entry| entry.id.clone())
+                .unwrap_or_default(),
+            timeout: loader.values().remove("timeout").unwrap_or_default(),
+            kernel_cmdline: selected.and_then(|entry| entry.options).unwrap_or_default(),
+        })
+    }
+
+    /// Config file of a boot loader other than grub2 that is watched for changes
+    pub fn loader_config_path(&self) -> Option<String> {
+        (self.bootloader != BootloaderKind::Grub2).then(|| self.bootloader.config_path())
+    }
+
+    /// Boot loader that manages the system and how it was detected
+    pub fn get_bootloader_json(&self) -> DResult<String> {
+        serde_json::to_string(&self.detection)
+            .ctx(dctx!(), "Failed to serialize boot loader detection")
+    }
+
+    /// On ostree systems /boot is managed by ostree and grub.cfg is generated from the
+    /// deployments, so only the kernel arguments can be changed and only through ostree
+    pub fn check_grub_editable(&self) -> DResult<()> {
+        if self.kargs_backend.is_ostree() {
+            return Err(DError::generic(
+                dctx!(),
+                "Boot configuration is managed by ostree and can't be edited directly, \
+                 only kernel arguments can be changed",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Config of the boot loader when it's not grub2
+    fn loader(&self) -> DResult<Box<dyn Bootloader>> {
+        self.bootloader.open()?.ok_or_else(|| {
+            DError::generic(
+                dctx!(),
+                "grub2 config is not handled as a generic boot loader",
+            )
+        })
     }
 
     async fn set_grub_system(
@@ -65,15 +987,44 @@ impl DbusHandler {
         grub_file: &mut GrubFile,
         selected_kernel: &Option<String>,
         from_snapshot: bool,
+    ) -> DResult<CommandOutput> {
+        self.set_selected_kernel(grub_file, selected_kernel, from_snapshot)
+            .await?;
+
+        let file = grub_file.as_string();
+
+        // TODO: start a background thread that executes the grub config
+        //       and return an ID that the client can use to poll information
+
+        // keep the old config around in case the generated grub.cfg is broken
+        let previous = read_to_string(GRUB_FILE_PATH).ok();
+
+        // WARN: this triggers FileChanged signal
+        Self::write_grub_file(&file)?;
+
+        match self.regenerate_grub_cfg(None).await {
+            Ok(output) => Ok(output),
+            Err(err) => {
+                if let Some(previous) = previous {
+                    Self::write_grub_file(&previous)?;
+                    log::debug!("Restored the previous {GRUB_FILE_PATH}");
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Set saved_entry in grubenv to `selected_kernel`, or remove it
+    async fn set_selected_kernel(
+        &self,
+        grub_file: &mut GrubFile,
+        selected_kernel: &Option<String>,
+        from_snapshot: bool,
     ) -> DResult<()> {
         if let Some(kernel) = &selected_kernel {
             let kernel_entries = GrubBootEntries::new()?;
-            let kernel_entry = if let Some(entry) = kernel_entries
-                .entries()
-                .iter()
-                .find(|entry| entry.entry() == kernel)
-            {
-                entry.full_path()
+            let kernel_entry = if let Some(entry) = kernel_entries.find(kernel) {
+                entry.default_value()
             } else {
                 return Err(DError::new(
                     dctx!(),
@@ -83,189 +1034,1901 @@ impl DbusHandler {
                 ));
             };
 
-            log::debug!("Calling grub2-set-default {kernel_entry}");
+            log::debug!("Setting saved_entry to {kernel_entry}");
+
+            EnvEditor::new(self.env_backend)
+                .set("saved_entry", &kernel_entry)
+                .await?;
+
+            log::debug!("Setting saved_entry to {kernel_entry} done");
+
+            // Only update grub file when selecting a snapshot
+            // old snapshots should always be set back the way they were
+            if !from_snapshot {
+                // make sure GRUB_DEFAULT is set to saved as it's required by grub
+                grub_file.set_key_value("GRUB_DEFAULT", "saved");
+            }
+        } else {
+            log::debug!("Removing default seleceted kernel");
+
+            EnvEditor::new(self.env_backend)
+                .unset("saved_entry")
+                .await?;
+
+            log::debug!("Removing default seleceted kernel done");
+        }
+        Ok(())
+    }
+
+    /// Generate grub.cfg with grub2-mkconfig into a temporary file and replace grub.cfg
+    /// only if the generation and the syntax check succeed.
+    /// Failed runs are recorded without a snapshot.
+    async fn regenerate_grub_cfg(
+        &self,
+        progress: Option<UnboundedSender<String>>,
+    ) -> DResult<CommandOutput> {
+        let cfg_path = &GrubLayout::get().cfg_path;
+        // generate into a temporary file so a broken grub.cfg never replaces the working one
+        let new_cfg_path = format!("{cfg_path}.new");
+        let (output, result) = generate_grub_cfg(&new_cfg_path, progress).await?;
+        if let Err(err) = result {
+            self.db.save_mkconfig_run(None, &output).await?;
+            return Err(err);
+        }
+
+        rename(&new_cfg_path, cfg_path).ctx(
+            dctx!(),
+            format!("Failed to move {new_cfg_path} to {cfg_path}"),
+        )?;
+
+        Ok(output)
+    }
+
+    /// Replace the grub config with `contents`. The contents are written to a
+    /// temporary file that's renamed over the config, so readers never see a
+    /// partially written file
+    fn write_grub_file(contents: &str) -> DResult<()> {
+        // keeps the permissions the admin gave to the config
+        write_file_atomic(GRUB_FILE_PATH, contents)?;
+        log::debug!("Grub2 config was written to {GRUB_FILE_PATH}");
+        Ok(())
+    }
+
+    async fn _get_grub2_config(&self) -> DResult<ConfigData> {
+        let config = GrubConfig::read(self.parse_mode)?;
+        let grub = config.main();
+        let kernel_entries = GrubBootEntries::new()?;
+        let selected = self.db.selected_snapshot().await?;
+        let selected_config = if let Some(id) = selected.grub2_snapshot_id {
+            self.db.grub2_snapshot(id).await?.grub_config
+        } else {
+            // nothing to compare with before the first snapshot
+            self.db
+                .latest_grub2()
+                .await?
+                .map_or_else(|| grub.as_string(), |latest| latest.grub_config)
+        };
+
+        let diff = TextDiff::from_lines(&selected_config, &grub.as_string())
+            .unified_diff()
+            .to_string();
+
+        let key_changes = GrubFile::parse(&selected_config, ParseMode::Lenient)?.diff(grub);
+        let key_changes = serde_json::to_value(key_changes)
+            .ctx(dctx!(), "Cannot turn grub key changes into json")?;
+
+        // TODO: add the potential difference in kernel entries to config_diff as well
+        let config_diff = if diff.is_empty() {
+            None
+        } else {
+            Some(Value::String(diff))
+        };
+
+        let value_map = serde_json::to_value(grub.keyvalues())
+            .ctx(dctx!(), "Cannot turn grub keyvalues into json")?;
+        let value_list =
+            serde_json::to_value(grub.lines()).ctx(dctx!(), "Cannot turn grub lines into json")?;
+        let effective_values = serde_json::to_value(config.effective())
+            .ctx(dctx!(), "Cannot turn effective grub values into json")?;
+
+        let parse_warnings = config.warnings();
+        for warning in &parse_warnings {
+            log::warn!("{warning}");
+        }
+
+        let deprecated = grub.deprecated_keys();
+        for deprecation in &deprecated {
+            log::warn!("{}", deprecation.message);
+        }
+        let deprecated_keys = serde_json::to_value(deprecated)
+            .ctx(dctx!(), "Cannot turn deprecated grub keys into json")?;
+
+        Ok(ConfigData {
+            value_list,
+            value_map,
+            config_diff,
+            key_changes: Some(key_changes),
+            selected_kernel: kernel_entries.selected().map(str::to_string),
+            effective_values: Some(effective_values),
+            deprecated_keys: Some(deprecated_keys),
+            migrate_deprecated: false,
+            parse_warnings,
+            comment: None,
+        })
+    }
+
+    /// Get grub config config (or the relevant error) that can be safely sent via dbus
+    pub async fn get_grub2_config_json(&self) -> DResult<String> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            return self.get_loader_config_json();
+        }
+
+        let data = self._get_grub2_config().await?;
+        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize grub2 config")
+    }
+
+    /// Save the current /etc/default/grub as a snapshot with `origin` unless it's
+    /// the same as `snapshot_config`, so that the write that follows can be undone.
+    /// A config that can't be parsed is only logged since the write may fix it
+    async fn snapshot_current_config(
+        &self,
+        origin: SnapshotOrigin,
+        snapshot_config: &str,
+    ) -> DResult<()> {
+        let current = match GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode) {
+            Ok(current) => current,
+            Err(err) => {
+                log::warn!(
+                    "Cannot take a {} snapshot of {GRUB_FILE_PATH}: {}",
+                    origin.as_str(),
+                    err.error()
+                );
+                return Ok(());
+            }
+        };
+        if current.as_string() == snapshot_config {
+            return Ok(());
+        }
+
+        let selected_kernel = GrubBootEntries::new()
+            .ok()
+            .and_then(|entries| entries.selected().map(str::to_string));
+        let id = self
+            .db
+            .save_grub2(
+                &current,
+                selected_kernel,
+                Caller::current().as_ref(),
+                origin,
+                None,
+                None,
+            )
+            .await?;
+        log::debug!(
+            "Saved the current config as {} snapshot {id}",
+            origin.as_str()
+        );
+        Ok(())
+    }
+
+    /// Write `grub_file` to the system and save it as the latest snapshot. The
+    /// config that's replaced is saved as a pre-save snapshot first if it's not
+    /// the latest snapshot already
+    async fn apply_grub_file(
+        &self,
+        grub_file: &mut GrubFile,
+        selected_kernel: Option<String>,
+        comment: Option<&str>,
+    ) -> DResult<()> {
+        grub_file.validate_changed()?;
+        // without any snapshot the current config is always saved first
+        let latest = self.db.latest_grub2().await?;
+        let latest_config = latest.map(|latest| latest.grub_config).unwrap_or_default();
+        self.snapshot_current_config(SnapshotOrigin::PreSave, &latest_config)
+            .await?;
+        let output = self
+            .set_grub_system(grub_file, &selected_kernel, false)
+            .await?;
+
+        // if everything is okay, save the snapshot to a database
+        let snapshot_id = self
+            .db
+            .save_grub2(
+                grub_file,
+                selected_kernel,
+                Caller::current().as_ref(),
+                SnapshotOrigin::Save,
+                comment,
+                None,
+            )
+            .await?;
+        self.db
+            .save_mkconfig_run(Some(snapshot_id), &output)
+            .await?;
+        // latest snapshot should be null so it's assumed that latest snapshot is selected
+        self.db.set_selected_snapshot(None).await?;
+
+        Ok(())
+    }
+
+    /// Apply the changes of `config`. Nothing is written if a changed key is
+    /// invalid, and the drop-ins are restored if the main file can't be applied
+    async fn apply_grub_config(&self, config: &mut GrubConfig) -> DResult<()> {
+        config.validate_changed()?;
+        let selected_kernel = GrubBootEntries::new()?.selected().map(str::to_string);
+
+        let backup = config.dropin_backup();
+        config.write_dropins()?;
+        if let Err(err) = self
+            .apply_grub_file(config.main_mut(), selected_kernel, None)
+            .await
+        {
+            GrubConfig::restore_dropins(&backup)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub async fn save_grub2_config(&self, data: &str) -> DResult<String> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            return self.save_loader_config(data).await;
+        }
+        self.check_grub_editable()?;
+
+        let config: ConfigData = serde_json::from_str(data)
+            .ctx(dctx!(), "Malformed JSON data received from the client")?;
+        let value_list: Vec<GrubLine> = serde_json::from_value(config.value_list)
+            .ctx(dctx!(), "Cannot turn json into GrubLines")?;
+
+        let ticket = self.queue.enqueue("SaveConfig").await;
+        let mut grub_file = GrubFile::from_lines(&value_list);
+
+        let mut warnings = if config.migrate_deprecated {
+            grub_file.migrate_deprecated()
+        } else {
+            Vec::new()
+        };
+        for deprecation in grub_file.deprecated_keys() {
+            log::warn!("{}", deprecation.message);
+            warnings.push(deprecation.message.into());
+        }
+        // SaveConfig only writes the main file, so a change a drop-in overrides
+        // would be saved without changing the config
+        let overridden = GrubConfig::read(self.parse_mode)?.overridden_changes(&grub_file);
+        if !overridden.is_empty() {
+            return Err(DError::invalid_values(dctx!(), overridden));
+        }
+
+        self.apply_grub_file(
+            &mut grub_file,
+            config.selected_kernel,
+            config.comment.as_deref(),
+        )
+        .await?;
+
+        ticket.reply_with_warnings(warnings)
+    }
+
+    /// Diff of what `save_grub2_config` would write with the same `data`, without
+    /// writing anything
+    pub fn preview_changes_json(&self, data: &str) -> DResult<String> {
+        let preview = if self.bootloader == BootloaderKind::Grub2 {
+            self.preview_grub2_changes(data)?
+        } else {
+            self.preview_loader_changes(data)?
+        };
+        serde_json::to_string(&preview).ctx(dctx!(), "Failed to serialize changes preview")
+    }
+
+    /// Validate `data`, in the same format as SaveConfig takes, like saving it would
+    /// without writing any config. For grub2 the syntax is checked with
+    /// grub2-script-check on a temporary copy. Problems are reported as diagnostics
+    /// instead of errors
+    pub async fn validate_config_json(&self, data: &str) -> DResult<String> {
+        let diagnostics = if self.bootloader == BootloaderKind::Grub2 {
+            let (mut diagnostics, grub_file) = validate_grub2_config(data);
+            if let Some(grub_file) = grub_file {
+                // grub2-script-check is run synchronously
+                let content = grub_file.as_string();
+                let checked = tokio::task::spawn_blocking(move || config_syntax_error(&content))
+                    .await
+                    .ctx(dctx!(), "Checking the config syntax stopped")?;
+                if let Some(error) = checked? {
+                    diagnostics.push(Diagnostic::error("script", None, error));
+                }
+            }
+            diagnostics
+        } else {
+            let loader = self.loader()?;
+            match serde_json::from_str::<LoaderConfigData>(data) {
+                Ok(config) => loader_invalid_values(loader.as_ref(), &config.value_map)
+                    .into_iter()
+                    .map(|(key, reason)| Diagnostic::error("value", Some(key), reason))
+                    .collect(),
+                Err(err) => vec![Diagnostic::error("parse", None, err.to_string())],
+            }
+        };
+        serde_json::to_string(&ValidationReport::new(diagnostics))
+            .ctx(dctx!(), "Failed to serialize validation report")
+    }
+
+    fn preview_grub2_changes(&self, data: &str) -> DResult<ChangesPreview> {
+        let config: ConfigData = serde_json::from_str(data)
+            .ctx(dctx!(), "Malformed JSON data received from the client")?;
+        let value_list: Vec<GrubLine> = serde_json::from_value(config.value_list)
+            .ctx(dctx!(), "Cannot turn json into GrubLines")?;
+
+        let mut grub_file = GrubFile::from_lines(&value_list);
+        let mut warnings = if config.migrate_deprecated {
+            grub_file.migrate_deprecated()
+        } else {
+            Vec::new()
+        };
+        warnings.extend(
+            grub_file
+                .deprecated_keys()
+                .into_iter()
+                .map(|deprecation| deprecation.message.into()),
+        );
+
+        let current = GrubConfig::read(self.parse_mode)?;
+        let current = current.main();
+        Ok(ChangesPreview {
+            path: GRUB_FILE_PATH.to_string(),
+            diff: unified_diff(&current.as_string(), &grub_file.as_string(), GRUB_FILE_PATH),
+            key_changes: current.diff(&grub_file),
+            invalid_values: grub_file.invalid_changed().into_iter().collect(),
+            warnings,
+        })
+    }
+
+    fn preview_loader_changes(&self, data: &str) -> DResult<ChangesPreview> {
+        let data: LoaderConfigData = serde_json::from_str(data)
+            .ctx(dctx!(), "Malformed JSON data received from the client")?;
+
+        let mut loader = self.loader()?;
+        let old_lines = loader.lines().join("\n");
+        let old_values = loader.values();
+        let invalid_values = loader_invalid_values(loader.as_ref(), &data.value_map)
+            .into_iter()
+            .collect();
+        edit_loader_values(loader.as_mut(), &data.value_map);
+
+        let path = loader.config_path().to_string();
+        Ok(ChangesPreview {
+            diff: unified_diff(&old_lines, &loader.lines().join("\n"), &path),
+            key_changes: value_changes(&old_values, &loader.values()),
+            path,
+            invalid_values,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Get the config of a boot loader other than grub2 that can be safely sent via dbus
+    fn get_loader_config_json(&self) -> DResult<String> {
+        let loader = self.loader()?;
+        let data = LoaderConfigData {
+            value_map: loader.values(),
+            value_list: loader.lines(),
+            selected_entry: loader.selected().map(|entry| entry.id),
+        };
+        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize boot loader config")
+    }
+
+    /// Set the keys of the boot loader config to the values in `value_map`
+    async fn save_loader_config(&self, data: &str) -> DResult<String> {
+        let data: LoaderConfigData = serde_json::from_str(data)
+            .ctx(dctx!(), "Malformed JSON data received from the client")?;
+
+        let ticket = self.queue.enqueue("SaveConfig").await;
+        self.save_loader_values(&data.value_map)?;
+        ticket.reply()
+    }
+
+    /// Set the keys of the boot loader config to `value_map`, removing the keys
+    /// that are missing from it
+    fn save_loader_values(&self, value_map: &BTreeMap<String, String>) -> DResult<()> {
+        let mut loader = self.loader()?;
+        let invalid = loader_invalid_values(loader.as_ref(), value_map);
+        if !invalid.is_empty() {
+            return Err(DError::invalid_values(dctx!(), invalid));
+        }
+
+        edit_loader_values(loader.as_mut(), value_map);
+        loader.write()
+    }
+
+    /// Values of the keys in the main config file, for the typed D-Bus API
+    pub fn get_config_values(&self) -> DResult<BTreeMap<String, String>> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            return Ok(self.loader()?.values());
+        }
+
+        let grub = GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode)?;
+        Ok(grub_file_values(&grub))
+    }
+
+    /// Set the keys of the main config file to `values`, removing the keys that
+    /// are missing from it. Returns the warnings the client should know about
+    pub async fn save_config_values(
+        &self,
+        values: BTreeMap<String, String>,
+    ) -> DResult<Vec<String>> {
+        let _ticket = self.queue.enqueue("SaveConfigValues").await;
+        if self.bootloader != BootloaderKind::Grub2 {
+            self.save_loader_values(&values)?;
+            return Ok(Vec::new());
+        }
+        self.check_grub_editable()?;
+
+        let mut grub_file = GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode)?;
+        replace_key_values(&mut grub_file, &values);
+
+        let warnings = grub_file
+            .deprecated_keys()
+            .iter()
+            .map(|deprecation| deprecation.message.to_string())
+            .collect();
+        let overridden = GrubConfig::read(self.parse_mode)?.overridden_changes(&grub_file);
+        if !overridden.is_empty() {
+            return Err(DError::invalid_values(dctx!(), overridden));
+        }
+        let selected_kernel = GrubBootEntries::new()?.selected().map(str::to_string);
+        self.apply_grub_file(&mut grub_file, selected_kernel, None)
+            .await?;
+
+        Ok(warnings)
+    }
+
+    /// Set all the `changes` in a single write, other keys are left as they are.
+    /// Nothing is written if any of the values is invalid or grub.cfg can't be
+    /// generated. Returns the warnings about the changed keys
+    pub async fn set_config_values(
+        &self,
+        changes: BTreeMap<String, String>,
+    ) -> DResult<Vec<String>> {
+        let _ticket = self.queue.enqueue("SetConfigValues").await;
+        self.apply_config_values(changes).await
+    }
+
+    /// [`Self::set_config_values`] for a caller that already holds the write queue
+    async fn apply_config_values(&self, changes: BTreeMap<String, String>) -> DResult<Vec<String>> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            let mut loader = self.loader()?;
+            let invalid: Vec<(String, String)> = changes
+                .iter()
+                .filter_map(|(key, value)| {
+                    loader
+                        .validate(key, value)
+                        .err()
+                        .map(|reason| (key.clone(), reason))
+                })
+                .collect();
+            if !invalid.is_empty() {
+                return Err(DError::invalid_values(dctx!(), invalid));
+            }
+
+            for (key, value) in &changes {
+                log::debug!("Setting {key} to '{value}' in {}", loader.config_path());
+                loader.set_value(key, value);
+            }
+            loader.write()?;
+            return Ok(Vec::new());
+        }
+
+        self.check_grub_editable()?;
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        let mut warnings = Vec::new();
+        let mut changed = false;
+        for (key, value) in &changes {
+            if config.value(key) == Some(value.as_str()) {
+                continue;
+            }
+            log::debug!("Setting {key} to '{value}'");
+            config.set_key_value(key, value);
+            changed = true;
+            if let Some(deprecation) = schema::deprecation(key) {
+                warnings.push(deprecation.message.to_string());
+            }
+        }
+        // every key is validated before anything is written
+        config.validate_changed()?;
+        if !changed {
+            return Ok(warnings);
+        }
+
+        self.apply_grub_config(&mut config).await?;
+
+        Ok(warnings)
+    }
+
+    /// Reconcile the grub config to the desired state in `data` with a single write.
+    /// Applying the same state again changes nothing
+    pub async fn apply_state_json(&self, data: &str) -> DResult<String> {
+        let state: DesiredState =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+        if self.bootloader != BootloaderKind::Grub2 {
+            return Err(DError::generic(
+                dctx!(),
+                format!("ApplyState is not supported for {}", self.bootloader_type()),
+            ));
+        }
+        self.check_grub_editable()?;
+        if let Some(cmdline) = &state.cmdline {
+            if !cmdline.cmdline.starts_with("GRUB_CMDLINE_") {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!("'{}' is not a kernel command line", cmdline.cmdline),
+                ));
+            }
+        }
+
+        let _ticket = self.queue.enqueue("ApplyState").await;
+        // the default entry is looked up before anything is written so that an
+        // unknown entry fails the whole state
+        let default_value = match &state.default_entry {
+            Some(entry) => Some(
+                GrubBootEntries::new()?
+                    .find(entry)
+                    .ok_or_else(|| {
+                        DError::generic(dctx!(), format!("Boot entry '{entry}' is not found"))
+                    })?
+                    .default_value(),
+            ),
+            None => None,
+        };
+
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        let key_changes = state_key_changes(&config, &state);
+        let mut warnings = Vec::new();
+        if !key_changes.is_empty() {
+            warnings = apply_key_changes(&mut config, &key_changes);
+            config.validate_changed()?;
+
+            self.apply_grub_config(&mut config).await?;
+        }
+
+        let mut default_entry_changed = false;
+        let mut default_entry_error = None;
+        if let Some(value) = default_value {
+            let saved_entry = GrubEnv::from_file(&GrubLayout::get().env_path)
+                .ok()
+                .and_then(|env| env.saved_entry().map(str::to_string));
+            if saved_entry.as_deref() != Some(value.as_str()) {
+                log::debug!("Setting saved_entry to {value}");
+                let result = EnvEditor::new(self.env_backend)
+                    .set("saved_entry", &value)
+                    .await;
+                match result {
+                    Ok(_) => default_entry_changed = true,
+                    // nothing was written yet, so the whole state fails
+                    Err(err) if key_changes.is_empty() => return Err(err),
+                    // the keys are already in grub.cfg, report what was applied
+                    Err(err) => {
+                        let message = err.error().as_string();
+                        log::error!("Failed to set saved_entry after writing the keys: {message}");
+                        default_entry_error = Some(message);
+                    }
+                }
+            }
+            if config.value("GRUB_DEFAULT") != Some("saved") {
+                warnings.push(
+                    "GRUB_DEFAULT is not 'saved' so grub doesn't boot the saved entry by default"
+                        .to_string(),
+                );
+            }
+        }
+
+        let applied = AppliedState {
+            changed: !key_changes.is_empty() || default_entry_changed,
+            key_changes,
+            default_entry_changed,
+            default_entry_error,
+            warnings,
+        };
+        serde_json::to_string(&applied).ctx(dctx!(), "Failed to serialize applied state")
+    }
+
+    /// Hash of the config files that the edits change
+    fn config_hash(&self) -> DResult<String> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            let loader = self.loader()?;
+            return Ok(content_hash(&[Path::new(loader.config_path())]));
+        }
+        let config = GrubConfig::read(self.parse_mode)?;
+        Ok(content_hash(&config.paths()))
+    }
+
+    /// Begin an edit that is committed later. Returns the token of the edit and
+    /// the hash of the config files the edit is based on
+    pub fn begin_edit(&self, owner: EditOwner) -> DResult<(String, String)> {
+        let hash = self.config_hash()?;
+        let name = owner.name.clone();
+        let token = self.edits.begin(owner, hash.clone())?;
+        log::debug!("{name} began edit {token} of config {hash}");
+        Ok((token, hash))
+    }
+
+    /// Stage a key to be set when the edit is committed
+    pub fn stage_edit(
+        &self,
+        owner: &EditOwner,
+        token: &str,
+        key: &str,
+        value: &str,
+    ) -> DResult<()> {
+        self.edits.stage(owner, token, key, value)
+    }
+
+    /// Write the staged changes of the edit, unless the config files changed after
+    /// the edit began. Returns the warnings about the changed keys
+    pub async fn commit_edit(&self, owner: &EditOwner, token: &str) -> DResult<Vec<String>> {
+        let (hash, changes) = self.edits.finish(owner, token)?;
+        let _ticket = self.queue.enqueue("CommitEdit").await;
+        if self.config_hash()? != hash {
+            return Err(DError::conflict(
+                dctx!(),
+                "Boot configuration was changed after the edit began, begin a new edit",
+            ));
+        }
+        self.apply_config_values(changes).await
+    }
+
+    /// Discard the staged changes of the edit
+    pub fn cancel_edit(&self, owner: &EditOwner, token: &str) -> DResult<()> {
+        self.edits.finish(owner, token)?;
+        log::debug!("Canceled edit {token}");
+        Ok(())
+    }
+
+    /// Drop the open edits of a client that disconnected from the bus
+    pub fn forget_edits(&self, name: &str) {
+        self.edits.forget(name);
+    }
+
+    /// Get the kernel command lines split into parameters that can be safely sent via dbus
+    pub async fn get_cmdline_json(&self) -> DResult<String> {
+        if self.kargs_backend.is_ostree() {
+            // deployment has a single command line that the default key stands for
+            let cmdlines = BTreeMap::from([(default_cmdline_key(), deployment_kargs()?)]);
+            return serde_json::to_string(&cmdlines)
+                .ctx(dctx!(), "Failed to serialize kernel command lines");
+        }
+
+        let config = GrubConfig::read(self.parse_mode)?;
+        let cmdlines: BTreeMap<String, CmdLine> = config
+            .effective()
+            .into_iter()
+            .filter(|(key, _)| key.starts_with("GRUB_CMDLINE_"))
+            .map(|(key, effective)| (key, CmdLine::new(&effective.value)))
+            .collect();
+
+        serde_json::to_string(&cmdlines).ctx(dctx!(), "Failed to serialize kernel command lines")
+    }
+
+    /// Get the configured kernel command line and how it differs from the command line
+    /// of the running kernel that can be safely sent via dbus
+    pub fn get_kernel_cmdline_json(&self) -> DResult<String> {
+        let properties = if self.bootloader == BootloaderKind::Grub2 {
+            self.grub_properties()?
+        } else {
+            self.loader_properties()?
+        };
+        let running = read_to_string(KERNEL_CMDLINE_PATH)
+            .ctx(dctx!(), format!("Cannot read {KERNEL_CMDLINE_PATH}"))?;
+        let running = running.trim().to_string();
+
+        let differences = CmdLine::new(&properties.kernel_cmdline).diff(&CmdLine::new(&running));
+        let status = KernelCmdlineStatus {
+            configured: properties.kernel_cmdline,
+            running,
+            reboot_needed: !differences.is_empty(),
+            differences,
+        };
+        serde_json::to_string(&status).ctx(dctx!(), "Failed to serialize kernel command line")
+    }
+
+    /// Add, remove or set a single kernel command line parameter
+    pub async fn set_cmdline_param(&self, data: &str) -> DResult<String> {
+        let param_data: CmdLineParamData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        if !param_data.cmdline.starts_with("GRUB_CMDLINE_") {
+            return Err(DError::generic(
+                dctx!(),
+                format!("'{}' is not a kernel command line", param_data.cmdline),
+            ));
+        }
+
+        let ticket = self.queue.enqueue("SetCmdlineParam").await;
+        if self.kargs_backend.is_ostree() {
+            let current = deployment_kargs()?;
+            let mut kargs = current.clone();
+            edit_cmdline(&mut kargs, &param_data);
+            set_kargs(self.kargs_backend, &current, &kargs).await?;
+            return ticket.reply();
+        }
+
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        let mut cmdline = config
+            .value(&param_data.cmdline)
+            .map(CmdLine::new)
+            .unwrap_or_default();
+        edit_cmdline(&mut cmdline, &param_data);
+
+        log::debug!("Setting {} to '{cmdline}'", param_data.cmdline);
+        config.set_key_value(&param_data.cmdline, &cmdline.to_string());
+
+        self.apply_grub_config(&mut config).await?;
+
+        ticket.reply()
+    }
+    /// Get the hibernation resume parameters that can be safely sent via dbus
+    pub async fn get_resume_json(&self) -> DResult<String> {
+        let cmdline = if self.kargs_backend.is_ostree() {
+            deployment_kargs()?
+        } else {
+            GrubConfig::read(self.parse_mode)?
+                .value(RESUME_CMDLINE)
+                .map(CmdLine::new)
+                .unwrap_or_default()
+        };
+        let param = |key: &str| cmdline.get(key).flatten().map(str::to_string);
+
+        let (detected, detection_error) = match resume_params().await {
+            Ok(params) => (Some(params), None),
+            Err(err) => (None, Some(err.error().to_string())),
+        };
+        let status = ResumeStatus {
+            detected,
+            detection_error,
+            resume: param("resume"),
+            resume_offset: param("resume_offset"),
+        };
+        serde_json::to_string(&status).ctx(dctx!(), "Failed to serialize resume parameters")
+    }
+
+    /// Set `resume=` and `resume_offset=` for the detected swap, or remove them
+    pub async fn set_resume(&self, data: &str) -> DResult<String> {
+        let resume_data: ResumeData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        let ticket = self.queue.enqueue("SetResume").await;
+        // grub config is not read on ostree systems
+        let (config, current) = if self.kargs_backend.is_ostree() {
+            (None, deployment_kargs()?)
+        } else {
+            let config = GrubConfig::read(self.parse_mode)?;
+            let cmdline = config
+                .value(RESUME_CMDLINE)
+                .map(CmdLine::new)
+                .unwrap_or_default();
+            (Some(config), cmdline)
+        };
+        let mut cmdline = current.clone();
+
+        let mut warnings = Vec::new();
+        if resume_data.enabled {
+            let params = resume_params().await?;
+            cmdline.set_param("resume", Some(&params.resume));
+            match params.resume_offset {
+                Some(offset) => cmdline.set_param("resume_offset", Some(&offset.to_string())),
+                None => {
+                    cmdline.remove_param("resume_offset");
+                }
+            }
+
+            if memory_kib().is_some_and(|memory| params.swap.size_kib < memory) {
+                warnings.push(format!(
+                    "{} is smaller than the memory, hibernation can fail if the memory is full",
+                    params.swap.path
+                ));
+            }
+        } else {
+            cmdline.remove_param("resume");
+            cmdline.remove_param("resume_offset");
+        }
+
+        let Some(mut config) = config else {
+            set_kargs(self.kargs_backend, &current, &cmdline).await?;
+            return ticket.reply_with_warnings(warnings);
+        };
+
+        log::debug!("Setting {RESUME_CMDLINE} to '{cmdline}'");
+        config.set_key_value(RESUME_CMDLINE, &cmdline.to_string());
+
+        self.apply_grub_config(&mut config).await?;
+
+        ticket.reply_with_warnings(warnings)
+    }
+
+    /// Regenerate grub.cfg from the current config without changing it
+    pub async fn regenerate_config(&self) -> DResult<String> {
+        let output = self.regenerate_config_with_progress(None).await?;
+        serde_json::to_string(&output).ctx(dctx!(), "Failed to serialize mkconfig output")
+    }
+
+    /// Same as [`Self::regenerate_config`] but sends the lines grub2-mkconfig
+    /// reports its progress with to `progress`
+    pub async fn regenerate_config_with_progress(
+        &self,
+        progress: Option<UnboundedSender<String>>,
+    ) -> DResult<CommandOutput> {
+        self.check_grub_editable()?;
+        let _ticket = self.queue.enqueue("RegenerateConfig").await;
+        let output = self.regenerate_grub_cfg(progress).await?;
+        self.save_selected_mkconfig_run(&output).await?;
+        Ok(output)
+    }
+
+    /// Record a grub2-mkconfig run that didn't change /etc/default/grub with the
+    /// selected snapshot
+    async fn save_selected_mkconfig_run(&self, output: &CommandOutput) -> DResult<()> {
+        let selected = self.db.selected_snapshot().await?;
+        let snapshot_id = match selected.grub2_snapshot_id {
+            Some(id) => Some(id),
+            None => self.db.latest_grub2().await?.map(|latest| latest.id),
+        };
+        self.db.save_mkconfig_run(snapshot_id, output).await
+    }
+
+    /// Get the grub menu password protection that can be safely sent via dbus
+    pub fn get_menu_protection_json(&self) -> DResult<String> {
+        let protection = MenuProtection::read(GRUB_SCRIPTS_PATH)?;
+        serde_json::to_string(&protection).ctx(dctx!(), "Failed to serialize menu protection")
+    }
+
+    /// Enable or disable the password protection of the grub menu
+    pub async fn set_menu_protection(&self, data: &str) -> DResult<String> {
+        self.check_grub_editable()?;
+        let protection: MenuProtectionData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        let ticket = self.queue.enqueue("SetMenuProtection").await;
+        let previous = read_users_script(GRUB_SCRIPTS_PATH);
+        if protection.enabled {
+            let user = protection.user.as_deref().unwrap_or("root");
+            if !is_valid_user(user) {
+                return Err(DError::invalid_values(
+                    dctx!(),
+                    vec![(
+                        "user".into(),
+                        format!("'{user}' can only contain letters, numbers, '_', '-' and '.'"),
+                    )],
+                ));
+            }
+
+            let hash = hash_password(protection.password.as_deref().unwrap_or_default()).await?;
+            log::debug!("Protecting the grub menu with the password of {user}");
+            write_users_script(GRUB_SCRIPTS_PATH, user, &hash)?;
+        } else if remove_users_script(GRUB_SCRIPTS_PATH)? {
+            log::debug!("Removed the grub menu protection");
+        } else {
+            return ticket.reply();
+        }
+
+        match self.regenerate_grub_cfg(None).await {
+            Ok(output) => self.save_selected_mkconfig_run(&output).await?,
+            Err(err) => {
+                restore_users_script(GRUB_SCRIPTS_PATH, previous.as_deref())?;
+                return Err(err);
+            }
+        }
+
+        ticket.reply()
+    }
+
+    /// 40_custom and custom.cfg files that hold the user defined entries
+    fn custom_files() -> DResult<[CustomFile; 2]> {
+        let custom_cfg = GrubLayout::get().boot_dir().join(CUSTOM_CFG);
+        Ok([
+            CustomFile::read(Path::new(GRUB_SCRIPTS_PATH).join(CUSTOM_SCRIPT), true)?,
+            CustomFile::read(custom_cfg, false)?,
+        ])
+    }
+
+    /// Write the custom entries and regenerate grub.cfg.
+    /// The previous file is restored if grub.cfg can't be generated
+    async fn apply_custom_file(&self, custom: &CustomFile) -> DResult<()> {
+        let path = custom.path();
+        let previous = read_to_string(path).ok();
+        custom.write()?;
+
+        match self.regenerate_grub_cfg(None).await {
+            Ok(output) => self.save_selected_mkconfig_run(&output).await,
+            Err(err) => {
+                match previous {
+                    Some(previous) => {
+                        CustomFile::write_contents(path, &previous, custom.is_script())?
+                    }
+                    None => remove_file(path).ctx(dctx!(), format!("Failed to remove {path:?}"))?,
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Get the user defined entries that can be safely sent via dbus
+    pub fn get_custom_entries_json(&self) -> DResult<String> {
+        let entries: Vec<CustomEntry> = Self::custom_files()?
+            .iter()
+            .flat_map(CustomFile::entries)
+            .collect();
+        serde_json::to_string(&entries).ctx(dctx!(), "Failed to serialize custom entries")
+    }
+
+    /// Add a user defined entry to 40_custom
+    pub async fn add_custom_entry(&self, data: &str) -> DResult<String> {
+        self.check_grub_editable()?;
+        let entry_data: AddCustomEntryData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+        let entry = entry_data
+            .template
+            .render(&entry_data.title)
+            .map_err(|invalid| DError::invalid_values(dctx!(), vec![invalid]))?;
+
+        let ticket = self.queue.enqueue("AddCustomEntry").await;
+        let [mut custom, _] = Self::custom_files()?;
+        log::debug!("Adding custom entry '{}'", entry_data.title);
+        custom.add(&entry_data.title, &entry)?;
+        self.apply_custom_file(&custom).await?;
+
+        ticket.reply()
+    }
+
+    /// Remove a user defined entry from 40_custom or custom.cfg
+    pub async fn remove_custom_entry(&self, data: &str) -> DResult<String> {
+        self.check_grub_editable()?;
+        let entry_data: RemoveCustomEntryData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        let ticket = self.queue.enqueue("RemoveCustomEntry").await;
+        let custom = Self::custom_files()?.into_iter().find_map(|mut custom| {
+            let source_matches = entry_data
+                .source
+                .as_deref()
+                .is_none_or(|source| custom.path() == Path::new(source));
+            (source_matches && custom.remove(&entry_data.title)).then_some(custom)
+        });
+        let Some(custom) = custom else {
+            return Err(DError::invalid_values(
+                dctx!(),
+                vec![(
+                    "title".into(),
+                    format!("custom entry '{}' doesn't exist", entry_data.title),
+                )],
+            ));
+        };
+
+        log::debug!(
+            "Removing custom entry '{}' from {:?}",
+            entry_data.title,
+            custom.path()
+        );
+        self.apply_custom_file(&custom).await?;
+
+        ticket.reply()
+    }
+
+    /// Get the config edits that haven't been applied to grub.cfg yet
+    /// that can be safely sent via dbus
+    pub async fn get_pending_changes_json(&self) -> DResult<String> {
+        let config = GrubConfig::read(self.parse_mode)?;
+        let cfg_path = &GrubLayout::get().cfg_path;
+        let grub_cfg = read_to_string(cfg_path).ctx(dctx!(), format!("Cannot read {cfg_path}"))?;
+
+        let content_changed = self
+            .last_generated_snapshot(&grub_cfg)
+            .await?
+            .map(|snapshot| snapshot.grub_config != config.main().as_string());
+
+        let status = StaleStatus::new(
+            modified_after(&config.paths(), cfg_path),
+            content_changed,
+            key_differences(&config.effective(), &grub_cfg),
+        );
+        serde_json::to_string(&status).ctx(dctx!(), "Failed to serialize pending changes")
+    }
+
+    /// Snapshot the current `grub_cfg` was generated from. None if it's not known,
+    /// like when grub.cfg was generated by something else after the last run
+    async fn last_generated_snapshot(&self, grub_cfg: &str) -> DResult<Option<Grub2Snapshot>> {
+        let last_run = self
+            .db
+            .mkconfig_runs()
+            .await?
+            .into_iter()
+            .find(|run| run.exit_status == Some(0) && run.grub2_snapshot_id.is_some());
+        let Some(run) = last_run else {
+            return Ok(None);
+        };
+        if run.grub_cfg_hash.as_deref()
+            != Some(crate::db::grub2::content_hash(grub_cfg, None).as_str())
+        {
+            log::debug!("grub.cfg was not generated by mkconfig run {}", run.id);
+            return Ok(None);
+        }
+        match run.grub2_snapshot_id {
+            Some(id) => Ok(Some(self.db.grub2_snapshot(id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Value of a single config key and whether grub.cfg has to be regenerated
+    /// before the value takes effect
+    pub async fn get_config_value(&self, key: &str) -> DResult<(String, bool)> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            let loader = self.loader()?;
+            let value = loader.values().remove(key).ok_or_else(|| {
+                DError::generic(
+                    dctx!(),
+                    format!("Key '{key}' is not set in {}", loader.config_path()),
+                )
+            })?;
+            // other boot loaders read their config directly
+            return Ok((value, false));
+        }
+
+        let config = GrubConfig::read(self.parse_mode)?;
+        let value = config.value(key).map(str::to_string).ok_or_else(|| {
+            DError::generic(
+                dctx!(),
+                format!("Key '{key}' is not set in the grub config"),
+            )
+        })?;
+
+        let cfg_path = &GrubLayout::get().cfg_path;
+        let grub_cfg = read_to_string(cfg_path).ctx(dctx!(), format!("Cannot read {cfg_path}"))?;
+        let differs_in_cfg = key_differences(&config.effective(), &grub_cfg)
+            .iter()
+            .any(|difference| difference.key == key);
+        let changed_since_generated = match self.last_generated_snapshot(&grub_cfg).await? {
+            Some(snapshot) => {
+                let generated = GrubFile::parse(&snapshot.grub_config, ParseMode::Lenient)?;
+                let main_value =
+                    |file: &GrubFile| file.keyvalues().get(key).map(|keyval| keyval.value.clone());
+                main_value(&generated) != main_value(config.main())
+            }
+            None => modified_after(&config.paths(), cfg_path),
+        };
+
+        Ok((value, differs_in_cfg || changed_since_generated))
+    }
+
+    /// Set a single config key and regenerate grub.cfg if the value changed.
+    /// Returns whether grub.cfg was regenerated and the warnings about the key
+    pub async fn set_config_value(&self, key: &str, value: &str) -> DResult<(bool, Vec<String>)> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            // the config is read after the previous writes are done so they aren't lost
+            let _ticket = self.queue.enqueue("SetConfigValue").await;
+            let mut loader = self.loader()?;
+            loader
+                .validate(key, value)
+                .map_err(|reason| DError::invalid_values(dctx!(), vec![(key.into(), reason)]))?;
+
+            if loader.values().get(key).map(String::as_str) != Some(value) {
+                log::debug!("Setting {key} to '{value}' in {}", loader.config_path());
+                loader.set_value(key, value);
+                loader.write()?;
+            }
+            return Ok((false, Vec::new()));
+        }
+
+        self.check_grub_editable()?;
+        if let Some(reason) = key_error(key).or_else(|| schema::validate(key, value).err()) {
+            return Err(DError::invalid_values(dctx!(), vec![(key.into(), reason)]));
+        }
+
+        let _ticket = self.queue.enqueue("SetConfigValue").await;
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        if config.value(key) == Some(value) {
+            log::debug!("{key} is already '{value}'");
+            return Ok((false, Vec::new()));
+        }
+        log::debug!("Setting {key} to '{value}'");
+        config.set_key_value(key, value);
+
+        let warnings = schema::deprecation(key)
+            .map(|deprecation| vec![deprecation.message.to_string()])
+            .unwrap_or_default();
+        self.apply_grub_config(&mut config).await?;
+
+        Ok((true, warnings))
+    }
+
+    /// Get the installed grub themes that can be safely sent via dbus
+    pub async fn get_themes_json(&self) -> DResult<String> {
+        let config = GrubConfig::read(self.parse_mode)?;
+        let current = config
+            .value("GRUB_THEME")
+            .filter(|value| !value.is_empty())
+            .map(str::to_string);
+        let current_error = current
+            .as_deref()
+            .and_then(|theme| validate_theme(theme).err());
+
+        let data = ThemesData {
+            themes: themes(GrubLayout::get().themes_dir())?,
+            current,
+            current_error,
+        };
+        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize grub themes")
+    }
+
+    /// Switch GRUB_THEME to an installed theme, or disable the theme
+    pub async fn set_theme(&self, data: &str) -> DResult<String> {
+        self.check_grub_editable()?;
+        let theme_data: SetThemeData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        let ticket = self.queue.enqueue("SetTheme").await;
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        let mut warnings = Vec::new();
+        if let Some(theme) = &theme_data.theme {
+            let installed = themes(GrubLayout::get().themes_dir())?;
+            let path = installed
+                .iter()
+                .find(|installed| installed.name == *theme || installed.path == *theme)
+                .map(|installed| installed.path.clone())
+                .unwrap_or_else(|| theme.clone());
+            validate_theme(&path).map_err(|reason| {
+                DError::invalid_values(dctx!(), vec![("GRUB_THEME".into(), reason)])
+            })?;
+
+            log::debug!("Setting GRUB_THEME to {path}");
+            config.set_key_value("GRUB_THEME", &path);
+            if config
+                .value("GRUB_TERMINAL_OUTPUT")
+                .is_some_and(|output| !output.contains("gfxterm"))
+            {
+                warnings.push("Themes are only shown when GRUB_TERMINAL_OUTPUT is gfxterm".into());
+            }
+        } else {
+            log::debug!("Disabling the grub theme");
+            config.remove_key("GRUB_THEME", RemoveMode::Delete);
+        }
+
+        self.apply_grub_config(&mut config).await?;
+
+        ticket.reply_with_warnings(warnings)
+    }
+
+    /// Get the operating systems os-prober added to grub.cfg that can be safely sent via dbus
+    pub fn get_os_prober_json(&self) -> DResult<String> {
+        let cfg_path = &GrubLayout::get().cfg_path;
+        let grub_cfg = read_to_string(cfg_path).ctx(dctx!(), format!("Cannot read {cfg_path}"))?;
+        let config = GrubConfig::read(self.parse_mode)?;
+        let status = OsProberStatus::new(&grub_cfg, config.value("GRUB_DISABLE_OS_PROBER"));
+        serde_json::to_string(&status).ctx(dctx!(), "Failed to serialize os-prober status")
+    }
+
+    /// Enable or disable os-prober and regenerate grub.cfg
+    pub async fn set_os_prober(&self, data: &str) -> DResult<String> {
+        self.check_grub_editable()?;
+        let os_prober: OsProberData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        let ticket = self.queue.enqueue("SetOsProber").await;
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        let disable = if os_prober.enabled { "false" } else { "true" };
+        log::debug!("Setting GRUB_DISABLE_OS_PROBER to {disable}");
+        config.set_key_value("GRUB_DISABLE_OS_PROBER", disable);
+
+        let mut warnings = Vec::new();
+        if os_prober.enabled && !Path::new(OS_PROBER_PATH).exists() {
+            warnings.push(format!(
+                "{OS_PROBER_PATH} is not installed so no other operating systems are found"
+            ));
+        }
+
+        self.apply_grub_config(&mut config).await?;
+
+        ticket.reply_with_warnings(warnings)
+    }
+
+    /// Set the seconds the boot menu waits before booting the default entry
+    pub async fn set_timeout(&self, data: &str) -> DResult<String> {
+        let timeout_data: TimeoutData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+        let timeout = timeout_data.timeout.to_string();
+
+        if self.bootloader != BootloaderKind::Grub2 {
+            // the config is read after the previous writes are done so they aren't lost
+            let ticket = self.queue.enqueue("SetTimeout").await;
+            let mut loader = self.loader()?;
+            // extlinux counts the timeout in tenths of a second
+            let value = match (self.bootloader, timeout_data.timeout) {
+                (BootloaderKind::Extlinux, secs) if secs >= 0 => (secs * 10).to_string(),
+                _ => timeout.clone(),
+            };
+            loader.validate("timeout", &value).map_err(|reason| {
+                DError::invalid_values(dctx!(), vec![("timeout".into(), reason)])
+            })?;
+
+            log::debug!("Setting timeout to {value} in {}", loader.config_path());
+            loader.set_value("timeout", &value);
+            loader.write()?;
+            return ticket.reply();
+        }
+
+        self.check_grub_editable()?;
+        schema::validate("GRUB_TIMEOUT", &timeout).map_err(|reason| {
+            DError::invalid_values(dctx!(), vec![("GRUB_TIMEOUT".into(), reason)])
+        })?;
+
+        let ticket = self.queue.enqueue("SetTimeout").await;
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        log::debug!("Setting GRUB_TIMEOUT to {timeout}");
+        config.set_key_value("GRUB_TIMEOUT", &timeout);
+
+        let mut warnings = Vec::new();
+        if timeout == "0" && config.value("GRUB_TIMEOUT_STYLE") == Some("hidden") {
+            warnings.push(HIDDEN_MENU_WARNING.to_string());
+        }
+
+        self.apply_grub_config(&mut config).await?;
+
+        ticket.reply_with_warnings(warnings)
+    }
+
+    /// Set how the grub menu is shown during the timeout
+    pub async fn set_timeout_style(&self, data: &str) -> DResult<String> {
+        let style_data: TimeoutStyleData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+        if self.bootloader != BootloaderKind::Grub2 {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "Timeout style is only supported with grub2, not {}",
+                    self.bootloader_type()
+                ),
+            ));
+        }
+
+        self.check_grub_editable()?;
+        let style = style_data.style;
+        schema::validate("GRUB_TIMEOUT_STYLE", &style).map_err(|reason| {
+            DError::invalid_values(dctx!(), vec![("GRUB_TIMEOUT_STYLE".into(), reason)])
+        })?;
+        // an empty value would pass the schema and leave grub on its default style
+        if style.is_empty() {
+            return Err(DError::invalid_values(
+                dctx!(),
+                vec![("GRUB_TIMEOUT_STYLE".into(), "style can't be empty".into())],
+            ));
+        }
+
+        let ticket = self.queue.enqueue("SetTimeoutStyle").await;
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        log::debug!("Setting GRUB_TIMEOUT_STYLE to {style}");
+        config.set_key_value("GRUB_TIMEOUT_STYLE", &style);
+
+        let mut warnings = Vec::new();
+        if style == "hidden" && config.value("GRUB_TIMEOUT") == Some("0") {
+            warnings.push(HIDDEN_MENU_WARNING.to_string());
+        }
+
+        self.apply_grub_config(&mut config).await?;
+
+        ticket.reply_with_warnings(warnings)
+    }
+
+    /// Remove a key from the grub config so grub falls back to its default value
+    pub async fn remove_key(&self, data: &str) -> DResult<String> {
+        self.check_grub_editable()?;
+        let remove_data: RemoveKeyData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        let ticket = self.queue.enqueue("RemoveKey").await;
+        let mut config = GrubConfig::read(self.parse_mode)?;
+        if !config.remove_key(&remove_data.key, remove_data.mode) {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Key '{}' is not set in the grub config", remove_data.key),
+            ));
+        }
+
+        self.apply_grub_config(&mut config).await?;
+
+        ticket.reply()
+    }
+
+    /// Get the descriptions of the known grub keys that can be safely sent via dbus
+    pub fn get_key_schema_json(&self) -> DResult<String> {
+        serde_json::to_string(KEY_SCHEMA).ctx(dctx!(), "Failed to serialize grub key schema")
+    }
+
+    /// Get the GRUB_GFXMODE values that fit the connected displays
+    pub fn get_gfx_modes_json(&self) -> DResult<String> {
+        serde_json::to_string(&gfx_modes(DRM_PATH)).ctx(dctx!(), "Failed to serialize gfx modes")
+    }
+
+    /// Get the status of the write queue that can be safely sent via dbus
+    pub fn get_write_queue_json(&self) -> DResult<String> {
+        serde_json::to_string(&self.queue.status())
+            .ctx(dctx!(), "Failed to serialize write queue status")
+    }
+
+    async fn _get_loader_entries(&self, sort: EntrySort) -> DResult<BootEntryData> {
+        let loader = self.loader()?;
+        let mut loader_entries = loader.entries();
+        sort_entries(&mut loader_entries, sort);
+        let titles: Vec<&str> = loader_entries
+            .iter()
+            .map(|entry| entry.title.as_str())
+            .collect();
+        let entries = serde_json::to_value(titles)
+            .ctx(dctx!(), "Cannot turn boot loader entries into json")?;
+        let selected_kernel = serde_json::to_value(loader.default_entry())
+            .ctx(dctx!(), "Cannot turn boot loader entries into json")?;
+        let default = loader.selected().map(|entry| entry.id);
+        let next_entry = loader.next_entry();
+
+        Ok(BootEntryData {
+            entries,
+            entry_ids: loader_entries
+                .iter()
+                .map(|entry| Some(entry.id.clone()))
+                .collect(),
+            details: loader_entries
+                .iter()
+                .map(|entry| BootEntryDetails {
+                    is_default: default.as_ref() == Some(&entry.id),
+                    is_next: next_entry.as_ref() == Some(&entry.id),
+                    ..BootEntryDetails::from(entry)
+                })
+                .collect(),
+            selected_kernel,
+            next_entry,
+        })
+    }
+
+    async fn _get_grub2_boot_entries(&self, sort: EntrySort) -> DResult<BootEntryData> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            return self._get_loader_entries(sort).await;
+        }
+
+        let mut grub_entries =
+            GrubBootEntries::new().ctx(dctx!(), "Couldn't read kernel entries")?;
+        grub_entries.sort(sort);
+        let entries = serde_json::to_value(grub_entries.entry_names())
+            .ctx(dctx!(), "Cannot trun grub kernel entries into json")?;
+        let selected_kernel = serde_json::to_value(grub_entries.selected())
+            .ctx(dctx!(), "Cannot trun grub kernel entries into json")?;
+
+        Ok(BootEntryData {
+            entries,
+            entry_ids: grub_entries.entry_ids(),
+            details: grub_entries
+                .entries()
+                .iter()
+                .map(|entry| BootEntryDetails {
+                    is_default: grub_entries.is_selected(entry),
+                    is_next: grub_entries.is_next(entry),
+                    ..BootEntryDetails::from(entry)
+                })
+                .collect(),
+            selected_kernel,
+            next_entry: grub_entries.next_entry().map(str::to_string),
+        })
+    }
+
+    /// Get the recorded boots that can be safely sent via dbus
+    pub async fn get_boot_timeline_json(&self) -> DResult<String> {
+        let boots = self.db.boot_timeline().await?;
+        serde_json::to_string(&boots).ctx(dctx!(), "Failed to serialize boot timeline")
+    }
+
+    /// Kernels in /boot with the boot entries that boot them
+    pub fn list_kernels(&self) -> DResult<Vec<InstalledKernel>> {
+        let mut kernels = installed_kernels()?;
+        let entries = self
+            .entry_details()
+            .ctx(dctx!(), "Cannot read the boot entries of the kernels")?;
+        for kernel in &mut kernels {
+            kernel.entries = entries
+                .iter()
+                .filter(|entry| {
+                    kernel.is_booted_by(
+                        entry.kernel.as_deref().unwrap_or_default(),
+                        entry.version.as_deref(),
+                    )
+                })
+                .map(|entry| entry.id.clone().unwrap_or_else(|| entry.title.clone()))
+                .collect();
+        }
+        Ok(kernels)
+    }
+
+    /// Boot entries in the menu order with their kernels and versions
+    fn entry_details(&self) -> DResult<Vec<BootEntryDetails>> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            let loader = self.loader()?;
+            return Ok(loader
+                .entries()
+                .iter()
+                .map(BootEntryDetails::from)
+                .collect());
+        }
+
+        let entries = GrubBootEntries::new().ctx(dctx!(), "Couldn't read kernel entries")?;
+        Ok(entries
+            .entries()
+            .iter()
+            .map(BootEntryDetails::from)
+            .collect())
+    }
+
+    pub fn list_kernels_json(&self) -> DResult<String> {
+        let kernels = self.list_kernels()?;
+        serde_json::to_string(&kernels).ctx(dctx!(), "Failed to serialize kernels")
+    }
+
+    /// Remove the images and initrds of the kernels that aren't running or booted
+    /// by any entry, always keeping the `keep` newest ones, and regenerate grub.cfg.
+    /// Kernels that belong to a package are only reported since removing their
+    /// files would leave the package database out of sync
+    pub async fn remove_old_kernels_json(&self, keep: u32, dry_run: bool) -> DResult<String> {
+        if keep == 0 {
+            return Err(DError::invalid_values(
+                dctx!(),
+                vec![("keep".into(), "At least one kernel has to be kept".into())],
+            ));
+        }
+        // other boot loaders don't list the entries they find by themselves, like
+        // the kernels rEFInd detects, so every kernel would look unused
+        if self.bootloader != BootloaderKind::Grub2 {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "RemoveOldKernels is not supported for {}, it can't list every boot entry",
+                    self.bootloader_type()
+                ),
+            ));
+        }
+        // ostree deploys the kernels to /boot itself
+        self.check_grub_editable()?;
+        let _ticket = if dry_run {
+            None
+        } else {
+            Some(self.queue.enqueue("RemoveOldKernels").await)
+        };
+
+        let kernels = self.list_kernels()?;
+        let mut cleanup = KernelCleanup {
+            dry_run,
+            removed: Vec::new(),
+            packaged: Vec::new(),
+            failed: Vec::new(),
+            regenerated: false,
+        };
+        let mut files_removed = false;
+        for kernel in removable_kernels(&kernels, keep as usize) {
+            match package_owner(&kernel.image).await {
+                Ok(Some(package)) => {
+                    log::info!(
+                        "Kernel {} belongs to {package}, leaving it to the package manager",
+                        kernel.version
+                    );
+                    cleanup.packaged.push(KernelPackage {
+                        version: kernel.version.clone(),
+                        package,
+                    });
+                    continue;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    cleanup.failed.push(CleanupFailure {
+                        path: kernel.image.clone(),
+                        error: err.error().to_string(),
+                    });
+                    continue;
+                }
+            }
+
+            if dry_run {
+                cleanup.removed.push(kernel.clone());
+                continue;
+            }
+            // the rest of the files are removed even if one of them fails
+            let mut complete = true;
+            for file in kernel.files() {
+                log::info!("Removing old kernel file {file}");
+                match remove_file(&file) {
+                    Ok(()) => files_removed = true,
+                    Err(err) => {
+                        log::warn!("Cannot remove {file}: {err}");
+                        complete = false;
+                        cleanup.failed.push(CleanupFailure {
+                            path: file,
+                            error: err.to_string(),
+                        });
+                    }
+                }
+            }
+            if complete {
+                cleanup.removed.push(kernel.clone());
+            }
+        }
+
+        if files_removed {
+            match self.regenerate_grub_cfg(None).await {
+                Ok(output) => {
+                    self.save_selected_mkconfig_run(&output).await?;
+                    cleanup.regenerated = true;
+                }
+                Err(err) => cleanup.failed.push(CleanupFailure {
+                    path: GrubLayout::get().cfg_path.clone(),
+                    error: err.error().to_string(),
+                }),
+            }
+        }
 
-            let set_default = Command::new("grub2-set-default")
-                .arg(&kernel_entry)
-                .output()
-                .ctx(dctx!(), "Failed to read output from grub2-set-default")?;
+        serde_json::to_string(&cleanup).ctx(dctx!(), "Failed to serialize kernel cleanup")
+    }
 
-            log::debug!(
-                "grub2-set-default stdout: {}",
-                String::from_utf8_lossy(&set_default.stdout)
-            );
-            log::debug!(
-                "grub2-set-default stderr: {}",
-                String::from_utf8_lossy(&set_default.stderr)
-            );
+    /// Set or clear the entry that is booted only once on the next boot,
+    /// same as grub2-reboot
+    pub async fn set_next_entry(&self, data: &str) -> DResult<String> {
+        let next_data: NextEntryData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+        self.set_next_entry_to(next_data.entry.as_deref()).await
+    }
 
-            log::debug!("Calling grub2-set-default {kernel_entry}, done");
+    /// Set the entry that is booted once to `entry`, none clears it
+    pub async fn set_next_entry_to(&self, entry: Option<&str>) -> DResult<String> {
+        let ticket = self.queue.enqueue("SetNextEntry").await;
+        match self.bootloader {
+            BootloaderKind::SystemdBoot => {
+                let entry = SdBoot::read()?.entry_file(entry)?;
+                log::debug!("Setting {ENTRY_ONESHOT_VAR} to '{entry}'");
+                bootctl_set_entry("set-oneshot", &entry).await?;
+                return ticket.reply();
+            }
+            BootloaderKind::Auto | BootloaderKind::Grub2 => {}
+            _ => {
+                return Err(DError::generic(
+                    dctx!(),
+                    format!(
+                        "{} doesn't support booting an entry once",
+                        self.loader()?.name()
+                    ),
+                ))
+            }
+        }
 
-            // Only update grub file when selecting a snapshot
-            // old snapshots should always be set back the way they were
-            if !from_snapshot {
-                // make sure GRUB_DEFAULT is set to saved as it's required by grub
-                grub_file.set_key_value("GRUB_DEFAULT", "saved");
+        let env = EnvEditor::new(self.env_backend);
+        let mut warnings = Vec::new();
+        if let Some(entry) = entry {
+            let entries = GrubBootEntries::new()?;
+            let next_entry = entries.find(entry).ok_or_else(|| {
+                DError::generic(dctx!(), format!("Boot entry '{entry}' is not found"))
+            })?;
+
+            log::debug!("Setting next_entry to {}", next_entry.default_value());
+            env.set("next_entry", &next_entry.default_value()).await?;
+
+            // 00_header of grub2-mkconfig reads and clears next_entry, a grub.cfg
+            // written some other way may not
+            let cfg_path = &GrubLayout::get().cfg_path;
+            let cfg = read_to_string(cfg_path).ctx(dctx!(), format!("Cannot read {cfg_path}"))?;
+            if !cfg.contains("next_entry") {
+                warnings.push(format!(
+                    "{cfg_path} doesn't use next_entry so the entry is not booted once"
+                ));
             }
         } else {
-            log::debug!("Removing default seleceted kernel");
+            log::debug!("Clearing next_entry");
+            env.unset("next_entry").await?;
+        }
 
-            // grub2-editenv /boot/grub2/grubenv unset saved_entry
-            let edit_env = Command::new("grub2-editenv")
-                .arg("/boot/grub2/grubenv")
-                .arg("unset")
-                .arg("saved_entry")
-                .output()
-                .ctx(dctx!(), "Failed to read output from grub2-editenv")?;
-
-            log::debug!(
-                "grub2-edit-env stdout: {}",
-                String::from_utf8_lossy(&edit_env.stdout)
-            );
-            log::debug!(
-                "grub2-edit-env stderr: {}",
-                String::from_utf8_lossy(&edit_env.stderr)
-            );
+        ticket.reply_with_warnings(warnings)
+    }
 
-            log::debug!("Removing default seleceted kernel done");
-        }
+    /// Set or clear the default boot entry without regenerating the boot loader config,
+    /// same as grub2-set-default or bootctl set-default
+    pub async fn set_default_entry(&self, data: &str) -> DResult<String> {
+        let default_data: DefaultEntryData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
 
-        let file = grub_file.as_string();
+        let ticket = self.queue.enqueue("SetDefaultEntry").await;
+        match self.bootloader {
+            BootloaderKind::SystemdBoot => {
+                let entry = SdBoot::read()?.entry_file(default_data.entry.as_deref())?;
+                log::debug!("Setting {ENTRY_DEFAULT_VAR} to '{entry}'");
+                bootctl_set_entry("set-default", &entry).await?;
+                return ticket.reply();
+            }
+            BootloaderKind::Auto | BootloaderKind::Grub2 => {}
+            _ => {
+                let mut loader = self.loader()?;
+                let default_key = loader.default_key();
+                if let Some(entry) = &default_data.entry {
+                    let entry = loader.find(entry).ok_or_else(|| {
+                        DError::generic(dctx!(), format!("Boot entry '{entry}' is not found"))
+                    })?;
+                    let value = loader.default_value(&entry).ok_or_else(|| {
+                        DError::generic(
+                            dctx!(),
+                            format!(
+                                "Boot entry '{}' can't be the default entry of {}",
+                                entry.id,
+                                loader.name()
+                            ),
+                        )
+                    })?;
+                    log::debug!("Setting {default_key} to {value}");
+                    loader.set_value(default_key, &value);
+                } else {
+                    log::debug!("Removing {default_key}");
+                    loader.remove_value(default_key);
+                }
+                loader.write()?;
+                return ticket.reply();
+            }
+        }
 
-        // TODO: start a background thread that executes the grub config
-        //       and return an ID that the client can use to poll information
+        let env = EnvEditor::new(self.env_backend);
+        let mut warnings = Vec::new();
+        if let Some(entry) = &default_data.entry {
+            let entries = GrubBootEntries::new()?;
+            let default_entry = entries.find(entry).ok_or_else(|| {
+                DError::generic(dctx!(), format!("Boot entry '{entry}' is not found"))
+            })?;
 
-        // WARN: this triggers FileChanged signal
-        let mut grub = File::create(GRUB_FILE_PATH).ctx(
-            dctx!(),
-            format!("Failed to create grub config in path '{GRUB_FILE_PATH}'"),
-        )?;
-        write!(grub, "{}", file).ctx(
-            dctx!(),
-            format!("Failed override grub config in path '{GRUB_FILE_PATH}'"),
-        )?;
-        log::debug!("Grub2 config was written to {GRUB_FILE_PATH}");
+            log::debug!("Setting saved_entry to {}", default_entry.default_value());
+            env.set("saved_entry", &default_entry.default_value())
+                .await?;
 
-        log::debug!("Calling grub2-mkconfig -o /boot/grub2/grub.cfg");
-        let mkconfig_child = Command::new("grub2-mkconfig")
-            .arg("-o")
-            .arg("/boot/grub2/grub.cfg")
-            .output()
-            .ctx(dctx!(), "Failed to read output from grub2-mkconfig")?;
+            let config = GrubConfig::read(self.parse_mode)?;
+            if config.value("GRUB_DEFAULT") != Some("saved") {
+                warnings.push(
+                    "GRUB_DEFAULT is not 'saved' so grub doesn't boot the saved entry by default"
+                        .to_string(),
+                );
+            }
+        } else {
+            log::debug!("Clearing saved_entry");
+            env.unset("saved_entry").await?;
+        }
 
-        log::debug!(
-            "grub2-mkconfig stdout: {}",
-            String::from_utf8(mkconfig_child.stdout).unwrap()
-        );
-        log::debug!(
-            "grub2-mkconfig stderr: {}",
-            String::from_utf8(mkconfig_child.stderr).unwrap()
-        );
+        ticket.reply_with_warnings(warnings)
+    }
 
-        log::debug!("Calling grub2-mkconfig -o /boot/grub2/grub.cfg done");
+    /// Get the firmware boot manager entries that can be safely sent via dbus
+    pub fn get_firmware_entries_json(&self) -> DResult<String> {
+        let firmware = FirmwareBoot::read()?;
+        serde_json::to_string(&firmware).ctx(dctx!(), "Failed to serialize firmware entries")
+    }
 
-        Ok(())
+    /// Get the enrolled machine owner keys and pending MokManager requests
+    /// that can be safely sent via dbus
+    pub fn get_mok_status_json(&self) -> DResult<String> {
+        serde_json::to_string(&MokStatus::read()).ctx(dctx!(), "Failed to serialize MOK status")
     }
 
-    async fn _get_grub2_config(&self) -> DResult<ConfigData> {
-        let grub = GrubFile::from_file(GRUB_FILE_PATH)?;
-        let kernel_entries = GrubBootEntries::new()?;
-        let selected = self.db.selected_snapshot().await?;
-        let selected_grub = if let Some(id) = selected.grub2_snapshot_id {
-            self.db.grub2_snapshot(id).await?
-        } else {
-            self.db.latest_grub2().await?
-        };
+    /// Boot a firmware entry once on the next boot without changing BootOrder
+    pub async fn set_firmware_boot_next(&self, data: &str) -> DResult<String> {
+        let next_data: FirmwareBootNextData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
 
-        let diff = TextDiff::from_lines(&selected_grub.grub_config, &grub.as_string())
-            .unified_diff()
-            .to_string();
+        let ticket = self.queue.enqueue("SetFirmwareBootNext").await;
+        let firmware = FirmwareBoot::read()?;
+        let mut warnings = Vec::new();
+        if let Some(number) = next_data.entry {
+            let entry = firmware.find(number).ok_or_else(|| {
+                DError::invalid_values(
+                    dctx!(),
+                    vec![(
+                        "entry".into(),
+                        format!("Firmware boot entry Boot{number:04X} is not found"),
+                    )],
+                )
+            })?;
+            if !entry.active {
+                warnings.push(format!(
+                    "Boot{number:04X} '{}' is not active, the firmware can skip it",
+                    entry.description
+                ));
+            }
+        }
 
-        // TODO: add the potential difference in kernel entries to config_diff as well
-        let config_diff = if diff.is_empty() {
-            None
-        } else {
-            Some(Value::String(diff))
-        };
+        if let Some(previous) = firmware.next.filter(|next| Some(*next) != next_data.entry) {
+            warnings.push(format!("Replaced the pending BootNext Boot{previous:04X}"));
+        }
+        if firmware.next_not_removed && next_data.entry.is_some() {
+            warnings.push(
+                "The firmware didn't remove BootNext after the last boot, \
+                 remove it after booting the entry"
+                    .to_string(),
+            );
+        }
 
-        let value_map = serde_json::to_value(grub.keyvalues())
-            .ctx(dctx!(), "Cannot turn grub keyvalues into json")?;
-        let value_list =
-            serde_json::to_value(grub.lines()).ctx(dctx!(), "Cannot turn grub lines into json")?;
+        log::debug!("Setting BootNext to {:?}", next_data.entry);
+        set_boot_next(next_data.entry).await?;
 
-        Ok(ConfigData {
-            value_list,
-            value_map,
-            config_diff,
-            selected_kernel: kernel_entries.selected().map(str::to_string),
-        })
+        ticket.reply_with_warnings(warnings)
     }
 
-    /// Get grub config config (or the relevant error) that can be safely sent via dbus
-    pub async fn get_grub2_config_json(&self) -> DResult<String> {
-        let data = self._get_grub2_config().await?;
-        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize grub2 config")
+    /// Get the boot counting variables from grubenv that can be safely sent via dbus
+    pub async fn get_boot_status_json(&self) -> DResult<String> {
+        let grub_env = GrubEnv::from_file(&GrubLayout::get().env_path)?;
+        serde_json::to_string(&grub_env.boot_status())
+            .ctx(dctx!(), "Failed to serialize boot status")
     }
 
-    pub async fn save_grub2_config(&self, data: &str) -> DResult<String> {
-        let config: ConfigData = serde_json::from_str(data)
-            .ctx(dctx!(), "Malformed JSON data received from the client")?;
-        let value_list: Vec<GrubLine> = serde_json::from_value(config.value_list)
-            .ctx(dctx!(), "Cannot turn json into GrubLines")?;
-
-        let mut grub_file = GrubFile::from_lines(&value_list);
-        self.set_grub_system(&mut grub_file, &config.selected_kernel, false)
+    /// Mark the current boot successful in grubenv
+    pub async fn mark_boot_successful(&self) -> DResult<String> {
+        let ticket = self.queue.enqueue("MarkBootSuccessful").await;
+        EnvEditor::new(self.env_backend)
+            .mark_boot_successful()
             .await?;
+        log::debug!("Current boot was marked successful");
 
-        // if everything is okay, save the snapshot to a database
-        self.db
-            .save_grub2(&grub_file, config.selected_kernel)
-            .await?;
-        // latest snapshot should be null so it's assumed that latest snapshot is selected
-        self.db.set_selected_snapshot(None).await?;
+        ticket.reply()
+    }
 
-        Ok("ok".into())
+    /// Get grub2 boot entries that can be safely sent via dbus
+    pub async fn get_grub2_boot_entries_json(&self) -> DResult<String> {
+        let data = self._get_grub2_boot_entries(self.entry_sort).await?;
+        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize grub2 bootentries")
     }
 
-    async fn _get_grub2_boot_entries(&self) -> DResult<BootEntryData> {
-        let grub_entries = GrubBootEntries::new().ctx(dctx!(), "Couldn't read kernel entries")?;
-        let entries = serde_json::to_value(grub_entries.entry_names())
-            .ctx(dctx!(), "Cannot trun grub kernel entries into json")?;
-        let selected_kernel = serde_json::to_value(grub_entries.selected())
-            .ctx(dctx!(), "Cannot trun grub kernel entries into json")?;
+    /// Boot entries in the default order, for the typed D-Bus API
+    pub async fn get_entry_list(&self) -> DResult<Vec<EntryInfo>> {
+        let data = self._get_grub2_boot_entries(self.entry_sort).await?;
+        Ok(data.details.into_iter().map(EntryInfo::from).collect())
+    }
 
-        Ok(BootEntryData {
-            entries,
-            selected_kernel,
-        })
+    /// Boot entries in the menu order with the properties of their D-Bus objects
+    pub fn entry_properties(&self) -> DResult<Vec<EntryProperties>> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            let loader = self.loader()?;
+            let default = loader.selected().map(|entry| entry.id);
+            return Ok(loader
+                .entries()
+                .into_iter()
+                .map(|entry| EntryProperties {
+                    is_default: default.as_ref() == Some(&entry.id),
+                    title: entry.title,
+                    id: entry.id,
+                    kernel: entry.kernel.unwrap_or_default(),
+                })
+                .collect());
+        }
+
+        let entries = GrubBootEntries::new().ctx(dctx!(), "Couldn't read kernel entries")?;
+        Ok(entries
+            .entries()
+            .iter()
+            .map(|entry| EntryProperties {
+                title: entry.entry().into(),
+                id: entry.id().unwrap_or_default().into(),
+                kernel: entry.kernel().unwrap_or_default().into(),
+                is_default: entries.is_selected(entry),
+            })
+            .collect())
     }
 
-    /// Get grub2 boot entries that can be safely sent via dbus
-    pub async fn get_grub2_boot_entries_json(&self) -> DResult<String> {
-        let data = self._get_grub2_boot_entries().await?;
+    /// Get grub2 boot entries in the requested order that can be safely sent via dbus
+    pub async fn get_sorted_boot_entries_json(&self, data: &str) -> DResult<String> {
+        let sort_data: EntrySortData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+        let data = self._get_grub2_boot_entries(sort_data.sort).await?;
         serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize grub2 bootentries")
     }
 
+    /// Set kernel command line of a single boot entry.
+    ///
+    /// Only supported when grub reads the entries from BLS snippets, as the options
+    /// of generated menuentries are overwritten by grub2-mkconfig. Other boot loaders
+    /// edit the entries in their own config.
+    pub async fn set_entry_cmdline(&self, data: &str) -> DResult<String> {
+        let cmdline_data: EntryCmdlineData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        let ticket = self.queue.enqueue("SetEntryCmdline").await;
+        if self.bootloader != BootloaderKind::Grub2 {
+            let mut loader = self.loader()?;
+            let entry = loader.find(&cmdline_data.entry).ok_or_else(|| {
+                DError::generic(
+                    dctx!(),
+                    format!("Boot entry '{}' is not found", cmdline_data.entry),
+                )
+            })?;
+            loader.set_entry_cmdline(&entry.id, &cmdline_data.cmdline)?;
+            loader.write()?;
+            return ticket.reply();
+        }
+
+        self.check_grub_editable()?;
+        let grub = GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode)?;
+        if !grub.bls_enabled() {
+            return Err(DError::generic(
+                dctx!(),
+                "Per entry kernel command line requires GRUB_ENABLE_BLSCFG=true",
+            ));
+        }
+
+        let entries = SdBootEntries::from_dir(BLS_ENTRIES_PATH)?;
+        let mut entry = entries.find(&cmdline_data.entry).cloned().ok_or_else(|| {
+            DError::generic(
+                dctx!(),
+                format!("Boot entry '{}' is not found", cmdline_data.entry),
+            )
+        })?;
+
+        log::debug!(
+            "Changing options of BLS entry '{}' from '{}' to '{}'",
+            entry.id(),
+            entry.options(),
+            cmdline_data.cmdline
+        );
+        entry.set_options(&cmdline_data.cmdline);
+        entry.write(BLS_ENTRIES_PATH)?;
+
+        ticket.reply()
+    }
+
+    /// Set the device tree file or directory of a single boot entry.
+    ///
+    /// Only boot loaders used on boards that need a device tree, like U-Boot with
+    /// extlinux.conf, support this.
+    pub async fn set_entry_devicetree(&self, data: &str) -> DResult<String> {
+        let devicetree_data: EntryDevicetreeData =
+            serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+
+        let ticket = self.queue.enqueue("SetEntryDevicetree").await;
+        if self.bootloader == BootloaderKind::Grub2 {
+            return Err(DError::generic(
+                dctx!(),
+                "Setting the device tree is not supported with grub2",
+            ));
+        }
+
+        let mut loader = self.loader()?;
+        let entry = loader.find(&devicetree_data.entry).ok_or_else(|| {
+            DError::generic(
+                dctx!(),
+                format!("Boot entry '{}' is not found", devicetree_data.entry),
+            )
+        })?;
+        loader.set_entry_devicetree(
+            &entry.id,
+            devicetree_data.devicetree.as_deref(),
+            devicetree_data.devicetree_dir.as_deref(),
+        )?;
+        loader.write()?;
+
+        ticket.reply()
+    }
+
     /// Get snapshots that can be safely sent via dbus
     async fn _get_snapshots(&self) -> DResult<SnapshotData> {
         let db_snapshots = self.db.grub2_snapshots().await?;
+        self.snapshot_data(db_snapshots).await
+    }
+
+    /// `db_snapshots` with their diffs and mkconfig runs, and the selected snapshot
+    async fn snapshot_data(&self, db_snapshots: Vec<Grub2Snapshot>) -> DResult<SnapshotData> {
         let selected = self.db.selected_snapshot().await?;
-        let grub = GrubFile::from_file(GRUB_FILE_PATH).ctx(dctx!(), "Failed to read grub file")?;
+        let grub = GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode)
+            .ctx(dctx!(), "Failed to read grub file")?;
         let current = grub.as_string();
+
+        // runs are ordered from the newest to the oldest so keep the first one
+        let mut latest_runs: HashMap<i64, MkconfigRun> = HashMap::new();
+        for run in self.db.mkconfig_runs().await? {
+            if let Some(id) = run.grub2_snapshot_id {
+                latest_runs.entry(id).or_insert(run);
+            }
+        }
+
         let snapshots: Vec<Grub2SnapshotData> = db_snapshots
             .into_iter()
             .map(|snapshot| {
-                let diff = TextDiff::from_lines(&current, &snapshot.grub_config)
-                    .unified_diff()
-                    .to_string();
-
-                let diff = if diff.trim().is_empty() {
-                    None
-                } else {
-                    Some(diff)
-                };
-
-                Grub2SnapshotData { snapshot, diff }
+                let mkconfig = latest_runs.remove(&snapshot.id);
+                Grub2SnapshotData::new(&grub, &current, snapshot, mkconfig)
             })
             .collect();
 
@@ -281,73 +2944,431 @@ impl DbusHandler {
         serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize snapshots")
     }
 
+    /// Page of the snapshots that match the filter, see [`SnapshotFilter::new`]
+    pub async fn query_snapshots_json(
+        &self,
+        offset: u32,
+        limit: u32,
+        origin: &str,
+        since: &str,
+        before: &str,
+    ) -> DResult<String> {
+        let filter = SnapshotFilter::new(origin, since, before)?;
+        let (db_snapshots, total) = self.db.grub2_snapshot_page(&filter, offset, limit).await?;
+        let data = SnapshotPageData {
+            data: self.snapshot_data(db_snapshots).await?,
+            total,
+            offset,
+        };
+        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize snapshots")
+    }
+
+    /// Get the snapshot with `id` that can be safely sent via dbus
+    pub async fn get_snapshot_json(&self, id: i64) -> DResult<String> {
+        let snapshot = self.db.grub2_snapshot(id).await?;
+        self.snapshot_json(snapshot).await
+    }
+
+    /// Get the snapshot tagged with `tag` that can be safely sent via dbus
+    pub async fn get_snapshot_by_tag_json(&self, tag: &str) -> DResult<String> {
+        let snapshot = self.db.grub2_snapshot_by_tag(tag).await?.ok_or_else(|| {
+            DError::generic(dctx!(), format!("Snapshot with tag '{tag}' is not found"))
+        })?;
+        self.snapshot_json(snapshot).await
+    }
+
+    /// Get the latest snapshot taken while the root filesystem was mounted from
+    /// the snapper snapshot `number`, so the config can be restored after a rollback
+    pub async fn get_snapshot_by_snapper_json(&self, number: i64) -> DResult<String> {
+        let snapshot = self
+            .db
+            .grub2_snapshot_by_snapper(number)
+            .await?
+            .ok_or_else(|| {
+                DError::generic(
+                    dctx!(),
+                    format!("No snapshot was taken in snapper snapshot {number}"),
+                )
+            })?;
+        self.snapshot_json(snapshot).await
+    }
+
+    /// Config snapshot ids by the snapper snapshot they were taken in
+    pub async fn snapper_snapshot_map(&self) -> DResult<BTreeMap<i64, Vec<i64>>> {
+        self.db.snapper_snapshot_map().await
+    }
+
+    async fn snapshot_json(&self, snapshot: Grub2Snapshot) -> DResult<String> {
+        snapshot.verify()?;
+        let id = snapshot.id;
+        let grub = GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode)
+            .ctx(dctx!(), "Failed to read grub file")?;
+        let current = grub.as_string();
+        // runs are ordered from the newest to the oldest
+        let mkconfig = self
+            .db
+            .mkconfig_runs()
+            .await?
+            .into_iter()
+            .find(|run| run.grub2_snapshot_id == Some(id));
+
+        let data = Grub2SnapshotData::new(&grub, &current, snapshot, mkconfig);
+        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize snapshot")
+    }
+
+    /// Changes from the snapshot `from` to the snapshot `to`, where
+    /// [`CURRENT_CONFIG_ID`] is the current config
+    pub async fn snapshot_diff_json(&self, from: i64, to: i64) -> DResult<String> {
+        let (from_label, from_config) = self.snapshot_config(from).await?;
+        let (to_label, to_config) = self.snapshot_config(to).await?;
+
+        let data = SnapshotDiffData::new(
+            (from, &from_label, &from_config),
+            (to, &to_label, &to_config),
+        )?;
+        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize snapshot diff")
+    }
+
+    /// Label used in the diff headers and the grub config of the snapshot `id`
+    async fn snapshot_config(&self, id: i64) -> DResult<(String, String)> {
+        if id == CURRENT_CONFIG_ID {
+            let grub = GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode)
+                .ctx(dctx!(), "Failed to read grub file")?;
+            Ok((GRUB_FILE_PATH.to_string(), grub.as_string()))
+        } else {
+            let snapshot = self.db.grub2_snapshot(id).await?;
+            snapshot.verify()?;
+            Ok((format!("snapshot {id}"), snapshot.grub_config))
+        }
+    }
+
+    /// Check all the snapshots against their content hashes, the database is on
+    /// the same disk as the configs so it can be damaged the same way
+    pub async fn verify_snapshots_json(&self) -> DResult<String> {
+        let results = self.db.verify_grub2_snapshots().await?;
+        let mut data = SnapshotVerifyData {
+            checked: results.len(),
+            unhashed: Vec::new(),
+            corrupted: Vec::new(),
+        };
+        for (id, integrity) in results {
+            match integrity {
+                SnapshotIntegrity::Intact => {}
+                SnapshotIntegrity::Unhashed => data.unhashed.push(id),
+                SnapshotIntegrity::Corrupted(error) => {
+                    log::warn!("Snapshot {id} is corrupted: {error}");
+                    data.corrupted.push(CorruptedSnapshot { id, error });
+                }
+            }
+        }
+        serde_json::to_string(&data).ctx(dctx!(), "Failed to serialize snapshot verification")
+    }
+
+    /// Save /etc/default/grub as a snapshot if it was changed by something else
+    /// than bootkit, so that the change shows up in the history. Returns true if
+    /// a snapshot was taken
+    pub async fn snapshot_external_change(&self) -> DResult<bool> {
+        if self.bootloader != BootloaderKind::Grub2 {
+            return Ok(false);
+        }
+        // a write by bootkit itself isn't taken as an external change halfway
+        let _ticket = self.queue.enqueue("SnapshotExternalChange").await;
+        let grub_file = GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode)?;
+        let selected = match self.db.selected_snapshot().await?.grub2_snapshot_id {
+            Some(id) => Some(self.db.grub2_snapshot(id).await?),
+            None => self.db.latest_grub2().await?,
+        };
+        if selected.is_some_and(|selected| selected.grub_config == grub_file.as_string()) {
+            return Ok(false);
+        }
+
+        let selected_kernel = GrubBootEntries::new()
+            .ok()
+            .and_then(|entries| entries.selected().map(str::to_string));
+        self.db
+            .save_grub2(
+                &grub_file,
+                selected_kernel,
+                None,
+                SnapshotOrigin::External,
+                None,
+                None,
+            )
+            .await?;
+        self.db.set_selected_snapshot(None).await?;
+        Ok(true)
+    }
+
+    /// Save the current /etc/default/grub as a manual snapshot tagged with `tag`,
+    /// even if it's the same as the latest snapshot. Returns the snapshot id
+    pub async fn create_tagged_snapshot(&self, tag: &str, comment: &str) -> DResult<i64> {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return Err(DError::generic(dctx!(), "Snapshot tag cannot be empty"));
+        }
+
+        let _ticket = self.queue.enqueue("CreateSnapshot").await;
+        if let Some(snapshot) = self.db.grub2_snapshot_by_tag(tag).await? {
+            return Err(DError::conflict(
+                dctx!(),
+                format!("Snapshot {} is already tagged with '{tag}'", snapshot.id),
+            ));
+        }
+
+        let grub_file = GrubFile::from_file(GRUB_FILE_PATH, self.parse_mode)?;
+        let selected_kernel = GrubBootEntries::new()
+            .ok()
+            .and_then(|entries| entries.selected().map(str::to_string));
+        let comment = (!comment.is_empty()).then_some(comment);
+        let id = self
+            .db
+            .save_grub2(
+                &grub_file,
+                selected_kernel,
+                Caller::current().as_ref(),
+                SnapshotOrigin::Manual,
+                comment,
+                Some(tag),
+            )
+            .await?;
+
+        log::debug!("Created snapshot {id} with tag '{tag}'");
+        Ok(id)
+    }
+
     pub async fn remove_snapshot(&self, data: &str) -> DResult<String> {
         let rm_data: RemoveSnapshotData =
             serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+        self.delete_snapshot(rm_data.snapshot_id).await?;
+        Ok("ok".into())
+    }
 
-        log::debug!("Trying to remove snapshot with id {}", rm_data.snapshot_id);
+    /// Remove the snapshot with `id`, the selected snapshot can't be removed
+    pub async fn delete_snapshot(&self, id: i64) -> DResult<()> {
+        log::debug!("Trying to remove snapshot with id {id}");
+        let _ticket = self.queue.enqueue("RemoveSnapshot").await;
 
         // Don't allow deleting the selected snapshot so things don't get confusing
         let selected = self.db.selected_snapshot().await?;
-        let selected_id = if let Some(id) = selected.grub2_snapshot_id {
-            id
-        } else {
-            self.db.latest_grub2().await?.id
+        let selected_id = match selected.grub2_snapshot_id {
+            Some(id) => Some(id),
+            None => self.db.latest_grub2().await?.map(|latest| latest.id),
         };
 
-        if rm_data.snapshot_id == selected_id {
+        if Some(id) == selected_id {
             return Err(DError::generic(
                 dctx!(),
                 "Cannot remove currently selected snapshot",
             ));
         }
 
-        self.db.remove_grub2(rm_data.snapshot_id).await?;
+        self.db.remove_grub2(id).await?;
 
-        log::debug!(
-            "Succesfully removed snapshot with id {}",
-            rm_data.snapshot_id
-        );
-        Ok("ok".into())
+        log::debug!("Succesfully removed snapshot with id {id}");
+        Ok(())
     }
 
     pub async fn select_snapshot(&self, data: &str) -> DResult<String> {
         let select_data: SelectSnapshotData =
             serde_json::from_str(data).ctx(dctx!(), "Malformed JSON data received from client")?;
+        self.restore_snapshot(select_data.snapshot_id, true).await
+    }
 
-        log::debug!(
-            "Trying to select snapshot with id {}",
-            select_data.snapshot_id
-        );
+    /// Write the snapshot with `id` to the system and select it. The current
+    /// config is saved as a pre-restore snapshot first, unless it's the same as
+    /// the selected snapshot. grubenv variables are restored when the snapshot has
+    /// them. grub.cfg is regenerated only if `regenerate` is set
+    pub async fn restore_snapshot(&self, id: i64, regenerate: bool) -> DResult<String> {
+        self.check_grub_editable()?;
+        log::debug!("Trying to select snapshot with id {id}");
 
+        let ticket = self.queue.enqueue("SelectSnapshot").await;
         // Don't allow reselecting the selected snapshot so things don't get confusing
         let selected = self.db.selected_snapshot().await?;
-        let selected_id = if let Some(id) = selected.grub2_snapshot_id {
-            id
-        } else {
-            self.db.latest_grub2().await?.id
+        let selected_id = match selected.grub2_snapshot_id {
+            Some(id) => Some(id),
+            None => self.db.latest_grub2().await?.map(|latest| latest.id),
         };
 
-        if select_data.snapshot_id == selected_id {
+        if Some(id) == selected_id {
             return Err(DError::generic(
                 dctx!(),
                 "Cannot reselect currently selected snapshot",
             ));
         }
 
-        let snapshot = self.db.grub2_snapshot(select_data.snapshot_id).await?;
+        let snapshot = self.db.grub2_snapshot(id).await?;
+        snapshot.verify()?;
         let mut grub_file = GrubFile::new(&snapshot.grub_config)?;
-        self.set_grub_system(&mut grub_file, &snapshot.selected_kernel, true)
-            .await?;
-        self.db
-            .set_selected_snapshot(Some(select_data.snapshot_id))
+
+        let selected_config = match selected_id {
+            Some(selected_id) => self.db.grub2_snapshot(selected_id).await?.grub_config,
+            None => String::new(),
+        };
+        self.snapshot_current_config(SnapshotOrigin::PreRestore, &selected_config)
             .await?;
 
-        log::debug!(
-            "Succesfully selected snapshot with id {}",
-            select_data.snapshot_id
+        if regenerate {
+            let output = self
+                .set_grub_system(&mut grub_file, &snapshot.selected_kernel, true)
+                .await?;
+            self.db.save_mkconfig_run(Some(id), &output).await?;
+        } else {
+            self.set_selected_kernel(&mut grub_file, &snapshot.selected_kernel, true)
+                .await?;
+            Self::write_grub_file(&grub_file.as_string())?;
+        }
+        // saved_entry was already set from the selected kernel that's looked up
+        // from the current grub.cfg, the entry may have moved since the snapshot
+        if let Some(grubenv) = &snapshot.grubenv {
+            EnvEditor::new(self.env_backend)
+                .restore(&GrubEnv::new(grubenv), &["saved_entry"])
+                .await?;
+        }
+        self.db.set_selected_snapshot(Some(id)).await?;
+
+        log::debug!("Succesfully selected snapshot with id {id}");
+
+        ticket.reply()
+    }
+}
+
+/// Config properties as the grub config sets them, the default entry is left
+/// as `saved` when it's read from grubenv
+fn grub_config_properties(config: &GrubConfig) -> ConfigProperties {
+    let value = |key| config.value(key).unwrap_or_default().to_string();
+    ConfigProperties {
+        default_entry: value("GRUB_DEFAULT"),
+        timeout: value("GRUB_TIMEOUT"),
+        kernel_cmdline: [value("GRUB_CMDLINE_LINUX"), value(RESUME_CMDLINE)]
+            .into_iter()
+            .filter(|cmdline| !cmdline.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+/// Values of all the keys in `grub_file`
+fn grub_file_values(grub_file: &GrubFile) -> BTreeMap<String, String> {
+    grub_file
+        .keyvalues()
+        .iter()
+        .map(|(key, keyval)| (key.clone(), keyval.value.clone()))
+        .collect()
+}
+
+/// Make the keys of `grub_file` match `values`, the keys missing from `values`
+/// are removed and the unchanged ones are left as they are
+fn replace_key_values(grub_file: &mut GrubFile, values: &BTreeMap<String, String>) {
+    let removed: Vec<String> = grub_file
+        .keyvalues()
+        .keys()
+        .filter(|key| !values.contains_key(*key))
+        .cloned()
+        .collect();
+    for key in removed {
+        log::debug!("Removing {key} from {GRUB_FILE_PATH}");
+        grub_file.remove_key(&key, RemoveMode::Delete);
+    }
+    for (key, value) in values {
+        if grub_file.keyvalues().get(key).map(|keyval| &keyval.value) != Some(value) {
+            log::debug!("Setting {key} to '{value}' in {GRUB_FILE_PATH}");
+            grub_file.set_key_value(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_state_twice() {
+        let mut config = GrubConfig::from_paths(
+            "test_data/grub_simple",
+            "test_data/grub.d",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        let state: DesiredState = serde_json::from_str(
+            r#"{
+                "keys": {"GRUB_DISABLE_RECOVERY": "true", "GRUB_HIDDEN_TIMEOUT_QUIET": null},
+                "timeout": 5,
+                "cmdline": {"present": ["console=tty1", "console=ttyS0"], "absent": ["quiet"]}
+            }"#,
+        )
+        .unwrap();
+
+        let changes = state_key_changes(&config, &state);
+        assert_eq!(changes.len(), 4);
+        apply_key_changes(&mut config, &changes);
+        assert_eq!(config.value("GRUB_TIMEOUT"), Some("5"));
+        assert_eq!(config.value("GRUB_HIDDEN_TIMEOUT_QUIET"), None);
+        assert_eq!(
+            config.value("GRUB_CMDLINE_LINUX_DEFAULT"),
+            Some("console=tty1 console=ttyS0")
         );
 
-        Ok("ok".into())
+        // the same state again changes nothing
+        assert!(state_key_changes(&config, &state).is_empty());
+    }
+
+    #[test]
+    fn test_config_values() {
+        let mut grub_file =
+            GrubFile::from_file("test_data/grub_simple", ParseMode::Strict).unwrap();
+        let mut values = grub_file_values(&grub_file);
+        assert_eq!(values.get("GRUB_TIMEOUT").map(String::as_str), Some("8"));
+        assert_eq!(values.get("GRUB_DISTRIBUTOR").map(String::as_str), Some(""));
+
+        // saving the values that were read changes nothing
+        let original = grub_file.as_string();
+        replace_key_values(&mut grub_file, &values);
+        assert_eq!(grub_file.as_string(), original);
+
+        values.remove("GRUB_HIDDEN_TIMEOUT_QUIET");
+        values.insert("GRUB_TIMEOUT".into(), "5".into());
+        values.insert("GRUB_DISABLE_RECOVERY".into(), "true".into());
+        replace_key_values(&mut grub_file, &values);
+        assert_eq!(grub_file_values(&grub_file), values);
+    }
+
+    #[test]
+    fn test_grub_config_properties() {
+        let mut config = GrubConfig::from_paths(
+            "test_data/grub_simple",
+            "test_data/grub.d",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        let properties = grub_config_properties(&config);
+        assert_eq!(properties.default_entry, "saved");
+        assert_eq!(properties.timeout, "3");
+        assert_eq!(properties.kernel_cmdline, "quiet");
+
+        config.set_key_value("GRUB_CMDLINE_LINUX", "console=ttyS0");
+        config.set_key_value("GRUB_DEFAULT", "1");
+        let properties = grub_config_properties(&config);
+        assert_eq!(properties.default_entry, "1");
+        assert_eq!(properties.kernel_cmdline, "console=ttyS0 quiet");
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let from = "GRUB_DEFAULT=saved\nGRUB_TIMEOUT=8\n";
+        let data = SnapshotDiffData::new((1, "snapshot 1", from), (2, "snapshot 2", from)).unwrap();
+        assert_eq!(data.diff, None);
+        assert!(data.key_changes.is_empty());
+
+        let to = "GRUB_DEFAULT=saved\nGRUB_TIMEOUT=5\nGRUB_DISABLE_RECOVERY=true\n";
+        let data = SnapshotDiffData::new(
+            (1, "snapshot 1", from),
+            (CURRENT_CONFIG_ID, GRUB_FILE_PATH, to),
+        )
+        .unwrap();
+        let diff = data.diff.unwrap();
+        assert!(diff.starts_with(&format!("--- snapshot 1\n+++ {GRUB_FILE_PATH}\n")));
+        assert!(diff.contains("-GRUB_TIMEOUT=8\n+GRUB_TIMEOUT=5\n"));
+        assert_eq!(data.key_changes.len(), 2);
     }
 }
@@ -0,0 +1,238 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::{mpsc, Mutex};
+use zbus::{interface, object_server::SignalEmitter, zvariant::OwnedObjectPath, Connection};
+
+use crate::{
+    dbus::handler::DbusHandler,
+    dctx,
+    errors::{DError, DErrorType, DRes},
+    system::caller::Caller,
+};
+
+const JOBS_PATH: &str = "/org/opensuse/bootkit/jobs";
+/// Finished jobs are kept for the clients that missed their signals,
+/// the oldest ones are removed after this many
+const MAX_FINISHED_JOBS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobStatus {
+    Running,
+    Finished,
+    Failed,
+}
+
+impl JobStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Running => "running",
+            JobStatus::Finished => "finished",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Long running write, like regenerating grub.cfg, that clients follow through
+/// its own object instead of waiting for the method call to return
+pub struct Job {
+    name: String,
+    status: JobStatus,
+    /// Progress lines reported so far
+    log: Vec<String>,
+    /// JSON of the captured output once the job is finished
+    output: String,
+    error: String,
+    error_code: String,
+}
+
+#[interface(name = "org.opensuse.bootkit.Job")]
+impl Job {
+    #[zbus(property)]
+    async fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// `running`, `finished` or `failed`
+    #[zbus(property)]
+    async fn status(&self) -> String {
+        self.status.as_str().to_string()
+    }
+
+    /// Progress lines reported so far, each one is also sent with the Progress signal
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn log(&self) -> Vec<String> {
+        self.log.clone()
+    }
+
+    /// Captured output of the command as JSON, empty until the job is finished
+    #[zbus(property)]
+    async fn output(&self) -> String {
+        self.output.clone()
+    }
+
+    /// Empty unless the job failed
+    #[zbus(property)]
+    async fn error(&self) -> String {
+        self.error.clone()
+    }
+
+    /// Stable code of the error, like `ERR_CONFLICT`. Empty unless the job failed
+    #[zbus(property)]
+    async fn error_code(&self) -> String {
+        self.error_code.clone()
+    }
+
+    #[zbus(signal)]
+    async fn progress(emitter: &SignalEmitter<'_>, line: &str) -> zbus::Result<()>;
+
+    /// Output is the same JSON as the Output property
+    #[zbus(signal)]
+    async fn finished(emitter: &SignalEmitter<'_>, output: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn failed(emitter: &SignalEmitter<'_>, error: &str) -> zbus::Result<()>;
+}
+
+impl Job {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            status: JobStatus::Running,
+            log: Vec::new(),
+            output: String::new(),
+            error: String::new(),
+            error_code: String::new(),
+        }
+    }
+
+    /// Send the changed properties and Finished or Failed of a job that's done
+    async fn signal_result(&self, emitter: &SignalEmitter<'_>) -> zbus::Result<()> {
+        self.status_changed(emitter).await?;
+        match self.status {
+            JobStatus::Failed => {
+                self.error_changed(emitter).await?;
+                self.error_code_changed(emitter).await?;
+                Job::failed(emitter, &self.error).await
+            }
+            _ => {
+                self.output_changed(emitter).await?;
+                Job::finished(emitter, &self.output).await
+            }
+        }
+    }
+}
+
+/// Job objects served under /org/opensuse/bootkit/jobs
+#[derive(Clone, Default)]
+pub struct JobList {
+    next_id: Arc<AtomicU64>,
+    finished: Arc<Mutex<VecDeque<OwnedObjectPath>>>,
+}
+
+impl JobList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve a new job object and regenerate grub.cfg in the background.
+    /// Returns the path of the job object
+    pub async fn start_regenerate_config(
+        &self,
+        connection: &Connection,
+        handler: DbusHandler,
+    ) -> zbus::Result<OwnedObjectPath> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let path = OwnedObjectPath::try_from(format!("{JOBS_PATH}/{id}"))?;
+        connection
+            .object_server()
+            .at(&path, Job::new("RegenerateConfig"))
+            .await?;
+        log::debug!("Started job {path}");
+
+        let jobs = self.clone();
+        let connection = connection.clone();
+        let job_path = path.clone();
+        // the job outlives the method call so the caller is passed on explicitly
+        let caller = Caller::current().unwrap_or_default();
+        tokio::spawn(async move {
+            if let Err(err) = jobs
+                .run_regenerate_config(&connection, &job_path, handler, caller)
+                .await
+            {
+                log::error!("Cannot report the state of job {job_path}: {err}");
+            }
+        });
+
+        Ok(path)
+    }
+
+    /// Run the job to the end. The job is always left finished or failed, failing
+    /// to signal the clients is only reported after its state is set
+    async fn run_regenerate_config(
+        &self,
+        connection: &Connection,
+        path: &OwnedObjectPath,
+        handler: DbusHandler,
+        caller: Caller,
+    ) -> zbus::Result<()> {
+        let job = connection.object_server().interface::<_, Job>(path).await?;
+
+        let (progress, mut lines) = mpsc::unbounded_channel();
+        let task = tokio::spawn(caller.scope(async move {
+            handler
+                .regenerate_config_with_progress(Some(progress))
+                .await
+        }));
+        // the channel is closed when the task is done with the progress sender
+        while let Some(line) = lines.recv().await {
+            job.get_mut().await.log.push(line.clone());
+            if let Err(err) = Job::progress(job.signal_emitter(), &line).await {
+                log::warn!("Cannot send the progress of job {path}: {err}");
+            }
+        }
+
+        let result = match task.await {
+            Ok(result) => result.and_then(|output| {
+                serde_json::to_string(&output).ctx(dctx!(), "Failed to serialize mkconfig output")
+            }),
+            Err(err) => Err(DError::new(
+                dctx!(),
+                DErrorType::JoinError("Regenerating grub.cfg stopped".into(), Box::new(err)),
+            )),
+        };
+
+        {
+            let mut state = job.get_mut().await;
+            match &result {
+                Ok(output) => {
+                    state.status = JobStatus::Finished;
+                    state.output = output.clone();
+                }
+                Err(err) => {
+                    state.status = JobStatus::Failed;
+                    state.error = err.error().as_string();
+                    state.error_code = err.error().code().to_string();
+                }
+            }
+        }
+        if let Err(err) = job.get().await.signal_result(job.signal_emitter()).await {
+            log::warn!("Cannot signal the result of job {path}: {err}");
+        }
+
+        let mut finished = self.finished.lock().await;
+        finished.push_back(path.clone());
+        while finished.len() > MAX_FINISHED_JOBS {
+            if let Some(old) = finished.pop_front() {
+                connection.object_server().remove::<Job, _>(&old).await?;
+                log::debug!("Removed job {old}");
+            }
+        }
+        Ok(())
+    }
+}
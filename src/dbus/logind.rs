@@ -0,0 +1,92 @@
+use zbus::{proxy, Connection};
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+/// Inhibitor lock as ListInhibitors returns it: what, who, why, mode, uid and pid
+type Inhibitor = (String, String, String, String, u32, u32);
+
+#[proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait Manager {
+    fn reboot(&self, interactive: bool) -> zbus::Result<()>;
+    fn can_reboot(&self) -> zbus::Result<String>;
+    fn list_inhibitors(&self) -> zbus::Result<Vec<Inhibitor>>;
+}
+
+/// Check that logind would reboot the machine right now. logind lets the daemon
+/// override the inhibitors since it runs as root, so the ones that block
+/// shutdown are honored here instead
+pub async fn check_reboot(connection: &Connection) -> DResult<()> {
+    let manager = ManagerProxy::new(connection)
+        .await
+        .ctx(dctx!(), "Cannot connect to logind")?;
+    let can_reboot = manager
+        .can_reboot()
+        .await
+        .ctx(dctx!(), "Cannot ask logind whether the machine can reboot")?;
+    if !matches!(can_reboot.as_str(), "yes" | "challenge") {
+        return Err(DError::generic(
+            dctx!(),
+            format!("logind doesn't allow rebooting: {can_reboot}"),
+        ));
+    }
+
+    let inhibitors = manager
+        .list_inhibitors()
+        .await
+        .ctx(dctx!(), "Cannot list the logind inhibitors")?;
+    if let Some((_, who, why, ..)) = blocking_inhibitor(&inhibitors) {
+        return Err(DError::conflict(
+            dctx!(),
+            format!("Reboot is inhibited by {who}: {why}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Ask logind to reboot the machine. The caller has to be authorized for
+/// [`crate::dbus::polkit::REBOOT`] and [`check_reboot`] called first since
+/// logind only sees the daemon
+pub async fn reboot(connection: &Connection) -> zbus::Result<()> {
+    log::info!("Rebooting through logind");
+    ManagerProxy::new(connection).await?.reboot(false).await
+}
+
+/// First inhibitor that blocks shutdown and reboot, delay locks only postpone them
+fn blocking_inhibitor(inhibitors: &[Inhibitor]) -> Option<&Inhibitor> {
+    inhibitors
+        .iter()
+        .find(|(what, _, _, mode, ..)| mode == "block" && what.split(':').any(|w| w == "shutdown"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inhibitor(what: &str, mode: &str) -> Inhibitor {
+        (what.into(), "who".into(), "why".into(), mode.into(), 0, 1)
+    }
+
+    #[test]
+    fn test_blocking_inhibitor() {
+        assert!(blocking_inhibitor(&[]).is_none());
+        assert!(blocking_inhibitor(&[
+            inhibitor("shutdown:sleep", "delay"),
+            inhibitor("sleep:idle", "block"),
+            inhibitor("handle-power-key", "block"),
+        ])
+        .is_none());
+
+        let inhibitors = [
+            inhibitor("sleep", "block"),
+            inhibitor("sleep:shutdown", "block"),
+        ];
+        assert_eq!(blocking_inhibitor(&inhibitors), Some(&inhibitors[1]));
+    }
+}
@@ -1,2 +1,13 @@
+mod api;
 pub mod connection;
+mod edit;
+pub mod entries;
 mod handler;
+mod job;
+mod logind;
+pub mod observer;
+mod polkit;
+mod queue;
+mod ratelimit;
+pub mod status;
+mod watch;
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use zbus::{interface, message::Header, object_server::SignalEmitter, Connection};
+
+use crate::{
+    dbus::{
+        handler::{DbusHandler, EntryInfo},
+        ratelimit::RateLimiter,
+        watch::{KeyWatches, WatchError},
+    },
+    errors::{codes, BusError},
+};
+
+pub const OBSERVER_PATH: &str = "/org/opensuse/bootkit/observer";
+
+/// Read only view of the boot configuration for unprivileged clients like desktop
+/// applets. It has no mutating methods so the D-Bus policy can allow it to anyone
+/// even when the admin interfaces are limited
+pub struct BootKitObserver {
+    handler: DbusHandler,
+    entries_limit: RateLimiter,
+    watches: KeyWatches,
+}
+
+impl BootKitObserver {
+    pub fn new(handler: DbusHandler) -> Self {
+        Self {
+            handler,
+            entries_limit: RateLimiter::new("Observer GetEntryList", 20, 5.0),
+            watches: KeyWatches::new(),
+        }
+    }
+
+    /// Keys the clients watch with WatchKeys
+    pub fn watches(&self) -> KeyWatches {
+        self.watches.clone()
+    }
+}
+
+#[interface(name = "org.opensuse.bootkit.Observer")]
+impl BootKitObserver {
+    /// Same as the DefaultEntry property of the Config interface
+    async fn get_default_entry(&self) -> String {
+        log::debug!("Calling org.opensuse.bootkit.Observer GetDefaultEntry");
+        self.handler.config_properties().default_entry
+    }
+
+    async fn get_entry_list(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> Result<Vec<EntryInfo>, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Observer GetEntryList");
+        self.entries_limit.check(connection, &header).await?;
+        let data = self.handler.get_entry_list().await?;
+        Ok(data)
+    }
+
+    async fn get_config_values(&self) -> Result<BTreeMap<String, String>, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Observer GetConfigValues");
+        let data = self.handler.get_config_values()?;
+        Ok(data)
+    }
+
+    async fn get_pending_changes(&self) -> Result<String, BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Observer GetPendingChanges");
+        let data = self.handler.get_pending_changes_json().await?;
+        Ok(data)
+    }
+
+    /// Send KeyChanged to the caller when any of `keys` of /etc/default/grub or
+    /// grubenv changes. The keys have to be in the key schema or grubenv variables.
+    /// The keys are forgotten when the caller disconnects
+    async fn watch_keys(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        keys: Vec<String>,
+    ) -> Result<(), BusError> {
+        log::debug!("Calling org.opensuse.bootkit.Observer WatchKeys");
+        let Some(sender) = header.sender() else {
+            return Err(BusError::Failed(
+                codes::FAILED,
+                "WatchKeys needs a sender".into(),
+            ));
+        };
+        match self.watches.watch(sender, &keys) {
+            Ok(()) => Ok(()),
+            Err(err @ WatchError::TooManyClients) => Err(BusError::RateLimited(err.to_string())),
+            Err(err) => Err(BusError::InvalidValues(err.to_string())),
+        }
+    }
+
+    /// Stop sending KeyChanged for `keys`, or for any key if `keys` is empty
+    async fn unwatch_keys(&self, #[zbus(header)] header: Header<'_>, keys: Vec<String>) {
+        log::debug!("Calling org.opensuse.bootkit.Observer UnwatchKeys");
+        if let Some(sender) = header.sender() {
+            self.watches.unwatch(sender, &keys);
+        }
+    }
+
+    /// Signal for a key that the receiver watches being changed, provided by zbus
+    /// macro. Sent only to the clients that called WatchKeys with the key. Empty
+    /// `old` or `new` means that the key was added or removed
+    #[zbus(signal)]
+    pub async fn key_changed(
+        emitter: &SignalEmitter<'_>,
+        key: &str,
+        old: &str,
+        new: &str,
+    ) -> zbus::Result<()>;
+
+    /// Same as FileChanged of the Config interface, provided by zbus macro
+    #[zbus(signal)]
+    async fn file_changed(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    /// Same as FileChangedDetailed of the Config interface, provided by zbus macro
+    #[zbus(signal)]
+    async fn file_changed_detailed(
+        emitter: &SignalEmitter<'_>,
+        file: &str,
+        keys: Vec<String>,
+    ) -> zbus::Result<()>;
+
+    /// Same as BootEntriesChanged of the BootEntry interface, provided by zbus macro
+    #[zbus(signal)]
+    async fn boot_entries_changed(
+        emitter: &SignalEmitter<'_>,
+        added: Vec<String>,
+        removed: Vec<String>,
+    ) -> zbus::Result<()>;
+
+    /// Same as DefaultEntryChanged of the BootEntry interface, provided by zbus macro
+    #[zbus(signal)]
+    async fn default_entry_changed(
+        emitter: &SignalEmitter<'_>,
+        old: &str,
+        new: &str,
+    ) -> zbus::Result<()>;
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use zbus::{
+    fdo,
+    message::{Flags, Header},
+    proxy,
+    zvariant::Value,
+    Connection,
+};
+
+/// Changing the boot loader config, custom entries and snapshots
+pub const MODIFY_CONFIG: &str = "org.opensuse.bootkit.modify-config";
+/// Choosing the entry that is booted, now or by default
+pub const SET_BOOT_ENTRY: &str = "org.opensuse.bootkit.set-boot-entry";
+
+/// Removing the old kernels from /boot
+pub const REMOVE_KERNELS: &str = "org.opensuse.bootkit.remove-kernels";
+
+/// Rebooting the machine, the action logind checks for the same
+pub const REBOOT: &str = "org.freedesktop.login1.reboot";
+
+/// Let polkit ask the user to authenticate
+const ALLOW_USER_INTERACTION: u32 = 0x1;
+
+#[proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait Authority {
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value<'_>>),
+        action_id: &str,
+        details: &HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+}
+
+/// Checks the callers of the mutating methods against the polkit actions
+#[derive(Debug, Clone, Copy)]
+pub struct Polkit {
+    /// Session bus has no polkit to ask so everything is allowed there
+    enabled: bool,
+}
+
+impl Polkit {
+    pub fn new(session: bool) -> Self {
+        Self { enabled: !session }
+    }
+
+    /// Are the methods authorized, false on the session bus
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Fail unless the sender of the message is authorized for `action`
+    pub async fn authorize(
+        &self,
+        connection: &Connection,
+        header: &Header<'_>,
+        action: &str,
+    ) -> fdo::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let sender = header
+            .sender()
+            .ok_or_else(|| fdo::Error::AccessDenied("Message has no sender".into()))?;
+        let interactive = header
+            .primary()
+            .flags()
+            .contains(Flags::AllowInteractiveAuth);
+        let subject = (
+            "system-bus-name",
+            HashMap::from([("name", Value::from(sender.as_str()))]),
+        );
+        let flags = if interactive {
+            ALLOW_USER_INTERACTION
+        } else {
+            0
+        };
+
+        let authority = AuthorityProxy::new(connection).await?;
+        let (authorized, challenge, _) = authority
+            .check_authorization(&subject, action, &HashMap::new(), flags, "")
+            .await?;
+        log::debug!("polkit {action} for {sender}: authorized {authorized}, challenge {challenge}");
+
+        if authorized {
+            Ok(())
+        } else if challenge {
+            Err(fdo::Error::InteractiveAuthorizationRequired(format!(
+                "Authentication is required for {action}"
+            )))
+        } else {
+            Err(fdo::Error::AccessDenied(format!(
+                "Not authorized for {action}"
+            )))
+        }
+    }
+}
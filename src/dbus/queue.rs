@@ -0,0 +1,201 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use serde::Serialize;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::{
+    dctx,
+    errors::{DRes, DResult},
+    system::caller::Caller,
+};
+
+/// Queue that makes sure that only one write is done to the boot configuration at a time
+#[derive(Clone, Default)]
+pub struct WriteQueue {
+    lock: Arc<Mutex<()>>,
+    /// Writes that are either running or waiting for their turn
+    pending: Arc<AtomicUsize>,
+    /// Moving average of how long a single write takes
+    average_ms: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct QueueStatus {
+    /// How many writes were ahead in the queue
+    pub queue_position: usize,
+    /// Estimated time to wait before the write could start
+    pub estimated_wait_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct WriteReply {
+    status: &'static str,
+    #[serde(flatten)]
+    queue: QueueStatus,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// Permission to write to the boot configuration. Next write in the queue can start
+/// after this is dropped.
+pub struct WriteTicket {
+    queue: WriteQueue,
+    status: QueueStatus,
+    started: Instant,
+    _guard: OwnedMutexGuard<()>,
+    _pending: Pending,
+}
+
+/// Counts a write as pending until it's dropped, also when the caller gives up
+/// waiting for its turn
+struct Pending(Arc<AtomicUsize>);
+
+impl Pending {
+    fn new(pending: &Arc<AtomicUsize>) -> Self {
+        pending.fetch_add(1, Ordering::Relaxed);
+        Self(pending.clone())
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl WriteQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current status for a write that would be queued now
+    pub fn status(&self) -> QueueStatus {
+        let queue_position = self.pending.load(Ordering::Relaxed);
+        QueueStatus {
+            queue_position,
+            estimated_wait_ms: queue_position as u64 * self.average_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Is a write to the boot configuration running right now
+    pub fn is_writing(&self) -> bool {
+        self.lock.try_lock().is_err()
+    }
+
+    /// Wait until all the previous writes are done
+    pub async fn enqueue(&self, name: &str) -> WriteTicket {
+        let status = self.status();
+        let pending = Pending::new(&self.pending);
+        if status.queue_position > 0 {
+            log::debug!(
+                "{name} queued behind {} writes, estimated wait {}ms",
+                status.queue_position,
+                status.estimated_wait_ms
+            );
+        }
+
+        let guard = self.lock.clone().lock_owned().await;
+        match Caller::current() {
+            Some(caller) => log::info!("Starting queued write {name} for {caller}"),
+            None => log::debug!("Starting queued write {name}"),
+        }
+
+        WriteTicket {
+            queue: self.clone(),
+            status,
+            started: Instant::now(),
+            _guard: guard,
+            _pending: pending,
+        }
+    }
+}
+
+impl WriteTicket {
+    /// Reply that can be sent to the client after a successful write
+    pub fn reply(&self) -> DResult<String> {
+        self.reply_with_warnings(Vec::new())
+    }
+
+    /// Reply for a successful write that the client should still know about
+    pub fn reply_with_warnings(&self, warnings: Vec<String>) -> DResult<String> {
+        let reply = WriteReply {
+            status: "ok",
+            queue: self.status,
+            warnings,
+        };
+        serde_json::to_string(&reply).ctx(dctx!(), "Failed to serialize write reply")
+    }
+}
+
+impl Drop for WriteTicket {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        let average = self.queue.average_ms.load(Ordering::Relaxed);
+        let average = if average == 0 {
+            elapsed
+        } else {
+            (average * 3 + elapsed) / 4
+        };
+        self.queue.average_ms.store(average, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_queue_position() {
+        let queue = WriteQueue::new();
+        assert_eq!(queue.status().queue_position, 0);
+
+        let first = queue.enqueue("first").await;
+        assert_eq!(first.status.queue_position, 0);
+        assert_eq!(queue.status().queue_position, 1);
+        assert!(queue.is_writing());
+
+        let second_queue = queue.clone();
+        let second = tokio::spawn(async move {
+            let ticket = second_queue.enqueue("second").await;
+            ticket.status.queue_position
+        });
+
+        // let the second write queue up behind the first one
+        while queue.status().queue_position < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!second.is_finished());
+
+        drop(first);
+        assert_eq!(second.await.unwrap(), 1);
+        assert_eq!(queue.status().queue_position, 0);
+        assert!(!queue.is_writing());
+    }
+
+    #[tokio::test]
+    async fn test_write_queue_cancelled() {
+        let queue = WriteQueue::new();
+        let first = queue.enqueue("first").await;
+
+        // the caller gives up while waiting behind the first write
+        let second_queue = queue.clone();
+        let second = tokio::spawn(async move {
+            second_queue.enqueue("second").await;
+        });
+        while queue.status().queue_position < 2 {
+            tokio::task::yield_now().await;
+        }
+        second.abort();
+        assert!(second.await.unwrap_err().is_cancelled());
+        assert_eq!(queue.status().queue_position, 1);
+
+        drop(first);
+        assert_eq!(queue.status().queue_position, 0);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use zbus::{fdo, message::Header, Connection};
+
+use crate::errors::BusError;
+
+/// Buckets that are kept before the full ones of the callers that went quiet are dropped
+const MAX_IDLE_BUCKETS: usize = 64;
+
+/// Token bucket of a single caller
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per user token buckets that limit how often a client can make expensive calls.
+/// The buckets are keyed by the uid of the caller so that opening more connections
+/// doesn't give more tokens. Each call takes a token and the tokens refill at a
+/// steady rate up to `burst`
+#[derive(Clone)]
+pub struct RateLimiter {
+    name: &'static str,
+    burst: f64,
+    per_second: f64,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(name: &'static str, burst: u32, per_second: f64) -> Self {
+        Self {
+            name,
+            burst: burst.into(),
+            per_second,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Take a token for the user that sent the message, failing with RateLimited if
+    /// it has none left
+    pub async fn check(
+        &self,
+        connection: &Connection,
+        header: &Header<'_>,
+    ) -> Result<(), BusError> {
+        let caller = caller_key(connection, header).await;
+        if self.take(&caller, Instant::now()) {
+            Ok(())
+        } else {
+            log::warn!("Rate limited {} calls of {caller}", self.name);
+            Err(BusError::RateLimited(format!(
+                "Too many {} calls, try again later",
+                self.name
+            )))
+        }
+    }
+
+    fn take(&self, caller: &str, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().expect("rate limiter lock is poisoned");
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(caller.into()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Tokens of `bucket` at `now`
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst)
+    }
+}
+
+/// Bucket key of the sender, its uid or the unique name if the bus doesn't know the uid
+async fn caller_key(connection: &Connection, header: &Header<'_>) -> String {
+    let Some(sender) = header.sender() else {
+        return String::new();
+    };
+    let uid = match fdo::DBusProxy::new(connection).await {
+        Ok(proxy) => proxy
+            .get_connection_unix_user(sender.clone().into())
+            .await
+            .map_err(zbus::Error::from),
+        Err(err) => Err(err),
+    };
+    match uid {
+        Ok(uid) => format!("uid {uid}"),
+        Err(err) => {
+            log::debug!("Cannot get the uid of {sender}: {err}");
+            sender.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new("Test", 2, 0.5);
+        let start = Instant::now();
+        assert!(limiter.take("uid 1000", start));
+        assert!(limiter.take("uid 1000", start));
+        assert!(!limiter.take("uid 1000", start));
+        // other users have their own buckets
+        assert!(limiter.take("uid 1001", start));
+
+        assert!(!limiter.take("uid 1000", start + Duration::from_secs(1)));
+        assert!(limiter.take("uid 1000", start + Duration::from_secs(2)));
+        // tokens don't refill past the burst
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.take("uid 1000", later));
+        assert!(limiter.take("uid 1000", later));
+        assert!(!limiter.take("uid 1000", later));
+    }
+}
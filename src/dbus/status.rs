@@ -0,0 +1,151 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use zbus::interface;
+
+use crate::{dbus::handler::DbusHandler, errors::last_error};
+
+/// Watched paths and when the daemon started, shared with the event listener
+#[derive(Clone)]
+pub struct Health {
+    started: Instant,
+    watches: Arc<Mutex<BTreeMap<String, bool>>>,
+}
+
+impl Health {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            watches: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Record whether the watch of `path` is working
+    pub fn set_watch(&self, path: &str, alive: bool) {
+        self.watches
+            .lock()
+            .expect("health lock is poisoned")
+            .insert(path.to_string(), alive);
+    }
+
+    /// Record that none of the watches work anymore, like when the listener stopped
+    pub fn set_watches_dead(&self) {
+        self.watches
+            .lock()
+            .expect("health lock is poisoned")
+            .values_mut()
+            .for_each(|alive| *alive = false);
+    }
+
+    /// Guard that marks every watch dead when it's dropped, also when the
+    /// listener holding it panics
+    pub fn watches_guard(&self) -> WatchesGuard {
+        WatchesGuard(self.clone())
+    }
+
+    pub fn watches(&self) -> BTreeMap<String, bool> {
+        self.watches
+            .lock()
+            .expect("health lock is poisoned")
+            .clone()
+    }
+
+    pub fn uptime(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+}
+
+/// See [`Health::watches_guard`]
+pub struct WatchesGuard(Health);
+
+impl Drop for WatchesGuard {
+    fn drop(&mut self) {
+        self.0.set_watches_dead();
+    }
+}
+
+/// Lets monitoring and installers check that the daemon is working instead of
+/// only being registered on the bus
+pub struct BootKitStatus {
+    handler: DbusHandler,
+    health: Health,
+}
+
+impl BootKitStatus {
+    pub fn new(handler: DbusHandler) -> Self {
+        Self {
+            handler,
+            health: Health::new(),
+        }
+    }
+
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+}
+
+#[interface(name = "org.opensuse.bootkit.Status")]
+impl BootKitStatus {
+    /// Boot loader the daemon manages, like `grub2` or `systemd-boot`
+    #[zbus(property)]
+    async fn backend(&self) -> String {
+        self.handler.bootloader_type()
+    }
+
+    /// Watched directories and whether their inotify watch is still working
+    #[zbus(property)]
+    async fn watched_paths(&self) -> HashMap<String, bool> {
+        self.health.watches().into_iter().collect()
+    }
+
+    /// Can the snapshot database be queried
+    #[zbus(property)]
+    async fn database_available(&self) -> bool {
+        self.handler.database_available().await
+    }
+
+    /// Message of the last failed method call, empty if none has failed
+    #[zbus(property)]
+    async fn last_error(&self) -> String {
+        last_error().unwrap_or_default()
+    }
+
+    /// Seconds since the daemon started
+    #[zbus(property)]
+    async fn uptime(&self) -> u64 {
+        self.health.uptime()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_watches() {
+        let health = Health::new();
+        health.set_watch("/etc/default", true);
+        health.set_watch("/boot/grub2", true);
+        health.clone().set_watch("/boot/grub2", false);
+
+        assert_eq!(
+            health.watches(),
+            BTreeMap::from([
+                ("/boot/grub2".to_string(), false),
+                ("/etc/default".to_string(), true),
+            ])
+        );
+        assert_eq!(health.uptime(), 0);
+
+        let guard = health.watches_guard();
+        let result = std::panic::catch_unwind(move || {
+            let _guard = guard;
+            panic!("listener failed");
+        });
+        assert!(result.is_err());
+        assert!(health.watches().values().all(|alive| !alive));
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use crate::grub2::schema;
+
+/// Keys a single client can watch, more is likely a client that watches
+/// everything and should use FileChangedDetailed instead
+pub const MAX_WATCHED_KEYS: usize = 128;
+/// Clients that can watch keys at the same time
+pub const MAX_WATCHERS: usize = 64;
+
+/// grubenv variables that can be watched on top of the keys in the schema
+const ENV_KEYS: &[&str] = &[
+    "saved_entry",
+    "next_entry",
+    "prev_saved_entry",
+    "boot_success",
+    "boot_indeterminate",
+    "menu_auto_hide",
+];
+
+/// Why WatchKeys was rejected
+#[derive(Debug, PartialEq, Eq)]
+pub enum WatchError {
+    /// Keys that are neither in the key schema nor grubenv variables
+    UnknownKeys(Vec<String>),
+    TooManyKeys,
+    TooManyClients,
+}
+
+impl std::fmt::Display for WatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchError::UnknownKeys(keys) => write!(f, "Unknown keys: {}", keys.join(", ")),
+            WatchError::TooManyKeys => write!(f, "Cannot watch more than {MAX_WATCHED_KEYS} keys"),
+            WatchError::TooManyClients => write!(
+                f,
+                "More than {MAX_WATCHERS} clients are watching keys, try again later"
+            ),
+        }
+    }
+}
+
+/// Keys that the clients are interested in, by the unique name of the client.
+/// KeyChanged is sent only to the clients that watch the key
+#[derive(Clone, Default)]
+pub struct KeyWatches {
+    clients: Arc<Mutex<HashMap<String, BTreeSet<String>>>>,
+}
+
+impl KeyWatches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `keys` to the keys `client` watches. Nothing is added if any of the keys
+    /// is unknown, the client would watch more than [`MAX_WATCHED_KEYS`] keys or
+    /// [`MAX_WATCHERS`] other clients are watching already
+    pub fn watch(&self, client: &str, keys: &[String]) -> Result<(), WatchError> {
+        let unknown: Vec<String> = keys
+            .iter()
+            .filter(|key| schema::key_schema(key).is_none() && !ENV_KEYS.contains(&key.as_str()))
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            return Err(WatchError::UnknownKeys(unknown));
+        }
+
+        let mut clients = self.clients.lock().expect("key watch lock is poisoned");
+        if !clients.contains_key(client) && clients.len() >= MAX_WATCHERS {
+            return Err(WatchError::TooManyClients);
+        }
+        let watched = clients.entry(client.to_string()).or_default();
+        let new_keys = keys.iter().filter(|key| !watched.contains(*key)).count();
+        if watched.len() + new_keys > MAX_WATCHED_KEYS {
+            if watched.is_empty() {
+                clients.remove(client);
+            }
+            return Err(WatchError::TooManyKeys);
+        }
+        watched.extend(keys.iter().cloned());
+        Ok(())
+    }
+
+    /// Stop watching `keys`, or every key if `keys` is empty
+    pub fn unwatch(&self, client: &str, keys: &[String]) {
+        let mut clients = self.clients.lock().expect("key watch lock is poisoned");
+        let Some(watched) = clients.get_mut(client) else {
+            return;
+        };
+        for key in keys {
+            watched.remove(key);
+        }
+        if keys.is_empty() || watched.is_empty() {
+            clients.remove(client);
+        }
+    }
+
+    /// Clients that watch `key`
+    pub fn watchers(&self, key: &str) -> Vec<String> {
+        let clients = self.clients.lock().expect("key watch lock is poisoned");
+        let mut watchers: Vec<String> = clients
+            .iter()
+            .filter(|(_, keys)| keys.contains(key))
+            .map(|(client, _)| client.clone())
+            .collect();
+        watchers.sort();
+        watchers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_key_watches() {
+        let watches = KeyWatches::new();
+        assert!(watches
+            .watch(":1.10", &keys(&["GRUB_CMDLINE_LINUX_DEFAULT"]))
+            .is_ok());
+        assert!(watches
+            .watch(":1.11", &keys(&["GRUB_TIMEOUT", "saved_entry"]))
+            .is_ok());
+        assert!(watches
+            .watch(":1.11", &keys(&["GRUB_CMDLINE_LINUX_DEFAULT"]))
+            .is_ok());
+
+        assert_eq!(
+            watches.watchers("GRUB_CMDLINE_LINUX_DEFAULT"),
+            vec![":1.10", ":1.11"]
+        );
+        assert_eq!(watches.watchers("saved_entry"), vec![":1.11"]);
+        assert!(watches.watchers("GRUB_DEFAULT").is_empty());
+
+        watches.unwatch(":1.11", &keys(&["GRUB_TIMEOUT"]));
+        assert!(watches.watchers("GRUB_TIMEOUT").is_empty());
+        assert_eq!(watches.watchers("saved_entry"), vec![":1.11"]);
+        watches.unwatch(":1.11", &[]);
+        assert_eq!(
+            watches.watchers("GRUB_CMDLINE_LINUX_DEFAULT"),
+            vec![":1.10"]
+        );
+
+        assert_eq!(
+            watches.watch(":1.12", &keys(&["GRUB_TIMEOUT", "MY_KEY"])),
+            Err(WatchError::UnknownKeys(keys(&["MY_KEY"])))
+        );
+        assert!(watches.watchers("GRUB_TIMEOUT").is_empty());
+    }
+
+    #[test]
+    fn test_key_watches_limits() {
+        let watches = KeyWatches::new();
+        let many: Vec<String> = (0..=MAX_WATCHED_KEYS).map(|i| format!("KEY_{i}")).collect();
+        {
+            let mut clients = watches.clients.lock().unwrap();
+            clients.insert(":1.12".into(), many.iter().skip(1).cloned().collect());
+        }
+        assert_eq!(
+            watches.watch(":1.12", &keys(&["GRUB_TIMEOUT"])),
+            Err(WatchError::TooManyKeys)
+        );
+        // watching the same keys again doesn't count against the limit
+        assert!(watches.watch(":1.13", &keys(&["GRUB_TIMEOUT"])).is_ok());
+        assert!(watches.watch(":1.13", &keys(&["GRUB_TIMEOUT"])).is_ok());
+
+        for i in 0..MAX_WATCHERS - 2 {
+            assert!(watches
+                .watch(&format!(":2.{i}"), &keys(&["saved_entry"]))
+                .is_ok());
+        }
+        assert_eq!(
+            watches.watch(":1.14", &keys(&["saved_entry"])),
+            Err(WatchError::TooManyClients)
+        );
+        // clients that watch already can watch more keys
+        assert!(watches.watch(":1.13", &keys(&["saved_entry"])).is_ok());
+        watches.unwatch(":1.12", &[]);
+        assert!(watches.watch(":1.14", &keys(&["saved_entry"])).is_ok());
+    }
+}
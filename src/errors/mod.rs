@@ -1,3 +1,11 @@
+use std::sync::Mutex;
+
+use zbus::{
+    message::{Header, Message},
+    names::ErrorName,
+    DBusError,
+};
+
 mod macros;
 
 /// Error context that should be created with `dctx!()` macro
@@ -23,6 +31,10 @@ pub enum DErrorType {
     /// Generic error when nothing else is applicable
     Error(String),
     GrubParse(String),
+    /// Invalid values given to keys, (key, reason)
+    InvalidValues(Vec<(String, String)>),
+    /// Boot configuration was changed by someone else in the middle of the operation
+    Conflict(String),
     Io(String, Box<std::io::Error>),
     Sqlx(String, Box<sqlx::Error>),
     Zbus(String, Box<zbus::Error>),
@@ -30,13 +42,53 @@ pub enum DErrorType {
     JoinError(String, Box<tokio::task::JoinError>),
 }
 
+/// Stable codes of the failures, sent along with the messages so that clients
+/// can localize them and scripts can branch on them
+pub mod codes {
+    pub const GRUB_PARSE: &str = "ERR_GRUB_PARSE";
+    pub const VALIDATION: &str = "ERR_VALIDATION";
+    pub const CONFLICT: &str = "ERR_CONFLICT";
+    pub const NOT_AUTHORIZED: &str = "ERR_NOT_AUTHORIZED";
+    pub const RATE_LIMITED: &str = "ERR_RATE_LIMITED";
+    /// Payload of the method isn't valid JSON or has unknown fields
+    pub const JSON: &str = "ERR_JSON";
+    pub const IO: &str = "ERR_IO";
+    pub const DATABASE: &str = "ERR_DATABASE";
+    pub const DBUS: &str = "ERR_DBUS";
+    /// Config file isn't valid shell
+    pub const SCRIPT: &str = "ERR_SCRIPT";
+    pub const FAILED: &str = "ERR_FAILED";
+}
+
 impl DErrorType {
+    /// Stable code of the error, one of [`codes`]
+    pub fn code(&self) -> &'static str {
+        match self {
+            DErrorType::Error(_) | DErrorType::JoinError(..) => codes::FAILED,
+            DErrorType::GrubParse(_) => codes::GRUB_PARSE,
+            DErrorType::InvalidValues(_) => codes::VALIDATION,
+            DErrorType::Conflict(_) => codes::CONFLICT,
+            DErrorType::Io(..) => codes::IO,
+            DErrorType::Sqlx(..) => codes::DATABASE,
+            DErrorType::Zbus(..) => codes::DBUS,
+            DErrorType::Serde(..) => codes::JSON,
+        }
+    }
+
     pub fn as_string(&self) -> String {
         match self {
             DErrorType::Error(msg) => format!("Error: {msg}"),
             DErrorType::GrubParse(msg) => {
                 format!("Internal Parse: Failed to parse grub config: {msg}")
             }
+            DErrorType::InvalidValues(values) => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|(key, reason)| format!("{key}: {reason}"))
+                    .collect();
+                format!("Invalid config values: {}", values.join("; "))
+            }
+            DErrorType::Conflict(msg) => format!("Conflict: {msg}"),
             DErrorType::Io(msg, error) => format!("Internal IO error: {msg} ({error})"),
             DErrorType::Sqlx(msg, error) => format!("Interal database error: {msg} ({error})"),
             DErrorType::Zbus(msg, error) => format!("Internal zbus error: {msg} ({error})"),
@@ -85,6 +137,14 @@ impl DError {
         Self::new(ctx, DErrorType::GrubParse(message.into()))
     }
 
+    pub fn invalid_values(ctx: DCtx, values: Vec<(String, String)>) -> Self {
+        Self::new(ctx, DErrorType::InvalidValues(values))
+    }
+
+    pub fn conflict<M: Into<String>>(ctx: DCtx, message: M) -> Self {
+        Self::new(ctx, DErrorType::Conflict(message.into()))
+    }
+
     pub fn error(&self) -> &DErrorType {
         &self.error
     }
@@ -107,6 +167,128 @@ impl From<DError> for zbus::fdo::Error {
     }
 }
 
+/// Error replies of the D-Bus methods, named so that clients can handle the
+/// failures without parsing the messages
+#[derive(Debug)]
+pub enum BusError {
+    /// Standard D-Bus error that keeps its org.freedesktop.DBus.Error name
+    Fdo(zbus::fdo::Error),
+    /// Config file couldn't be parsed
+    ParseFailed(String),
+    /// Values given to the keys were rejected
+    InvalidValues(String),
+    /// Boot configuration was changed by someone else, the client should reload it
+    Conflict(String),
+    /// Caller isn't authorized for the method
+    NotAuthorized(String),
+    /// Caller made too many expensive calls in a short time
+    RateLimited(String),
+    /// Any other failure, with the code of the error
+    Failed(&'static str, String),
+}
+
+impl BusError {
+    fn message(&self) -> &str {
+        match self {
+            BusError::Fdo(err) => err.description().unwrap_or_default(),
+            BusError::ParseFailed(msg)
+            | BusError::InvalidValues(msg)
+            | BusError::Conflict(msg)
+            | BusError::NotAuthorized(msg)
+            | BusError::RateLimited(msg)
+            | BusError::Failed(_, msg) => msg,
+        }
+    }
+
+    /// Stable code of the error, one of [`codes`]. Sent as the second argument
+    /// of the error reply, after the message, except for the standard D-Bus errors
+    pub fn code(&self) -> &'static str {
+        match self {
+            BusError::Fdo(_) => codes::DBUS,
+            BusError::ParseFailed(_) => codes::GRUB_PARSE,
+            BusError::InvalidValues(_) => codes::VALIDATION,
+            BusError::Conflict(_) => codes::CONFLICT,
+            BusError::NotAuthorized(_) => codes::NOT_AUTHORIZED,
+            BusError::RateLimited(_) => codes::RATE_LIMITED,
+            BusError::Failed(code, _) => code,
+        }
+    }
+}
+
+impl zbus::DBusError for BusError {
+    fn create_reply(&self, call: &Header<'_>) -> zbus::Result<Message> {
+        match self {
+            BusError::Fdo(err) => err.create_reply(call),
+            _ => Message::error(call, self.name())?.build(&(self.message(), self.code())),
+        }
+    }
+
+    fn name(&self) -> ErrorName<'_> {
+        let name = match self {
+            BusError::Fdo(err) => return err.name(),
+            BusError::ParseFailed(_) => "org.opensuse.bootkit.Error.ParseFailed",
+            BusError::InvalidValues(_) => "org.opensuse.bootkit.Error.InvalidValues",
+            BusError::Conflict(_) => "org.opensuse.bootkit.Error.Conflict",
+            BusError::NotAuthorized(_) => "org.opensuse.bootkit.Error.NotAuthorized",
+            BusError::RateLimited(_) => "org.opensuse.bootkit.Error.RateLimited",
+            BusError::Failed(..) => "org.opensuse.bootkit.Error.Failed",
+        };
+        ErrorName::from_static_str_unchecked(name)
+    }
+
+    fn description(&self) -> Option<&str> {
+        Some(self.message())
+    }
+}
+
+impl std::fmt::Display for BusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} [{}]: {}", self.name(), self.code(), self.message())
+    }
+}
+
+impl std::error::Error for BusError {}
+
+/// Message of the last D-Bus method call that failed
+static LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Message of the last failed D-Bus method call, shown by the Status interface
+pub fn last_error() -> Option<String> {
+    LAST_ERROR.lock().ok().and_then(|error| error.clone())
+}
+
+impl From<DError> for BusError {
+    fn from(value: DError) -> Self {
+        let message = value.error().as_string();
+        if let Ok(mut last) = LAST_ERROR.lock() {
+            *last = Some(message.clone());
+        }
+        match value.error() {
+            DErrorType::GrubParse(_) => Self::ParseFailed(message),
+            DErrorType::InvalidValues(_) => Self::InvalidValues(message),
+            DErrorType::Conflict(_) => Self::Conflict(message),
+            other => Self::Failed(other.code(), message),
+        }
+    }
+}
+
+impl From<zbus::fdo::Error> for BusError {
+    fn from(value: zbus::fdo::Error) -> Self {
+        match value {
+            zbus::fdo::Error::AccessDenied(msg) => Self::NotAuthorized(msg),
+            // clients retry InteractiveAuthorizationRequired with the flag set,
+            // so it keeps its standard name
+            other => Self::Fdo(other),
+        }
+    }
+}
+
+impl From<zbus::Error> for BusError {
+    fn from(value: zbus::Error) -> Self {
+        Self::from(zbus::fdo::Error::from(value))
+    }
+}
+
 pub type DResult<T> = core::result::Result<T, DError>;
 
 pub trait DRes<T> {
@@ -179,3 +361,46 @@ impl<T> DRes<T> for core::result::Result<T, tokio::task::JoinError> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dctx;
+
+    #[test]
+    fn test_bus_error_names() {
+        let conflict = BusError::from(DError::conflict(dctx!(), "changed"));
+        assert_eq!(conflict.name(), "org.opensuse.bootkit.Error.Conflict");
+        assert_eq!(conflict.description(), Some("Conflict: changed"));
+        assert_eq!(conflict.code(), codes::CONFLICT);
+
+        let invalid = BusError::from(DError::invalid_values(
+            dctx!(),
+            vec![("GRUB_TIMEOUT".into(), "not a number".into())],
+        ));
+        assert_eq!(invalid.name(), "org.opensuse.bootkit.Error.InvalidValues");
+        assert_eq!(invalid.code(), "ERR_VALIDATION");
+
+        let generic = BusError::from(DError::generic(dctx!(), "failed"));
+        assert_eq!(generic.name(), "org.opensuse.bootkit.Error.Failed");
+        assert_eq!(generic.code(), codes::FAILED);
+
+        let io = std::io::Result::<()>::Err(std::io::ErrorKind::NotFound.into())
+            .ctx(dctx!(), "missing")
+            .unwrap_err();
+        let io = BusError::from(io);
+        assert_eq!(io.name(), "org.opensuse.bootkit.Error.Failed");
+        assert_eq!(io.code(), codes::IO);
+
+        let denied = BusError::from(zbus::fdo::Error::AccessDenied("no".into()));
+        assert_eq!(denied.name(), "org.opensuse.bootkit.Error.NotAuthorized");
+
+        let interactive = BusError::from(zbus::fdo::Error::InteractiveAuthorizationRequired(
+            "auth".into(),
+        ));
+        assert_eq!(
+            interactive.name(),
+            "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired"
+        );
+    }
+}
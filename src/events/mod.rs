@@ -1,5 +1,7 @@
 use std::{
+    collections::BTreeMap,
     io::ErrorKind,
+    path::Path,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -8,19 +10,33 @@ use std::{
 };
 
 use event_listener::Listener;
-use inotify::{EventMask, Inotify, WatchMask};
+use futures_util::StreamExt;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
 use tokio::task::JoinHandle;
-use zbus::Connection;
+use zbus::{fdo::DBusProxy, names::BusName, object_server::SignalEmitter, Connection};
 
 use crate::{
-    config::{ConfigArgs, GRUB_ROOT_PATH},
-    dbus::connection::BootKitConfigSignals,
+    config::{layout::GrubLayout, ConfigArgs, GRUB_FILE_PATH, GRUB_ROOT_PATH},
+    dbus::{
+        connection::{BootEntry, BootEntrySignals, BootKitConfig, BootKitConfigSignals, BUS_NAME},
+        entries::EntryChanges,
+        observer::{BootKitObserver, BootKitObserverSignals, OBSERVER_PATH},
+        status::{BootKitStatus, Health},
+    },
     dctx,
     errors::{DRes, DResult},
+    grub2::{env::GrubEnv, GrubFile, ParseMode},
+    system::notify::{notify, Watchdog},
 };
 
 type EventHandle<T> = JoinHandle<DResult<T>>;
 
+/// How long clients have to call KeepAlive after the ShuttingDown signal
+const IDLE_GRACE_MS: u64 = 3000;
+/// Calls that were sent before the bus name was released are handled until
+/// there's been no activity for this long
+const DRAIN_IDLE_MS: u64 = 500;
+
 #[derive(Clone)]
 pub struct BootkitEvents {
     connection: Connection,
@@ -44,16 +60,260 @@ impl BootkitEvents {
         self.shutdown.store(true, Ordering::Relaxed);
     }
 
+    /// Read the current saved_entry from grubenv. Errors are only logged
+    /// since grubenv can be missing or in the middle of being rewritten
+    fn read_saved_entry() -> Option<String> {
+        GrubEnv::from_file(&GrubLayout::get().env_path)
+            .ok()
+            .and_then(|env| env.saved_entry().map(str::to_string))
+    }
+
+    /// Values of the keys in /etc/default/grub, empty if it can't be read
+    fn read_grub_values() -> BTreeMap<String, String> {
+        GrubFile::from_file(GRUB_FILE_PATH, ParseMode::Lenient)
+            .map(|grub| {
+                grub.keyvalues()
+                    .iter()
+                    .map(|(key, keyval)| (key.clone(), keyval.value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Values of the variables in grubenv, empty if it can't be read
+    fn read_env_values() -> BTreeMap<String, String> {
+        GrubEnv::from_file(&GrubLayout::get().env_path)
+            .map(|env| env.values())
+            .unwrap_or_default()
+    }
+
+    /// Send FileChangedDetailed for `file` with the keys whose values differ between
+    /// `values` and `new_values`, and replace `values` with the new ones
+    async fn file_changed_detailed(
+        &self,
+        file: &str,
+        values: &mut BTreeMap<String, String>,
+        new_values: BTreeMap<String, String>,
+    ) -> zbus::Result<()> {
+        let keys = changed_keys(values, &new_values);
+        self.keys_changed(&keys, values, &new_values).await;
+        *values = new_values;
+        log::debug!("Keys {keys:?} of {file} were changed. Signaling dbus");
+        let server = self.connection.object_server();
+        server
+            .interface::<_, BootKitConfig>("/org/opensuse/bootkit")
+            .await?
+            .file_changed_detailed(file, keys.clone())
+            .await?;
+        server
+            .interface::<_, BootKitObserver>(OBSERVER_PATH)
+            .await?
+            .file_changed_detailed(file, keys)
+            .await
+    }
+
+    /// Send KeyChanged of the changed `keys` to the clients that watch them.
+    /// Failures are only logged so one broken client doesn't stop the others
+    /// from being signaled
+    async fn keys_changed(
+        &self,
+        keys: &[String],
+        old: &BTreeMap<String, String>,
+        new: &BTreeMap<String, String>,
+    ) {
+        let watches = match self
+            .connection
+            .object_server()
+            .interface::<_, BootKitObserver>(OBSERVER_PATH)
+            .await
+        {
+            Ok(observer) => observer.get().await.watches(),
+            Err(err) => {
+                log::warn!("Cannot find the observer interface to send KeyChanged: {err}");
+                return;
+            }
+        };
+        for key in keys {
+            let value =
+                |values: &BTreeMap<String, String>| values.get(key).cloned().unwrap_or_default();
+            for client in watches.watchers(key) {
+                if let Err(err) = self
+                    .key_changed(&client, key, &value(old), &value(new))
+                    .await
+                {
+                    log::warn!("Cannot send KeyChanged of {key} to {client}: {err}");
+                }
+            }
+        }
+    }
+
+    /// Send KeyChanged to `client` only
+    async fn key_changed(&self, client: &str, key: &str, old: &str, new: &str) -> zbus::Result<()> {
+        let name = BusName::try_from(client)?;
+        let emitter = SignalEmitter::new(&self.connection, OBSERVER_PATH)?.set_destination(name);
+        BootKitObserver::key_changed(&emitter, key, old, new).await
+    }
+
+    /// Send FileChanged from the config and the observer interfaces
+    async fn file_changed(&self) -> zbus::Result<()> {
+        let server = self.connection.object_server();
+        server
+            .interface::<_, BootKitConfig>("/org/opensuse/bootkit")
+            .await?
+            .file_changed()
+            .await?;
+        server
+            .interface::<_, BootKitObserver>(OBSERVER_PATH)
+            .await?
+            .file_changed()
+            .await
+    }
+
+    /// Send BootEntriesChanged from the boot entry and the observer interfaces
+    async fn boot_entries_changed(&self, changes: EntryChanges) -> zbus::Result<()> {
+        log::debug!(
+            "Boot entries {:?} were added and {:?} removed. Signaling dbus",
+            changes.added,
+            changes.removed
+        );
+        let server = self.connection.object_server();
+        server
+            .interface::<_, BootEntry>("/org/opensuse/bootkit")
+            .await?
+            .boot_entries_changed(changes.added.clone(), changes.removed.clone())
+            .await?;
+        server
+            .interface::<_, BootKitObserver>(OBSERVER_PATH)
+            .await?
+            .boot_entries_changed(changes.added, changes.removed)
+            .await
+    }
+
+    /// Send DefaultEntryChanged from the boot entry and the observer interfaces
+    async fn default_entry_changed(&self, old: &str, new: &str) -> zbus::Result<()> {
+        let server = self.connection.object_server();
+        server
+            .interface::<_, BootEntry>("/org/opensuse/bootkit")
+            .await?
+            .default_entry_changed(old, new)
+            .await?;
+        server
+            .interface::<_, BootKitObserver>(OBSERVER_PATH)
+            .await?
+            .default_entry_changed(old, new)
+            .await
+    }
+
     async fn listen_files_loop(&self) -> zbus::Result<()> {
+        let env_path = Path::new(&GrubLayout::get().env_path);
+        let env_dir = env_path.parent().expect("grubenv path has no parent");
+        let env_name = env_path.file_name().expect("grubenv path has no file name");
+
+        let health = self
+            .connection
+            .object_server()
+            .interface::<_, BootKitStatus>("/org/opensuse/bootkit")
+            .await?
+            .get()
+            .await
+            .health();
+        // nothing is watched anymore once this returns or panics
+        let _watches_guard = health.watches_guard();
+        let mut watched = Vec::new();
+
         let mut inotify = Inotify::init().expect("Failed to initialize inotify");
-        inotify
-            .watches()
-            .add(GRUB_ROOT_PATH, WatchMask::MODIFY)
-            .expect("Failed to watch /etc/default/grub");
+        add_watch(
+            &mut inotify,
+            &health,
+            &mut watched,
+            Path::new(GRUB_ROOT_PATH),
+            // bootkit and many editors replace the file by renaming a new one over it
+            WatchMask::MODIFY | WatchMask::MOVED_TO | WatchMask::CLOSE_WRITE,
+        )
+        .expect("Failed to watch /etc/default/grub");
+        // MASK_ADD in case grubenv is in the same directory as the grub file.
+        // systemd-boot systems don't have the grubenv directory at all
+        if let Err(err) = add_watch(
+            &mut inotify,
+            &health,
+            &mut watched,
+            env_dir,
+            WatchMask::MODIFY | WatchMask::MOVED_TO | WatchMask::MASK_ADD,
+        ) {
+            log::warn!("Cannot watch grubenv in {env_dir:?}: {err}");
+        }
+        // grub.cfg is usually next to grubenv, MASK_ADD keeps both watches
+        let cfg_path = Path::new(&GrubLayout::get().cfg_path);
+        let cfg_name = cfg_path.file_name().map(|name| name.to_os_string());
+        if let Some(cfg_dir) = cfg_path.parent() {
+            if let Err(err) = add_watch(
+                &mut inotify,
+                &health,
+                &mut watched,
+                cfg_dir,
+                WatchMask::MODIFY | WatchMask::MOVED_TO | WatchMask::MASK_ADD,
+            ) {
+                log::warn!("Cannot watch grub.cfg in {cfg_dir:?}: {err}");
+            }
+        }
+
+        let config = self
+            .connection
+            .object_server()
+            .interface::<_, BootKitConfig>("/org/opensuse/bootkit")
+            .await?;
+        // config of other boot loaders is watched for the property changes
+        let loader_path = config.get().await.loader_config_path();
+        let loader_name = loader_path.as_ref().and_then(|path| {
+            let path = Path::new(path);
+            let dir = path.parent()?;
+            if let Err(err) = add_watch(
+                &mut inotify,
+                &health,
+                &mut watched,
+                dir,
+                WatchMask::MODIFY | WatchMask::MOVED_TO | WatchMask::MASK_ADD,
+            ) {
+                log::warn!("Cannot watch boot loader config in {dir:?}: {err}");
+            }
+            path.file_name().map(|name| name.to_os_string())
+        });
+        let mut properties = config.get().await.properties();
+
+        let bootentry = self
+            .connection
+            .object_server()
+            .interface::<_, BootEntry>("/org/opensuse/bootkit")
+            .await?;
+        bootentry.get().await.sync_entries(&self.connection).await?;
+
+        // the daemon exits when idle, so edits made while it wasn't running are
+        // only noticed here
+        config.get().await.snapshot_external_change().await;
+
+        let mut saved_entry = Self::read_saved_entry();
+        let mut grub_values = Self::read_grub_values();
+        let mut env_values = Self::read_env_values();
 
         log::info!("Listening to config changes");
+        // the bus name and the database are ready before the events are listened to
+        notify("READY=1");
+        let mut watchdog = Watchdog::from_env();
+
+        // grub file changes made by bootkit itself are signaled once the write is done,
+        // instead of once for every intermediate state of the file
+        let mut pending_signal = false;
 
         while !self.shutdown.load(Ordering::Relaxed) {
+            watchdog.ping();
+            if pending_signal && !config.get().await.write_in_progress() {
+                pending_signal = false;
+                self.file_changed().await?;
+                log::debug!("{GRUB_ROOT_PATH} was written by bootkit. Signaling dbus");
+                self.file_changed_detailed("grub", &mut grub_values, Self::read_grub_values())
+                    .await?;
+            }
+
             let mut buffer = [0; 4096];
 
             let events = match inotify.read_events(&mut buffer) {
@@ -64,21 +324,104 @@ impl BootkitEvents {
 
             // prevent duplicate modify event triggers
             let mut signaled = false;
+            let mut grub_written = false;
+            let mut env_changed = false;
+            let mut cfg_changed = false;
+            let mut loader_changed = false;
             for event in events {
-                if event.mask.contains(EventMask::MODIFY)
+                // the watch is gone when its directory is removed or unmounted
+                if event.mask.contains(EventMask::IGNORED) {
+                    for (wd, path) in &watched {
+                        if *wd == event.wd {
+                            log::warn!("Lost the watch of {path}");
+                            health.set_watch(path, false);
+                        }
+                    }
+                }
+                if event.name.is_some_and(|name| name == env_name) {
+                    env_changed = true;
+                }
+                if event
+                    .name
+                    .is_some_and(|name| cfg_name.as_deref() == Some(name))
+                {
+                    cfg_changed = true;
+                }
+                if event
+                    .name
+                    .is_some_and(|name| loader_name.as_deref() == Some(name))
+                {
+                    loader_changed = true;
+                }
+
+                // the whole file is written only once the writer closes it
+                if event
+                    .mask
+                    .intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO)
+                    && event.name.is_some_and(|name| name == "grub")
+                {
+                    grub_written = true;
+                }
+
+                if event
+                    .mask
+                    .intersects(EventMask::MODIFY | EventMask::MOVED_TO)
                     && !signaled
                     && event.name.is_some_and(|name| name == "grub")
                 {
                     signaled = true;
-                    self.connection
-                        .object_server()
-                        .interface("/org/opensuse/bootkit")
-                        .await?
-                        .file_changed()
-                        .await?;
+                    if config.get().await.write_in_progress() {
+                        pending_signal = true;
+                        continue;
+                    }
+                    self.file_changed().await?;
                     log::debug!("{GRUB_ROOT_PATH} contents was modified. Signaling dbus");
+                    self.file_changed_detailed("grub", &mut grub_values, Self::read_grub_values())
+                        .await?;
+                }
+            }
+
+            if grub_written && !config.get().await.write_in_progress() {
+                config.get().await.snapshot_external_change().await;
+            }
+
+            if cfg_changed {
+                // grub.cfg is generated so there are no keys to report
+                self.file_changed_detailed("grub.cfg", &mut BTreeMap::new(), BTreeMap::new())
+                    .await?;
+            }
+
+            if env_changed {
+                let new_values = Self::read_env_values();
+                if new_values != env_values {
+                    self.file_changed_detailed("grubenv", &mut env_values, new_values)
+                        .await?;
+                }
+
+                let new_entry = Self::read_saved_entry();
+                if new_entry != saved_entry {
+                    let old = saved_entry.as_deref().unwrap_or_default();
+                    let new = new_entry.as_deref().unwrap_or_default();
+                    log::debug!("Default entry changed from '{old}' to '{new}'. Signaling dbus");
+                    self.default_entry_changed(old, new).await?;
+                    saved_entry = new_entry;
+                }
+            }
+
+            if cfg_changed || env_changed || loader_changed {
+                let changes = bootentry.get().await.sync_entries(&self.connection).await?;
+                if !changes.is_empty() {
+                    self.boot_entries_changed(changes).await?;
                 }
             }
+
+            if signaled || env_changed || loader_changed {
+                config
+                    .get()
+                    .await
+                    .emit_properties_changed(config.signal_emitter(), &mut properties)
+                    .await?;
+            }
         }
 
         Ok(())
@@ -93,6 +436,45 @@ impl BootkitEvents {
         })
     }
 
+    /// Drop the state of the clients that disconnect from the bus
+    fn listen_disconnects(&self) -> EventHandle<()> {
+        let copy = self.clone();
+        tokio::spawn(async move {
+            let dbus = DBusProxy::new(&copy.connection)
+                .await
+                .ctx(dctx!(), "Failed to create the D-Bus proxy")?;
+            let mut changes = dbus
+                .receive_name_owner_changed()
+                .await
+                .ctx(dctx!(), "Failed to listen to NameOwnerChanged")?;
+            let server = copy.connection.object_server();
+            let config = server
+                .interface::<_, BootKitConfig>("/org/opensuse/bootkit")
+                .await
+                .ctx(dctx!(), "Cannot find the config interface")?;
+            let watches = server
+                .interface::<_, BootKitObserver>(OBSERVER_PATH)
+                .await
+                .ctx(dctx!(), "Cannot find the observer interface")?
+                .get()
+                .await
+                .watches();
+
+            while let Some(change) = changes.next().await {
+                let Ok(args) = change.args() else {
+                    continue;
+                };
+                // unique names are never reused, so they are gone for good once they lose their owner
+                if args.name().starts_with(':') && args.new_owner().is_none() {
+                    config.get().await.forget_client(args.name());
+                    watches.unwatch(args.name(), &[]);
+                }
+            }
+
+            Ok(())
+        })
+    }
+
     fn detect_idle_connection(&self, timeout: Option<u64>) -> EventHandle<()> {
         let copy = self.clone();
         tokio::spawn(async move {
@@ -103,33 +485,82 @@ impl BootkitEvents {
                 return Ok(());
             };
 
-            let mut counter = 0;
-
-            while counter < timeout && !copy.shutdown.load(Ordering::Relaxed) {
-                let activity = copy
-                    .connection
-                    .monitor_activity()
-                    .wait_timeout(Duration::from_millis(100));
-                if activity.is_none() {
-                    counter += 100;
-                } else {
-                    counter = 0;
+            while copy.wait_idle(timeout) {
+                log::debug!("Idle counter limit exceeded. Signaling dbus");
+                copy.connection
+                    .object_server()
+                    .interface("/org/opensuse/bootkit")
+                    .await
+                    .ctx(dctx!(), "Cannot find the config interface")?
+                    .shutting_down(IDLE_GRACE_MS)
+                    .await
+                    .ctx(dctx!(), "Failed to send the ShuttingDown signal")?;
+
+                // any method call, like KeepAlive, during the grace period keeps the program running
+                if copy.wait_idle(IDLE_GRACE_MS) {
+                    log::debug!("No client kept the program alive. Stopping the program");
+                    return copy.release_name().await;
                 }
+                log::debug!("Client activity during the shutdown grace period, staying alive");
             }
 
-            // TODO: when this happens, send a signal to clients
-            log::debug!("Idle counter limit exceeded. Stopping the program");
             Ok(())
         })
     }
 
+    /// Release the bus name so the next method call activates a new instance, and
+    /// wait for the calls and writes this instance already got to finish
+    async fn release_name(&self) -> DResult<()> {
+        self.connection
+            .release_name(BUS_NAME)
+            .await
+            .ctx(dctx!(), format!("Failed to release {BUS_NAME}"))?;
+        log::debug!("Released {BUS_NAME}, draining the calls in flight");
+
+        let config = self
+            .connection
+            .object_server()
+            .interface::<_, BootKitConfig>("/org/opensuse/bootkit")
+            .await
+            .ctx(dctx!(), "Cannot find the config interface")?;
+        while self.wait_idle(DRAIN_IDLE_MS) && config.get().await.write_in_progress() {
+            log::debug!("Waiting for the queued writes before stopping");
+        }
+        Ok(())
+    }
+
+    /// Block until there has been no D-Bus activity for `timeout` milliseconds.
+    /// Returns false if the shutdown was signaled before that
+    fn wait_idle(&self, timeout: u64) -> bool {
+        let mut counter = 0;
+        while counter < timeout {
+            if self.shutdown.load(Ordering::Relaxed) {
+                return false;
+            }
+            let activity = self
+                .connection
+                .monitor_activity()
+                .wait_timeout(Duration::from_millis(100));
+            if activity.is_none() {
+                counter += 100;
+            } else {
+                counter = 0;
+            }
+        }
+        true
+    }
+
     pub async fn listen_events(&self, config: &ConfigArgs) -> DResult<()> {
         let file_changes = self.listen_files();
         let idle_connection = self.detect_idle_connection(config.allowed_idle_time());
+        let disconnects = self.listen_disconnects();
         let res = tokio::select! {
            res = file_changes => {
                res.ctx(dctx!(), "File change detection panicked")
            }
+           res = disconnects => {
+               res.ctx(dctx!(), "Disconnect detection panicked")
+           }
            res = idle_connection, if config.allowed_idle_time().is_some() => {
                res.ctx(dctx!(), "Idle detection panicked")
            }
@@ -138,3 +569,63 @@ impl BootkitEvents {
         res?
     }
 }
+
+/// Watch `dir` and record in `health` whether it worked
+fn add_watch(
+    inotify: &mut Inotify,
+    health: &Health,
+    watched: &mut Vec<(WatchDescriptor, String)>,
+    dir: &Path,
+    mask: WatchMask,
+) -> std::io::Result<()> {
+    let path = dir.to_string_lossy().to_string();
+    match inotify.watches().add(dir, mask) {
+        Ok(wd) => {
+            health.set_watch(&path, true);
+            watched.push((wd, path));
+            Ok(())
+        }
+        Err(err) => {
+            health.set_watch(&path, false);
+            Err(err)
+        }
+    }
+}
+
+/// Keys that were added, removed or got a different value from `old` to `new`
+fn changed_keys(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<String> {
+    let mut keys: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_keys() {
+        let old = BTreeMap::from([
+            ("GRUB_TIMEOUT".to_string(), "5".to_string()),
+            ("GRUB_DEFAULT".to_string(), "saved".to_string()),
+        ]);
+        let new = BTreeMap::from([
+            ("GRUB_TIMEOUT".to_string(), "8".to_string()),
+            ("GRUB_DEFAULT".to_string(), "saved".to_string()),
+            ("GRUB_TERMINAL".to_string(), "console".to_string()),
+        ]);
+
+        assert!(changed_keys(&old, &old).is_empty());
+        assert_eq!(
+            changed_keys(&old, &new),
+            vec!["GRUB_TERMINAL", "GRUB_TIMEOUT"]
+        );
+        assert_eq!(changed_keys(&new, &BTreeMap::new()).len(), 3);
+    }
+}
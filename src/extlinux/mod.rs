@@ -0,0 +1,433 @@
+use std::{collections::BTreeMap, fs::read_to_string, ops::Range, path::Path};
+
+use crate::{
+    bootloader::{Bootloader, LoaderEntry},
+    config::EXTLINUX_CONF_PATH,
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::kernel_version,
+    system::write_file_atomic,
+};
+
+/// Keywords that set the kernel of a label, `linux` is an alias of `kernel`
+const KERNEL_KEYWORDS: &[&str] = &["kernel", "linux"];
+
+fn split_keyword(line: &str) -> (String, &str) {
+    line.split_once(char::is_whitespace)
+        .map_or((line.to_ascii_lowercase(), ""), |(key, value)| {
+            (key.to_ascii_lowercase(), value.trim())
+        })
+}
+
+/// Split a line into the lowercased keyword and the rest, none for comments
+/// and empty lines. `menu` keywords include the second word, like `menu label`
+fn keyword(line: &str) -> Option<(String, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (key, value) = split_keyword(line);
+    if key == "menu" {
+        let (menu_key, value) = split_keyword(value);
+        return Some((format!("menu {menu_key}"), value));
+    }
+    Some((key, value))
+}
+
+/// `label` block of extlinux.conf
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Label {
+    name: String,
+    /// Lines of the block, including the `label` line
+    lines: Range<usize>,
+}
+
+/// extlinux.conf that U-Boot distro boot reads, used on many ARM boards.
+///
+/// Keywords are case insensitive and the entries are `label` blocks that last
+/// until the next label. The raw lines are kept so the file is written back
+/// as it was apart from the changed lines.
+#[derive(Debug, Clone, Default)]
+pub struct Extlinux {
+    lines: Vec<String>,
+}
+
+impl Extlinux {
+    pub fn parse(contents: &str) -> Self {
+        Self {
+            lines: contents.split('\n').map(str::to_string).collect(),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> DResult<Self> {
+        let path = path.as_ref();
+        let contents =
+            read_to_string(path).ctx(dctx!(), format!("Cannot read extlinux config {path:?}"))?;
+        Ok(Self::parse(&contents))
+    }
+
+    pub fn read() -> DResult<Self> {
+        Self::from_file(EXTLINUX_CONF_PATH)
+    }
+
+    fn labels(&self) -> Vec<Label> {
+        let mut labels: Vec<Label> = Vec::new();
+        for (idx, line) in self.lines.iter().enumerate() {
+            let Some((key, name)) = keyword(line) else {
+                continue;
+            };
+            if key == "label" {
+                if let Some(previous) = labels.last_mut() {
+                    previous.lines.end = idx;
+                }
+                labels.push(Label {
+                    name: name.to_string(),
+                    lines: idx..self.lines.len(),
+                });
+            }
+        }
+        labels
+    }
+
+    /// Lines before the first label
+    fn global_end(&self) -> usize {
+        self.labels()
+            .first()
+            .map_or(self.lines.len(), |label| label.lines.start)
+    }
+
+    fn label_value<'a>(&'a self, label: &Label, keys: &[&str]) -> Option<&'a str> {
+        self.lines[label.lines.clone()]
+            .iter()
+            .filter_map(|line| keyword(line))
+            .find(|(key, _)| keys.contains(&key.as_str()))
+            .map(|(_, value)| value)
+    }
+
+    fn loader_entry(&self, label: &Label) -> LoaderEntry {
+        let kernel = self.label_value(label, KERNEL_KEYWORDS).map(str::to_string);
+        LoaderEntry {
+            id: label.name.clone(),
+            title: self
+                .label_value(label, &["menu label"])
+                .unwrap_or(&label.name)
+                .to_string(),
+            version: kernel.as_deref().and_then(kernel_version),
+            kernel,
+            initrds: self
+                .label_value(label, &["initrd"])
+                .map(|initrd| initrd.split(',').map(str::to_string).collect())
+                .unwrap_or_default(),
+            options: self.label_value(label, &["append"]).map(str::to_string),
+            // `devicetree` and `fdtdir` are aliases of `fdt` and `devicetreedir`
+            devicetree: self
+                .label_value(label, &["fdt", "devicetree"])
+                .map(str::to_string),
+            devicetree_dir: self
+                .label_value(label, &["fdtdir", "devicetreedir"])
+                .map(str::to_string),
+            classes: Vec::new(),
+        }
+    }
+
+    fn find_label(&self, id: &str) -> DResult<Label> {
+        self.labels()
+            .into_iter()
+            .find(|label| label.name == id)
+            .ok_or_else(|| DError::generic(dctx!(), format!("Boot entry '{id}' is not found")))
+    }
+
+    /// Set `key` inside the label block, or remove it if `value` is none.
+    /// New lines use the indentation and keyword case of the block
+    fn set_label_value(&mut self, label: &Label, keys: &[&str], value: Option<&str>) {
+        let existing: Vec<usize> = label
+            .lines
+            .clone()
+            .filter(|idx| {
+                keyword(&self.lines[*idx]).is_some_and(|(key, _)| keys.contains(&key.as_str()))
+            })
+            .collect();
+
+        let label_line = &self.lines[label.lines.start];
+        let uppercase = label_line.trim_start().starts_with("LABEL");
+        let indent = self.lines[label.lines.clone()]
+            .iter()
+            .skip(1)
+            .find(|line| !line.trim().is_empty())
+            .map_or("\t".to_string(), |line| {
+                line[..line.len() - line.trim_start().len()].to_string()
+            });
+        let key = if uppercase {
+            keys[0].to_ascii_uppercase()
+        } else {
+            keys[0].to_string()
+        };
+
+        for idx in existing.iter().skip(1).rev() {
+            self.lines.remove(*idx);
+        }
+        match (existing.first(), value) {
+            (Some(idx), Some(value)) => self.lines[*idx] = format!("{indent}{key} {value}"),
+            (Some(idx), None) => {
+                self.lines.remove(*idx);
+            }
+            (None, Some(value)) => {
+                // after the last line of the block, before the empty lines between blocks
+                let idx = label
+                    .lines
+                    .clone()
+                    .rev()
+                    .find(|idx| !self.lines[*idx].trim().is_empty())
+                    .map_or(label.lines.start, |idx| idx)
+                    + 1;
+                self.lines.insert(idx, format!("{indent}{key} {value}"));
+            }
+            (None, None) => {}
+        }
+    }
+
+    pub fn as_string(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+impl Bootloader for Extlinux {
+    fn name(&self) -> &'static str {
+        "extlinux"
+    }
+
+    fn config_path(&self) -> &str {
+        EXTLINUX_CONF_PATH
+    }
+
+    /// Keywords are case insensitive so they are returned in lowercase
+    fn values(&self) -> BTreeMap<String, String> {
+        self.lines[..self.global_end()]
+            .iter()
+            .filter_map(|line| keyword(line))
+            .map(|(key, value)| (key, value.to_string()))
+            .collect()
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.lines.clone()
+    }
+
+    fn validate(&self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            // in tenths of a second
+            "timeout" | "totaltimeout" if value.parse::<u32>().is_err() => {
+                Err(format!("'{value}' is not a number of tenths of a second"))
+            }
+            "default" if !self.labels().iter().any(|label| label.name == value) => {
+                Err(format!("'{value}' is not a label"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn set_value(&mut self, key: &str, value: &str) {
+        let global_end = self.global_end();
+        let existing: Vec<usize> = (0..global_end)
+            .filter(|idx| keyword(&self.lines[*idx]).is_some_and(|(line_key, _)| line_key == key))
+            .collect();
+
+        match existing.split_first() {
+            Some((first, rest)) => {
+                self.lines[*first] = format!("{key} {value}");
+                for idx in rest.iter().rev() {
+                    self.lines.remove(*idx);
+                }
+            }
+            None => {
+                // after the last global line, before the empty lines and the labels
+                let idx = self.lines[..global_end]
+                    .iter()
+                    .rposition(|line| !line.trim().is_empty())
+                    .map_or(0, |idx| idx + 1);
+                self.lines.insert(idx, format!("{key} {value}"));
+            }
+        }
+    }
+
+    fn remove_value(&mut self, key: &str) -> bool {
+        let existing: Vec<usize> = (0..self.global_end())
+            .filter(|idx| keyword(&self.lines[*idx]).is_some_and(|(line_key, _)| line_key == key))
+            .collect();
+        for idx in existing.iter().rev() {
+            self.lines.remove(*idx);
+        }
+        !existing.is_empty()
+    }
+
+    fn entries(&self) -> Vec<LoaderEntry> {
+        self.labels()
+            .iter()
+            .map(|label| self.loader_entry(label))
+            .collect()
+    }
+
+    fn default_key(&self) -> &'static str {
+        "default"
+    }
+
+    fn default_value(&self, entry: &LoaderEntry) -> Option<String> {
+        Some(entry.id.clone())
+    }
+
+    /// `default` label, or the label with `menu default`, or the first label
+    fn selected(&self) -> Option<LoaderEntry> {
+        let labels = self.labels();
+        let default = self.default_entry();
+        let label = labels
+            .iter()
+            .find(|label| default.as_deref() == Some(label.name.as_str()))
+            .or_else(|| {
+                labels.iter().find(|label| {
+                    self.lines[label.lines.clone()]
+                        .iter()
+                        .any(|line| keyword(line).is_some_and(|(key, _)| key == "menu default"))
+                })
+            })
+            .or(labels.first())?;
+        Some(self.loader_entry(label))
+    }
+
+    fn set_entry_cmdline(&mut self, id: &str, cmdline: &str) -> DResult<()> {
+        let label = self.find_label(id)?;
+        log::debug!("Changing append of extlinux label '{id}' to '{cmdline}'");
+        self.set_label_value(&label, &["append"], Some(cmdline));
+        Ok(())
+    }
+
+    /// `fdt` and `fdtdir` are exclusive, U-Boot ignores `fdtdir` if `fdt` is set
+    fn set_entry_devicetree(
+        &mut self,
+        id: &str,
+        devicetree: Option<&str>,
+        devicetree_dir: Option<&str>,
+    ) -> DResult<()> {
+        if devicetree.is_some() && devicetree_dir.is_some() {
+            return Err(DError::generic(
+                dctx!(),
+                "Only one of the device tree file or directory can be set",
+            ));
+        }
+
+        log::debug!(
+            "Setting device tree of extlinux label '{id}' to {devicetree:?} {devicetree_dir:?}"
+        );
+        let label = self.find_label(id)?;
+        self.set_label_value(&label, &["fdt", "devicetree"], devicetree);
+        // the block can change size so find it again
+        let label = self.find_label(id)?;
+        self.set_label_value(&label, &["fdtdir", "devicetreedir"], devicetree_dir);
+        Ok(())
+    }
+
+    fn write(&self) -> DResult<()> {
+        write_file_atomic(EXTLINUX_CONF_PATH, &self.as_string())?;
+        log::debug!("extlinux config was written to {EXTLINUX_CONF_PATH}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extlinux_parse() {
+        let extlinux = Extlinux::from_file("test_data/extlinux.conf").unwrap();
+        let values = extlinux.values();
+        assert_eq!(values.get("timeout").map(String::as_str), Some("20"));
+        assert_eq!(
+            values.get("menu title").map(String::as_str),
+            Some("Fedora Linux boot options")
+        );
+        assert!(!values.contains_key("kernel"));
+
+        let entries = extlinux.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, "Fedora-6.12.5");
+        assert_eq!(entries[0].title, "Fedora-6.12.5");
+        assert_eq!(
+            entries[0].version.as_deref(),
+            Some("6.12.5-200.fc41.aarch64")
+        );
+        assert_eq!(
+            entries[0].options.as_deref(),
+            Some("ro root=UUID=0abc console=ttyS0,115200")
+        );
+        assert_eq!(
+            entries[0].devicetree_dir.as_deref(),
+            Some("/dtb-6.12.5-200.fc41.aarch64/")
+        );
+        assert_eq!(entries[1].title, "Fedora rescue");
+        assert_eq!(entries[1].kernel.as_deref(), Some("/vmlinuz-0-rescue"));
+        assert_eq!(
+            entries[1].initrds,
+            vec!["/initramfs-0-rescue.img", "/microcode.img"]
+        );
+        assert_eq!(
+            entries[1].devicetree.as_deref(),
+            Some("/dtb/rockchip/rk3399-rockpro64.dtb")
+        );
+
+        assert_eq!(
+            extlinux.selected().map(|entry| entry.id),
+            Some("Fedora-6.12.5".into())
+        );
+        assert!(extlinux.validate("default", "rescue").is_ok());
+        assert!(extlinux.validate("default", "missing").is_err());
+        assert!(extlinux.validate("timeout", "1.5").is_err());
+    }
+
+    #[test]
+    fn test_extlinux_edit() {
+        let mut extlinux = Extlinux::from_file("test_data/extlinux.conf").unwrap();
+        extlinux.set_value("default", "rescue");
+        extlinux.set_value("prompt", "0");
+        assert!(extlinux.remove_value("totaltimeout"));
+        assert_eq!(
+            extlinux.selected().map(|entry| entry.id),
+            Some("rescue".into())
+        );
+
+        extlinux.set_entry_cmdline("rescue", "ro single").unwrap();
+        extlinux
+            .set_entry_devicetree("Fedora-6.12.5", Some("/dtb/board.dtb"), None)
+            .unwrap();
+        extlinux
+            .set_entry_devicetree("rescue", None, Some("/dtb/"))
+            .unwrap();
+        assert!(extlinux
+            .set_entry_devicetree("rescue", Some("a.dtb"), Some("/dtb/"))
+            .is_err());
+
+        assert_eq!(
+            extlinux.as_string(),
+            "# extlinux.conf generated by appliance-tools\n\
+             ui menu.c32\n\
+             menu title Fedora Linux boot options\n\
+             menu autoboot Booting Fedora in # second{,s}.\n\
+             timeout 20\n\
+             default rescue\n\
+             prompt 0\n\
+             \n\
+             label Fedora-6.12.5\n\
+             \tkernel /vmlinuz-6.12.5-200.fc41.aarch64\n\
+             \tappend ro root=UUID=0abc console=ttyS0,115200\n\
+             \tinitrd /initramfs-6.12.5-200.fc41.aarch64.img\n\
+             \tfdt /dtb/board.dtb\n\
+             \n\
+             label rescue\n\
+             \tmenu label Fedora rescue\n\
+             \tlinux /vmlinuz-0-rescue\n\
+             \tinitrd /initramfs-0-rescue.img,/microcode.img\n\
+             \tappend ro single\n\
+             \tfdtdir /dtb/\n"
+        );
+    }
+}
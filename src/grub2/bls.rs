@@ -0,0 +1,248 @@
+use std::{
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+    system::write_file_atomic,
+};
+
+/// Boot Loader Specification entry, usually found in `/boot/loader/entries/*.conf`
+///
+/// The raw lines are kept so the entry can be written back without touching
+/// the parts we didn't modify.
+#[derive(Debug, Clone)]
+pub struct BlsEntry {
+    /// Name of the file without the `.conf` suffix, used by grub as the entry id
+    id: String,
+    lines: Vec<String>,
+}
+
+impl BlsEntry {
+    pub fn new<I: Into<String>>(id: I, contents: &str) -> Self {
+        Self {
+            id: id.into(),
+            lines: contents.split('\n').map(str::to_string).collect(),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> DResult<Self> {
+        let path = path.as_ref();
+        let id = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .ok_or_else(|| {
+                DError::grub_parse_error(dctx!(), format!("Invalid BLS entry path {path:?}"))
+            })?;
+        let contents =
+            read_to_string(path).ctx(dctx!(), format!("Cannot read BLS entry {path:?}"))?;
+        Ok(Self::new(id, &contents))
+    }
+
+    /// Read all the `.conf` entries from the BLS directory, sorted by their id
+    pub fn from_dir<P: AsRef<Path>>(path: P) -> DResult<Vec<Self>> {
+        let path = path.as_ref();
+        let dir = read_dir(path).ctx(dctx!(), format!("Cannot read BLS directory {path:?}"))?;
+
+        let mut files = Vec::new();
+        for file in dir {
+            let file = file.ctx(dctx!(), format!("Cannot read BLS directory {path:?}"))?;
+            let file_path = file.path();
+            if file_path.extension().is_some_and(|ext| ext == "conf") {
+                files.push(file_path);
+            }
+        }
+        files.sort();
+
+        files.iter().map(Self::from_file).collect()
+    }
+
+    fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.lines.iter().filter_map(move |line| {
+            let line = line.trim();
+            if line.starts_with('#') {
+                return None;
+            }
+            let (line_key, value) = line.split_once(char::is_whitespace)?;
+            (line_key == key).then(|| value.trim())
+        })
+    }
+
+    fn value<'a>(&'a self, key: &'a str) -> Option<&'a str> {
+        self.values(key).next()
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Title of the entry. Falls back to the id if title is not defined
+    pub fn title(&self) -> &str {
+        self.value("title").unwrap_or(&self.id)
+    }
+
+    /// Menu classes grub uses for the entry, from `grub_class` lines
+    pub fn classes(&self) -> Vec<String> {
+        self.values("grub_class")
+            .flat_map(str::split_whitespace)
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Kernel image of the entry
+    pub fn linux(&self) -> Option<&str> {
+        self.value("linux")
+    }
+
+    /// Initrd images of the entry. One line can list multiple images
+    pub fn initrds(&self) -> Vec<String> {
+        self.values("initrd")
+            .flat_map(str::split_whitespace)
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.value("version")
+    }
+
+    /// Device tree blob loaded for the kernel
+    pub fn devicetree(&self) -> Option<&str> {
+        self.value("devicetree")
+    }
+
+    /// Key systemd-boot orders the entries by before their versions
+    pub fn sort_key(&self) -> Option<&str> {
+        self.value("sort-key")
+    }
+
+    /// Kernel command line. Multiple `options` lines are joined with spaces
+    pub fn options(&self) -> String {
+        self.values("options").collect::<Vec<_>>().join(" ")
+    }
+
+    /// Replace the kernel command line with `options`
+    pub fn set_options(&mut self, options: &str) {
+        let mut replaced = false;
+        self.lines.retain_mut(|line| {
+            let is_options = line
+                .trim()
+                .split_once(char::is_whitespace)
+                .is_some_and(|(key, _)| key == "options");
+            if !is_options {
+                return true;
+            }
+
+            if replaced {
+                // only keep the first options line
+                return false;
+            }
+
+            replaced = true;
+            *line = format!("options {options}");
+            true
+        });
+
+        if !replaced {
+            // keep the trailing empty line at the end of the file
+            let idx = self
+                .lines
+                .iter()
+                .rposition(|line| !line.trim().is_empty())
+                .map_or(0, |idx| idx + 1);
+            self.lines.insert(idx, format!("options {options}"));
+        }
+    }
+
+    pub fn as_string(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    pub fn path<P: AsRef<Path>>(&self, dir: P) -> PathBuf {
+        dir.as_ref().join(format!("{}.conf", self.id))
+    }
+
+    /// Write the entry back to `dir`
+    pub fn write<P: AsRef<Path>>(&self, dir: P) -> DResult<()> {
+        let path = self.path(dir);
+        write_file_atomic(&path, &self.as_string())?;
+        log::debug!("BLS entry was written to {path:?}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bls_write() {
+        let dir = std::env::temp_dir().join(format!("bootkit-bls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut entry = BlsEntry::from_dir("test_data/loader/entries")
+            .unwrap()
+            .remove(0);
+        entry.set_options("root=UUID=0abc quiet");
+        entry.write(&dir).unwrap();
+
+        // only the options line changes, the classes and other lines are kept
+        let written = BlsEntry::from_file(entry.path(&dir)).unwrap();
+        assert_eq!(written.options(), "root=UUID=0abc quiet");
+        assert_eq!(written.classes(), vec!["fedora"]);
+        assert_eq!(written.as_string(), entry.as_string());
+        assert!(!dir.join(format!("{}.conf.new", entry.id())).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bls_parsing() {
+        let entries = BlsEntry::from_dir("test_data/loader/entries").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].id(),
+            "4c1d5b8ad1e94d1c8f3b1e2a3d4c5b6a-6.11.4-301.fc41.x86_64"
+        );
+        assert_eq!(
+            entries[0].title(),
+            "Fedora Linux (6.11.4-301.fc41.x86_64) 41 (Workstation Edition)"
+        );
+        assert_eq!(
+            entries[0].options(),
+            "root=UUID=5d2b1a6e-8f3c-4e7a-9b1d-2c3e4f5a6b7c ro rhgb quiet"
+        );
+        assert_eq!(entries[0].classes(), vec!["fedora"]);
+        assert_eq!(entries[0].linux(), Some("/vmlinuz-6.11.4-301.fc41.x86_64"));
+        assert_eq!(
+            entries[0].initrds(),
+            vec!["/initramfs-6.11.4-301.fc41.x86_64.img"]
+        );
+        assert_eq!(entries[0].version(), Some("6.11.4-301.fc41.x86_64"));
+        assert_eq!(
+            entries[1].title(),
+            "Fedora Linux (6.12.5-200.fc41.x86_64) 41 (Workstation Edition)"
+        );
+    }
+
+    #[test]
+    fn test_bls_set_options() {
+        let mut entry = BlsEntry::new(
+            "test",
+            "title Test\nlinux /vmlinuz\noptions root=/dev/sda1\noptions quiet\n",
+        );
+        entry.set_options("root=/dev/sda2 splash");
+        assert_eq!(entry.options(), "root=/dev/sda2 splash");
+        assert_eq!(
+            entry.as_string(),
+            "title Test\nlinux /vmlinuz\noptions root=/dev/sda2 splash\n"
+        );
+
+        let mut entry = BlsEntry::new("test", "title Test\nlinux /vmlinuz\n");
+        entry.set_options("quiet");
+        assert_eq!(
+            entry.as_string(),
+            "title Test\nlinux /vmlinuz\noptions quiet\n"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    fs::{remove_file, write, DirBuilder},
+    io::ErrorKind,
+    os::unix::fs::DirBuilderExt,
+    path::Path,
+    process::Command,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    config::{layout::GrubLayout, VALIDATE_DIR},
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+/// Keeps the files of concurrent [`config_syntax_error`] calls apart
+static CHECK_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Check the syntax of a generated grub.cfg with grub2-script-check.
+///
+/// The check is skipped if grub2-script-check is not installed, since it's
+/// only an extra safety net on top of grub2-mkconfig.
+pub fn check_script<P: AsRef<Path>>(path: P) -> DResult<()> {
+    let path = path.as_ref();
+    match script_check_output(path)? {
+        None => Ok(()),
+        Some(errors) => Err(DError::generic(
+            dctx!(),
+            format!("Generated grub config {path:?} has syntax errors: {errors}"),
+        )),
+    }
+}
+
+/// Syntax errors that grub2-script-check finds in `content`, the contents of a
+/// grub config that aren't on disk yet. The content is checked in a temporary
+/// file in a directory only root can read, nothing else is written
+pub fn config_syntax_error(content: &str) -> DResult<Option<String>> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(VALIDATE_DIR)
+        .ctx(dctx!(), format!("Cannot create {VALIDATE_DIR}"))?;
+    let count = CHECK_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = Path::new(VALIDATE_DIR).join(format!("grub-{}-{count}", std::process::id()));
+    write(&path, content).ctx(dctx!(), format!("Cannot write {path:?}"))?;
+
+    let result = script_check_output(&path);
+    // the file is only used for this check so failing to remove it is fine
+    let _ = remove_file(&path);
+    result
+}
+
+/// Output of grub2-script-check on `path` if it found errors
+fn script_check_output(path: &Path) -> DResult<Option<String>> {
+    let script_check = GrubLayout::get().script_check;
+    log::debug!("Calling {script_check} {path:?}");
+
+    let output = match Command::new(script_check).arg(path).output() {
+        Ok(output) => output,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            log::debug!("{script_check} is not installed, skipping the syntax check");
+            return Ok(None);
+        }
+        Err(err) => {
+            return Err(DError::generic(
+                dctx!(),
+                format!("Failed to run {script_check}: {err}"),
+            ))
+        }
+    };
+
+    if output.status.success() {
+        return Ok(None);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(Some(format!("{stdout}{stderr}").trim().to_string()))
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Single kernel command line parameter, either a flag like `quiet`
+/// or key value pair like `mitigations=auto`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CmdLineParam {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl CmdLineParam {
+    fn parse(token: &str) -> Self {
+        match token.split_once('=') {
+            Some((key, value)) => Self {
+                key: key.into(),
+                value: Some(value.into()),
+            },
+            None => Self {
+                key: token.into(),
+                value: None,
+            },
+        }
+    }
+}
+
+impl Display for CmdLineParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={value}", self.key),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+/// Parameters the boot loader adds to the command line itself, so they
+/// never show up in the configured command line
+const LOADER_PARAMS: &[&str] = &["BOOT_IMAGE", "initrd", "root", "rootflags", "ro", "rw"];
+
+/// Parameters the kernel or initrd accept multiple times, setting one of them
+/// must not drop the other values
+const REPEATABLE_PARAMS: &[&str] = &[
+    "console",
+    "ip",
+    "rd.dasd",
+    "rd.luks.uuid",
+    "rd.lvm.lv",
+    "rd.md.uuid",
+    "rd.zfcp",
+    "rd.znet",
+];
+
+/// Parameter whose values differ between two command lines. Parameters like
+/// `console` can be given multiple times so all the values are listed
+/// in order, an empty list means the parameter is missing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ParamDiff {
+    pub key: String,
+    pub configured: Vec<Option<String>>,
+    pub running: Vec<Option<String>>,
+}
+
+/// Kernel command line, like GRUB_CMDLINE_LINUX_DEFAULT, split into ordered parameters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CmdLine {
+    params: Vec<CmdLineParam>,
+}
+
+impl CmdLine {
+    pub fn new(cmdline: &str) -> Self {
+        let mut tokens = Vec::new();
+        let mut token = String::new();
+        let mut quoted = false;
+
+        // parameters are separated with whitespace, unless the whitespace is quoted
+        for ch in cmdline.chars() {
+            if ch == '"' {
+                quoted = !quoted;
+            }
+
+            if ch.is_whitespace() && !quoted {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            } else {
+                token.push(ch);
+            }
+        }
+
+        if !token.is_empty() {
+            tokens.push(token);
+        }
+
+        Self {
+            params: tokens
+                .iter()
+                .map(|token| CmdLineParam::parse(token))
+                .collect(),
+        }
+    }
+
+    pub fn params(&self) -> &[CmdLineParam] {
+        &self.params
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.params.iter().any(|param| param.key == key)
+    }
+
+    /// Value of the first parameter named `key`. `Some(None)` means the parameter is a flag
+    pub fn get(&self, key: &str) -> Option<Option<&str>> {
+        self.params
+            .iter()
+            .find(|param| param.key == key)
+            .map(|param| param.value.as_deref())
+    }
+
+    /// Append a parameter to the end of the command line, even if it already exists
+    /// since some parameters like `console` can be defined multiple times
+    pub fn add_param(&mut self, key: &str, value: Option<&str>) {
+        self.params.push(CmdLineParam {
+            key: key.into(),
+            value: value.map(str::to_string),
+        });
+    }
+
+    /// Parameters that differ between this configured command line and the command
+    /// line of the `running` kernel, ignoring the ones the boot loader adds itself
+    pub fn diff(&self, running: &CmdLine) -> Vec<ParamDiff> {
+        fn values(cmdline: &CmdLine) -> BTreeMap<&str, Vec<Option<String>>> {
+            let mut values: BTreeMap<&str, Vec<Option<String>>> = BTreeMap::new();
+            for param in &cmdline.params {
+                if !LOADER_PARAMS.contains(&param.key.as_str()) {
+                    values
+                        .entry(param.key.as_str())
+                        .or_default()
+                        .push(param.value.clone());
+                }
+            }
+            values
+        }
+
+        let mut configured = values(self);
+        let mut running = values(running);
+        let keys: BTreeSet<&str> = configured.keys().chain(running.keys()).copied().collect();
+        keys.into_iter()
+            .filter_map(|key| {
+                let configured = configured.remove(key).unwrap_or_default();
+                let running = running.remove(key).unwrap_or_default();
+                (configured != running).then(|| ParamDiff {
+                    key: key.to_string(),
+                    configured,
+                    running,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove all the parameters named `key`. Returns true if anything was removed
+    pub fn remove_param(&mut self, key: &str) -> bool {
+        let len = self.params.len();
+        self.params.retain(|param| param.key != key);
+        len != self.params.len()
+    }
+
+    /// Make sure the `present` parameters, like `quiet` or `splash=silent`, are set
+    /// and the parameters named in `absent` aren't. Parameters that can be given
+    /// multiple times, like `console`, are added next to the existing values.
+    /// Returns true if anything changed
+    pub fn reconcile(&mut self, present: &[String], absent: &[String]) -> bool {
+        let present: Vec<CmdLineParam> = present
+            .iter()
+            .map(|param| CmdLineParam::parse(param))
+            .collect();
+        let mut changed = false;
+        for param in &present {
+            if self.params.contains(param) {
+                continue;
+            }
+            let repeatable = REPEATABLE_PARAMS.contains(&param.key.as_str())
+                || present.iter().filter(|p| p.key == param.key).count() > 1;
+            if repeatable {
+                self.add_param(&param.key, param.value.as_deref());
+            } else {
+                self.set_param(&param.key, param.value.as_deref());
+            }
+            changed = true;
+        }
+        for key in absent {
+            changed |= self.remove_param(key);
+        }
+        changed
+    }
+
+    /// Set the value of `key`, keeping the position of the first occurrence.
+    /// Other occurrences are removed. Parameter is added if it doesn't exist.
+    pub fn set_param(&mut self, key: &str, value: Option<&str>) {
+        let Some(idx) = self.params.iter().position(|param| param.key == key) else {
+            self.add_param(key, value);
+            return;
+        };
+
+        self.params[idx].value = value.map(str::to_string);
+        let mut seen = false;
+        self.params.retain(|param| {
+            if param.key != key {
+                return true;
+            }
+            let keep = !seen;
+            seen = true;
+            keep
+        });
+    }
+}
+
+impl Display for CmdLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let params: Vec<String> = self.params.iter().map(|param| param.to_string()).collect();
+        write!(f, "{}", params.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmdline_parse() {
+        let cmdline =
+            CmdLine::new("splash=silent quiet  security=apparmor foo=\"a b\" mitigations=auto");
+        assert_eq!(cmdline.params.len(), 5);
+        assert_eq!(cmdline.get("splash"), Some(Some("silent")));
+        assert_eq!(cmdline.get("quiet"), Some(None));
+        assert_eq!(cmdline.get("foo"), Some(Some("\"a b\"")));
+        assert_eq!(cmdline.get("missing"), None);
+        assert!(cmdline.contains("mitigations"));
+        assert_eq!(
+            cmdline.to_string(),
+            "splash=silent quiet security=apparmor foo=\"a b\" mitigations=auto"
+        );
+
+        assert_eq!(CmdLine::new("").to_string(), "");
+    }
+
+    #[test]
+    fn test_cmdline_reconcile() {
+        let mut cmdline = CmdLine::new("splash=silent quiet mitigations=auto");
+        let present = vec![
+            "quiet".to_string(),
+            "splash=verbose".into(),
+            "nomodeset".into(),
+        ];
+        let absent = vec!["mitigations".to_string()];
+        assert!(cmdline.reconcile(&present, &absent));
+        assert_eq!(cmdline.to_string(), "splash=verbose quiet nomodeset");
+        // reconciling again changes nothing
+        assert!(!cmdline.reconcile(&present, &absent));
+
+        // repeatable parameters keep the other values
+        let mut cmdline = CmdLine::new("console=tty1 quiet");
+        let present = vec!["console=ttyS0".to_string()];
+        assert!(cmdline.reconcile(&present, &[]));
+        assert_eq!(cmdline.to_string(), "console=tty1 quiet console=ttyS0");
+        assert!(!cmdline.reconcile(&present, &[]));
+
+        // so do parameters that are asked for multiple times
+        let mut cmdline = CmdLine::new("foo=a");
+        let present = vec!["foo=b".to_string(), "foo=c".into()];
+        assert!(cmdline.reconcile(&present, &[]));
+        assert_eq!(cmdline.to_string(), "foo=a foo=b foo=c");
+        assert!(!cmdline.reconcile(&present, &[]));
+    }
+
+    #[test]
+    fn test_cmdline_edit() {
+        let mut cmdline = CmdLine::new("console=tty1 quiet console=ttyS0,115200n8");
+        cmdline.add_param("splash", Some("silent"));
+        assert_eq!(
+            cmdline.to_string(),
+            "console=tty1 quiet console=ttyS0,115200n8 splash=silent"
+        );
+
+        assert!(cmdline.remove_param("quiet"));
+        assert!(!cmdline.remove_param("quiet"));
+        assert_eq!(
+            cmdline.to_string(),
+            "console=tty1 console=ttyS0,115200n8 splash=silent"
+        );
+
+        cmdline.set_param("console", Some("ttyS1"));
+        assert_eq!(cmdline.to_string(), "console=ttyS1 splash=silent");
+
+        cmdline.set_param("quiet", None);
+        assert_eq!(cmdline.to_string(), "console=ttyS1 splash=silent quiet");
+    }
+
+    #[test]
+    fn test_cmdline_diff() {
+        let configured =
+            CmdLine::new("splash=silent quiet console=tty1 console=ttyS0 mitigations=off");
+        let running = CmdLine::new(
+            "BOOT_IMAGE=/boot/vmlinuz root=UUID=1234 ro splash=silent console=tty1 mitigations=auto resume=/dev/sda2",
+        );
+
+        let diffs = configured.diff(&running);
+        let keys: Vec<&str> = diffs.iter().map(|diff| diff.key.as_str()).collect();
+        assert_eq!(keys, ["console", "mitigations", "quiet", "resume"]);
+        assert_eq!(
+            diffs[0].configured,
+            [Some("tty1".to_string()), Some("ttyS0".to_string())]
+        );
+        assert_eq!(diffs[0].running, [Some("tty1".to_string())]);
+        assert_eq!(diffs[2].configured, [None]);
+        assert!(diffs[2].running.is_empty());
+        assert!(diffs[3].configured.is_empty());
+
+        assert!(running.diff(&running).is_empty());
+    }
+}
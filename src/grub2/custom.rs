@@ -0,0 +1,378 @@
+use std::{
+    fs::{read_to_string, set_permissions, Permissions},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::script::GrubScript,
+    system::write_file_atomic,
+};
+
+/// grub.d script for the entries written by the user
+pub const CUSTOM_SCRIPT: &str = "40_custom";
+/// File that 41_custom sources at boot time, next to grub.cfg
+pub const CUSTOM_CFG: &str = "custom.cfg";
+
+/// 40_custom prints everything after its first two lines
+const CUSTOM_SCRIPT_HEADER: &[&str] = &["#!/bin/sh", "exec tail -n +3 $0"];
+
+/// User defined menuentry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CustomEntry {
+    pub title: String,
+    /// File the entry is defined in
+    pub source: String,
+    /// Whole menuentry block
+    pub script: String,
+}
+
+#[derive(Debug)]
+enum Block {
+    Line(String),
+    Entry { title: String, lines: Vec<String> },
+}
+
+/// Template of a custom entry that clients can fill without writing grub script
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "template", rename_all = "snake_case")]
+pub enum EntryTemplate {
+    /// Chainload an EFI binary of another OS, like the Windows Boot Manager
+    Chainload {
+        /// Filesystem UUID of the partition that has the EFI binary
+        uuid: String,
+        efi_path: String,
+    },
+    /// Boot a kernel from an ISO image
+    Iso {
+        /// Path to the ISO image on any filesystem grub can read
+        iso_path: String,
+        /// Kernel path inside the ISO image
+        kernel: String,
+        /// Initrd path inside the ISO image
+        initrd: String,
+        #[serde(default)]
+        args: String,
+    },
+    /// Grub script that goes inside the menuentry block
+    Raw { body: String },
+}
+
+/// Check that a template value can be put into the script without quoting
+fn plain_value(name: &str, value: &str) -> Result<(), (String, String)> {
+    let special = |ch: char| "'\"`$\\{};&|<>#".contains(ch) || ch.is_whitespace();
+    if value.is_empty() || value.contains(special) {
+        return Err((
+            name.into(),
+            format!("'{value}' can't be empty or contain whitespace, quotes or grub script syntax"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Braces of the grub script need to be balanced so the body can't end the menuentry
+fn balanced(body: &str) -> bool {
+    let mut depth = 0i32;
+    for ch in body.chars() {
+        match ch {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    depth == 0
+}
+
+impl EntryTemplate {
+    /// Body of the menuentry and the class of the entry
+    fn body(&self) -> Result<(String, &'static str), (String, String)> {
+        match self {
+            Self::Chainload { uuid, efi_path } => {
+                plain_value("uuid", uuid)?;
+                plain_value("efi_path", efi_path)?;
+                let body = [
+                    "insmod part_gpt".to_string(),
+                    "insmod fat".to_string(),
+                    "insmod chain".to_string(),
+                    format!("search --no-floppy --fs-uuid --set=root {uuid}"),
+                    format!("chainloader {efi_path}"),
+                ];
+                Ok((body.join("\n"), "os"))
+            }
+            Self::Iso {
+                iso_path,
+                kernel,
+                initrd,
+                args,
+            } => {
+                plain_value("iso_path", iso_path)?;
+                plain_value("kernel", kernel)?;
+                plain_value("initrd", initrd)?;
+                for arg in args.split_whitespace() {
+                    plain_value("args", arg)?;
+                }
+                let body = [
+                    "insmod loopback".to_string(),
+                    "insmod iso9660".to_string(),
+                    format!("set isofile=\"{iso_path}\""),
+                    "search --no-floppy --file --set=root $isofile".to_string(),
+                    "loopback loop $isofile".to_string(),
+                    format!("linux (loop){kernel} iso-scan/filename=$isofile {args}")
+                        .trim_end()
+                        .to_string(),
+                    format!("initrd (loop){initrd}"),
+                ];
+                Ok((body.join("\n"), "iso"))
+            }
+            Self::Raw { body } => {
+                if !balanced(body) {
+                    return Err(("body".into(), "braces of the body are not balanced".into()));
+                }
+                Ok((body.trim().to_string(), "os"))
+            }
+        }
+    }
+
+    /// Whole menuentry block for `title`
+    pub fn render(&self, title: &str) -> Result<String, (String, String)> {
+        if title.trim().is_empty() || title.contains('\n') {
+            return Err((
+                "title".into(),
+                "title must be a non-empty single line".into(),
+            ));
+        }
+
+        let (body, class) = self.body()?;
+        let title = title.replace('\'', "'\\''");
+        let body: Vec<String> = body.lines().map(|line| format!("    {line}")).collect();
+        Ok(format!(
+            "menuentry '{title}' --class {class} {{\n{}\n}}",
+            body.join("\n")
+        ))
+    }
+}
+
+/// 40_custom or custom.cfg split into the menuentries and the rest of the lines
+#[derive(Debug)]
+pub struct CustomFile {
+    path: PathBuf,
+    /// Lines that are not part of the generated grub.cfg
+    header: Vec<String>,
+    blocks: Vec<Block>,
+}
+
+impl CustomFile {
+    /// Read `path`. `script` is set for 40_custom, where the first two lines
+    /// are the shell script that prints the rest. Missing file has no entries
+    pub fn read<P: AsRef<Path>>(path: P, script: bool) -> DResult<Self> {
+        let path = path.as_ref();
+        let contents = if path.exists() {
+            read_to_string(path).ctx(dctx!(), format!("Cannot read {path:?}"))?
+        } else if script {
+            CUSTOM_SCRIPT_HEADER.join("\n")
+        } else {
+            String::new()
+        };
+
+        Ok(Self::from_contents(path, &contents, script))
+    }
+
+    fn from_contents(path: &Path, contents: &str, script: bool) -> Self {
+        let mut lines = contents.lines().map(str::to_string);
+        let header = if script {
+            lines.by_ref().take(CUSTOM_SCRIPT_HEADER.len()).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut blocks = Vec::new();
+        let mut depth = 0i32;
+        for line in lines {
+            if let Some(Block::Entry { lines, .. }) = blocks.last_mut() {
+                if depth > 0 {
+                    depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+                    lines.push(line);
+                    continue;
+                }
+            }
+
+            let title = GrubScript::command_args(&line, "menuentry")
+                .and_then(|args| GrubScript::default().parse_word(args))
+                .map(|(title, _)| title);
+            match title {
+                Some(title) => {
+                    depth = line.matches('{').count() as i32 - line.matches('}').count() as i32;
+                    blocks.push(Block::Entry {
+                        title,
+                        lines: vec![line],
+                    });
+                }
+                None => blocks.push(Block::Line(line)),
+            }
+        }
+
+        Self {
+            path: path.to_path_buf(),
+            header,
+            blocks,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// File is the 40_custom script instead of a plain grub script
+    pub fn is_script(&self) -> bool {
+        !self.header.is_empty()
+    }
+
+    pub fn entries(&self) -> Vec<CustomEntry> {
+        self.blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Entry { title, lines } => Some(CustomEntry {
+                    title: title.clone(),
+                    source: self.path.to_string_lossy().to_string(),
+                    script: lines.join("\n"),
+                }),
+                Block::Line(_) => None,
+            })
+            .collect()
+    }
+
+    /// Append the `entry` block rendered by [`EntryTemplate::render`]
+    pub fn add(&mut self, title: &str, entry: &str) -> DResult<()> {
+        if self
+            .entries()
+            .iter()
+            .any(|existing| existing.title == title)
+        {
+            return Err(DError::invalid_values(
+                dctx!(),
+                vec![("title".into(), format!("entry '{title}' already exists"))],
+            ));
+        }
+
+        let separated = match self.blocks.last() {
+            Some(Block::Line(line)) => line.trim().is_empty(),
+            Some(Block::Entry { .. }) => false,
+            None => true,
+        };
+        if !separated {
+            self.blocks.push(Block::Line(String::new()));
+        }
+        self.blocks.push(Block::Entry {
+            title: title.to_string(),
+            lines: entry.lines().map(str::to_string).collect(),
+        });
+
+        Ok(())
+    }
+
+    /// Remove the entries called `title`. Returns false if there was none
+    pub fn remove(&mut self, title: &str) -> bool {
+        let count = self.blocks.len();
+        self.blocks
+            .retain(|block| !matches!(block, Block::Entry { title: entry, .. } if entry == title));
+        count != self.blocks.len()
+    }
+
+    pub fn as_string(&self) -> String {
+        let lines = self
+            .header
+            .iter()
+            .cloned()
+            .chain(self.blocks.iter().flat_map(|block| match block {
+                Block::Line(line) => vec![line.clone()],
+                Block::Entry { lines, .. } => lines.clone(),
+            }));
+        let mut contents = lines.collect::<Vec<_>>().join("\n");
+        contents.push('\n');
+        contents
+    }
+
+    pub fn write(&self) -> DResult<()> {
+        Self::write_contents(&self.path, &self.as_string(), self.is_script())
+    }
+
+    /// Write `contents` to `path`, 40_custom needs to be executable for grub2-mkconfig
+    pub fn write_contents(path: &Path, contents: &str, script: bool) -> DResult<()> {
+        write_file_atomic(path, contents)?;
+        if script {
+            set_permissions(path, Permissions::from_mode(0o755))
+                .ctx(dctx!(), format!("Failed to make {path:?} executable"))?;
+        }
+        log::debug!("Custom entries were written to {path:?}");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_entries() {
+        let contents = read_to_string("test_data/40_custom").unwrap();
+        let mut custom = CustomFile::from_contents(Path::new("40_custom"), &contents, true);
+        let entries = custom.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title, "Windows Boot Manager");
+        assert_eq!(entries[1].title, "Memtest");
+        assert!(entries[1].script.ends_with("    fi\n}"));
+        assert_eq!(custom.as_string(), contents);
+
+        assert!(custom.remove("Windows Boot Manager"));
+        assert!(!custom.remove("Windows Boot Manager"));
+        assert_eq!(custom.entries().len(), 1);
+        assert!(custom
+            .as_string()
+            .starts_with("#!/bin/sh\nexec tail -n +3 $0\n"));
+
+        let missing = CustomFile::read("test_data/missing/40_custom", true).unwrap();
+        assert!(missing.entries().is_empty());
+        assert_eq!(missing.as_string(), "#!/bin/sh\nexec tail -n +3 $0\n");
+    }
+
+    #[test]
+    fn test_custom_templates() {
+        let iso = EntryTemplate::Iso {
+            iso_path: "/iso/rescue.iso".into(),
+            kernel: "/boot/vmlinuz".into(),
+            initrd: "/boot/initrd".into(),
+            args: "quiet".into(),
+        };
+        let entry = iso.render("Rescue's ISO").unwrap();
+        assert!(entry.starts_with("menuentry 'Rescue'\\''s ISO' --class iso {\n"));
+        assert!(
+            entry.contains("\n    linux (loop)/boot/vmlinuz iso-scan/filename=$isofile quiet\n")
+        );
+
+        let mut custom = CustomFile::from_contents(Path::new("custom.cfg"), "", false);
+        custom.add("Rescue's ISO", &entry).unwrap();
+        assert_eq!(custom.entries()[0].title, "Rescue's ISO");
+        assert!(custom.add("Rescue's ISO", &entry).is_err());
+
+        let chainload = EntryTemplate::Chainload {
+            uuid: "1234-ABCD; reboot".into(),
+            efi_path: "/EFI/Microsoft/Boot/bootmgfw.efi".into(),
+        };
+        assert_eq!(chainload.render("Windows").unwrap_err().0, "uuid");
+
+        let raw = EntryTemplate::Raw {
+            body: "}\nmenuentry 'evil' {".into(),
+        };
+        assert!(raw.render("Raw").is_err());
+        assert!(iso.render("").is_err());
+    }
+}
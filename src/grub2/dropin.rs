@@ -0,0 +1,454 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_dir, read_to_string, remove_file},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{GRUB_DROPIN_PATH, GRUB_FILE_PATH},
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::{GrubFile, ParseMode, RemoveMode},
+    system::write_file_atomic,
+};
+
+/// Drop-in that is created for new keys if the drop-in directory is used
+pub const BOOTKIT_DROPIN: &str = "90-bootkitd.cfg";
+
+/// Grub config file that is part of the effective configuration
+#[derive(Debug)]
+struct ConfigFile {
+    path: PathBuf,
+    grub: GrubFile,
+    changed: bool,
+}
+
+/// Effective value of a key after all the drop-ins are applied
+#[derive(Debug, Clone, Serialize)]
+pub struct EffectiveValue {
+    pub value: String,
+    /// File where the value comes from
+    pub source: String,
+}
+
+/// /etc/default/grub combined with the drop-ins in /etc/default/grub.d/*.cfg
+///
+/// Drop-ins are sourced in lexical order after the main file, so the last
+/// definition of a key wins.
+#[derive(Debug)]
+pub struct GrubConfig {
+    main: ConfigFile,
+    dropins: Vec<ConfigFile>,
+    dropin_dir: Option<PathBuf>,
+}
+
+impl GrubConfig {
+    pub fn read(mode: ParseMode) -> DResult<Self> {
+        Self::from_paths(GRUB_FILE_PATH, GRUB_DROPIN_PATH, mode)
+    }
+
+    pub fn from_paths<P: AsRef<Path>, D: AsRef<Path>>(
+        main: P,
+        dropin_dir: D,
+        mode: ParseMode,
+    ) -> DResult<Self> {
+        let main = ConfigFile {
+            path: main.as_ref().to_path_buf(),
+            grub: GrubFile::from_file(main, mode)?,
+            changed: false,
+        };
+
+        let dropin_dir = dropin_dir.as_ref();
+        if !dropin_dir.is_dir() {
+            return Ok(Self {
+                main,
+                dropins: Vec::new(),
+                dropin_dir: None,
+            });
+        }
+
+        let dir = read_dir(dropin_dir).ctx(
+            dctx!(),
+            format!("Cannot read drop-in directory {dropin_dir:?}"),
+        )?;
+
+        let mut paths = Vec::new();
+        for file in dir {
+            let file = file.ctx(
+                dctx!(),
+                format!("Cannot read drop-in directory {dropin_dir:?}"),
+            )?;
+            let path = file.path();
+            if path.extension().is_some_and(|ext| ext == "cfg") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut dropins = Vec::new();
+        for path in paths {
+            log::debug!("Reading grub drop-in {path:?}");
+            dropins.push(ConfigFile {
+                grub: GrubFile::from_file(&path, mode)?,
+                path,
+                changed: false,
+            });
+        }
+
+        Ok(Self {
+            main,
+            dropins,
+            dropin_dir: Some(dropin_dir.to_path_buf()),
+        })
+    }
+
+    fn files(&self) -> impl Iterator<Item = &ConfigFile> {
+        std::iter::once(&self.main).chain(self.dropins.iter())
+    }
+
+    /// Parse warnings and duplicate keys of all the files, prefixed with the file they're from
+    pub fn warnings(&self) -> Vec<String> {
+        self.files()
+            .flat_map(|file| {
+                let duplicates = file.grub.duplicate_keys().into_iter().map(|key| {
+                    format!("{key} is defined multiple times, the last definition is used")
+                });
+                file.grub
+                    .warnings()
+                    .iter()
+                    .cloned()
+                    .chain(duplicates)
+                    .map(|warning| format!("{}: {warning}", file.path.display()))
+            })
+            .collect()
+    }
+
+    /// Paths of the main file and the drop-ins
+    pub fn paths(&self) -> Vec<&Path> {
+        self.files().map(|file| file.path.as_path()).collect()
+    }
+
+    pub fn main(&self) -> &GrubFile {
+        &self.main.grub
+    }
+
+    pub fn main_mut(&mut self) -> &mut GrubFile {
+        &mut self.main.grub
+    }
+
+    /// All the keys with the value that grub2-mkconfig will see
+    pub fn effective(&self) -> BTreeMap<String, EffectiveValue> {
+        let mut values = BTreeMap::new();
+        for file in self.files() {
+            for (key, keyval) in file.grub.keyvalues() {
+                values.insert(
+                    key.clone(),
+                    EffectiveValue {
+                        value: keyval.value.clone(),
+                        source: file.path.to_string_lossy().to_string(),
+                    },
+                );
+            }
+        }
+
+        values
+    }
+
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.files()
+            .filter_map(|file| file.grub.keyvalues().get(key))
+            .last()
+            .map(|keyval| keyval.value.as_str())
+    }
+
+    /// Keys that `main` would change in the main file but that a drop-in overrides,
+    /// with the reason. Writing those to the main file wouldn't change the config
+    pub fn overridden_changes(&self, main: &GrubFile) -> Vec<(String, String)> {
+        let current = self.main.grub.keyvalues();
+        let new = main.keyvalues();
+        let mut keys: Vec<&String> = current.keys().chain(new.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        keys.into_iter()
+            .filter(|key| {
+                current.get(*key).map(|keyval| &keyval.value)
+                    != new.get(*key).map(|keyval| &keyval.value)
+            })
+            .filter_map(|key| {
+                let dropin = self
+                    .dropins
+                    .iter()
+                    .rfind(|file| file.grub.keyvalues().contains_key(key))?;
+                Some((
+                    key.clone(),
+                    format!(
+                        "{key} is overridden by {}, set it with SetConfigValues",
+                        dropin.path.display()
+                    ),
+                ))
+            })
+            .collect()
+    }
+
+    /// Set the key in the file that defines the effective value.
+    ///
+    /// New keys go to `90-bootkitd.cfg` if the drop-in directory exists, otherwise
+    /// to the main file.
+    pub fn set_key_value(&mut self, key: &str, value: &str) {
+        let defining = self
+            .dropins
+            .iter()
+            .rposition(|file| file.grub.keyvalues().contains_key(key));
+
+        if let Some(idx) = defining {
+            let file = &mut self.dropins[idx];
+            log::debug!("Setting {key} in drop-in {:?}", file.path);
+            file.grub.set_key_value(key, value);
+            file.changed = true;
+            return;
+        }
+
+        match &self.dropin_dir {
+            Some(dir) if !self.main.grub.keyvalues().contains_key(key) => {
+                let path = dir.join(BOOTKIT_DROPIN);
+                let idx = match self.dropins.iter().position(|file| file.path == path) {
+                    Some(idx) => idx,
+                    None => {
+                        self.dropins.push(ConfigFile {
+                            path,
+                            grub: GrubFile::from_lines(&[]),
+                            changed: false,
+                        });
+                        // keep the lexical order so the effective values stay correct
+                        self.dropins.sort_by(|a, b| a.path.cmp(&b.path));
+                        self.dropins
+                            .iter()
+                            .position(|file| file.path.ends_with(BOOTKIT_DROPIN))
+                            .expect("bootkit drop-in was just added")
+                    }
+                };
+                let file = &mut self.dropins[idx];
+                log::debug!("Setting {key} in drop-in {:?}", file.path);
+                file.grub.set_key_value(key, value);
+                file.changed = true;
+            }
+            _ => self.main.grub.set_key_value(key, value),
+        }
+    }
+
+    /// Remove `key` from the main file and all the drop-ins defining it so grub
+    /// uses its default value. Returns false if the key is not set anywhere
+    pub fn remove_key(&mut self, key: &str, mode: RemoveMode) -> bool {
+        let mut removed = self.main.grub.remove_key(key, mode);
+        for file in &mut self.dropins {
+            if file.grub.remove_key(key, mode) {
+                log::debug!("Removed {key} from drop-in {:?}", file.path);
+                file.changed = true;
+                removed = true;
+            }
+        }
+
+        removed
+    }
+
+    /// Validate the changed keys of the main file and the drop-ins, see
+    /// [`GrubFile::validate_changed`]
+    pub fn validate_changed(&self) -> DResult<()> {
+        let invalid: Vec<(String, String)> = self
+            .files()
+            .flat_map(|file| file.grub.invalid_changed())
+            .collect();
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(DError::invalid_values(dctx!(), invalid))
+        }
+    }
+
+    /// Contents of the changed drop-ins on disk, none for the ones that don't exist yet,
+    /// so the drop-ins can be restored if the rest of the change fails
+    pub fn dropin_backup(&self) -> Vec<(PathBuf, Option<String>)> {
+        self.dropins
+            .iter()
+            .filter(|file| file.changed)
+            .map(|file| (file.path.clone(), read_to_string(&file.path).ok()))
+            .collect()
+    }
+
+    /// Undo [`Self::write_dropins`] with the contents from [`Self::dropin_backup`]
+    pub fn restore_dropins(backup: &[(PathBuf, Option<String>)]) -> DResult<()> {
+        for (path, contents) in backup {
+            match contents {
+                Some(contents) => write_file_atomic(path, contents)?,
+                None => remove_file(path)
+                    .ctx(dctx!(), format!("Failed to remove grub drop-in {path:?}"))?,
+            }
+            log::debug!("Restored grub drop-in {path:?}");
+        }
+        Ok(())
+    }
+
+    /// Write the changed drop-ins. Main file is written separately since it's part of
+    /// the snapshots.
+    pub fn write_dropins(&mut self) -> DResult<()> {
+        for file in self.dropins.iter_mut().filter(|file| file.changed) {
+            let mut contents = file.grub.as_string();
+            if !contents.ends_with('\n') {
+                contents.push('\n');
+            }
+
+            write_file_atomic(&file.path, &contents)?;
+            log::debug!("Grub drop-in was written to {:?}", file.path);
+            file.changed = false;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::DErrorType;
+
+    #[test]
+    fn test_dropin_effective_values() {
+        let config = GrubConfig::from_paths(
+            "test_data/grub_simple",
+            "test_data/grub.d",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        let effective = config.effective();
+
+        assert_eq!(effective["GRUB_TIMEOUT"].value, "3");
+        assert_eq!(
+            effective["GRUB_TIMEOUT"].source,
+            "test_data/grub.d/20-timeout.cfg"
+        );
+        assert_eq!(effective["GRUB_DEFAULT"].value, "saved");
+        assert_eq!(effective["GRUB_DEFAULT"].source, "test_data/grub_simple");
+        assert_eq!(effective["GRUB_CMDLINE_LINUX_DEFAULT"].value, "quiet");
+        assert_eq!(config.value("GRUB_TIMEOUT"), Some("3"));
+    }
+
+    #[test]
+    fn test_dropin_set_key_value() {
+        let mut config = GrubConfig::from_paths(
+            "test_data/grub_simple",
+            "test_data/grub.d",
+            ParseMode::Strict,
+        )
+        .unwrap();
+
+        // overridden key is written to the drop-in
+        config.set_key_value("GRUB_TIMEOUT", "10");
+        assert!(config.dropins[1].changed);
+        assert_eq!(config.main().keyvalues()["GRUB_TIMEOUT"].value, "8");
+        assert_eq!(config.value("GRUB_TIMEOUT"), Some("10"));
+
+        // key only in the main file stays in the main file
+        config.set_key_value("GRUB_DEFAULT", "0");
+        assert_eq!(config.main().keyvalues()["GRUB_DEFAULT"].value, "0");
+
+        // new keys go to the bootkit drop-in
+        config.set_key_value("GRUB_DISABLE_OS_PROBER", "true");
+        let effective = config.effective();
+        assert_eq!(
+            effective["GRUB_DISABLE_OS_PROBER"].source,
+            "test_data/grub.d/90-bootkitd.cfg"
+        );
+        assert!(!config
+            .main()
+            .keyvalues()
+            .contains_key("GRUB_DISABLE_OS_PROBER"));
+    }
+
+    #[test]
+    fn test_dropin_validate_changed() {
+        let mut config = GrubConfig::from_paths(
+            "test_data/grub_simple",
+            "test_data/grub.d",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        assert!(config.validate_changed().is_ok());
+        assert!(config.dropin_backup().is_empty());
+
+        config.set_key_value("GRUB_TIMEOUT", "-1");
+        config.set_key_value("GRUB_DEFAULT", "0");
+        config.set_key_value("GRUB_TERMINAL", "invalid");
+        let err = config.validate_changed().unwrap_err();
+        assert!(matches!(err.error(), DErrorType::InvalidValues(values) if values.len() == 2));
+
+        let backup = config.dropin_backup();
+        assert_eq!(backup.len(), 2);
+        assert_eq!(
+            backup[0].0,
+            PathBuf::from("test_data/grub.d/20-timeout.cfg")
+        );
+        assert!(backup[0]
+            .1
+            .as_ref()
+            .is_some_and(|contents| contents.contains("GRUB_TIMEOUT")));
+        assert_eq!(
+            backup[1].0,
+            PathBuf::from("test_data/grub.d/90-bootkitd.cfg")
+        );
+        assert_eq!(backup[1].1, None);
+    }
+
+    #[test]
+    fn test_dropin_overridden_changes() {
+        let config = GrubConfig::from_paths(
+            "test_data/grub_simple",
+            "test_data/grub.d",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        let mut main = GrubFile::from_file("test_data/grub_simple", ParseMode::Strict).unwrap();
+        assert!(config.overridden_changes(&main).is_empty());
+
+        main.set_key_value("GRUB_DEFAULT", "0");
+        assert!(config.overridden_changes(&main).is_empty());
+
+        main.set_key_value("GRUB_TIMEOUT", "10");
+        main.set_key_value("GRUB_CMDLINE_LINUX_DEFAULT", "splash");
+        let overridden = config.overridden_changes(&main);
+        assert_eq!(overridden.len(), 2);
+        assert_eq!(overridden[0].0, "GRUB_CMDLINE_LINUX_DEFAULT");
+        assert!(overridden[0].1.contains("test_data/grub.d/10-cmdline.cfg"));
+        assert_eq!(overridden[1].0, "GRUB_TIMEOUT");
+    }
+
+    #[test]
+    fn test_dropin_missing_dir() {
+        let mut config = GrubConfig::from_paths(
+            "test_data/grub_simple",
+            "test_data/missing",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        config.set_key_value("GRUB_DISABLE_OS_PROBER", "true");
+        assert!(config
+            .main()
+            .keyvalues()
+            .contains_key("GRUB_DISABLE_OS_PROBER"));
+    }
+
+    #[test]
+    fn test_dropin_remove_key() {
+        let mut config = GrubConfig::from_paths(
+            "test_data/grub_simple",
+            "test_data/grub.d",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        assert!(config.remove_key("GRUB_TIMEOUT", RemoveMode::Delete));
+        assert_eq!(config.value("GRUB_TIMEOUT"), None);
+        assert!(!config.remove_key("GRUB_TIMEOUT", RemoveMode::Delete));
+    }
+}
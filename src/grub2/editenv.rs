@@ -0,0 +1,127 @@
+use clap::ValueEnum;
+
+use crate::{
+    config::layout::GrubLayout,
+    errors::DResult,
+    grub2::env::{EnvChange, GrubEnv},
+    system::{filesystem_type, process},
+};
+
+/// Filesystems where grub2-editenv has special handling for the environment block,
+/// so writing grubenv directly is not safe
+const TOOLS_FILESYSTEMS: &[&str] = &["btrfs", "zfs"];
+
+/// How grubenv is modified
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum EnvBackend {
+    /// Use the grub tools if grubenv is on a filesystem they handle specially
+    #[default]
+    Auto,
+    /// Write grubenv directly
+    Native,
+    /// Use grub2-set-default, grub2-reboot and grub2-editenv
+    Tools,
+}
+
+/// Modifies grubenv either natively or with the grub tools
+#[derive(Debug)]
+pub struct EnvEditor {
+    path: String,
+    tools: bool,
+}
+
+impl EnvEditor {
+    pub fn new(backend: EnvBackend) -> Self {
+        let path = GrubLayout::get().env_path.clone();
+        let tools = match backend {
+            EnvBackend::Native => false,
+            EnvBackend::Tools => true,
+            EnvBackend::Auto => filesystem_type(&path)
+                .is_some_and(|fs_type| TOOLS_FILESYSTEMS.contains(&fs_type.as_str())),
+        };
+
+        if tools {
+            log::debug!("Using grub tools to modify {path}");
+        }
+
+        Self { path, tools }
+    }
+
+    async fn run_tool(&self, name: &str, args: &[&str]) -> DResult<()> {
+        let tool = GrubLayout::get().tool(name);
+        process::run(&tool, args).await?.check()
+    }
+
+    /// Set `key` to `value`. With the tools saved_entry is set with grub2-set-default
+    /// and next_entry with grub2-reboot
+    pub async fn set(&self, key: &str, value: &str) -> DResult<()> {
+        if !self.tools {
+            let mut grub_env = GrubEnv::from_file(&self.path)?;
+            grub_env.set(key, value);
+            return grub_env.write(&self.path);
+        }
+
+        match key {
+            "saved_entry" => self.run_tool("set-default", &[value]).await,
+            "next_entry" => self.run_tool("reboot", &[value]).await,
+            _ => {
+                let assignment = format!("{key}={value}");
+                self.run_tool("editenv", &[&self.path, "set", &assignment])
+                    .await
+            }
+        }
+    }
+
+    pub async fn unset(&self, key: &str) -> DResult<()> {
+        if !self.tools {
+            let mut grub_env = GrubEnv::from_file(&self.path)?;
+            if grub_env.unset(key) {
+                grub_env.write(&self.path)?;
+            }
+            return Ok(());
+        }
+
+        self.run_tool("editenv", &[&self.path, "unset", key]).await
+    }
+
+    /// Change the variables to the ones in `snapshot`, except the `skip` variables.
+    /// Natively the block is written once, with the tools each change is a separate run
+    pub async fn restore(&self, snapshot: &GrubEnv, skip: &[&str]) -> DResult<()> {
+        let mut grub_env = GrubEnv::from_file(&self.path)?;
+        let changes = grub_env.changes_to(snapshot, skip);
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        if !self.tools {
+            changes.iter().for_each(|change| grub_env.apply(change));
+            return grub_env.write(&self.path);
+        }
+
+        for change in &changes {
+            match change {
+                EnvChange::Set(key, value) => self.set(key, value).await?,
+                EnvChange::Unset(key) => self.unset(key).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark the current boot successful so the fallback isn't triggered
+    pub async fn mark_boot_successful(&self) -> DResult<()> {
+        let mut grub_env = GrubEnv::from_file(&self.path)?;
+        if !self.tools {
+            grub_env.mark_boot_successful();
+            return grub_env.write(&self.path);
+        }
+
+        self.set("boot_success", "1").await?;
+        if grub_env.get("boot_indeterminate").is_some() {
+            self.set("boot_indeterminate", "0").await?;
+        }
+        if grub_env.unset("boot_counter") {
+            self.unset("boot_counter").await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+/// Size of the environment block that grub expects
+pub const ENV_BLOCK_SIZE: usize = 1024;
+
+const DEFAULT_HEADER: &[&str] = &[
+    "# GRUB Environment Block",
+    "# WARNING: Do not edit this file by tools other than grub2-editenv!!!",
+];
+
+/// Variables of the grub environment block (grubenv)
+///
+/// The block is a fixed size file padded with `#` so grub can rewrite it in place
+/// without allocating new disk blocks.
+#[derive(Debug, Clone)]
+pub struct GrubEnv {
+    header: Vec<String>,
+    vars: Vec<(String, String)>,
+    /// Size of the block. Grub always uses 1024 but bigger blocks are kept as they are
+    size: usize,
+}
+
+impl Default for GrubEnv {
+    fn default() -> Self {
+        Self {
+            header: DEFAULT_HEADER.iter().map(|line| line.to_string()).collect(),
+            vars: Vec::new(),
+            size: ENV_BLOCK_SIZE,
+        }
+    }
+}
+
+/// Boot counting variables used by boot failure fallback schemes,
+/// like systemd's grub-boot-success.timer and greenboot
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BootStatus {
+    /// Set to 1 after the user session has been running long enough
+    pub boot_success: Option<bool>,
+    /// Set when the previous boot state can't be known, like after a crash
+    pub boot_indeterminate: Option<u32>,
+    /// Remaining boot attempts before grub falls back to the previous entry
+    pub boot_counter: Option<i64>,
+}
+
+/// Change that turns one environment block into another, see [`GrubEnv::changes_to`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvChange {
+    Set(String, String),
+    Unset(String),
+}
+
+/// Backslash and new line are escaped with a backslash, same as grub2-editenv does
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if ch == '\\' || ch == '\n' {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            if let Some(next) = chars.next() {
+                unescaped.push(next);
+            }
+        } else {
+            unescaped.push(ch);
+        }
+    }
+    unescaped
+}
+
+impl GrubEnv {
+    pub fn new(contents: &str) -> Self {
+        let mut header = Vec::new();
+        let mut vars = Vec::new();
+
+        // join escaped new lines before splitting the variables
+        let mut logical_lines: Vec<String> = Vec::new();
+        let mut continued = false;
+        for line in contents.split('\n') {
+            if continued {
+                if let Some(last) = logical_lines.last_mut() {
+                    last.push('\n');
+                    last.push_str(line);
+                }
+            } else {
+                logical_lines.push(line.to_string());
+            }
+            let backslashes = line.chars().rev().take_while(|ch| *ch == '\\').count();
+            continued = backslashes % 2 == 1;
+        }
+
+        for line in &logical_lines {
+            if line.starts_with('#') {
+                // padding is a line of only #, everything else before the values is a header
+                if vars.is_empty() && !line.chars().all(|ch| ch == '#') {
+                    header.push(line.clone());
+                }
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                vars.push((key.to_string(), unescape(value)));
+            }
+        }
+
+        if header.is_empty() {
+            header = DEFAULT_HEADER.iter().map(|line| line.to_string()).collect();
+        }
+
+        Self {
+            header,
+            vars,
+            size: contents.len().max(ENV_BLOCK_SIZE),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> DResult<Self> {
+        let contents = read_to_string(path.as_ref())
+            .ctx(dctx!(), format!("Error reading {:?}", path.as_ref()))?;
+        Ok(Self::new(&contents))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars
+            .iter()
+            .find(|(var, _)| var == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All the variables. The first definition is used like in `get`
+    pub fn values(&self) -> BTreeMap<String, String> {
+        let mut values = BTreeMap::new();
+        for (key, value) in &self.vars {
+            values.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        values
+    }
+
+    /// Set variable, keeping its position if it already exists
+    pub fn set(&mut self, key: &str, value: &str) {
+        if let Some((_, old)) = self.vars.iter_mut().find(|(var, _)| var == key) {
+            *old = value.into();
+        } else {
+            self.vars.push((key.into(), value.into()));
+        }
+    }
+
+    /// Remove variable. Returns true if the variable existed
+    pub fn unset(&mut self, key: &str) -> bool {
+        let len = self.vars.len();
+        self.vars.retain(|(var, _)| var != key);
+        len != self.vars.len()
+    }
+
+    /// Changes that turn the variables of this block into the variables of
+    /// `other`. Variables in `skip` are left as they are
+    pub fn changes_to(&self, other: &GrubEnv, skip: &[&str]) -> Vec<EnvChange> {
+        let current = self.values();
+        let target = other.values();
+        let mut changes: Vec<EnvChange> = target
+            .iter()
+            .filter(|(key, value)| current.get(*key) != Some(*value))
+            .map(|(key, value)| EnvChange::Set(key.clone(), value.clone()))
+            .collect();
+        changes.extend(
+            current
+                .keys()
+                .filter(|key| !target.contains_key(*key))
+                .map(|key| EnvChange::Unset(key.clone())),
+        );
+        changes.retain(|change| match change {
+            EnvChange::Set(key, _) | EnvChange::Unset(key) => !skip.contains(&key.as_str()),
+        });
+        changes
+    }
+
+    /// Apply `change` to the block
+    pub fn apply(&mut self, change: &EnvChange) {
+        match change {
+            EnvChange::Set(key, value) => self.set(key, value),
+            EnvChange::Unset(key) => {
+                self.unset(key);
+            }
+        }
+    }
+
+    /// Currently saved default entry, if it's set to a non empty value
+    pub fn saved_entry(&self) -> Option<&str> {
+        self.get("saved_entry")
+            .filter(|value| !value.trim().is_empty())
+    }
+
+    pub fn boot_status(&self) -> BootStatus {
+        BootStatus {
+            boot_success: self.get("boot_success").map(|value| value.trim() == "1"),
+            boot_indeterminate: self
+                .get("boot_indeterminate")
+                .and_then(|value| value.trim().parse().ok()),
+            boot_counter: self
+                .get("boot_counter")
+                .and_then(|value| value.trim().parse().ok()),
+        }
+    }
+
+    /// Mark the current boot successful so the fallback isn't triggered
+    pub fn mark_boot_successful(&mut self) {
+        self.set("boot_success", "1");
+        if self.get("boot_indeterminate").is_some() {
+            self.set("boot_indeterminate", "0");
+        }
+        // counter is only set when a new entry is being tried
+        self.unset("boot_counter");
+    }
+
+    /// Environment block padded with `#` to the block size
+    pub fn as_block(&self) -> DResult<String> {
+        let mut block = String::with_capacity(self.size);
+        for line in &self.header {
+            block.push_str(line);
+            block.push('\n');
+        }
+        for (key, value) in &self.vars {
+            block.push_str(key);
+            block.push('=');
+            block.push_str(&escape(value));
+            block.push('\n');
+        }
+
+        if block.len() > self.size {
+            return Err(DError::generic(
+                dctx!(),
+                format!(
+                    "Grub environment block is too big: {} bytes, max {} bytes",
+                    block.len(),
+                    self.size
+                ),
+            ));
+        }
+
+        block.push_str(&"#".repeat(self.size - block.len()));
+        Ok(block)
+    }
+
+    /// Write the environment block in place so the file keeps its disk blocks.
+    /// The file isn't truncated first, the block overwrites the old one and only
+    /// a longer file is cut to the block size afterwards
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> DResult<()> {
+        let path = path.as_ref();
+        let block = self.as_block()?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .ctx(dctx!(), format!("Failed to open {path:?} for writing"))?;
+        file.write_all(block.as_bytes()).ctx(
+            dctx!(),
+            format!("Failed to write grub environment to {path:?}"),
+        )?;
+        let len = file
+            .metadata()
+            .ctx(dctx!(), format!("Failed to stat {path:?}"))?
+            .len();
+        if len > block.len() as u64 {
+            file.set_len(block.len() as u64)
+                .ctx(dctx!(), format!("Failed to truncate {path:?}"))?;
+        }
+        file.sync_all().ctx(
+            dctx!(),
+            format!("Failed to sync grub environment to {path:?}"),
+        )?;
+
+        log::debug!("Grub environment was written to {path:?}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grubenv_saved_entry() {
+        let env = GrubEnv::new(&read_to_string("test_data/grubenv_saved").unwrap());
+        assert_eq!(
+            env.saved_entry(),
+            Some("Advanced options for openSUSE Tumbleweed Minimal>openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default")
+        );
+
+        let env = GrubEnv::new(&read_to_string("test_data/grubenv_empty").unwrap());
+        assert_eq!(env.saved_entry(), None);
+    }
+
+    #[test]
+    fn test_grubenv_round_trip() {
+        for file in ["test_data/grubenv_saved", "test_data/grubenv_empty"] {
+            let contents = read_to_string(file).unwrap();
+            let env = GrubEnv::new(&contents);
+            assert_eq!(env.as_block().unwrap(), contents);
+        }
+    }
+
+    #[test]
+    fn test_grubenv_write() {
+        let path = std::env::temp_dir().join(format!("bootkit-grubenv-{}", std::process::id()));
+        // longer file is cut to the block size
+        std::fs::write(&path, "#".repeat(ENV_BLOCK_SIZE + 10)).unwrap();
+
+        let contents = read_to_string("test_data/grubenv_saved").unwrap();
+        GrubEnv::new(&contents).write(&path).unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), contents);
+
+        // the next write overwrites the block in place
+        let empty = read_to_string("test_data/grubenv_empty").unwrap();
+        GrubEnv::new(&empty).write(&path).unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), empty);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_grubenv_set_unset() {
+        let contents = read_to_string("test_data/grubenv_empty").unwrap();
+        let mut env = GrubEnv::new(&contents);
+        env.set("saved_entry", "openSUSE Tumbleweed Minimal");
+        env.set("next_entry", "line\\nbreak");
+
+        let block = env.as_block().unwrap();
+        assert_eq!(block.len(), ENV_BLOCK_SIZE);
+        assert!(block.starts_with(
+            "# GRUB Environment Block\n# WARNING: Do not edit this file by tools other than grub2-editenv!!!\nsaved_entry=openSUSE Tumbleweed Minimal\nnext_entry=line\\\\nbreak\n#"
+        ));
+
+        let parsed = GrubEnv::new(&block);
+        assert_eq!(parsed.get("next_entry"), Some("line\\nbreak"));
+        assert_eq!(
+            parsed.values(),
+            BTreeMap::from([
+                ("next_entry".to_string(), "line\\nbreak".to_string()),
+                (
+                    "saved_entry".to_string(),
+                    "openSUSE Tumbleweed Minimal".to_string()
+                ),
+            ])
+        );
+
+        env.set("saved_entry", "0");
+        assert!(env.unset("next_entry"));
+        assert!(!env.unset("next_entry"));
+        assert_eq!(
+            GrubEnv::new(&env.as_block().unwrap()).saved_entry(),
+            Some("0")
+        );
+
+        assert!(env.unset("saved_entry"));
+        assert_eq!(env.as_block().unwrap(), contents);
+    }
+
+    #[test]
+    fn test_grubenv_changes_to() {
+        let mut current =
+            GrubEnv::new("saved_entry=1\nnext_entry=2\nboot_success=1\nboot_counter=3\n");
+        let snapshot = GrubEnv::new("saved_entry=0\nboot_success=0\nmenu_auto_hide=1\n");
+
+        let changes = current.changes_to(&snapshot, &["saved_entry"]);
+        assert_eq!(
+            changes,
+            vec![
+                EnvChange::Set("boot_success".into(), "0".into()),
+                EnvChange::Set("menu_auto_hide".into(), "1".into()),
+                EnvChange::Unset("boot_counter".into()),
+                EnvChange::Unset("next_entry".into()),
+            ]
+        );
+
+        changes.iter().for_each(|change| current.apply(change));
+        assert_eq!(current.get("saved_entry"), Some("1"));
+        assert!(current.changes_to(&snapshot, &["saved_entry"]).is_empty());
+    }
+
+    #[test]
+    fn test_grubenv_escaped_newline() {
+        let mut env = GrubEnv::default();
+        env.set("multi", "first\nsecond");
+        let block = env.as_block().unwrap();
+        assert!(block.contains("multi=first\\\nsecond\n"));
+        assert_eq!(GrubEnv::new(&block).get("multi"), Some("first\nsecond"));
+    }
+
+    #[test]
+    fn test_grubenv_boot_status() {
+        let mut env = GrubEnv::default();
+        assert_eq!(env.boot_status(), BootStatus::default());
+
+        env.set("boot_success", "0");
+        env.set("boot_indeterminate", "2");
+        env.set("boot_counter", "1");
+        assert_eq!(
+            env.boot_status(),
+            BootStatus {
+                boot_success: Some(false),
+                boot_indeterminate: Some(2),
+                boot_counter: Some(1),
+            }
+        );
+
+        env.mark_boot_successful();
+        assert_eq!(
+            env.boot_status(),
+            BootStatus {
+                boot_success: Some(true),
+                boot_indeterminate: Some(0),
+                boot_counter: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_grubenv_too_big() {
+        let mut env = GrubEnv::default();
+        env.set("big", &"x".repeat(ENV_BLOCK_SIZE));
+        assert!(env.as_block().is_err());
+    }
+}
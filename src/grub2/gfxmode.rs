@@ -0,0 +1,130 @@
+use std::{fs::read_dir, path::Path};
+
+use serde::Serialize;
+
+/// Connected displays expose their EDID under the DRM connectors
+pub const DRM_PATH: &str = "/sys/class/drm";
+
+/// Common VESA modes offered when the display can't be detected
+const COMMON_MODES: &[(u32, u32)] = &[
+    (640, 480),
+    (800, 600),
+    (1024, 768),
+    (1280, 720),
+    (1280, 1024),
+    (1366, 768),
+    (1440, 900),
+    (1600, 900),
+    (1680, 1050),
+    (1920, 1080),
+    (1920, 1200),
+    (2560, 1440),
+    (3840, 2160),
+];
+
+/// Resolutions that can be used for GRUB_GFXMODE
+#[derive(Debug, Serialize)]
+pub struct GfxModes {
+    /// `auto` followed by the resolutions that the connected displays can show
+    pub modes: Vec<String>,
+    /// Preferred resolutions reported by the connected displays
+    pub native: Vec<String>,
+}
+
+/// Check that `mode` is `WIDTHxHEIGHT` or `WIDTHxHEIGHTxDEPTH`
+pub fn is_resolution(mode: &str) -> bool {
+    let parts: Vec<&str> = mode.split('x').collect();
+    (2..=3).contains(&parts.len())
+        && parts
+            .iter()
+            .all(|part| part.parse::<u32>().is_ok_and(|num| num > 0))
+}
+
+/// Preferred resolution from the first detailed timing descriptor of an EDID blob
+fn edid_resolution(edid: &[u8]) -> Option<(u32, u32)> {
+    const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+    if edid.len() < 128 || edid[..8] != HEADER {
+        return None;
+    }
+
+    let timing = &edid[54..72];
+    // zero pixel clock means the descriptor is not a timing
+    if timing[0] == 0 && timing[1] == 0 {
+        return None;
+    }
+
+    let width = timing[2] as u32 | ((timing[4] as u32 & 0xf0) << 4);
+    let height = timing[5] as u32 | ((timing[7] as u32 & 0xf0) << 4);
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Preferred resolutions of the displays connected under `drm_dir`
+fn native_resolutions<P: AsRef<Path>>(drm_dir: P) -> Vec<(u32, u32)> {
+    let Ok(dir) = read_dir(drm_dir) else {
+        return Vec::new();
+    };
+
+    let mut resolutions: Vec<(u32, u32)> = dir
+        .filter_map(|entry| std::fs::read(entry.ok()?.path().join("edid")).ok())
+        .filter_map(|edid| edid_resolution(&edid))
+        .collect();
+    resolutions.sort();
+    resolutions.dedup();
+    resolutions
+}
+
+/// Plausible GRUB_GFXMODE values for the displays connected under `drm_dir`.
+///
+/// Common modes that are larger than the largest connected display are left out.
+/// If no display can be detected, all the common modes are listed.
+pub fn gfx_modes<P: AsRef<Path>>(drm_dir: P) -> GfxModes {
+    let native = native_resolutions(drm_dir);
+    let fits = |(width, height): (u32, u32)| {
+        native.is_empty() || native.iter().any(|(w, h)| width <= *w && height <= *h)
+    };
+
+    let mut resolutions: Vec<(u32, u32)> = COMMON_MODES
+        .iter()
+        .copied()
+        .filter(|mode| fits(*mode))
+        .chain(native.iter().copied())
+        .collect();
+    resolutions.sort();
+    resolutions.dedup();
+
+    let format = |(width, height): (u32, u32)| format!("{width}x{height}");
+    GfxModes {
+        modes: std::iter::once("auto".to_string())
+            .chain(resolutions.into_iter().map(format))
+            .collect(),
+        native: native.into_iter().map(format).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gfxmode() {
+        assert!(is_resolution("1024x768"));
+        assert!(is_resolution("1920x1080x32"));
+        assert!(!is_resolution("1024"));
+        assert!(!is_resolution("1024x"));
+        assert!(!is_resolution("0x768"));
+        assert!(!is_resolution("1024x768x32x1"));
+        assert!(!is_resolution("big"));
+
+        let mut edid = vec![0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+        edid.resize(128, 0);
+        // 1920x1080 with 148.5 MHz pixel clock
+        edid[54..62].copy_from_slice(&[0x02, 0x3a, 0x80, 0x18, 0x71, 0x38, 0x2d, 0x40]);
+        assert_eq!(edid_resolution(&edid), Some((1920, 1080)));
+        assert_eq!(edid_resolution(&edid[..100]), None);
+
+        let modes = gfx_modes("test_data/missing");
+        assert_eq!(modes.modes[0], "auto");
+        assert!(modes.modes.contains(&"3840x2160".to_string()));
+        assert!(modes.native.is_empty());
+    }
+}
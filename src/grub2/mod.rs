@@ -1,18 +1,92 @@
+use clap::ValueEnum;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Display, fs::read_to_string, path::Path};
+use similar::{ChangeTag, TextDiff};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    fs::read_to_string,
+    path::Path,
+    sync::LazyLock,
+};
 
 use crate::{
-    config::{GRUB_CFG_PATH, GRUB_ENV_PATH},
+    config::{layout::GrubLayout, BLS_ENTRIES_PATH, GRUB_FILE_PATH},
     dctx,
     errors::{DError, DRes, DResult},
+    grub2::{bls::BlsEntry, env::GrubEnv, script::GrubScript},
 };
 
+pub mod bls;
+pub mod check;
+pub mod cmdline;
+pub mod custom;
+pub mod dropin;
+pub mod editenv;
+pub mod env;
+pub mod gfxmode;
+pub mod osprober;
+pub mod schema;
+pub mod script;
+pub mod stale;
+pub mod themes;
+pub mod users;
+pub mod version;
+
+/// Quotes used around a value in the grub file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Quote {
+    None,
+    Single,
+    #[default]
+    Double,
+}
+
+impl Quote {
+    fn parse(value: &str) -> (Self, &str) {
+        for (quote, ch) in [(Self::Double, '"'), (Self::Single, '\'')] {
+            if value.len() >= 2 && value.starts_with(ch) && value.ends_with(ch) {
+                return (quote, &value[1..value.len() - 1]);
+            }
+        }
+
+        (Self::None, value)
+    }
+
+    /// Quote style that can be used to write `value` while staying as close
+    /// to this style as possible
+    fn for_value(self, value: &str) -> Self {
+        match self {
+            Self::None
+                if value
+                    .chars()
+                    .any(|ch| ch.is_whitespace() || "\"'$`\\;&|<>()#".contains(ch)) =>
+            {
+                Self::Double
+            }
+            Self::Single if value.contains('\'') => Self::Double,
+            quote => quote,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Single => "'",
+            Self::Double => "\"",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyValue {
     line: usize,
     original: String,
     changed: bool,
+    /// Quotes used in the original line, new values are written with the same quotes
+    #[serde(default)]
+    quote: Quote,
 
     pub key: String,
     pub value: String,
@@ -23,6 +97,7 @@ impl KeyValue {
         let mut kv = Self {
             line,
             changed: false,
+            quote: Quote::None,
             key: "".into(),
             value: "".into(),
             original: original.into(),
@@ -37,14 +112,16 @@ impl KeyValue {
             line,
             original: String::new(),
             changed: true,
+            quote: Quote::Double,
             key: key.into(),
             value: value.into(),
         }
     }
 
     fn parse(&mut self) -> DResult<()> {
-        // TODO: save the type of quotes so they can be returned to orignal
-        let trimmed = self.original.trim();
+        // backslash-newline is a line continuation, same as in shell
+        let joined = self.original.replace("\\\n", "");
+        let trimmed = joined.trim();
         let split = if let Some(split) = trimmed.split_once('=') {
             split
         } else {
@@ -53,12 +130,52 @@ impl KeyValue {
                 format!("Expected '=' on line: {}", self.line + 1),
             ));
         };
+        let (quote, value) = Quote::parse(split.1);
         self.key = split.0.into();
-        self.value = split.1.replace(['\'', '"'], "");
+        self.quote = quote;
+        self.value = value.into();
 
         Ok(())
     }
 
+    fn as_line(&self) -> String {
+        if !self.changed {
+            self.original.clone()
+        } else {
+            let quote = self.quote.for_value(&self.value).as_str();
+            format!("{}={quote}{}{quote}", self.key, self.value)
+        }
+    }
+
+    /// Check that the written line can be sourced by shell as a single value
+    fn shell_error(&self) -> Option<String> {
+        if self.quote.for_value(&self.value) != Quote::Double {
+            return None;
+        }
+
+        let mut backslashes = 0;
+        for ch in self.value.chars() {
+            match ch {
+                '"' if backslashes % 2 == 0 => {
+                    return Some("Unescaped '\"' would end the value early".into())
+                }
+                '`' => return Some("Command substitution is not allowed".into()),
+                _ => {}
+            }
+            backslashes = if ch == '\\' { backslashes + 1 } else { 0 };
+        }
+
+        if backslashes % 2 == 1 {
+            return Some("Trailing '\\' would escape the closing quote".into());
+        }
+
+        if self.value.contains("$(") {
+            return Some("Command substitution is not allowed".into());
+        }
+
+        None
+    }
+
     fn update<V: Into<String>>(&mut self, value: V) {
         let new_value = value.into();
         if self.value != new_value {
@@ -73,18 +190,14 @@ impl From<KeyValue> for String {
         if !value.changed {
             value.original
         } else {
-            format!("{}=\"{}\"", value.key, value.value)
+            value.as_line()
         }
     }
 }
 
 impl From<&KeyValue> for String {
     fn from(value: &KeyValue) -> Self {
-        if !value.changed {
-            value.original.clone()
-        } else {
-            format!("{}=\"{}\"", value.key, value.value)
-        }
+        value.as_line()
     }
 }
 
@@ -113,21 +226,173 @@ impl From<&GrubLine> for String {
     }
 }
 
+/// Reason why `key` can't be written to the config, none if it's a valid shell variable name
+pub fn key_error(key: &str) -> Option<String> {
+    let valid = key
+        .chars()
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && key
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    (!valid).then(|| format!("'{key}' is not a valid key name"))
+}
+
+/// Does the line end with an unescaped backslash
+fn ends_with_continuation(line: &str) -> bool {
+    let backslashes = line.chars().rev().take_while(|ch| *ch == '\\').count();
+    backslashes % 2 == 1
+}
+
+/// Groups of related keys. New keys are placed next to the other keys in their group
+/// so the generated file stays readable.
+const KEY_SECTIONS: &[&[&str]] = &[
+    &[
+        "GRUB_DEFAULT",
+        "GRUB_SAVEDEFAULT",
+        "GRUB_DEFAULT_BUTTON",
+        "GRUB_DISTRIBUTOR",
+    ],
+    &[
+        "GRUB_TIMEOUT",
+        "GRUB_TIMEOUT_STYLE",
+        "GRUB_HIDDEN_TIMEOUT",
+        "GRUB_HIDDEN_TIMEOUT_QUIET",
+        "GRUB_TIMEOUT_BUTTON",
+        "GRUB_TIMEOUT_STYLE_BUTTON",
+        "GRUB_HIDDEN_TIMEOUT_BUTTON",
+        "GRUB_RECORDFAIL_TIMEOUT",
+    ],
+    &[
+        "GRUB_CMDLINE_LINUX",
+        "GRUB_CMDLINE_LINUX_DEFAULT",
+        "GRUB_CMDLINE_LINUX_RECOVERY",
+        "GRUB_CMDLINE_XEN",
+        "GRUB_CMDLINE_XEN_DEFAULT",
+    ],
+    &[
+        "GRUB_TERMINAL",
+        "GRUB_TERMINAL_INPUT",
+        "GRUB_TERMINAL_OUTPUT",
+        "GRUB_SERIAL_COMMAND",
+        "GRUB_GFXMODE",
+        "GRUB_GFXPAYLOAD_LINUX",
+        "GRUB_BACKGROUND",
+        "GRUB_THEME",
+        "GRUB_FONT",
+    ],
+];
+
+/// Order of the boot entries returned to the clients
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EntrySort {
+    /// Same order as in the grub menu
+    #[default]
+    Menu,
+    /// Newest kernel first, recovery entries after their main entry
+    Version,
+}
+
+/// What happens to the line of a removed key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoveMode {
+    /// Delete the whole line
+    #[default]
+    Delete,
+    /// Keep the line as a `#KEY=value` comment
+    Comment,
+}
+
+/// Difference between two grub files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum KeyChange {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        old: String,
+        new: String,
+    },
+    /// Comment or other line that isn't a key=value pair
+    LineAdded {
+        line: String,
+    },
+    LineRemoved {
+        line: String,
+    },
+}
+
+/// Changes needed to turn the `old` key values into `new`, sorted by the key
+pub fn value_changes(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Vec<KeyChange> {
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| match (old.get(key), new.get(key)) {
+            (Some(old), Some(new)) if old != new => Some(KeyChange::Changed {
+                key: key.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            }),
+            (Some(old), None) => Some(KeyChange::Removed {
+                key: key.clone(),
+                value: old.clone(),
+            }),
+            (None, Some(new)) => Some(KeyChange::Added {
+                key: key.clone(),
+                value: new.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// How lines that aren't key=value pairs are handled when reading a grub file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail to read the file
+    #[default]
+    Strict,
+    /// Keep the lines as they are and record a warning
+    Lenient,
+}
+
 #[derive(Debug)]
 pub struct GrubFile {
     lines: Vec<GrubLine>,
     keyvals: HashMap<String, KeyValue>,
+    /// Lines that were kept as is in lenient mode
+    warnings: Vec<String>,
 }
 
 impl GrubFile {
     pub fn new(file: &str) -> DResult<Self> {
+        Self::parse(file, ParseMode::Strict)
+    }
+
+    pub fn parse(file: &str, mode: ParseMode) -> DResult<Self> {
         let mut lines = Vec::new();
         let mut keyvals = HashMap::new();
+        let mut warnings = Vec::new();
 
         // use split instead of lines to save the trailing empty new line
         // this doesn't handle \r\n but this is very unlikely to run on
         // windows anyways
-        for (idx, line) in file.split('\n').enumerate() {
+        let mut physical_lines = file.split('\n').enumerate();
+        while let Some((idx, line)) = physical_lines.next() {
             let trimmed = line.trim();
             if trimmed.is_empty() || trimmed.starts_with('#') {
                 lines.push(GrubLine::String {
@@ -136,33 +401,171 @@ impl GrubFile {
                 continue;
             }
 
-            let keyval = KeyValue::new(idx, line)?;
+            // join the lines ending with a backslash into a single logical line
+            // while keeping the original layout
+            let mut logical = line.to_string();
+            while ends_with_continuation(&logical) {
+                if let Some((_, next)) = physical_lines.next() {
+                    logical.push('\n');
+                    logical.push_str(next);
+                } else {
+                    break;
+                }
+            }
+
+            if mode == ParseMode::Lenient && !logical.contains('=') {
+                warnings.push(format!(
+                    "Expected '=' on line {}, keeping it as is: {}",
+                    idx + 1,
+                    logical.trim()
+                ));
+                lines.push(GrubLine::String { raw_line: logical });
+                continue;
+            }
+
+            let mut keyval = KeyValue::new(idx, &logical)?;
+            // key values refer to the logical line, not the physical line
+            keyval.line = lines.len();
             keyvals.insert(keyval.key.clone(), keyval.clone());
             lines.push(GrubLine::KeyValue(keyval));
         }
 
-        Ok(Self { lines, keyvals })
+        Ok(Self {
+            lines,
+            keyvals,
+            warnings,
+        })
     }
 
     pub fn set_key_value(&mut self, key: &str, value: &str) {
         if let Some(keyval) = self.keyvals.get_mut(key) {
-            // If keyvalue exists, update it
+            // If keyvalue exists, update it. The file is sourced by shell so the last
+            // definition wins, but all of them are updated to keep the file consistent
             keyval.update(value);
-            if let GrubLine::KeyValue(keyval) = &mut self.lines[keyval.line] {
-                keyval.update(value);
+            for line in &mut self.lines {
+                if let GrubLine::KeyValue(keyval) = line {
+                    if keyval.key == key {
+                        keyval.update(value);
+                    }
+                }
             }
+        } else if !self.uncomment(key, value) {
+            // else add a new value close to the keys it's related to
+            let idx = self.placement_for(key);
+            self.insert_line(idx, key, value);
+        }
+    }
+
+    /// Index of the last commented out `# KEY=value` line of `key`
+    fn commented_line(&self, key: &str) -> Option<usize> {
+        self.lines.iter().rposition(|line| match line {
+            GrubLine::String { raw_line } => raw_line
+                .trim_start()
+                .strip_prefix('#')
+                .and_then(|line| line.trim_start().split_once('='))
+                .is_some_and(|(line_key, _)| line_key == key),
+            GrubLine::KeyValue(_) => false,
+        })
+    }
+
+    /// Activate the commented out `key` in place with `value`, keeping its quotes.
+    /// Returns false if the key isn't commented out in the file
+    fn uncomment(&mut self, key: &str, value: &str) -> bool {
+        let Some(idx) = self.commented_line(key) else {
+            return false;
+        };
+
+        let GrubLine::String { raw_line } = &self.lines[idx] else {
+            return false;
+        };
+        let line = raw_line.trim_start()[1..].trim_start().to_string();
+        // commented_line already made sure the line has '='
+        let Ok(mut keyval) = KeyValue::new(idx, &line) else {
+            return false;
+        };
+        keyval.update(value);
+        keyval.changed = true;
+
+        self.lines[idx] = GrubLine::KeyValue(keyval);
+        self.reindex();
+        true
+    }
+
+    /// Set `key` to `value`, placing it right after `anchor` if the key doesn't exist yet.
+    ///
+    /// Existing and commented out keys are updated in place. If `anchor` isn't found, this behaves
+    /// like `set_key_value`.
+    pub fn insert_key_after(&mut self, key: &str, value: &str, anchor: &str) {
+        if self.keyvals.contains_key(key) || self.commented_line(key).is_some() {
+            self.set_key_value(key, value);
+            return;
+        }
+
+        if let Some(anchor) = self.keyvals.get(anchor) {
+            let idx = anchor.line + 1;
+            self.insert_line(idx, key, value);
         } else {
-            // else add a new value
-            let keyval = KeyValue::from_key_val(self.lines.len(), key, value);
-            self.keyvals.insert(keyval.key.clone(), keyval.clone());
-            self.lines.push(GrubLine::KeyValue(keyval));
+            log::debug!("Anchor key '{anchor}' not found, using default placement for '{key}'");
+            self.set_key_value(key, value);
+        }
+    }
+
+    /// Find the line index where a new `key` should be inserted.
+    ///
+    /// Keys are placed after the last existing key of the same section. If the
+    /// section has no keys yet, the key is placed before the trailing empty lines.
+    fn placement_for(&self, key: &str) -> usize {
+        let section = KEY_SECTIONS.iter().find(|section| section.contains(&key));
+
+        if let Some(section) = section {
+            let last_related = self
+                .lines
+                .iter()
+                .enumerate()
+                .rev()
+                .find(|(_, line)| match line {
+                    GrubLine::KeyValue(keyval) => section.contains(&keyval.key.as_str()),
+                    _ => false,
+                });
+
+            if let Some((idx, _)) = last_related {
+                return idx + 1;
+            }
         }
+
+        let trailing_empty = self
+            .lines
+            .iter()
+            .rev()
+            .take_while(
+                |line| matches!(line, GrubLine::String { raw_line } if raw_line.trim().is_empty()),
+            )
+            .count();
+
+        self.lines.len() - trailing_empty
     }
 
-    pub fn from_file<P: AsRef<Path>>(path: P) -> DResult<Self> {
+    fn insert_line(&mut self, idx: usize, key: &str, value: &str) {
+        let keyval = KeyValue::from_key_val(idx, key, value);
+        self.lines.insert(idx, GrubLine::KeyValue(keyval));
+        self.reindex();
+    }
+
+    /// Update the line numbers of all keys after lines were inserted or removed
+    fn reindex(&mut self) {
+        self.keyvals.clear();
+        for (idx, line) in self.lines.iter_mut().enumerate() {
+            if let GrubLine::KeyValue(keyval) = line {
+                keyval.line = idx;
+                self.keyvals.insert(keyval.key.clone(), keyval.clone());
+            }
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P, mode: ParseMode) -> DResult<Self> {
         let file = read_to_string(path.as_ref())
             .ctx(dctx!(), format!("Error reading {:?}", path.as_ref()))?;
-        Self::new(&file)
+        Self::parse(&file, mode)
     }
 
     pub fn from_lines(grub_lines: &[GrubLine]) -> Self {
@@ -176,13 +579,25 @@ impl GrubFile {
             }
         }
 
-        Self { lines, keyvals }
+        // line numbers sent by clients can't be trusted to match the line positions
+        let mut grub = Self {
+            lines,
+            keyvals,
+            warnings: Vec::new(),
+        };
+        grub.reindex();
+        grub
     }
 
     pub fn lines(&self) -> &[GrubLine] {
         &self.lines
     }
 
+    /// Lines that were not key=value pairs when reading in lenient mode
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
     pub fn keyvalues(&self) -> &HashMap<String, KeyValue> {
         &self.keyvals
     }
@@ -191,21 +606,218 @@ impl GrubFile {
         let lines: Vec<String> = self.lines().iter().map(|val| val.into()).collect();
         lines.join("\n")
     }
+
+    /// Validate the values of the changed keys against the key schema.
+    ///
+    /// Only changed keys are validated so values that are already on the system
+    /// don't prevent saving unrelated changes.
+    pub fn validate_changed(&self) -> DResult<()> {
+        let invalid = self.invalid_changed();
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(DError::invalid_values(dctx!(), invalid))
+        }
+    }
+
+    /// Changed keys with an invalid value, (key, reason)
+    pub fn invalid_changed(&self) -> Vec<(String, String)> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                GrubLine::KeyValue(keyval) if keyval.changed => Some(keyval),
+                _ => None,
+            })
+            .filter_map(|keyval| {
+                key_error(&keyval.key)
+                    .or_else(|| keyval.shell_error())
+                    .or_else(|| schema::validate(&keyval.key, &keyval.value).err())
+                    .map(|reason| (keyval.key.clone(), reason))
+            })
+            .collect()
+    }
+
+    /// Deprecated keys that are set in the file, in the order they appear
+    pub fn deprecated_keys(&self) -> Vec<&'static schema::Deprecation> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                GrubLine::KeyValue(keyval) => schema::deprecation(&keyval.key),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Replace the deprecated keys with their modern equivalents.
+    ///
+    /// Deprecated keys are commented out instead of removed so the original values
+    /// are still visible in the file. Returns descriptions of the done migrations.
+    pub fn migrate_deprecated(&mut self) -> Vec<String> {
+        let mut migrations = Vec::new();
+        let value = |grub: &Self, key: &str| grub.keyvals.get(key).map(|kv| kv.value.clone());
+
+        if let Some(hidden) = value(self, "GRUB_HIDDEN_TIMEOUT") {
+            let quiet =
+                value(self, "GRUB_HIDDEN_TIMEOUT_QUIET").is_some_and(|quiet| quiet == "true");
+            let style = if quiet { "hidden" } else { "countdown" };
+            if !hidden.is_empty() {
+                self.set_key_value("GRUB_TIMEOUT_STYLE", style);
+                self.set_key_value("GRUB_TIMEOUT", &hidden);
+                migrations.push(format!(
+                    "GRUB_HIDDEN_TIMEOUT={hidden} migrated to GRUB_TIMEOUT_STYLE={style} and GRUB_TIMEOUT={hidden}"
+                ));
+            }
+        }
+
+        if let Some(hidden) = value(self, "GRUB_HIDDEN_TIMEOUT_BUTTON") {
+            if !hidden.is_empty() {
+                self.set_key_value("GRUB_TIMEOUT_STYLE_BUTTON", "hidden");
+                self.set_key_value("GRUB_TIMEOUT_BUTTON", &hidden);
+                migrations.push(format!(
+                    "GRUB_HIDDEN_TIMEOUT_BUTTON={hidden} migrated to GRUB_TIMEOUT_STYLE_BUTTON=hidden and GRUB_TIMEOUT_BUTTON={hidden}"
+                ));
+            }
+        }
+
+        if let Some(disable) = value(self, "GRUB_DISABLE_LINUX_RECOVERY") {
+            if !self.keyvals.contains_key("GRUB_DISABLE_RECOVERY") {
+                self.insert_key_after(
+                    "GRUB_DISABLE_RECOVERY",
+                    &disable,
+                    "GRUB_DISABLE_LINUX_RECOVERY",
+                );
+                migrations.push(format!(
+                    "GRUB_DISABLE_LINUX_RECOVERY={disable} migrated to GRUB_DISABLE_RECOVERY={disable}"
+                ));
+            }
+        }
+
+        for deprecated in schema::DEPRECATED_KEYS {
+            if self.remove_key(deprecated.key, RemoveMode::Comment) {
+                log::info!("Commented out deprecated key {}", deprecated.key);
+            }
+        }
+
+        migrations
+    }
+
+    /// Remove or comment out, depending on `mode`, all the definitions of `key` so
+    /// grub uses its default value. Returns false if the key is not set
+    pub fn remove_key(&mut self, key: &str, mode: RemoveMode) -> bool {
+        if !self.keyvals.contains_key(key) {
+            return false;
+        }
+
+        match mode {
+            RemoveMode::Delete => self
+                .lines
+                .retain(|line| !matches!(line, GrubLine::KeyValue(keyval) if keyval.key == key)),
+            RemoveMode::Comment => {
+                for line in &mut self.lines {
+                    if let GrubLine::KeyValue(keyval) = line {
+                        if keyval.key == key {
+                            let raw: String = (&*keyval).into();
+                            *line = GrubLine::String {
+                                raw_line: format!("#{raw}"),
+                            };
+                        }
+                    }
+                }
+            }
+        }
+        self.reindex();
+        true
+    }
+
+    /// Changes needed to turn `self` into `other`. Key changes are sorted by the key
+    /// and are followed by the changes in the other lines
+    pub fn diff(&self, other: &GrubFile) -> Vec<KeyChange> {
+        let values = |grub: &GrubFile| -> BTreeMap<String, String> {
+            grub.keyvals
+                .iter()
+                .map(|(key, keyval)| (key.clone(), keyval.value.clone()))
+                .collect()
+        };
+        let mut changes = value_changes(&values(self), &values(other));
+
+        let raw_lines = |grub: &GrubFile| -> Vec<String> {
+            grub.lines
+                .iter()
+                .filter_map(|line| match line {
+                    GrubLine::String { raw_line } => Some(raw_line.clone()),
+                    GrubLine::KeyValue(_) => None,
+                })
+                .collect()
+        };
+        let old_lines = raw_lines(self);
+        let new_lines = raw_lines(other);
+        let old_refs: Vec<&str> = old_lines.iter().map(String::as_str).collect();
+        let new_refs: Vec<&str> = new_lines.iter().map(String::as_str).collect();
+        for change in TextDiff::from_slices(&old_refs, &new_refs).iter_all_changes() {
+            let line = change.value().to_string();
+            match change.tag() {
+                ChangeTag::Insert => changes.push(KeyChange::LineAdded { line }),
+                ChangeTag::Delete => changes.push(KeyChange::LineRemoved { line }),
+                ChangeTag::Equal => {}
+            }
+        }
+
+        changes
+    }
+
+    /// Keys that are defined more than once. The last definition is the effective one
+    pub fn duplicate_keys(&self) -> Vec<&str> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for line in &self.lines {
+            if let GrubLine::KeyValue(keyval) = line {
+                *counts.entry(keyval.key.as_str()).or_default() += 1;
+            }
+        }
+
+        let mut duplicates: Vec<&str> = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(key, _)| key)
+            .collect();
+        duplicates.sort();
+        duplicates
+    }
+
+    /// Is grub configured to read boot entries from BLS snippets
+    /// instead of the generated menuentries (GRUB_ENABLE_BLSCFG=true)
+    pub fn bls_enabled(&self) -> bool {
+        self.keyvals
+            .get("GRUB_ENABLE_BLSCFG")
+            .is_some_and(|keyval| keyval.value == "true")
+    }
 }
 
 #[derive(Debug)]
 enum GrubEnvValue<'a> {
-    /// Index of the bootentry
-    Index(usize),
+    /// Indexes of the submenus and the bootentry separated by `>`, like `1>2`
+    Index(Vec<usize>),
     /// Name of the bootentry
     // Name(String),
     Name(&'a str),
 }
 
+impl<'a> GrubEnvValue<'a> {
+    fn parse(value: &'a str) -> Self {
+        let indexes: Result<Vec<usize>, _> = value.split('>').map(str::parse).collect();
+        match indexes {
+            Ok(indexes) => GrubEnvValue::Index(indexes),
+            Err(_) => GrubEnvValue::Name(value),
+        }
+    }
+}
+
 impl Display for GrubEnvValue<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            GrubEnvValue::Index(idx) => write!(f, "{idx}"),
+            GrubEnvValue::Index(indexes) => {
+                let indexes: Vec<String> = indexes.iter().map(usize::to_string).collect();
+                write!(f, "{}", indexes.join(">"))
+            }
             GrubEnvValue::Name(name) => write!(f, "{name}"),
         }
     }
@@ -217,23 +829,147 @@ pub struct GrubBootEntry {
     entry: String,
     /// (nested) submenus
     submenus: Vec<String>,
+    /// Id grub can use to refer to the entry, like the BLS file name
+    /// or the `--id` of the menuentry
+    id: Option<String>,
+    /// Ids of the (nested) submenus
+    submenu_ids: Vec<Option<String>>,
+    /// `--class` values GUIs use to pick an icon for the entry
+    classes: Vec<String>,
+    /// Kernel image loaded with `linux` or `linuxefi`
+    kernel: Option<String>,
+    /// Images loaded with `initrd` or `initrdefi`
+    initrds: Vec<String>,
+    /// Kernel version from the BLS entry or the kernel file name
+    version: Option<String>,
+    /// Unique number of each (nested) submenu, tells apart submenus with the same title
+    submenu_keys: Vec<usize>,
+    /// Index of each (nested) submenu and the entry in its parent menu,
+    /// the numeric path grub uses for the default entry
+    index_path: Vec<usize>,
 }
 
+/// Commands grub.cfg uses to load the kernel and the initrd
+const LINUX_COMMANDS: &[&str] = &["linux", "linuxefi", "linux16"];
+const INITRD_COMMANDS: &[&str] = &["initrd", "initrdefi", "initrd16"];
+
+/// Prefixes of the kernel file names that are followed by the version
+const KERNEL_PREFIXES: &[&str] = &["vmlinuz-", "vmlinux-", "Image-", "zImage-", "kernel-"];
+
+/// Version of a kernel image like `/boot/vmlinuz-6.17.5-1-default`
+pub fn kernel_version(kernel: &str) -> Option<String> {
+    let name = Path::new(kernel).file_name()?.to_str()?;
+    KERNEL_PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .filter(|version| !version.is_empty())
+        .map(str::to_string)
+}
+
+/// Id of a menuentry or submenu given with `--id` or `$menuentry_id_option`.
+/// Compiled once since the entries are parsed on every `GetEntries` call
+static ENTRY_ID_RE: LazyLock<Regex> = LazyLock::new(|| {
+    // this is an unrecovable error so panic is appropriate
+    Regex::new(r"(?:\$menuentry_id_option|--id)[\s=]+'([^']+)'").expect("Invalid regex")
+});
+
+/// Classes grub-mkconfig adds to every entry that don't tell which OS it is
+const GENERIC_CLASSES: &[&str] = &["gnu-linux", "gnu", "os", "efi", "recovery", "kernel"];
+
 impl GrubBootEntry {
-    fn new(entry: String, submenus: Vec<String>) -> Self {
-        Self { entry, submenus }
+    fn from_bls(entry: &BlsEntry) -> Self {
+        Self {
+            entry: entry.title().into(),
+            submenus: Vec::new(),
+            id: Some(entry.id().into()),
+            submenu_ids: Vec::new(),
+            classes: entry.classes(),
+            kernel: entry.linux().map(str::to_string),
+            initrds: entry.initrds(),
+            version: entry
+                .version()
+                .map(str::to_string)
+                .or_else(|| entry.linux().and_then(kernel_version)),
+            submenu_keys: Vec::new(),
+            index_path: Vec::new(),
+        }
+    }
+
+    /// Record the kernel or initrd if `line` inside the menuentry loads them
+    fn parse_body_line(&mut self, script: &GrubScript, line: &str) {
+        if let Some(args) = LINUX_COMMANDS
+            .iter()
+            .find_map(|command| GrubScript::command_args(line, command))
+        {
+            if let Some((kernel, _)) = script.parse_word(args) {
+                self.version = kernel_version(&kernel);
+                self.kernel = Some(kernel);
+            }
+        } else if let Some(mut args) = INITRD_COMMANDS
+            .iter()
+            .find_map(|command| GrubScript::command_args(line, command))
+        {
+            while let Some((initrd, rest)) = script.parse_word(args) {
+                self.initrds.push(initrd);
+                args = rest;
+            }
+        }
+    }
+
+    /// Values of the `--class` arguments of a menuentry
+    fn parse_classes(script: &GrubScript, mut args: &str) -> Vec<String> {
+        let mut classes = Vec::new();
+        while let Some((word, rest)) = script.parse_word(args) {
+            if word == "{" {
+                break;
+            }
+
+            if let Some(class) = word.strip_prefix("--class=") {
+                classes.push(class.to_string());
+            } else if word == "--class" {
+                if let Some((class, rest)) = script.parse_word(rest) {
+                    classes.push(class);
+                    args = rest;
+                    continue;
+                }
+            }
+            args = rest;
+        }
+
+        classes
+    }
+
+    /// Id of a menuentry or submenu given with `--id` or `$menuentry_id_option`
+    fn parse_id(line: &str) -> Option<String> {
+        ENTRY_ID_RE
+            .captures(line)
+            .map(|capture| capture[1].to_string())
     }
 
     fn parse_entries(contents: &str) -> DResult<Vec<GrubBootEntry>> {
-        let mut entries = Vec::new();
-        let mut submenus = Vec::new();
-        // these are unrecovable error so panic is appropriate
-        let entry_re = Regex::new(r"menuentry\s+'([^']+)").expect("Invalid regex");
-        let submenu_re = Regex::new(r"submenu\s+'([^']+)").expect("Invalid regex");
+        let mut entries: Vec<Self> = Vec::new();
+        let mut submenus: Vec<(String, Option<String>, usize)> = Vec::new();
+        let mut submenu_count = 0;
+        // variables set in grub.cfg, used to expand the titles
+        let mut script = GrubScript::default();
 
         let mut menuentry_open = false;
         for line in contents.lines() {
             let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // fast path for the body of a menuentry, which is most of a large grub.cfg
+            if menuentry_open && !line.starts_with('}') {
+                if line.starts_with(['l', 'i']) {
+                    if let Some(entry) = entries.last_mut() {
+                        entry.parse_body_line(&script, line);
+                    }
+                }
+                continue;
+            }
+
             if line.starts_with('}') {
                 if menuentry_open {
                     menuentry_open = false;
@@ -244,17 +980,33 @@ impl GrubBootEntry {
                 continue;
             }
 
-            if line.starts_with("menuentry") {
+            if let Some(title) = GrubScript::command_args(line, "menuentry") {
                 menuentry_open = true;
                 // TODO: error if this fails
-                if let Some(capture) = entry_re.captures(line) {
-                    entries.push(Self::new(capture[1].to_string(), submenus.clone()))
+                if let Some((title, args)) = script.parse_word(title) {
+                    entries.push(Self {
+                        entry: title,
+                        classes: Self::parse_classes(&script, args),
+                        submenus: submenus.iter().map(|(title, ..)| title.clone()).collect(),
+                        id: Self::parse_id(line),
+                        submenu_ids: submenus.iter().map(|(_, id, _)| id.clone()).collect(),
+                        kernel: None,
+                        initrds: Vec::new(),
+                        version: None,
+                        submenu_keys: submenus.iter().map(|(.., key)| *key).collect(),
+                        index_path: Vec::new(),
+                    })
                 }
-            } else if line.starts_with("submenu") {
+            } else if let Some(title) = GrubScript::command_args(line, "submenu") {
                 // TODO: error if this fails
-                if let Some(capture) = submenu_re.captures(line) {
-                    submenus.push(capture[1].to_string())
+                if let Some((title, _)) = script.parse_word(title) {
+                    submenus.push((title, Self::parse_id(line), submenu_count));
+                    submenu_count += 1;
                 }
+            } else {
+                // variables set inside menuentries only apply when booting the entry,
+                // those lines are skipped above
+                script.assign(line);
             }
         }
 
@@ -265,6 +1017,101 @@ impl GrubBootEntry {
         &self.entry
     }
 
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    pub fn kernel(&self) -> Option<&str> {
+        self.kernel.as_deref()
+    }
+
+    pub fn initrds(&self) -> &[String] {
+        &self.initrds
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Recovery mode entry of a kernel
+    pub fn is_recovery(&self) -> bool {
+        self.classes.iter().any(|class| class == "recovery")
+            || self.entry.contains("(recovery mode)")
+            || self.entry.contains("(rescue")
+            || self
+                .id
+                .as_deref()
+                .is_some_and(|id| id.contains("-recovery-"))
+    }
+
+    /// OS of the entry detected from the classes, like `opensuse` or `windows`
+    pub fn os(&self) -> Option<&str> {
+        self.classes
+            .iter()
+            .map(String::as_str)
+            .find(|class| !GENERIC_CLASSES.contains(class))
+    }
+
+    /// Path to the entry using the ids of the submenus and the entry,
+    /// e.g. `gnulinux-advanced-<uuid>>gnulinux-<version>-advanced-<uuid>`.
+    /// Unlike titles, ids don't change when the kernel version is bumped
+    pub fn id_path(&self) -> Option<String> {
+        let id = self.id.as_ref()?;
+        // all the submenus need an id
+        let submenu_ids: Option<Vec<&str>> =
+            self.submenu_ids.iter().map(Option::as_deref).collect();
+        let submenu_ids = submenu_ids?;
+        if submenu_ids.is_empty() {
+            Some(id.clone())
+        } else {
+            Some(format!("{}>{id}", submenu_ids.join(">")))
+        }
+    }
+
+    /// Value that should be given to grub2-set-default to select this entry
+    pub fn default_value(&self) -> String {
+        self.id_path().unwrap_or_else(|| self.full_path())
+    }
+
+    /// Check if `value` is a `>` separated path to this entry where each part is the
+    /// index, title or id of the submenu or the entry, like `Advanced options>2`
+    fn matches_menu_path(&self, value: &str) -> bool {
+        let parts: Vec<&str> = value.split('>').collect();
+        if parts.len() != self.index_path.len() {
+            return false;
+        }
+
+        parts.iter().enumerate().all(|(level, part)| {
+            if let Ok(index) = part.parse::<usize>() {
+                return self.index_path[level] == index;
+            }
+
+            let (title, id) = if level < self.submenus.len() {
+                (
+                    self.submenus[level].as_str(),
+                    self.submenu_ids[level].as_deref(),
+                )
+            } else {
+                (self.entry.as_str(), self.id())
+            };
+            title == *part || id == Some(part)
+        })
+    }
+
+    /// Check if `value` refers to this entry by its title, full path, id, id path
+    /// or menu path
+    fn matches(&self, value: &str) -> bool {
+        self.entry == value
+            || self.full_path() == value
+            || self.id() == Some(value)
+            || self.id_path().as_deref() == Some(value)
+            || self.matches_menu_path(value)
+    }
+
     pub fn full_path(&self) -> String {
         if self.submenus.is_empty() {
             self.entry.clone()
@@ -278,23 +1125,114 @@ impl GrubBootEntry {
 pub struct GrubBootEntries {
     entries: Vec<GrubBootEntry>,
     selected: Option<GrubBootEntry>,
+    /// Entry that is booted once on the next boot
+    next_entry: Option<GrubBootEntry>,
 }
 
 impl GrubBootEntries {
     pub fn new() -> DResult<Self> {
-        log::debug!("Reading kenrnel boot entries from {GRUB_CFG_PATH}");
-        let config =
-            read_to_string(GRUB_CFG_PATH).ctx(dctx!(), format!("Cannot read {GRUB_CFG_PATH}"))?;
-
-        log::debug!("Reading default boot entry from {GRUB_ENV_PATH}");
-        let grub_env =
-            read_to_string(GRUB_ENV_PATH).ctx(dctx!(), format!("Cannot read {GRUB_ENV_PATH}"))?;
+        let layout = GrubLayout::get();
+        log::debug!("Reading kenrnel boot entries from {}", layout.cfg_path);
+        let config = read_to_string(&layout.cfg_path)
+            .ctx(dctx!(), format!("Cannot read {}", layout.cfg_path))?;
+
+        log::debug!("Reading default boot entry from {}", layout.env_path);
+        let grub_env = read_to_string(&layout.env_path)
+            .ctx(dctx!(), format!("Cannot read {}", layout.env_path))?;
+
+        // only GRUB_ENABLE_BLSCFG is needed so other malformed lines don't matter, and
+        // without the file the blscfg command in grub.cfg still tells if it's enabled
+        let bls_enabled = GrubFile::from_file(GRUB_FILE_PATH, ParseMode::Lenient)
+            .map(|grub| grub.bls_enabled())
+            .unwrap_or(false);
+        if bls_enabled || Self::blscfg_line(&config).is_some() {
+            log::debug!("blscfg is enabled, reading boot entries from {BLS_ENTRIES_PATH}");
+            let bls_entries = match BlsEntry::from_dir(BLS_ENTRIES_PATH) {
+                Ok(entries) => entries,
+                Err(_) => {
+                    // the error itself is logged when it's dropped
+                    log::warn!("Failed to read BLS entries, using only {}", layout.cfg_path);
+                    Vec::new()
+                }
+            };
+            return Self::from_bls(&config, &bls_entries, &grub_env);
+        }
 
         Self::from_contents(&config, &grub_env)
     }
 
+    /// Index of the line running the `blscfg` command in grub.cfg
+    fn blscfg_line(grub_config: &str) -> Option<usize> {
+        grub_config.lines().position(|line| {
+            let line = line.trim();
+            line == "blscfg" || line.starts_with("blscfg ")
+        })
+    }
+
     fn from_contents(grub_config: &str, grub_env: &str) -> DResult<Self> {
         let entries = GrubBootEntry::parse_entries(grub_config)?;
+        Self::from_entries(entries, grub_env)
+    }
+
+    /// Merge the BLS entries with the menuentries of grub.cfg.
+    ///
+    /// grub adds the BLS entries where the `blscfg` command is run so the order
+    /// is kept the same. If grub.cfg hasn't been regenerated with blscfg yet,
+    /// the BLS entries are listed first.
+    fn from_bls(grub_config: &str, bls_entries: &[BlsEntry], grub_env: &str) -> DResult<Self> {
+        let lines: Vec<&str> = grub_config.lines().collect();
+        let (before, after) = match Self::blscfg_line(grub_config) {
+            Some(idx) => (lines[..idx].join("\n"), lines[idx + 1..].join("\n")),
+            None => (String::new(), grub_config.to_string()),
+        };
+
+        let mut entries = GrubBootEntry::parse_entries(&before)?;
+        // keep the submenus of both parts apart
+        let offset = entries
+            .iter()
+            .flat_map(|entry| entry.submenu_keys.iter())
+            .max()
+            .map_or(0, |key| key + 1);
+        entries.extend(bls_entries.iter().map(GrubBootEntry::from_bls));
+        entries.extend(
+            GrubBootEntry::parse_entries(&after)?
+                .into_iter()
+                .map(|mut entry| {
+                    entry.submenu_keys.iter_mut().for_each(|key| *key += offset);
+                    entry
+                }),
+        );
+        Self::from_entries(entries, grub_env)
+    }
+
+    /// Number the entries the same way grub does, submenus count as one entry in
+    /// their parent menu
+    fn set_index_paths(entries: &mut [GrubBootEntry]) {
+        let mut previous: Option<(&[usize], Vec<usize>)> = None;
+        for entry in entries.iter_mut() {
+            let mut path = Vec::with_capacity(entry.submenu_keys.len() + 1);
+            match &previous {
+                Some((keys, previous_path)) => {
+                    let shared = keys
+                        .iter()
+                        .zip(&entry.submenu_keys)
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    path.extend_from_slice(&previous_path[..shared]);
+                    path.push(previous_path[shared] + 1);
+                }
+                None => path.push(0),
+            }
+            // the rest of the submenus were just opened
+            path.resize(entry.submenu_keys.len() + 1, 0);
+
+            entry.index_path = path.clone();
+            previous = Some((&entry.submenu_keys, path));
+        }
+    }
+
+    fn from_entries(mut entries: Vec<GrubBootEntry>, grub_env: &str) -> DResult<Self> {
+        Self::set_index_paths(&mut entries);
 
         let selected_idx = grub_env
             .lines()
@@ -315,24 +1253,12 @@ impl GrubBootEntries {
                     ));
                 }
 
-                let value = if let Ok(index) = value.parse::<usize>() {
-                    GrubEnvValue::Index(index)
-                } else {
-                    GrubEnvValue::Name(value)
-                };
-
-                Ok(value)
+                Ok(GrubEnvValue::parse(value))
             });
 
         let selected = if let Some(value) = selected_idx {
             let value = value?;
-            let entry = match value {
-                GrubEnvValue::Index(idx) => entries.get(idx).cloned(),
-                GrubEnvValue::Name(name) => entries
-                    .iter()
-                    .find(|entry| entry.full_path() == name)
-                    .cloned(),
-            };
+            let entry = Self::find_entry(&entries, &value);
 
             if entry.is_none() {
                 log::warn!("Saved kernel '{value}' was defined as saved_entry but not found in grub. Assuming default kernel.");
@@ -344,7 +1270,52 @@ impl GrubBootEntries {
             None
         };
 
-        Ok(Self { entries, selected })
+        let env = GrubEnv::new(grub_env);
+        let next_entry = env
+            .get("next_entry")
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .and_then(|value| {
+                let value = GrubEnvValue::parse(value);
+                let entry = Self::find_entry(&entries, &value);
+                if entry.is_none() {
+                    log::warn!(
+                        "One-shot entry '{value}' was defined as next_entry but not found in grub."
+                    );
+                }
+                entry
+            });
+
+        Ok(Self {
+            entries,
+            selected,
+            next_entry,
+        })
+    }
+
+    fn find_entry(entries: &[GrubBootEntry], value: &GrubEnvValue) -> Option<GrubBootEntry> {
+        match value {
+            GrubEnvValue::Index(indexes) => entries
+                .iter()
+                .find(|entry| entry.index_path == *indexes)
+                .cloned(),
+            GrubEnvValue::Name(name) => entries
+                .iter()
+                .find(|entry| {
+                    entry.full_path() == *name
+                        || entry.id() == Some(name)
+                        || entry.id_path().as_deref() == Some(name)
+                })
+                .or_else(|| entries.iter().find(|entry| entry.matches_menu_path(name)))
+                .cloned(),
+        }
+    }
+
+    /// Find entry by its title, full submenu path, id or id path
+    pub fn find(&self, entry: &str) -> Option<&GrubBootEntry> {
+        self.entries
+            .iter()
+            .find(|candidate| candidate.matches(entry))
     }
 
     pub fn entry_names(&self) -> Vec<&str> {
@@ -356,6 +1327,31 @@ impl GrubBootEntries {
         &self.entries
     }
 
+    /// Reorder the entries. Sorting by version puts the newest kernel first and keeps
+    /// the entries with the same version, like the recovery entries, right after each other.
+    /// Entries without a kernel version keep their order after the versioned entries
+    pub fn sort(&mut self, sort: EntrySort) {
+        if sort == EntrySort::Menu {
+            return;
+        }
+
+        self.entries
+            .sort_by(|a, b| match (a.version(), b.version()) {
+                (Some(version_a), Some(version_b)) => {
+                    version::compare_versions(version_b, version_a)
+                        .then_with(|| a.is_recovery().cmp(&b.is_recovery()))
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            });
+    }
+
+    /// Ids of the entries in the same order as `entries`
+    pub fn entry_ids(&self) -> Vec<Option<String>> {
+        self.entries.iter().map(GrubBootEntry::id_path).collect()
+    }
+
     pub fn selected(&self) -> Option<&str> {
         if let Some(selected) = &self.selected {
             Some(selected.entry())
@@ -363,6 +1359,25 @@ impl GrubBootEntries {
             None
         }
     }
+
+    /// Is `entry` the one that's booted by default
+    pub fn is_selected(&self, entry: &GrubBootEntry) -> bool {
+        self.selected
+            .as_ref()
+            .is_some_and(|selected| selected.index_path == entry.index_path)
+    }
+
+    /// Pending one-shot entry that's booted on the next boot only
+    pub fn next_entry(&self) -> Option<&str> {
+        self.next_entry.as_ref().map(|entry| entry.entry())
+    }
+
+    /// Is `entry` the one-shot entry of the next boot
+    pub fn is_next(&self, entry: &GrubBootEntry) -> bool {
+        self.next_entry
+            .as_ref()
+            .is_some_and(|next| next.index_path == entry.index_path)
+    }
 }
 
 #[cfg(test)]
@@ -532,6 +1547,334 @@ mod tests {
         assert_eq!(file.as_string(), file_data);
     }
 
+    #[test]
+    fn test_grub2_parsing_lenient() {
+        let file_data = "GRUB_TIMEOUT=5\nfi\nGRUB_DEFAULT=saved\n";
+        assert!(GrubFile::new(file_data).is_err());
+
+        let mut file = GrubFile::parse(file_data, ParseMode::Lenient).unwrap();
+        assert_eq!(
+            file.warnings(),
+            vec!["Expected '=' on line 2, keeping it as is: fi"]
+        );
+        assert_eq!(file.lines()[1], "fi");
+        assert_eq!(file.keyvalues()["GRUB_DEFAULT"].value, "saved");
+
+        file.set_key_value("GRUB_TIMEOUT", "10");
+        assert_eq!(
+            file.as_string(),
+            "GRUB_TIMEOUT=10\nfi\nGRUB_DEFAULT=saved\n"
+        );
+    }
+
+    #[test]
+    fn test_grub2_preserve_quotes() {
+        let file_data = "A='single'\nB=\"double\"\nC=none\nD=\n";
+        let mut file = GrubFile::new(file_data).unwrap();
+        assert_eq!(file.lines()[0], ("A", "single"));
+        assert_eq!(file.lines()[1], ("B", "double"));
+        assert_eq!(file.lines()[2], ("C", "none"));
+        assert_eq!(file.lines()[3], ("D", ""));
+
+        file.set_key_value("A", "changed");
+        file.set_key_value("B", "changed");
+        file.set_key_value("C", "changed");
+        file.set_key_value("D", "changed");
+        assert_eq!(
+            file.as_string(),
+            "A='changed'\nB=\"changed\"\nC=changed\nD=changed\n"
+        );
+
+        // values that can't be written with the original quotes fall back to double quotes
+        file.set_key_value("A", "it's");
+        file.set_key_value("C", "two words");
+        assert_eq!(
+            file.as_string(),
+            "A=\"it's\"\nB=\"changed\"\nC=\"two words\"\nD=changed\n"
+        );
+    }
+
+    #[test]
+    fn test_grub2_line_continuation() {
+        let file_data =
+            "GRUB_TIMEOUT=8\nGRUB_CMDLINE_LINUX_DEFAULT=\"splash=silent \\\n    quiet\"\nGRUB_DEFAULT=saved\n";
+        let mut file = GrubFile::new(file_data).unwrap();
+        let lines = file.lines();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            ("GRUB_CMDLINE_LINUX_DEFAULT", "splash=silent     quiet")
+        );
+        assert_eq!(lines[2], ("GRUB_DEFAULT", "saved"));
+        assert_eq!(file.as_string(), file_data);
+
+        file.set_key_value("GRUB_DEFAULT", "0");
+        assert_eq!(file.lines()[2], ("GRUB_DEFAULT", "0"));
+
+        // changed values are written on a single line
+        file.set_key_value("GRUB_CMDLINE_LINUX_DEFAULT", "quiet");
+        assert_eq!(
+            file.as_string(),
+            "GRUB_TIMEOUT=8\nGRUB_CMDLINE_LINUX_DEFAULT=\"quiet\"\nGRUB_DEFAULT=0\n"
+        );
+
+        // escaped backslash doesn't continue the line
+        let file = GrubFile::new("A=\\\\\nB=1").unwrap();
+        assert_eq!(file.lines().len(), 2);
+    }
+
+    #[test]
+    fn test_grub2_validate_changed() {
+        // unchanged values are not validated
+        let mut file = GrubFile::new("GRUB_TIMEOUT=soon\nGRUB_TERMINAL=gfxterm\n").unwrap();
+        assert!(file.validate_changed().is_ok());
+
+        file.set_key_value("GRUB_TERMINAL", "invalid");
+        file.set_key_value("GRUB_TIMEOUT_STYLE", "hidden");
+        file.set_key_value("GRUB_RECORDFAIL_TIMEOUT", "-5");
+        let err = file.validate_changed().unwrap_err();
+        assert_eq!(
+            err.error().as_string(),
+            "Invalid config values: GRUB_RECORDFAIL_TIMEOUT: '-5' is not a non-negative integer; GRUB_TERMINAL: 'invalid' is not one of console, serial, gfxterm, vga_text, mda_text, morse, spkmodem, ofconsole"
+        );
+    }
+
+    #[test]
+    fn test_grub2_validate_shell_quoting() {
+        let mut file = GrubFile::new("GRUB_DISTRIBUTOR=\"openSUSE\"\n").unwrap();
+        file.set_key_value("GRUB_DISTRIBUTOR", "open \\\"SUSE\\\" $NAME");
+        assert!(file.validate_changed().is_ok());
+
+        for value in ["open\"SUSE", "$(reboot)", "`reboot`", "openSUSE\\"] {
+            file.set_key_value("GRUB_DISTRIBUTOR", value);
+            assert!(
+                file.validate_changed().is_err(),
+                "{value} should not be valid"
+            );
+        }
+
+        assert_eq!(key_error("GRUB_TIMEOUT"), None);
+        assert_eq!(key_error("_PRIVATE2"), None);
+        for key in ["", "2GRUB", "GRUB TIMEOUT", "GRUB_$(reboot)"] {
+            assert!(key_error(key).is_some(), "{key} should not be valid");
+        }
+    }
+
+    #[test]
+    fn test_grub2_deprecated_keys() {
+        let file_data = read_to_string("test_data/grub_full").unwrap();
+        let mut file = GrubFile::new(&file_data).unwrap();
+        let deprecated: Vec<&str> = file.deprecated_keys().iter().map(|dep| dep.key).collect();
+        assert_eq!(
+            deprecated,
+            vec!["GRUB_HIDDEN_TIMEOUT", "GRUB_HIDDEN_TIMEOUT_QUIET"]
+        );
+
+        let migrations = file.migrate_deprecated();
+        assert_eq!(
+            migrations,
+            vec!["GRUB_HIDDEN_TIMEOUT=0 migrated to GRUB_TIMEOUT_STYLE=hidden and GRUB_TIMEOUT=0"]
+        );
+        assert!(file.deprecated_keys().is_empty());
+
+        let lines = file.lines();
+        assert_eq!(lines[7], "#GRUB_HIDDEN_TIMEOUT=0");
+        assert_eq!(lines[8], "#GRUB_HIDDEN_TIMEOUT_QUIET=true");
+        assert_eq!(lines[9], ("GRUB_TIMEOUT", "0"));
+        assert_eq!(lines[10], ("GRUB_TIMEOUT_STYLE", "hidden"));
+
+        let mut file = GrubFile::new("GRUB_DISABLE_LINUX_RECOVERY=true\n").unwrap();
+        file.migrate_deprecated();
+        assert_eq!(
+            file.as_string(),
+            "#GRUB_DISABLE_LINUX_RECOVERY=true\nGRUB_DISABLE_RECOVERY=\"true\"\n"
+        );
+    }
+
+    #[test]
+    fn test_grub2_new_key_before_trailing_newline() {
+        let mut file = GrubFile::new("GRUB_DEFAULT=saved\n").unwrap();
+        file.set_key_value("GRUB_DISABLE_OS_PROBER", "true");
+        assert_eq!(
+            file.as_string(),
+            "GRUB_DEFAULT=saved\nGRUB_DISABLE_OS_PROBER=\"true\"\n"
+        );
+    }
+
+    #[test]
+    fn test_grub2_new_key_section_placement() {
+        let file_data = read_to_string("test_data/grub_full").unwrap();
+        let mut file = GrubFile::new(&file_data).unwrap();
+        file.set_key_value("GRUB_GFXPAYLOAD_LINUX", "keep");
+        let lines = file.lines();
+        assert_eq!(lines.len(), 47);
+        // GRUB_THEME is the last display option in the file
+        assert_eq!(
+            lines[39],
+            ("GRUB_THEME", "/boot/grub2/themes/openSUSE/theme.txt")
+        );
+        assert_eq!(lines[40], ("GRUB_GFXPAYLOAD_LINUX", "keep"));
+        assert_eq!(lines[41], ("SUSE_BTRFS_SNAPSHOT_BOOTING", "true"));
+
+        // line numbers after the insertion must still point to the right lines
+        file.set_key_value("GRUB_USE_LINUXEFI", "false");
+        assert_eq!(file.lines()[42], ("GRUB_USE_LINUXEFI", "false"));
+    }
+
+    #[test]
+    fn test_grub2_uncomment_key() {
+        let file_data = "GRUB_DEFAULT=saved\n# Save the selected entry\n# GRUB_SAVEDEFAULT=\"false\"\nGRUB_TIMEOUT=5\n";
+        let mut file = GrubFile::new(file_data).unwrap();
+        file.set_key_value("GRUB_SAVEDEFAULT", "true");
+        assert_eq!(
+            file.as_string(),
+            "GRUB_DEFAULT=saved\n# Save the selected entry\nGRUB_SAVEDEFAULT=\"true\"\nGRUB_TIMEOUT=5\n"
+        );
+        assert_eq!(file.keyvalues()["GRUB_SAVEDEFAULT"].line, 2);
+        assert_eq!(file.keyvalues()["GRUB_TIMEOUT"].line, 3);
+
+        // commented keys are kept as comments when they are not set
+        let mut file = GrubFile::new("#GRUB_TERMINAL=console\n").unwrap();
+        file.set_key_value("GRUB_TIMEOUT", "5");
+        assert_eq!(
+            file.as_string(),
+            "#GRUB_TERMINAL=console\nGRUB_TIMEOUT=\"5\"\n"
+        );
+
+        file.insert_key_after("GRUB_TERMINAL", "console", "GRUB_TIMEOUT");
+        assert_eq!(
+            file.as_string(),
+            "GRUB_TERMINAL=console\nGRUB_TIMEOUT=\"5\"\n"
+        );
+    }
+
+    #[test]
+    fn test_grub2_remove_key() {
+        let file_data = "GRUB_DEFAULT=saved\nGRUB_TIMEOUT=5\nGRUB_TERMINAL=console\n";
+        let mut file = GrubFile::new(file_data).unwrap();
+        assert!(file.remove_key("GRUB_TIMEOUT", RemoveMode::Delete));
+        assert!(!file.remove_key("GRUB_TIMEOUT", RemoveMode::Delete));
+        assert_eq!(
+            file.as_string(),
+            "GRUB_DEFAULT=saved\nGRUB_TERMINAL=console\n"
+        );
+        assert_eq!(file.keyvalues()["GRUB_TERMINAL"].line, 1);
+
+        assert!(file.remove_key("GRUB_TERMINAL", RemoveMode::Comment));
+        assert_eq!(
+            file.as_string(),
+            "GRUB_DEFAULT=saved\n#GRUB_TERMINAL=console\n"
+        );
+        assert!(!file.keyvalues().contains_key("GRUB_TERMINAL"));
+    }
+
+    #[test]
+    fn test_grub2_duplicate_keys() {
+        let file_data = "GRUB_TIMEOUT=5\nGRUB_DEFAULT=saved\nGRUB_TIMEOUT=8\n";
+        let mut file = GrubFile::new(file_data).unwrap();
+        assert_eq!(file.duplicate_keys(), vec!["GRUB_TIMEOUT"]);
+        // last definition wins like in shell
+        assert_eq!(file.keyvalues()["GRUB_TIMEOUT"].value, "8");
+        assert_eq!(file.keyvalues()["GRUB_TIMEOUT"].line, 2);
+
+        file.set_key_value("GRUB_TIMEOUT", "10");
+        assert_eq!(
+            file.as_string(),
+            "GRUB_TIMEOUT=10\nGRUB_DEFAULT=saved\nGRUB_TIMEOUT=10\n"
+        );
+
+        assert!(file.remove_key("GRUB_TIMEOUT", RemoveMode::Comment));
+        assert_eq!(
+            file.as_string(),
+            "#GRUB_TIMEOUT=10\nGRUB_DEFAULT=saved\n#GRUB_TIMEOUT=10\n"
+        );
+        assert!(file.duplicate_keys().is_empty());
+
+        let mut file = GrubFile::new(file_data).unwrap();
+        assert!(file.remove_key("GRUB_TIMEOUT", RemoveMode::Delete));
+        assert_eq!(file.as_string(), "GRUB_DEFAULT=saved\n");
+        assert_eq!(file.keyvalues()["GRUB_DEFAULT"].line, 0);
+    }
+
+    #[test]
+    fn test_grub2_diff() {
+        let old = GrubFile::new("# timeout\nGRUB_TIMEOUT=5\nGRUB_DEFAULT=saved\n").unwrap();
+        let new = GrubFile::new("# menu timeout\nGRUB_TIMEOUT=8\nGRUB_TERMINAL=console\n").unwrap();
+
+        assert_eq!(old.diff(&old), vec![]);
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                KeyChange::Removed {
+                    key: "GRUB_DEFAULT".into(),
+                    value: "saved".into()
+                },
+                KeyChange::Added {
+                    key: "GRUB_TERMINAL".into(),
+                    value: "console".into()
+                },
+                KeyChange::Changed {
+                    key: "GRUB_TIMEOUT".into(),
+                    old: "5".into(),
+                    new: "8".into()
+                },
+                KeyChange::LineRemoved {
+                    line: "# timeout".into()
+                },
+                KeyChange::LineAdded {
+                    line: "# menu timeout".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_value_changes() {
+        let old = BTreeMap::from([
+            ("timeout".to_string(), "5".to_string()),
+            ("default".to_string(), "linux".to_string()),
+        ]);
+        let new = BTreeMap::from([
+            ("timeout".to_string(), "5".to_string()),
+            ("editor".to_string(), "no".to_string()),
+        ]);
+
+        assert_eq!(value_changes(&old, &old), vec![]);
+        assert_eq!(
+            value_changes(&old, &new),
+            vec![
+                KeyChange::Removed {
+                    key: "default".into(),
+                    value: "linux".into()
+                },
+                KeyChange::Added {
+                    key: "editor".into(),
+                    value: "no".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_grub2_insert_key_after() {
+        let file_data = read_to_string("test_data/grub_simple").unwrap();
+        let mut file = GrubFile::new(&file_data).unwrap();
+        file.insert_key_after("GRUB_SAVEDEFAULT", "true", "GRUB_DEFAULT");
+        let lines = file.lines();
+        assert_eq!(lines[1], ("GRUB_DEFAULT", "saved"));
+        assert_eq!(lines[2], ("GRUB_SAVEDEFAULT", "true"));
+        assert_eq!(lines[3], ("GRUB_HIDDEN_TIMEOUT_QUIET", "true"));
+
+        // existing keys are updated in place
+        file.insert_key_after("GRUB_TIMEOUT", "3", "GRUB_DISTRIBUTOR");
+        assert_eq!(file.lines()[4], ("GRUB_TIMEOUT", "3"));
+
+        // missing anchor falls back to the default placement
+        file.insert_key_after("GRUB_ENABLE_CRYPTODISK", "y", "GRUB_MISSING");
+        assert_eq!(file.lines()[5], ("GRUB_ENABLE_CRYPTODISK", "y"));
+        assert_eq!(file.lines()[6], "");
+    }
+
     #[test]
     fn test_grub2_bootentries_noselect() {
         let config = read_to_string("test_data/grub.cfg").unwrap();
@@ -561,4 +1904,308 @@ mod tests {
         assert_eq!(entries.entries()[3].submenus, Vec::<String>::new());
         assert_eq!(entries.selected(), None);
     }
+
+    #[test]
+    fn test_grub2_bootentries_index_path() {
+        let config = "menuentry 'Linux' {\n}\n\
+            submenu 'Advanced' $menuentry_id_option 'advanced' {\n\
+                menuentry 'Linux 6.17' {\n}\n\
+                submenu 'Older' {\n\
+                    menuentry 'Linux 6.9' {\n}\n\
+                    menuentry 'Linux 6.8' {\n}\n\
+                }\n\
+                menuentry 'Linux 6.17 (recovery mode)' {\n}\n\
+            }\n\
+            submenu 'Advanced' {\n\
+                menuentry 'Other' {\n}\n\
+            }\n\
+            menuentry 'UEFI Firmware Settings' {\n}\n";
+        let selected = |saved_entry: &str| {
+            let grub_env = format!("saved_entry={saved_entry}\n");
+            let entries = GrubBootEntries::from_contents(config, &grub_env).unwrap();
+            entries.selected().map(str::to_string)
+        };
+
+        assert_eq!(selected("0").as_deref(), Some("Linux"));
+        assert_eq!(selected("1>0").as_deref(), Some("Linux 6.17"));
+        assert_eq!(selected("1>1>1").as_deref(), Some("Linux 6.8"));
+        assert_eq!(
+            selected("1>2").as_deref(),
+            Some("Linux 6.17 (recovery mode)")
+        );
+        assert_eq!(selected("2>0").as_deref(), Some("Other"));
+        assert_eq!(selected("3").as_deref(), Some("UEFI Firmware Settings"));
+        assert_eq!(selected("advanced>Older>0").as_deref(), Some("Linux 6.9"));
+        assert_eq!(
+            selected("Advanced>1>Linux 6.8").as_deref(),
+            Some("Linux 6.8")
+        );
+        // submenus are not entries
+        assert_eq!(selected("1"), None);
+        assert_eq!(selected("1>5"), None);
+
+        // SetDefaultEntry saves the index path as a name that doesn't change
+        // when new kernels are added
+        let entries = GrubBootEntries::from_contents(config, "").unwrap();
+        let entry = entries.find("1>1>0").unwrap();
+        assert_eq!(entry.default_value(), "Advanced>Older>Linux 6.9");
+    }
+
+    #[test]
+    fn test_grub2_bootentries_ids() {
+        let config = read_to_string("test_data/grub.cfg").unwrap();
+        let grub_env = GrubEnv::new(&read_to_string("test_data/grubenv_empty").unwrap());
+        let entries =
+            GrubBootEntries::from_contents(&config, &grub_env.as_block().unwrap()).unwrap();
+
+        let uuid = "0abc385d-dbed-8e40-8db1-1178f94b177c";
+        assert_eq!(
+            entries.entries()[1].id(),
+            Some(format!("gnulinux-6.17.5-1-default-advanced-{uuid}").as_str())
+        );
+        let id_path = format!("gnulinux-advanced-{uuid}>gnulinux-6.17.5-1-default-advanced-{uuid}");
+        assert_eq!(entries.entries()[1].default_value(), id_path);
+        assert_eq!(entries.entries()[3].default_value(), "uefi-firmware");
+        assert_eq!(
+            entries.find(&id_path).map(GrubBootEntry::entry),
+            Some("openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default")
+        );
+
+        let mut grub_env = grub_env;
+        grub_env.set("saved_entry", &id_path);
+        let entries =
+            GrubBootEntries::from_contents(&config, &grub_env.as_block().unwrap()).unwrap();
+        assert_eq!(
+            entries.selected(),
+            Some("openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default")
+        );
+        assert!(entries.is_selected(&entries.entries()[1]));
+        assert!(!entries.is_selected(&entries.entries()[0]));
+
+        // entries without ids fall back to titles
+        let config = "menuentry 'Linux' {\n}\n";
+        let entries = GrubBootEntries::from_contents(config, "").unwrap();
+        assert_eq!(entries.entries()[0].default_value(), "Linux");
+        assert_eq!(entries.entry_ids(), vec![None]);
+    }
+
+    #[test]
+    fn test_grub2_bootentries_variables() {
+        let config = r#"
+set os="openSUSE Tumbleweed"
+version=6.17.5
+menuentry "$os" --class os {
+    set os="Changed"
+}
+submenu "Advanced options for ${os}" {
+    menuentry "${os}, with Linux $version" {
+    }
+    menuentry '${os} (fallback)' {
+    }
+}
+"#;
+        let entries = GrubBootEntries::from_contents(config, "").unwrap();
+        assert_eq!(
+            entries.entry_names(),
+            vec![
+                "openSUSE Tumbleweed",
+                "openSUSE Tumbleweed, with Linux 6.17.5",
+                "${os} (fallback)",
+            ]
+        );
+        assert_eq!(
+            entries.entries()[1].full_path(),
+            "Advanced options for openSUSE Tumbleweed>openSUSE Tumbleweed, with Linux 6.17.5"
+        );
+    }
+
+    #[test]
+    fn test_grub2_bootentries_classes() {
+        let config = read_to_string("test_data/grub.cfg").unwrap();
+        let entries = GrubBootEntries::from_contents(&config, "").unwrap();
+        assert_eq!(
+            entries.entries()[0].classes(),
+            vec!["opensuse", "gnu-linux", "gnu", "os"]
+        );
+        assert_eq!(entries.entries()[0].os(), Some("opensuse"));
+        assert!(entries.entries()[3].classes().is_empty());
+        assert_eq!(entries.entries()[3].os(), None);
+
+        let config = "menuentry 'Windows' --class=windows --class os {\n}\n";
+        let entries = GrubBootEntries::from_contents(config, "").unwrap();
+        assert_eq!(entries.entries()[0].classes(), vec!["windows", "os"]);
+        assert_eq!(entries.entries()[0].os(), Some("windows"));
+    }
+
+    #[test]
+    fn test_grub2_bootentries_kernels() {
+        let config = read_to_string("test_data/grub.cfg").unwrap();
+        let entries = GrubBootEntries::from_contents(&config, "").unwrap();
+        let entry = &entries.entries()[1];
+        assert_eq!(entry.kernel(), Some("/boot/vmlinuz-6.17.5-1-default"));
+        assert_eq!(entry.initrds(), &["/boot/initrd-6.17.5-1-default"]);
+        assert_eq!(entry.version(), Some("6.17.5-1-default"));
+        assert_eq!(entries.entries()[3].kernel(), None);
+
+        let config = "set isofile=/iso/rescue.iso\nmenuentry 'ISO' {\n\tlinuxefi (loop)/boot/vmlinuz iso=$isofile\n\tinitrdefi /ucode.img /initrd.img\n}\n";
+        let entries = GrubBootEntries::from_contents(config, "").unwrap();
+        let entry = &entries.entries()[0];
+        assert_eq!(entry.kernel(), Some("(loop)/boot/vmlinuz"));
+        assert_eq!(entry.version(), None);
+        assert_eq!(entry.initrds(), &["/ucode.img", "/initrd.img"]);
+
+        let bls_entries = BlsEntry::from_dir("test_data/loader/entries").unwrap();
+        let entries = GrubBootEntries::from_bls("", &bls_entries, "").unwrap();
+        assert_eq!(
+            entries.entries()[1].version(),
+            Some("6.12.5-200.fc41.x86_64")
+        );
+        assert_eq!(
+            kernel_version("/boot/Image-6.1.0-arm64"),
+            Some("6.1.0-arm64".into())
+        );
+        assert_eq!(kernel_version("/boot/vmlinuz"), None);
+    }
+
+    #[test]
+    fn test_grub2_bootentries_sort() {
+        let config = "menuentry 'Windows' {\n}\n\
+            menuentry 'Linux 6.9' {\n linux /vmlinuz-6.9\n}\n\
+            menuentry 'Linux 6.9 (recovery mode)' {\n linux /vmlinuz-6.9 single\n}\n\
+            menuentry 'Linux 6.17' {\n linux /vmlinuz-6.17\n}\n\
+            menuentry 'Linux 6.17 (recovery mode)' --class recovery {\n linux /vmlinuz-6.17\n}\n\
+            menuentry 'Linux 6.18-rc1' {\n linux /vmlinuz-6.18~rc1\n}\n";
+        let mut entries = GrubBootEntries::from_contents(config, "").unwrap();
+        entries.sort(EntrySort::Menu);
+        assert_eq!(entries.entry_names()[0], "Windows");
+
+        entries.sort(EntrySort::Version);
+        assert_eq!(
+            entries.entry_names(),
+            vec![
+                "Linux 6.18-rc1",
+                "Linux 6.17",
+                "Linux 6.17 (recovery mode)",
+                "Linux 6.9",
+                "Linux 6.9 (recovery mode)",
+                "Windows",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_grub2_bootentries_large_config() {
+        let mut config = String::from("set os=\"Linux\"\n");
+        for idx in 0..5000 {
+            config.push_str(&format!(
+                "menuentry \"$os {idx}\" --class os $menuentry_id_option 'linux-{idx}' {{\n"
+            ));
+            for _ in 0..10 {
+                config.push_str("\tinsmod part_gpt\n\techo 'Loading Linux ...'\n");
+            }
+            config.push_str("}\n");
+        }
+
+        let start = std::time::Instant::now();
+        let entries = GrubBootEntries::from_contents(&config, "").unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(entries.entries().len(), 5000);
+        assert_eq!(entries.entries()[4999].entry(), "Linux 4999");
+        assert_eq!(entries.entries()[4999].id(), Some("linux-4999"));
+        // generous limit so this only catches accidental quadratic parsing
+        assert!(elapsed.as_secs() < 5, "parsing took {elapsed:?}");
+    }
+
+    #[test]
+    fn test_grub2_bootentries_next_entry() {
+        let config = read_to_string("test_data/grub.cfg").unwrap();
+        let mut grub_env = GrubEnv::new(&read_to_string("test_data/grubenv_saved").unwrap());
+        // the advanced options submenu is a single entry in the main menu
+        grub_env.set("next_entry", "2");
+        let entries =
+            GrubBootEntries::from_contents(&config, &grub_env.as_block().unwrap()).unwrap();
+        assert_eq!(
+            entries.selected(),
+            Some("openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default")
+        );
+        assert_eq!(entries.next_entry(), Some("UEFI Firmware Settings"));
+
+        grub_env.set(
+            "next_entry",
+            "Advanced options for openSUSE Tumbleweed Minimal>openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default (recovery mode)",
+        );
+        let entries =
+            GrubBootEntries::from_contents(&config, &grub_env.as_block().unwrap()).unwrap();
+        assert_eq!(
+            entries.next_entry(),
+            Some("openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default (recovery mode)")
+        );
+        let next: Vec<&str> = entries
+            .entries()
+            .iter()
+            .filter(|entry| entries.is_next(entry))
+            .map(|entry| entry.entry())
+            .collect();
+        assert_eq!(
+            next,
+            vec!["openSUSE Tumbleweed Minimal, with Linux 6.17.5-1-default (recovery mode)"]
+        );
+
+        grub_env.set("next_entry", "");
+        let entries =
+            GrubBootEntries::from_contents(&config, &grub_env.as_block().unwrap()).unwrap();
+        assert_eq!(entries.next_entry(), None);
+    }
+
+    #[test]
+    fn test_grub2_bls_enabled() {
+        let file_data = read_to_string("test_data/grub_bls").unwrap();
+        let file = GrubFile::new(&file_data).unwrap();
+        assert!(file.bls_enabled());
+
+        let file_data = read_to_string("test_data/grub_full").unwrap();
+        let file = GrubFile::new(&file_data).unwrap();
+        assert!(!file.bls_enabled());
+    }
+
+    #[test]
+    fn test_grub2_bootentries_bls() {
+        let bls_entries = BlsEntry::from_dir("test_data/loader/entries").unwrap();
+        let grub_env = read_to_string("test_data/grubenv_bls").unwrap();
+        let entries = GrubBootEntries::from_bls("", &bls_entries, &grub_env).unwrap();
+
+        assert_eq!(entries.entries().len(), 2);
+        assert_eq!(
+            entries.entries()[0].default_value(),
+            "4c1d5b8ad1e94d1c8f3b1e2a3d4c5b6a-6.11.4-301.fc41.x86_64"
+        );
+        assert_eq!(
+            entries.selected(),
+            Some("Fedora Linux (6.12.5-200.fc41.x86_64) 41 (Workstation Edition)")
+        );
+    }
+
+    #[test]
+    fn test_grub2_bootentries_blscfg_merge() {
+        let bls_entries = BlsEntry::from_dir("test_data/loader/entries").unwrap();
+        let config = read_to_string("test_data/grub_bls.cfg").unwrap();
+        let grub_env = read_to_string("test_data/grubenv_bls").unwrap();
+        assert_eq!(GrubBootEntries::blscfg_line(&config), Some(39));
+        assert_eq!(GrubBootEntries::blscfg_line("insmod blscfg\n"), None);
+
+        let entries = GrubBootEntries::from_bls(&config, &bls_entries, &grub_env).unwrap();
+        assert_eq!(
+            entries.entry_names(),
+            vec![
+                "Fedora Linux (6.11.4-301.fc41.x86_64) 41 (Workstation Edition)",
+                "Fedora Linux (6.12.5-200.fc41.x86_64) 41 (Workstation Edition)",
+                "UEFI Firmware Settings",
+            ]
+        );
+        assert_eq!(
+            entries.selected(),
+            Some("Fedora Linux (6.12.5-200.fc41.x86_64) 41 (Workstation Edition)")
+        );
+    }
 }
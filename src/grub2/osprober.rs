@@ -0,0 +1,92 @@
+use serde::Serialize;
+
+use crate::grub2::script::GrubScript;
+
+/// grub.d script that adds the entries of the operating systems os-prober finds
+pub const OS_PROBER_SCRIPT: &str = "/etc/grub.d/30_os-prober";
+/// The script does nothing if os-prober is not installed
+pub const OS_PROBER_PATH: &str = "/usr/bin/os-prober";
+
+/// What os-prober added to grub.cfg
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct OsProberStatus {
+    /// Effective GRUB_DISABLE_OS_PROBER, os-prober is disabled by default since grub 2.06
+    pub disabled: bool,
+    /// grub.cfg was generated with the os-prober script
+    pub section_found: bool,
+    /// Titles of the top level entries and submenus os-prober added
+    pub systems: Vec<String>,
+}
+
+impl OsProberStatus {
+    /// Find the os-prober entries from `grub_cfg`. `disable_value` is the effective
+    /// GRUB_DISABLE_OS_PROBER value
+    pub fn new(grub_cfg: &str, disable_value: Option<&str>) -> Self {
+        let disabled = disable_value.is_none_or(|value| value != "false");
+        let Some(section) = GrubScript::section(grub_cfg, OS_PROBER_SCRIPT) else {
+            return Self {
+                disabled,
+                ..Default::default()
+            };
+        };
+
+        let mut systems = Vec::new();
+        let mut depth = 0;
+        for line in section {
+            if depth == 0 {
+                let args = GrubScript::command_args(line, "menuentry")
+                    .or_else(|| GrubScript::command_args(line, "submenu"));
+                if let Some((title, _)) =
+                    args.and_then(|args| GrubScript::default().parse_word(args))
+                {
+                    systems.push(title);
+                }
+            }
+            depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+        }
+
+        Self {
+            disabled,
+            section_found: true,
+            systems,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use super::*;
+
+    #[test]
+    fn test_os_prober_status() {
+        let grub_cfg = read_to_string("test_data/grub_osprober.cfg").unwrap();
+        let status = OsProberStatus::new(&grub_cfg, Some("false"));
+        assert!(!status.disabled);
+        assert!(status.section_found);
+        assert_eq!(
+            status.systems,
+            vec![
+                "Windows Boot Manager (on /dev/nvme0n1p1)",
+                "Fedora Linux 42 (on /dev/nvme0n1p5)",
+                "Advanced options for Fedora Linux 42 (on /dev/nvme0n1p5)",
+            ]
+        );
+
+        let grub_cfg = read_to_string("test_data/grub.cfg").unwrap();
+        let status = OsProberStatus::new(&grub_cfg, None);
+        assert!(status.disabled);
+        assert!(status.section_found);
+        assert!(status.systems.is_empty());
+
+        let grub_cfg = read_to_string("test_data/grub_bls.cfg").unwrap();
+        assert_eq!(
+            OsProberStatus::new(&grub_cfg, Some("true")),
+            OsProberStatus {
+                disabled: true,
+                ..Default::default()
+            }
+        );
+    }
+}
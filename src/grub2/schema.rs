@@ -0,0 +1,477 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::grub2::gfxmode::is_resolution;
+
+/// Type of the value a grub key expects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Bool,
+    Int,
+    Enum,
+    String,
+    Path,
+    /// Comma or semicolon separated list of `WIDTHxHEIGHT[xDEPTH]` resolutions
+    /// or the keywords in `allowed_values`
+    Resolution,
+}
+
+/// Description of a known grub key that front-ends can use to render the right widget
+#[derive(Debug, Serialize)]
+pub struct KeySchema {
+    pub key: &'static str,
+    pub value_type: ValueType,
+    /// Values that are accepted. Empty if any value of `value_type` is accepted.
+    /// For bools, these are the true and false spellings grub expects.
+    pub allowed_values: &'static [&'static str],
+    /// Value is a space separated list of `allowed_values`
+    pub multiple: bool,
+    /// Value grub uses when the key is not set
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+const BOOL: &[&str] = &["true", "false"];
+const GFXMODE: &[&str] = &["auto"];
+const GFXPAYLOAD: &[&str] = &["text", "keep", "auto"];
+const TERMINAL_INPUT: &[&str] = &[
+    "console",
+    "serial",
+    "ofconsole",
+    "at_keyboard",
+    "usb_keyboard",
+];
+const TERMINAL_OUTPUT: &[&str] = &[
+    "console",
+    "serial",
+    "gfxterm",
+    "vga_text",
+    "mda_text",
+    "morse",
+    "spkmodem",
+    "ofconsole",
+];
+
+const fn key(
+    key: &'static str,
+    value_type: ValueType,
+    default: Option<&'static str>,
+    description: &'static str,
+) -> KeySchema {
+    let allowed_values = match value_type {
+        ValueType::Bool => BOOL,
+        ValueType::Resolution => GFXMODE,
+        _ => &[],
+    };
+
+    KeySchema {
+        key,
+        value_type,
+        allowed_values,
+        multiple: false,
+        default,
+        description,
+    }
+}
+
+const fn enum_key(
+    key: &'static str,
+    allowed_values: &'static [&'static str],
+    multiple: bool,
+    default: Option<&'static str>,
+    description: &'static str,
+) -> KeySchema {
+    KeySchema {
+        key,
+        value_type: ValueType::Enum,
+        allowed_values,
+        multiple,
+        default,
+        description,
+    }
+}
+
+pub const KEY_SCHEMA: &[KeySchema] = &[
+    key(
+        "GRUB_DEFAULT",
+        ValueType::String,
+        Some("0"),
+        "Default menu entry. Index, title, id or 'saved'",
+    ),
+    key(
+        "GRUB_SAVEDEFAULT",
+        ValueType::Bool,
+        Some("false"),
+        "Save the booted entry as the new default. Requires GRUB_DEFAULT=saved",
+    ),
+    key(
+        "GRUB_TIMEOUT",
+        ValueType::Int,
+        Some("5"),
+        "Seconds to wait before booting the default entry",
+    ),
+    enum_key(
+        "GRUB_TIMEOUT_STYLE",
+        &["menu", "countdown", "hidden"],
+        false,
+        Some("menu"),
+        "How the menu is shown during the timeout",
+    ),
+    key(
+        "GRUB_HIDDEN_TIMEOUT",
+        ValueType::Int,
+        None,
+        "Seconds to wait with the menu hidden",
+    ),
+    key(
+        "GRUB_HIDDEN_TIMEOUT_QUIET",
+        ValueType::Bool,
+        Some("false"),
+        "Hide the countdown during GRUB_HIDDEN_TIMEOUT",
+    ),
+    key(
+        "GRUB_RECORDFAIL_TIMEOUT",
+        ValueType::Int,
+        None,
+        "Timeout used after a failed boot",
+    ),
+    key(
+        "GRUB_DISTRIBUTOR",
+        ValueType::String,
+        None,
+        "Distribution name used in the menu entry titles",
+    ),
+    enum_key(
+        "GRUB_TERMINAL",
+        TERMINAL_OUTPUT,
+        true,
+        None,
+        "Terminal used for both input and output",
+    ),
+    enum_key(
+        "GRUB_TERMINAL_INPUT",
+        TERMINAL_INPUT,
+        true,
+        None,
+        "Terminal input devices",
+    ),
+    enum_key(
+        "GRUB_TERMINAL_OUTPUT",
+        TERMINAL_OUTPUT,
+        true,
+        None,
+        "Terminal output devices",
+    ),
+    key(
+        "GRUB_SERIAL_COMMAND",
+        ValueType::String,
+        None,
+        "Serial port configuration when serial terminal is used",
+    ),
+    key(
+        "GRUB_CMDLINE_LINUX",
+        ValueType::String,
+        None,
+        "Kernel command line for all Linux entries",
+    ),
+    key(
+        "GRUB_CMDLINE_LINUX_DEFAULT",
+        ValueType::String,
+        None,
+        "Kernel command line for the default entries, but not the recovery entries",
+    ),
+    key(
+        "GRUB_CMDLINE_XEN",
+        ValueType::String,
+        None,
+        "Xen hypervisor command line for all Xen entries",
+    ),
+    key(
+        "GRUB_CMDLINE_XEN_DEFAULT",
+        ValueType::String,
+        None,
+        "Xen hypervisor command line for the default Xen entries",
+    ),
+    key(
+        "GRUB_DISABLE_LINUX_UUID",
+        ValueType::Bool,
+        Some("false"),
+        "Pass root device path instead of root=UUID to the kernel",
+    ),
+    key(
+        "GRUB_DISABLE_LINUX_PARTUUID",
+        ValueType::Bool,
+        Some("true"),
+        "Don't pass root=PARTUUID to the kernel",
+    ),
+    key(
+        "GRUB_DISABLE_RECOVERY",
+        ValueType::Bool,
+        Some("false"),
+        "Don't generate recovery mode entries",
+    ),
+    key(
+        "GRUB_DISABLE_SUBMENU",
+        ValueType::Bool,
+        Some("false"),
+        "Show all kernels in the main menu instead of a submenu",
+    ),
+    key(
+        "GRUB_DISABLE_OS_PROBER",
+        ValueType::Bool,
+        Some("true"),
+        "Don't search for other operating systems",
+    ),
+    key(
+        "GRUB_OS_PROBER_SKIP_LIST",
+        ValueType::String,
+        None,
+        "Space separated filesystem UUIDs os-prober should skip",
+    ),
+    KeySchema {
+        key: "GRUB_ENABLE_CRYPTODISK",
+        value_type: ValueType::Bool,
+        allowed_values: &["y", "n"],
+        multiple: false,
+        default: Some("n"),
+        description: "Allow booting from encrypted disks",
+    },
+    key(
+        "GRUB_ENABLE_BLSCFG",
+        ValueType::Bool,
+        Some("false"),
+        "Read boot entries from Boot Loader Specification snippets",
+    ),
+    key(
+        "GRUB_GFXMODE",
+        ValueType::Resolution,
+        Some("auto"),
+        "Resolution of the graphical terminal, like 1024x768 or auto",
+    ),
+    KeySchema {
+        key: "GRUB_GFXPAYLOAD_LINUX",
+        value_type: ValueType::Resolution,
+        allowed_values: GFXPAYLOAD,
+        multiple: false,
+        default: None,
+        description: "Resolution passed to the kernel. 'text', 'keep' or a resolution",
+    },
+    key(
+        "GRUB_BACKGROUND",
+        ValueType::Path,
+        None,
+        "Background image of the graphical terminal",
+    ),
+    key(
+        "GRUB_THEME",
+        ValueType::Path,
+        None,
+        "Theme file of the graphical menu",
+    ),
+    key(
+        "GRUB_FONT",
+        ValueType::Path,
+        None,
+        "Font file used in the graphical terminal",
+    ),
+    key(
+        "GRUB_INIT_TUNE",
+        ValueType::String,
+        None,
+        "Tune played when grub starts",
+    ),
+    key(
+        "GRUB_BADRAM",
+        ValueType::String,
+        None,
+        "Memory regions that should be filtered out",
+    ),
+    key(
+        "GRUB_PRELOAD_MODULES",
+        ValueType::String,
+        None,
+        "Grub modules that are loaded as early as possible",
+    ),
+    key(
+        "GRUB_USE_LINUXEFI",
+        ValueType::Bool,
+        None,
+        "Use linuxefi and initrdefi commands on EFI systems",
+    ),
+    key(
+        "SUSE_BTRFS_SNAPSHOT_BOOTING",
+        ValueType::Bool,
+        None,
+        "Allow booting into read-only btrfs snapshots",
+    ),
+];
+
+/// Key that grub still understands but that has a modern replacement
+#[derive(Debug, Serialize)]
+pub struct Deprecation {
+    pub key: &'static str,
+    pub replacement: &'static str,
+    pub message: &'static str,
+}
+
+pub const DEPRECATED_KEYS: &[Deprecation] = &[
+    Deprecation {
+        key: "GRUB_HIDDEN_TIMEOUT",
+        replacement: "GRUB_TIMEOUT_STYLE",
+        message: "GRUB_HIDDEN_TIMEOUT is deprecated, use GRUB_TIMEOUT_STYLE=hidden or countdown with GRUB_TIMEOUT",
+    },
+    Deprecation {
+        key: "GRUB_HIDDEN_TIMEOUT_QUIET",
+        replacement: "GRUB_TIMEOUT_STYLE",
+        message: "GRUB_HIDDEN_TIMEOUT_QUIET is deprecated, use GRUB_TIMEOUT_STYLE=hidden",
+    },
+    Deprecation {
+        key: "GRUB_HIDDEN_TIMEOUT_BUTTON",
+        replacement: "GRUB_TIMEOUT_STYLE_BUTTON",
+        message: "GRUB_HIDDEN_TIMEOUT_BUTTON is deprecated, use GRUB_TIMEOUT_STYLE_BUTTON with GRUB_TIMEOUT_BUTTON",
+    },
+    Deprecation {
+        key: "GRUB_DISABLE_LINUX_RECOVERY",
+        replacement: "GRUB_DISABLE_RECOVERY",
+        message: "GRUB_DISABLE_LINUX_RECOVERY was renamed to GRUB_DISABLE_RECOVERY",
+    },
+];
+
+pub fn deprecation(key: &str) -> Option<&'static Deprecation> {
+    DEPRECATED_KEYS
+        .iter()
+        .find(|deprecated| deprecated.key == key)
+}
+
+/// Schema of a known grub key
+pub fn key_schema(key: &str) -> Option<&'static KeySchema> {
+    KEY_SCHEMA.iter().find(|schema| schema.key == key)
+}
+
+impl KeySchema {
+    /// Check that `value` is valid for this key. Empty value means that the key is unset
+    /// so it's always valid.
+    pub fn validate(&self, value: &str) -> Result<(), String> {
+        if value.is_empty() {
+            return Ok(());
+        }
+
+        match self.value_type {
+            ValueType::Int => {
+                if value.parse::<u64>().is_err() {
+                    return Err(format!("'{value}' is not a non-negative integer"));
+                }
+            }
+            ValueType::Path => {
+                if !Path::new(value).exists() {
+                    return Err(format!("path '{value}' does not exist"));
+                }
+            }
+            ValueType::String => {}
+            ValueType::Resolution => {
+                if let Some(invalid) = value
+                    .split([',', ';'])
+                    .map(str::trim)
+                    .find(|mode| !self.allowed_values.contains(mode) && !is_resolution(mode))
+                {
+                    return Err(format!(
+                        "'{invalid}' is not a WIDTHxHEIGHT[xDEPTH] resolution or one of {}",
+                        self.allowed_values.join(", ")
+                    ));
+                }
+            }
+            ValueType::Bool | ValueType::Enum => {
+                let values: Vec<&str> = if self.multiple {
+                    value.split_whitespace().collect()
+                } else {
+                    vec![value]
+                };
+
+                if let Some(invalid) = values
+                    .iter()
+                    .find(|value| !self.allowed_values.contains(value))
+                {
+                    return Err(format!(
+                        "'{invalid}' is not one of {}",
+                        self.allowed_values.join(", ")
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Validate a value of any key. Unknown keys are always valid
+pub fn validate(key: &str, value: &str) -> Result<(), String> {
+    match key_schema(key) {
+        Some(schema) => schema.validate(value),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_schema() {
+        let timeout = key_schema("GRUB_TIMEOUT").unwrap();
+        assert_eq!(timeout.value_type, ValueType::Int);
+        assert_eq!(timeout.default, Some("5"));
+
+        let style = key_schema("GRUB_TIMEOUT_STYLE").unwrap();
+        assert_eq!(style.allowed_values, &["menu", "countdown", "hidden"]);
+
+        assert_eq!(key_schema("GRUB_SAVEDEFAULT").unwrap().allowed_values, BOOL);
+        assert!(key_schema("GRUB_UNKNOWN").is_none());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("GRUB_TIMEOUT", "8").is_ok());
+        assert!(validate("GRUB_TIMEOUT", "").is_ok());
+        assert!(validate("GRUB_TIMEOUT", "-1").is_err());
+        assert!(validate("GRUB_TIMEOUT", "soon").is_err());
+
+        assert!(validate("GRUB_TERMINAL", "gfxterm").is_ok());
+        assert!(validate("GRUB_TERMINAL", "console serial").is_ok());
+        assert_eq!(
+            validate("GRUB_TIMEOUT_STYLE", "menu countdown").unwrap_err(),
+            "'menu countdown' is not one of menu, countdown, hidden"
+        );
+
+        assert!(validate("GRUB_SAVEDEFAULT", "true").is_ok());
+        assert!(validate("GRUB_SAVEDEFAULT", "y").is_err());
+        assert!(validate("GRUB_ENABLE_CRYPTODISK", "y").is_ok());
+
+        assert!(validate("GRUB_THEME", "test_data/grub_full").is_ok());
+        assert!(validate("GRUB_THEME", "test_data/missing/theme.txt").is_err());
+
+        assert!(validate("GRUB_GFXMODE", "auto").is_ok());
+        assert!(validate("GRUB_GFXMODE", "1920x1080x32,1024x768,auto").is_ok());
+        assert!(validate("GRUB_GFXMODE", "1920x1080;800x600").is_ok());
+        assert!(validate("GRUB_GFXMODE", "1920*1080").is_err());
+        assert!(validate("GRUB_GFXMODE", "keep").is_err());
+        assert!(validate("GRUB_GFXPAYLOAD_LINUX", "keep").is_ok());
+        assert!(validate("GRUB_GFXPAYLOAD_LINUX", "1280x1024").is_ok());
+
+        assert!(validate("GRUB_SOMETHING_NEW", "anything").is_ok());
+    }
+
+    #[test]
+    fn test_key_schema_unique() {
+        for (idx, schema) in KEY_SCHEMA.iter().enumerate() {
+            assert!(
+                KEY_SCHEMA[idx + 1..]
+                    .iter()
+                    .all(|other| other.key != schema.key),
+                "{} is defined twice",
+                schema.key
+            );
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+/// Minimal subset of the grub script language needed to read menuentry titles.
+///
+/// Keeps track of simple `set name=value` assignments and expands `$name` and
+/// `${name}` the same way grub does: inside double quotes and unquoted words,
+/// but not inside single quotes. Conditionals are not evaluated so the last
+/// assignment wins.
+#[derive(Debug, Default)]
+pub struct GrubScript {
+    vars: HashMap<String, String>,
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+impl GrubScript {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(String::as_str)
+    }
+
+    /// Arguments of `line` if it runs the `command`
+    pub fn command_args<'a>(line: &'a str, command: &str) -> Option<&'a str> {
+        let args = line.trim_start().strip_prefix(command)?;
+        args.starts_with(char::is_whitespace).then_some(args)
+    }
+
+    /// Lines between the BEGIN and END markers of a grub.d `script` in grub.cfg
+    pub fn section<'a>(grub_cfg: &'a str, script: &str) -> Option<Vec<&'a str>> {
+        let begin = format!("### BEGIN {script} ###");
+        let end = format!("### END {script} ###");
+        let mut lines = grub_cfg.lines().skip_while(|line| line.trim() != begin);
+        lines.next()?;
+
+        Some(lines.take_while(|line| line.trim() != end).collect())
+    }
+
+    /// Record the variable if `line` is an assignment like `set name=value`
+    /// or `name=value`. Returns true if the line was an assignment
+    pub fn assign(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        let line = line.strip_prefix("set ").unwrap_or(line).trim_start();
+        let Some((name, value)) = line.split_once('=') else {
+            return false;
+        };
+
+        let is_name = name.chars().next().is_some_and(|ch| !ch.is_ascii_digit())
+            && name.chars().all(is_name_char);
+        if !is_name {
+            return false;
+        }
+
+        let value = self
+            .parse_word(value)
+            .map_or(String::new(), |(word, _)| word);
+        self.vars.insert(name.to_string(), value);
+        true
+    }
+
+    /// Parse the first word of `input` with grub quoting rules and expand the variables.
+    /// Returns the word and the rest of the input after it
+    pub fn parse_word<'a>(&self, input: &'a str) -> Option<(String, &'a str)> {
+        let input = input.trim_start();
+        let mut word = String::new();
+        let mut chars = input.char_indices();
+        let mut found = false;
+
+        while let Some((idx, ch)) = chars.next() {
+            match ch {
+                ch if ch.is_whitespace() => return Some((word, &input[idx..])),
+                '\'' => {
+                    for (_, ch) in chars.by_ref() {
+                        if ch == '\'' {
+                            break;
+                        }
+                        word.push(ch);
+                    }
+                }
+                '"' => {
+                    let start = idx + 1;
+                    let mut end = input.len();
+                    let mut escaped = false;
+                    for (idx, ch) in chars.by_ref() {
+                        if ch == '"' && !escaped {
+                            end = idx;
+                            break;
+                        }
+                        escaped = ch == '\\' && !escaped;
+                    }
+                    word.push_str(&self.expand(&input[start..end], true));
+                }
+                '\\' => {
+                    if let Some((_, ch)) = chars.next() {
+                        word.push(ch);
+                    }
+                }
+                '$' => {
+                    let rest = &input[idx..];
+                    let (value, len) = self.variable(rest);
+                    word.push_str(&value);
+                    // skip the characters of the variable reference
+                    for _ in 1..rest[..len].chars().count() {
+                        chars.next();
+                    }
+                }
+                ch => word.push(ch),
+            }
+            found = true;
+        }
+
+        found.then_some((word, ""))
+    }
+
+    /// Value of the variable reference at the start of `input` and the length of the reference.
+    /// Unknown variables expand to an empty string like in grub
+    fn variable(&self, input: &str) -> (String, usize) {
+        let rest = &input[1..];
+        let (name, len) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 3),
+                None => return ("$".into(), 1),
+            }
+        } else {
+            let end = rest.find(|ch| !is_name_char(ch)).unwrap_or(rest.len());
+            (&rest[..end], end + 1)
+        };
+
+        if name.is_empty() {
+            return ("$".into(), 1);
+        }
+
+        (self.get(name).unwrap_or_default().to_string(), len)
+    }
+
+    /// Expand the variables in `text`. Backslash escapes are only handled when `quoted`
+    /// is set, since they're already removed from unquoted words
+    fn expand(&self, text: &str, quoted: bool) -> String {
+        let mut result = String::new();
+        let mut idx = 0;
+        while idx < text.len() {
+            let rest = &text[idx..];
+            let ch = rest.chars().next().unwrap_or_default();
+            if ch == '$' {
+                let (value, len) = self.variable(rest);
+                result.push_str(&value);
+                idx += len;
+                continue;
+            }
+
+            if quoted && ch == '\\' {
+                if let Some(next) = rest[1..].chars().next() {
+                    if matches!(next, '"' | '\\' | '$') {
+                        result.push(next);
+                        idx += 1 + next.len_utf8();
+                        continue;
+                    }
+                }
+            }
+
+            result.push(ch);
+            idx += ch.len_utf8();
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_parse_word() {
+        let mut script = GrubScript::default();
+        assert!(script.assign("set os=\"openSUSE Tumbleweed\""));
+        assert!(script.assign("version=6.17"));
+        assert!(!script.assign("export os"));
+        assert!(!script.assign("fi"));
+
+        let (word, rest) = script.parse_word("'Linux $version' --class os {").unwrap();
+        assert_eq!(word, "Linux $version");
+        assert_eq!(rest, " --class os {");
+
+        let (word, _) = script.parse_word("\"${os}, with Linux $version\"").unwrap();
+        assert_eq!(word, "openSUSE Tumbleweed, with Linux 6.17");
+
+        let (word, _) = script.parse_word("'Tux'\\''s Linux' {").unwrap();
+        assert_eq!(word, "Tux's Linux");
+
+        let (word, _) = script.parse_word("$os-${version}_$missing").unwrap();
+        assert_eq!(word, "openSUSE Tumbleweed-6.17_");
+
+        assert_eq!(script.parse_word("   "), None);
+
+        assert_eq!(
+            GrubScript::command_args("menuentry 'Linux' {", "menuentry"),
+            Some(" 'Linux' {")
+        );
+        assert_eq!(
+            GrubScript::command_args("menuentry_id_option=\"--id\"", "menuentry"),
+            None
+        );
+    }
+}
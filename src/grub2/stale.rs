@@ -0,0 +1,199 @@
+use std::{collections::BTreeMap, fs::metadata, path::Path, time::SystemTime};
+
+use serde::Serialize;
+
+use crate::grub2::{dropin::EffectiveValue, schema::key_schema, script::GrubScript};
+
+const HEADER_SCRIPT: &str = "/etc/grub.d/00_header";
+const LINUX_SCRIPT: &str = "/etc/grub.d/10_linux";
+
+/// Key whose value in the grub config is not the one grub.cfg was generated with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyDifference {
+    pub key: String,
+    /// Effective value in the config, or the default grub uses if it's not set
+    pub config: Option<String>,
+    /// Value found from grub.cfg
+    pub grub_cfg: Option<String>,
+}
+
+/// Tells if the grub config has edits that are not applied to grub.cfg yet
+#[derive(Debug, Default, Serialize)]
+pub struct StaleStatus {
+    /// grub.cfg needs to be regenerated
+    pub stale: bool,
+    /// Some config file was modified after grub.cfg was generated
+    pub modified_after: bool,
+    /// /etc/default/grub differs from the snapshot grub.cfg was last generated from.
+    /// None if grub.cfg hasn't been generated by bootkit
+    pub content_changed: Option<bool>,
+    /// Keys with a different value in grub.cfg
+    pub key_differences: Vec<KeyDifference>,
+}
+
+impl StaleStatus {
+    pub fn new(
+        modified_after: bool,
+        content_changed: Option<bool>,
+        key_differences: Vec<KeyDifference>,
+    ) -> Self {
+        // modification time is only used if the content can't be compared,
+        // the files could have been just touched
+        let stale = !key_differences.is_empty() || content_changed.unwrap_or(modified_after);
+
+        Self {
+            stale,
+            modified_after,
+            content_changed,
+            key_differences,
+        }
+    }
+}
+
+/// Check if any of the `config_paths` was modified after `grub_cfg`
+pub fn modified_after<P: AsRef<Path>>(config_paths: &[P], grub_cfg: &str) -> bool {
+    let modified = |path: &Path| metadata(path).and_then(|meta| meta.modified()).ok();
+    let Some(generated) = modified(Path::new(grub_cfg)) else {
+        return false;
+    };
+
+    config_paths
+        .iter()
+        .filter_map(|path| modified(path.as_ref()))
+        .any(|time: SystemTime| time > generated)
+}
+
+/// Value of the last `set name=value` in `lines` without the quotes.
+/// Variables are not expanded so `${saved_entry}` can be recognized
+fn last_set(lines: &[&str], name: &str, skip: &[&str]) -> Option<String> {
+    let prefix = format!("set {name}=");
+    lines
+        .iter()
+        .filter_map(|line| line.trim().strip_prefix(&prefix))
+        .rfind(|value| !skip.contains(value))
+        .map(|value| value.trim_matches(['"', '\'']).to_string())
+}
+
+/// Values of the grub config keys that 00_header embeds into grub.cfg
+fn header_values(grub_cfg: &str) -> BTreeMap<&'static str, Option<String>> {
+    let header = GrubScript::section(grub_cfg, HEADER_SCRIPT).unwrap_or_default();
+    let default = last_set(&header, "default", &["\"${next_entry}\""]).map(|value| {
+        if value == "${saved_entry}" {
+            "saved".to_string()
+        } else {
+            value
+        }
+    });
+
+    BTreeMap::from([
+        ("GRUB_DEFAULT", default),
+        // the last timeout is the normal one, the first is for one time boots
+        ("GRUB_TIMEOUT", last_set(&header, "timeout", &[])),
+        (
+            "GRUB_TIMEOUT_STYLE",
+            last_set(&header, "timeout_style", &[]),
+        ),
+        ("GRUB_GFXMODE", last_set(&header, "gfxmode", &[])),
+    ])
+}
+
+/// Keys that have a different value in grub.cfg than in the config
+pub fn key_differences(
+    effective: &BTreeMap<String, EffectiveValue>,
+    grub_cfg: &str,
+) -> Vec<KeyDifference> {
+    let config_value = |key: &str| {
+        effective
+            .get(key)
+            .map(|value| value.value.clone())
+            .filter(|value| !value.is_empty())
+            .or_else(|| key_schema(key).and_then(|schema| schema.default.map(str::to_string)))
+    };
+
+    let mut differences: Vec<KeyDifference> = header_values(grub_cfg)
+        .into_iter()
+        .filter_map(|(key, grub_cfg)| {
+            let config = config_value(key);
+            (config != grub_cfg).then(|| KeyDifference {
+                key: key.to_string(),
+                config,
+                grub_cfg,
+            })
+        })
+        .collect();
+
+    // generated entries have GRUB_CMDLINE_LINUX and GRUB_CMDLINE_LINUX_DEFAULT in the
+    // kernel command line, BLS entries have their own options
+    let linux_args = GrubScript::section(grub_cfg, LINUX_SCRIPT).and_then(|section| {
+        section.into_iter().find_map(|line| {
+            let args = GrubScript::command_args(line, "linux")
+                .or_else(|| GrubScript::command_args(line, "linuxefi"))?;
+            let mut args = args.split_whitespace();
+            // skip the kernel image
+            args.next();
+            Some(args.collect::<Vec<_>>())
+        })
+    });
+    if let Some(linux_args) = linux_args {
+        for key in ["GRUB_CMDLINE_LINUX", "GRUB_CMDLINE_LINUX_DEFAULT"] {
+            let config = config_value(key);
+            let missing = config
+                .as_deref()
+                .unwrap_or_default()
+                .split_whitespace()
+                .any(|arg| !linux_args.contains(&arg));
+            if missing {
+                differences.push(KeyDifference {
+                    key: key.to_string(),
+                    config,
+                    grub_cfg: Some(linux_args.join(" ")),
+                });
+            }
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use crate::grub2::{dropin::GrubConfig, ParseMode};
+
+    use super::*;
+
+    #[test]
+    fn test_stale_key_differences() {
+        let grub_cfg = read_to_string("test_data/grub.cfg").unwrap();
+        let config = GrubConfig::from_paths(
+            "test_data/grub_full",
+            "test_data/missing",
+            ParseMode::Strict,
+        )
+        .unwrap();
+        let differences = key_differences(&config.effective(), &grub_cfg);
+        let keys: Vec<&str> = differences.iter().map(|diff| diff.key.as_str()).collect();
+        assert_eq!(keys, vec!["GRUB_TIMEOUT", "GRUB_CMDLINE_LINUX_DEFAULT"]);
+        assert_eq!(
+            differences[0],
+            KeyDifference {
+                key: "GRUB_TIMEOUT".into(),
+                config: Some("8".into()),
+                grub_cfg: Some("1".into()),
+            }
+        );
+
+        let values = header_values(&grub_cfg);
+        assert_eq!(values["GRUB_DEFAULT"].as_deref(), Some("saved"));
+        assert_eq!(values["GRUB_GFXMODE"].as_deref(), Some("auto"));
+
+        assert!(!modified_after(
+            &["test_data/grub_full"],
+            "test_data/missing.cfg"
+        ));
+        assert!(StaleStatus::new(false, None, differences).stale);
+        assert!(!StaleStatus::new(true, Some(false), Vec::new()).stale);
+        assert!(StaleStatus::new(true, None, Vec::new()).stale);
+    }
+}
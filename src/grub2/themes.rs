@@ -0,0 +1,84 @@
+use std::{fs::read_dir, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    dctx,
+    errors::{DRes, DResult},
+};
+
+/// File grub loads the theme from, GRUB_THEME points to it
+pub const THEME_FILE: &str = "theme.txt";
+
+/// Theme installed in the grub themes directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GrubTheme {
+    /// Name of the theme directory
+    pub name: String,
+    /// Path to the theme.txt of the theme, used as the GRUB_THEME value
+    pub path: String,
+}
+
+/// Themes in `dir` that have a theme.txt, sorted by name.
+/// Missing themes directory means that no themes are installed
+pub fn themes<P: AsRef<Path>>(dir: P) -> DResult<Vec<GrubTheme>> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let entries = read_dir(dir).ctx(dctx!(), format!("Cannot read themes directory {dir:?}"))?;
+    let mut themes = Vec::new();
+    for entry in entries {
+        let entry = entry.ctx(dctx!(), format!("Cannot read themes directory {dir:?}"))?;
+        let theme_file = entry.path().join(THEME_FILE);
+        if !theme_file.is_file() {
+            log::debug!("{:?} has no {THEME_FILE}, skipping it", entry.path());
+            continue;
+        }
+
+        themes.push(GrubTheme {
+            name: entry.file_name().to_string_lossy().to_string(),
+            path: theme_file.to_string_lossy().to_string(),
+        });
+    }
+    themes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(themes)
+}
+
+/// Check that GRUB_THEME value points to an existing theme.txt
+pub fn validate_theme(value: &str) -> Result<(), String> {
+    let path = Path::new(value);
+    if path.file_name().is_none_or(|name| name != THEME_FILE) {
+        return Err(format!("'{value}' is not a {THEME_FILE} file"));
+    }
+
+    if !path.is_file() {
+        return Err(format!("theme '{value}' does not exist"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_themes() {
+        let themes = themes("test_data/themes").unwrap();
+        assert_eq!(
+            themes,
+            vec![GrubTheme {
+                name: "starfield".into(),
+                path: "test_data/themes/starfield/theme.txt".into(),
+            }]
+        );
+        assert!(super::themes("test_data/missing").unwrap().is_empty());
+
+        assert!(validate_theme("test_data/themes/starfield/theme.txt").is_ok());
+        assert!(validate_theme("test_data/themes/incomplete/theme.txt").is_err());
+        assert!(validate_theme("test_data/themes/incomplete/README").is_err());
+    }
+}
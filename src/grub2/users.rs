@@ -0,0 +1,210 @@
+use std::{
+    fs::{read_to_string, remove_file, set_permissions, OpenOptions, Permissions},
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::layout::GrubLayout,
+    dctx,
+    errors::{DError, DRes, DResult},
+    system::process,
+};
+
+/// grub.d script that defines the superusers. Runs right after 00_header like
+/// the 01_users of the distributions
+pub const USERS_SCRIPT: &str = "01_bootkit_users";
+
+const SCRIPT_HEADER: &str =
+    "#!/bin/sh\n# Generated by bootkitd, manual changes will be overwritten\n";
+
+/// Password protection of the grub menu
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MenuProtection {
+    /// Editing entries and the grub shell require a password
+    pub enabled: bool,
+    pub superusers: Vec<String>,
+    /// Users that have a password. The hashes are never exposed
+    pub users: Vec<String>,
+}
+
+impl MenuProtection {
+    /// Parse the grub commands that the users script outputs
+    fn from_script(script: &str) -> Self {
+        let mut protection = Self::default();
+        for line in script.lines().map(str::trim) {
+            if let Some(users) = line.strip_prefix("set superusers=") {
+                protection.superusers = users
+                    .trim_matches(['"', '\''])
+                    .split([' ', ',', ';', '|', '&'])
+                    .filter(|user| !user.is_empty())
+                    .map(str::to_string)
+                    .collect();
+            } else if let Some(args) = line
+                .strip_prefix("password_pbkdf2 ")
+                .or_else(|| line.strip_prefix("password "))
+            {
+                if let Some(user) = args.split_whitespace().next() {
+                    protection.users.push(user.to_string());
+                }
+            }
+        }
+        protection.enabled = !protection.superusers.is_empty();
+
+        protection
+    }
+
+    /// Read the protection from the users script in `scripts_dir`.
+    /// Missing script means that the menu is not protected
+    pub fn read<P: AsRef<Path>>(scripts_dir: P) -> DResult<Self> {
+        let path = scripts_dir.as_ref().join(USERS_SCRIPT);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let script = read_to_string(&path).ctx(dctx!(), format!("Cannot read {path:?}"))?;
+        Ok(Self::from_script(&script))
+    }
+}
+
+/// Grub user names can't contain the separators of the superusers list
+pub fn is_valid_user(user: &str) -> bool {
+    !user.is_empty()
+        && user
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '_' | '-' | '.'))
+}
+
+/// Hash `password` with grub2-mkpasswd-pbkdf2
+pub async fn hash_password(password: &str) -> DResult<String> {
+    if password.is_empty() || password.contains('\n') {
+        return Err(DError::invalid_values(
+            dctx!(),
+            vec![(
+                "password".into(),
+                "password must be a non-empty single line".into(),
+            )],
+        ));
+    }
+
+    let tool = GrubLayout::get().tool("mkpasswd-pbkdf2");
+    // the tool asks for the password twice
+    let input = format!("{password}\n{password}\n");
+    let output = process::run_with_input(&tool, &[], Some(&input)).await?;
+    output.check()?;
+
+    output
+        .stdout
+        .split_whitespace()
+        .find(|word| word.starts_with("grub.pbkdf2."))
+        .map(str::to_string)
+        .ok_or_else(|| DError::generic(dctx!(), format!("{tool} didn't output a password hash")))
+}
+
+/// Contents of the users script that makes `user` the only superuser
+fn users_script(user: &str, hash: &str) -> String {
+    format!("{SCRIPT_HEADER}cat << 'EOF'\nset superusers=\"{user}\"\npassword_pbkdf2 {user} {hash}\nEOF\n")
+}
+
+/// Contents of the users script in `scripts_dir`, none if the menu is not protected
+pub fn read_users_script<P: AsRef<Path>>(scripts_dir: P) -> Option<String> {
+    read_to_string(scripts_dir.as_ref().join(USERS_SCRIPT)).ok()
+}
+
+/// Protect the grub menu with the password of `user`
+pub fn write_users_script<P: AsRef<Path>>(scripts_dir: P, user: &str, hash: &str) -> DResult<()> {
+    write_script(scripts_dir.as_ref(), &users_script(user, hash))
+}
+
+/// Put back the users script returned by [`read_users_script`]
+pub fn restore_users_script<P: AsRef<Path>>(scripts_dir: P, script: Option<&str>) -> DResult<()> {
+    match script {
+        Some(script) => write_script(scripts_dir.as_ref(), script),
+        None => remove_users_script(scripts_dir).map(|_| ()),
+    }
+}
+
+/// Write the users script readable only by root since it holds the password hash.
+/// grub2-mkconfig runs as root and only runs executable scripts
+fn write_script(scripts_dir: &Path, script: &str) -> DResult<()> {
+    let path = scripts_dir.join(USERS_SCRIPT);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o700)
+        .open(&path)
+        .ctx(dctx!(), format!("Failed to create {path:?}"))?;
+    // the mode is only applied to new files
+    set_permissions(&path, Permissions::from_mode(0o700)).ctx(
+        dctx!(),
+        format!("Failed to set the permissions of {path:?}"),
+    )?;
+    write!(file, "{script}").ctx(dctx!(), format!("Failed to write {path:?}"))?;
+    log::debug!("Grub users script was written to {path:?}");
+
+    Ok(())
+}
+
+/// Remove the menu protection. Returns false if the menu wasn't protected
+pub fn remove_users_script<P: AsRef<Path>>(scripts_dir: P) -> DResult<bool> {
+    let path = scripts_dir.as_ref().join(USERS_SCRIPT);
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    remove_file(&path).ctx(dctx!(), format!("Failed to remove {path:?}"))?;
+    log::debug!("Grub users script {path:?} was removed");
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu_protection() {
+        let script = users_script("admin", "grub.pbkdf2.sha512.10000.AB.CD");
+        assert_eq!(
+            MenuProtection::from_script(&script),
+            MenuProtection {
+                enabled: true,
+                superusers: vec!["admin".into()],
+                users: vec!["admin".into()],
+            }
+        );
+
+        let protection = MenuProtection::from_script(
+            "cat << EOF\nset superusers=\"root admin\"\npassword guest secret\nEOF",
+        );
+        assert_eq!(protection.superusers, vec!["root", "admin"]);
+        assert_eq!(protection.users, vec!["guest"]);
+
+        assert!(!MenuProtection::read("test_data/missing").unwrap().enabled);
+
+        assert!(is_valid_user("admin-1"));
+        assert!(!is_valid_user("root admin"));
+        assert!(!is_valid_user("root;reboot"));
+        assert!(!is_valid_user(""));
+    }
+
+    #[test]
+    fn test_write_script() {
+        let dir = std::env::temp_dir().join(format!("bootkit-users-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(USERS_SCRIPT);
+        std::fs::write(&path, "old").unwrap();
+        set_permissions(&path, Permissions::from_mode(0o755)).unwrap();
+
+        write_users_script(&dir, "admin", "grub.pbkdf2.sha512.10000.AB.CD").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert!(MenuProtection::read(&dir).unwrap().enabled);
+
+        assert!(remove_users_script(&dir).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+use std::cmp::Ordering;
+
+/// Compare versions like rpmvercmp and dpkg do.
+///
+/// Versions are split into numeric and alphabetic segments and the other characters
+/// only separate them. Numeric segments are compared as numbers and are newer than
+/// alphabetic ones. `~` sorts before anything, even the end of the version, so
+/// `6.18~rc1` is older than `6.18`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let is_separator = |ch: char| !ch.is_ascii_alphanumeric() && ch != '~';
+    let mut a = a;
+    let mut b = b;
+
+    loop {
+        a = a.trim_start_matches(is_separator);
+        b = b.trim_start_matches(is_separator);
+
+        match (a.strip_prefix('~'), b.strip_prefix('~')) {
+            (Some(rest_a), Some(rest_b)) => {
+                a = rest_a;
+                b = rest_b;
+                continue;
+            }
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => {}
+        }
+
+        if a.is_empty() || b.is_empty() {
+            return a.len().cmp(&b.len());
+        }
+
+        let numeric = a.starts_with(|ch: char| ch.is_ascii_digit());
+        if numeric != b.starts_with(|ch: char| ch.is_ascii_digit()) {
+            return if numeric {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let segment_end = |version: &str| {
+            version
+                .find(|ch: char| {
+                    if numeric {
+                        !ch.is_ascii_digit()
+                    } else {
+                        !ch.is_ascii_alphabetic()
+                    }
+                })
+                .unwrap_or(version.len())
+        };
+        let (segment_a, rest_a) = a.split_at(segment_end(a));
+        let (segment_b, rest_b) = b.split_at(segment_end(b));
+
+        let ordering = if numeric {
+            let segment_a = segment_a.trim_start_matches('0');
+            let segment_b = segment_b.trim_start_matches('0');
+            segment_a
+                .len()
+                .cmp(&segment_b.len())
+                .then_with(|| segment_a.cmp(segment_b))
+        } else {
+            segment_a.cmp(segment_b)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        a = rest_a;
+        b = rest_b;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("6.17.5", "6.17.5"), Ordering::Equal);
+        assert_eq!(compare_versions("6.9", "6.17"), Ordering::Less);
+        assert_eq!(compare_versions("6.17.10-1", "6.17.9-3"), Ordering::Greater);
+        assert_eq!(
+            compare_versions("6.12.5-200.fc41.x86_64", "6.11.4-301.fc41.x86_64"),
+            Ordering::Greater
+        );
+        assert_eq!(compare_versions("6.18~rc1", "6.18"), Ordering::Less);
+        assert_eq!(compare_versions("6.18~rc1", "6.18~rc2"), Ordering::Less);
+        assert_eq!(compare_versions("1.0a", "1.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0.1", "1.0a"), Ordering::Greater);
+        assert_eq!(compare_versions("010", "9"), Ordering::Greater);
+        assert_eq!(
+            compare_versions("6.17-default", "6.17_default"),
+            Ordering::Equal
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use std::{collections::BTreeMap, fs::read_to_string, ops::Range, path::Path};
+
+use crate::{
+    bootloader::{Bootloader, LoaderEntry},
+    config::LIMINE_CONF_PATH,
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::kernel_version,
+    system::write_file_atomic,
+};
+
+/// Option names of the kernel and its command line, the `kernel_` ones are
+/// the names used before limine 8
+const KERNEL_KEYS: &[&str] = &["path", "kernel_path"];
+const CMDLINE_KEYS: &[&str] = &["cmdline", "kernel_cmdline"];
+
+/// Split an option line into the lowercased key and the value, none for
+/// comments, empty lines, entries and macro definitions
+fn option(line: &str) -> Option<(String, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(['#', '/', '$']) {
+        return None;
+    }
+
+    let (key, value) = line.split_once(':')?;
+    Some((key.trim().to_ascii_lowercase(), value.trim()))
+}
+
+/// Path of a file without the `#` hash limine verifies the file with
+fn without_hash(path: &str) -> &str {
+    path.split_once('#').map_or(path, |(path, _)| path)
+}
+
+/// Entry or directory of the limine menu
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MenuEntry {
+    name: String,
+    /// Names of the parent directories and the entry separated by `/`
+    path: String,
+    /// Number of the slashes in front of the name, 1 for the top level
+    depth: usize,
+    /// Directory that contains other entries instead of booting anything
+    directory: bool,
+    /// Shown when the menu opens, entries of collapsed directories are not
+    visible: bool,
+    /// Line of the entry name
+    line: usize,
+    /// Option lines after the name, until the next entry
+    options: Range<usize>,
+}
+
+/// limine config file.
+///
+/// Entries are lines that start with `/`, with one more slash for each level of
+/// directories, and their options are the `key: value` lines after them. The raw
+/// lines are kept so the file is written back as it was apart from the changed lines.
+#[derive(Debug, Clone, Default)]
+pub struct Limine {
+    lines: Vec<String>,
+}
+
+impl Limine {
+    pub fn parse(contents: &str) -> Self {
+        Self {
+            lines: contents.split('\n').map(str::to_string).collect(),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> DResult<Self> {
+        let path = path.as_ref();
+        let contents =
+            read_to_string(path).ctx(dctx!(), format!("Cannot read limine config {path:?}"))?;
+        Ok(Self::parse(&contents))
+    }
+
+    pub fn read() -> DResult<Self> {
+        Self::from_file(LIMINE_CONF_PATH)
+    }
+
+    /// All the entries and directories in menu order
+    fn menu(&self) -> Vec<MenuEntry> {
+        let mut menu: Vec<MenuEntry> = Vec::new();
+        // names and expanded state of the current parent directories
+        let mut parents: Vec<(String, bool)> = Vec::new();
+
+        for (idx, line) in self.lines.iter().enumerate() {
+            let line = line.trim();
+            if !line.starts_with('/') {
+                continue;
+            }
+
+            let name = line.trim_start_matches('/');
+            let depth = line.len() - name.len();
+            let (name, expanded) = match name.strip_prefix('+') {
+                Some(name) => (name.trim(), true),
+                None => (name.trim(), false),
+            };
+
+            if let Some(previous) = menu.last_mut() {
+                previous.options.end = idx;
+                previous.directory = depth > previous.depth;
+            }
+
+            parents.truncate(depth - 1);
+            let visible = parents.iter().all(|(_, expanded)| *expanded);
+            let path = parents
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .chain([name])
+                .collect::<Vec<_>>()
+                .join("/");
+            parents.push((name.to_string(), expanded));
+
+            menu.push(MenuEntry {
+                name: name.to_string(),
+                path,
+                depth,
+                directory: false,
+                visible,
+                line: idx,
+                options: idx + 1..self.lines.len(),
+            });
+        }
+
+        menu
+    }
+
+    /// Lines before the first entry
+    fn global_end(&self) -> usize {
+        self.menu()
+            .first()
+            .map_or(self.lines.len(), |entry| entry.line)
+    }
+
+    fn entry_values<'a>(&'a self, entry: &MenuEntry, keys: &[&str]) -> Vec<&'a str> {
+        self.lines[entry.options.clone()]
+            .iter()
+            .filter_map(|line| option(line))
+            .filter(|(key, _)| keys.contains(&key.as_str()))
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    fn loader_entry(&self, entry: &MenuEntry) -> LoaderEntry {
+        let first = |keys| {
+            self.entry_values(entry, keys)
+                .first()
+                .map(|value| value.to_string())
+        };
+        let kernel = first(KERNEL_KEYS);
+        LoaderEntry {
+            id: entry.path.clone(),
+            title: entry.name.clone(),
+            version: kernel
+                .as_deref()
+                .and_then(|kernel| kernel_version(without_hash(kernel))),
+            kernel,
+            initrds: self
+                .entry_values(entry, &["module_path"])
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            options: first(CMDLINE_KEYS),
+            devicetree: first(&["dtb_path"]),
+            devicetree_dir: None,
+            classes: Vec::new(),
+        }
+    }
+
+    pub fn as_string(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+impl Bootloader for Limine {
+    fn name(&self) -> &'static str {
+        "limine"
+    }
+
+    fn config_path(&self) -> &str {
+        LIMINE_CONF_PATH
+    }
+
+    /// Option names are case insensitive so they are returned in lowercase
+    fn values(&self) -> BTreeMap<String, String> {
+        self.lines[..self.global_end()]
+            .iter()
+            .filter_map(|line| option(line))
+            .map(|(key, value)| (key, value.to_string()))
+            .collect()
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.lines.clone()
+    }
+
+    fn validate(&self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "timeout" if value == "no" || value.parse::<u32>().is_ok() => Ok(()),
+            "timeout" => Err(format!("'{value}' is not a number of seconds or 'no'")),
+            "default_entry" => {
+                let visible = self.menu().iter().filter(|entry| entry.visible).count();
+                match value.parse::<usize>() {
+                    Ok(position) if (1..=visible).contains(&position) => Ok(()),
+                    _ => Err(format!(
+                        "'{value}' is not a position between 1 and {visible}"
+                    )),
+                }
+            }
+            "remember_last_entry" | "quiet" | "serial" | "verbose"
+                if value != "yes" && value != "no" =>
+            {
+                Err(format!("'{value}' is not 'yes' or 'no'"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn set_value(&mut self, key: &str, value: &str) {
+        let global_end = self.global_end();
+        let existing: Vec<usize> = (0..global_end)
+            .filter(|idx| option(&self.lines[*idx]).is_some_and(|(line_key, _)| line_key == key))
+            .collect();
+
+        match existing.split_first() {
+            Some((first, rest)) => {
+                self.lines[*first] = format!("{key}: {value}");
+                for idx in rest.iter().rev() {
+                    self.lines.remove(*idx);
+                }
+            }
+            None => {
+                // after the last global line, before the empty lines and the entries
+                let idx = self.lines[..global_end]
+                    .iter()
+                    .rposition(|line| !line.trim().is_empty())
+                    .map_or(0, |idx| idx + 1);
+                self.lines.insert(idx, format!("{key}: {value}"));
+            }
+        }
+    }
+
+    fn remove_value(&mut self, key: &str) -> bool {
+        let existing: Vec<usize> = (0..self.global_end())
+            .filter(|idx| option(&self.lines[*idx]).is_some_and(|(line_key, _)| line_key == key))
+            .collect();
+        for idx in existing.iter().rev() {
+            self.lines.remove(*idx);
+        }
+        !existing.is_empty()
+    }
+
+    /// Entries that boot something, the directories are left out
+    fn entries(&self) -> Vec<LoaderEntry> {
+        self.menu()
+            .iter()
+            .filter(|entry| !entry.directory)
+            .map(|entry| self.loader_entry(entry))
+            .collect()
+    }
+
+    fn default_key(&self) -> &'static str {
+        "default_entry"
+    }
+
+    /// `default_entry` is the position of the entry among the entries shown
+    /// when the menu opens, so entries in collapsed directories can't be the default
+    fn default_value(&self, entry: &LoaderEntry) -> Option<String> {
+        self.menu()
+            .iter()
+            .filter(|menu_entry| menu_entry.visible)
+            .position(|menu_entry| menu_entry.path == entry.id)
+            .map(|position| (position + 1).to_string())
+    }
+
+    /// Entry at the `default_entry` position, the first one if it's not set.
+    /// A directory as the default selects nothing
+    fn selected(&self) -> Option<LoaderEntry> {
+        let position = self
+            .default_entry()
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(1);
+        let menu = self.menu();
+        let entry = menu
+            .iter()
+            .filter(|entry| entry.visible)
+            .nth(position.checked_sub(1)?)
+            .filter(|entry| !entry.directory)?;
+        Some(self.loader_entry(entry))
+    }
+
+    fn set_entry_cmdline(&mut self, id: &str, cmdline: &str) -> DResult<()> {
+        let entry = self
+            .menu()
+            .into_iter()
+            .find(|entry| entry.path == id && !entry.directory)
+            .ok_or_else(|| DError::generic(dctx!(), format!("Boot entry '{id}' is not found")))?;
+
+        let existing = entry.options.clone().find(|idx| {
+            option(&self.lines[*idx]).is_some_and(|(key, _)| CMDLINE_KEYS.contains(&key.as_str()))
+        });
+        match existing {
+            Some(idx) => {
+                let line = &self.lines[idx];
+                let key_end = line.find(':').unwrap_or_default();
+                self.lines[idx] = format!("{}: {cmdline}", &line[..key_end]);
+            }
+            None => {
+                let last = entry
+                    .options
+                    .clone()
+                    .rev()
+                    .find(|idx| !self.lines[*idx].trim().is_empty())
+                    .unwrap_or(entry.line);
+                let indent = if last == entry.line {
+                    format!("{}    ", " ".repeat((entry.depth - 1) * 4))
+                } else {
+                    let line = &self.lines[last];
+                    line[..line.len() - line.trim_start().len()].to_string()
+                };
+                self.lines
+                    .insert(last + 1, format!("{indent}cmdline: {cmdline}"));
+            }
+        }
+        log::debug!("Changed cmdline of limine entry '{id}' to '{cmdline}'");
+        Ok(())
+    }
+
+    fn write(&self) -> DResult<()> {
+        write_file_atomic(LIMINE_CONF_PATH, &self.as_string())?;
+        log::debug!("limine config was written to {LIMINE_CONF_PATH}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limine_parse() {
+        let limine = Limine::from_file("test_data/limine.conf").unwrap();
+        let values = limine.values();
+        assert_eq!(values.get("timeout").map(String::as_str), Some("5"));
+        assert_eq!(
+            values.get("interface_branding").map(String::as_str),
+            Some("Test system")
+        );
+        assert!(!values.contains_key("protocol"));
+
+        let entries = limine.entries();
+        let ids: Vec<&str> = entries.iter().map(|entry| entry.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "Arch Linux/linux 6.12.5",
+                "Arch Linux/Fallback",
+                "Old kernels/linux 6.11.4",
+                "Windows"
+            ]
+        );
+        assert_eq!(entries[0].title, "linux 6.12.5");
+        assert_eq!(entries[0].version.as_deref(), Some("6.12.5-arch1-1"));
+        assert_eq!(
+            entries[0].initrds,
+            vec![
+                "boot():/intel-ucode.img",
+                "boot():/initramfs-6.12.5-arch1-1.img"
+            ]
+        );
+        assert_eq!(
+            entries[0].options.as_deref(),
+            Some("root=UUID=0abc rw quiet")
+        );
+        // options of limine before version 8
+        assert_eq!(
+            entries[1].kernel.as_deref(),
+            Some("boot():/vmlinuz-6.12.5-arch1-1")
+        );
+        assert_eq!(entries[1].options.as_deref(), Some("root=UUID=0abc rw"));
+        assert_eq!(
+            entries[2].devicetree.as_deref(),
+            Some("boot():/dtbs/rockchip/rk3399-rockpro64.dtb")
+        );
+
+        // directories are counted but the collapsed one hides its entries
+        assert_eq!(
+            limine.selected().map(|entry| entry.id),
+            Some("Arch Linux/Fallback".into())
+        );
+        assert_eq!(limine.default_value(&entries[3]), Some("5".into()));
+        assert_eq!(limine.default_value(&entries[2]), None);
+    }
+
+    #[test]
+    fn test_limine_edit() {
+        let mut limine = Limine::from_file("test_data/limine.conf").unwrap();
+        limine.set_value("default_entry", "4");
+        assert_eq!(limine.selected(), None);
+        limine.set_value("remember_last_entry", "yes");
+        assert!(limine.remove_value("interface_branding"));
+        assert!(!limine.remove_value("interface_branding"));
+
+        limine
+            .set_entry_cmdline("Arch Linux/Fallback", "root=UUID=0abc ro single")
+            .unwrap();
+        limine.set_entry_cmdline("Windows", "quiet").unwrap();
+        limine
+            .set_entry_cmdline("Old kernels/linux 6.11.4", "quiet")
+            .unwrap();
+        assert!(limine.set_entry_cmdline("Old kernels", "quiet").is_err());
+
+        let contents = limine.as_string();
+        assert!(contents.starts_with(
+            "# limine.conf of the test system\n\
+             timeout: 5\n\
+             default_entry: 4\n\
+             remember_last_entry: yes\n\
+             \n\
+             /+Arch Linux\n"
+        ));
+        assert!(contents.contains("        kernel_cmdline: root=UUID=0abc ro single\n"));
+        assert!(contents.contains(
+            "        dtb_path: boot():/dtbs/rockchip/rk3399-rockpro64.dtb\n        \
+             cmdline: quiet\n\n"
+        ));
+        assert!(contents.ends_with("bootmgfw.efi\n    cmdline: quiet\n"));
+
+        assert!(limine.validate("timeout", "no").is_ok());
+        assert!(limine.validate("timeout", "-1").is_err());
+        assert!(limine.validate("default_entry", "5").is_ok());
+        assert!(limine.validate("default_entry", "6").is_err());
+        assert!(limine.validate("remember_last_entry", "true").is_err());
+    }
+}
@@ -1,31 +1,61 @@
 use clap::Parser;
 
+mod bootloader;
 mod config;
 mod db;
 mod dbus;
 mod errors;
 mod events;
+mod extlinux;
 mod grub2;
+mod limine;
 mod logging;
+mod refind;
+mod sdboot;
+mod system;
 
 use crate::{
-    config::ConfigArgs,
+    config::{layout::GrubLayout, ConfigArgs},
     db::Database,
     dbus::connection::create_connection,
     errors::{DRes, DResult},
     events::BootkitEvents,
+    grub2::env::GrubEnv,
     logging::setup_logging,
+    system::{notify::notify, CurrentBoot},
 };
 
+/// Add the current boot to the boot timeline. Failing to do this is not fatal
+async fn record_boot(db: &Database) {
+    let boot = match CurrentBoot::read() {
+        Ok(boot) => boot,
+        Err(_) => {
+            log::warn!("Cannot read current boot information, not recording the boot");
+            return;
+        }
+    };
+
+    let saved_entry = GrubEnv::from_file(&GrubLayout::get().env_path)
+        .ok()
+        .and_then(|env| env.saved_entry().map(str::to_string));
+
+    if db.record_boot(&boot, saved_entry).await.is_err() {
+        log::warn!("Failed to record boot {}", boot.boot_id);
+    }
+}
+
 #[tokio::main]
 async fn main() -> DResult<()> {
     let args = ConfigArgs::parse();
 
     setup_logging(&args)?;
     log::info!("Starting bootkit service");
+    // detect the grub layout once before anything reads the boot files
+    GrubLayout::get();
 
-    let db = Database::new().await?;
-    db.initialize().await?;
+    let db = Database::new(&args.database).await?;
+    db.initialize(args.parse_mode()).await?;
+    record_boot(&db).await;
 
     let connection = create_connection(&args, &db)
         .await
@@ -34,6 +64,7 @@ async fn main() -> DResult<()> {
     let events = BootkitEvents::new(&connection);
     let event_res = events.listen_events(&args).await;
     log::debug!("Event listener exited. Shutting down all events.");
+    notify("STOPPING=1");
     events.signal_shutdown();
     // This will hang until all the references to connection are dropped
     // so be careful where you clone connections!
@@ -0,0 +1,422 @@
+use std::{collections::BTreeMap, fs::read_to_string, path::Path};
+
+use crate::{
+    bootloader::{Bootloader, LoaderEntry},
+    config::REFIND_CONF_PATH,
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::kernel_version,
+    system::write_file_atomic,
+};
+
+/// `default_selection` value that boots the previously booted entry
+const PREVIOUS_SELECTION: &str = "+";
+
+/// `menuentry` stanza in refind.conf
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stanza {
+    title: String,
+    /// Line of the `menuentry` token
+    start: usize,
+    /// Line of the closing brace
+    end: usize,
+    /// Lines of the stanza body that aren't inside a `submenuentry`
+    body: Vec<usize>,
+}
+
+/// Split a line into the lowercased token and the rest,
+/// none for comments and empty lines
+fn token(line: &str) -> Option<(String, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (key, value) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(key, value)| (key, value.trim()));
+    Some((key.to_ascii_lowercase(), value))
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// First value of a line, keeping the spaces inside quotes
+fn first_value(value: &str) -> &str {
+    if let Some(quoted) = value.strip_prefix('"') {
+        return quoted.split('"').next().unwrap_or_default();
+    }
+    value.split_whitespace().next().unwrap_or_default()
+}
+
+/// rEFInd config file from the EFI system partition.
+///
+/// Only the `menuentry` stanzas of refind.conf are listed, the kernels rEFInd
+/// detects by scanning the disks aren't known until boot. The raw lines are kept
+/// so the file is written back as it was apart from the changed lines.
+#[derive(Debug, Clone, Default)]
+pub struct Refind {
+    lines: Vec<String>,
+}
+
+impl Refind {
+    pub fn parse(contents: &str) -> Self {
+        Self {
+            lines: contents.split('\n').map(str::to_string).collect(),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> DResult<Self> {
+        let path = path.as_ref();
+        let contents =
+            read_to_string(path).ctx(dctx!(), format!("Cannot read rEFInd config {path:?}"))?;
+        Ok(Self::parse(&contents))
+    }
+
+    pub fn read() -> DResult<Self> {
+        Self::from_file(REFIND_CONF_PATH)
+    }
+
+    /// Indexes of the global lines that aren't part of any stanza
+    fn global_lines(&self) -> Vec<usize> {
+        let stanzas = self.stanzas();
+        (0..self.lines.len())
+            .filter(|idx| {
+                !stanzas
+                    .iter()
+                    .any(|stanza| (stanza.start..=stanza.end).contains(idx))
+            })
+            .collect()
+    }
+
+    fn stanzas(&self) -> Vec<Stanza> {
+        let mut stanzas = Vec::new();
+        let mut current: Option<Stanza> = None;
+        let mut depth = 0;
+
+        for (idx, line) in self.lines.iter().enumerate() {
+            let Some((key, value)) = token(line) else {
+                continue;
+            };
+
+            if depth == 0 && current.is_none() && key == "menuentry" {
+                let title = value.trim_end_matches('{').trim();
+                current = Some(Stanza {
+                    title: unquote(title).to_string(),
+                    start: idx,
+                    end: idx,
+                    body: Vec::new(),
+                });
+            } else if depth == 1 && !line.contains('}') {
+                if let Some(stanza) = &mut current {
+                    stanza.body.push(idx);
+                }
+            }
+
+            depth += line.matches('{').count();
+            depth = depth.saturating_sub(line.matches('}').count());
+            if depth == 0 && line.contains('}') {
+                if let Some(mut stanza) = current.take() {
+                    stanza.end = idx;
+                    stanzas.push(stanza);
+                }
+            }
+        }
+
+        stanzas
+    }
+
+    /// Values of `key` in the body of the stanza
+    fn stanza_values<'a>(&'a self, stanza: &'a Stanza, key: &'a str) -> Vec<&'a str> {
+        stanza
+            .body
+            .iter()
+            .filter_map(|idx| token(&self.lines[*idx]))
+            .filter(|(line_key, _)| line_key == key)
+            .map(|(_, value)| unquote(value))
+            .collect()
+    }
+
+    fn loader_entry(&self, stanza: &Stanza) -> LoaderEntry {
+        let kernel = self
+            .stanza_values(stanza, "loader")
+            .first()
+            .map(|loader| loader.to_string());
+        LoaderEntry {
+            id: stanza.title.clone(),
+            title: stanza.title.clone(),
+            version: kernel
+                .as_deref()
+                .and_then(|kernel| kernel_version(&kernel.replace('\\', "/"))),
+            kernel,
+            initrds: self
+                .stanza_values(stanza, "initrd")
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            options: self
+                .stanza_values(stanza, "options")
+                .first()
+                .map(|options| options.to_string()),
+            devicetree: None,
+            devicetree_dir: None,
+            classes: Vec::new(),
+        }
+    }
+
+    pub fn as_string(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+impl Bootloader for Refind {
+    fn name(&self) -> &'static str {
+        "rEFInd"
+    }
+
+    fn config_path(&self) -> &str {
+        REFIND_CONF_PATH
+    }
+
+    /// Global tokens are case insensitive so they are returned in lowercase
+    fn values(&self) -> BTreeMap<String, String> {
+        self.global_lines()
+            .into_iter()
+            .filter_map(|idx| token(&self.lines[idx]))
+            .map(|(key, value)| (key, value.to_string()))
+            .collect()
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.lines.clone()
+    }
+
+    fn validate(&self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            // -1 boots the default immediately, 0 waits forever
+            "timeout" if value.parse::<i32>().is_ok_and(|timeout| timeout >= -1) => Ok(()),
+            "timeout" => Err(format!("'{value}' is not a number of seconds or -1")),
+            "default_selection" if first_value(value).is_empty() => {
+                Err("default_selection can't be empty".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn set_value(&mut self, key: &str, value: &str) {
+        let mut global = self.global_lines().into_iter().filter(|idx| {
+            token(&self.lines[*idx]).is_some_and(|(line_key, _)| line_key == key.to_lowercase())
+        });
+
+        match global.next() {
+            Some(idx) => {
+                let rest: Vec<usize> = global.collect();
+                self.lines[idx] = format!("{key} {value}");
+                for idx in rest.into_iter().rev() {
+                    self.lines.remove(idx);
+                }
+            }
+            None => {
+                // keep the trailing empty line at the end of the file
+                let idx = self
+                    .lines
+                    .iter()
+                    .rposition(|line| !line.trim().is_empty())
+                    .map_or(0, |idx| idx + 1);
+                self.lines.insert(idx, format!("{key} {value}"));
+            }
+        }
+    }
+
+    fn remove_value(&mut self, key: &str) -> bool {
+        let remove: Vec<usize> = self
+            .global_lines()
+            .into_iter()
+            .filter(|idx| {
+                token(&self.lines[*idx]).is_some_and(|(line_key, _)| line_key == key.to_lowercase())
+            })
+            .collect();
+        for idx in remove.iter().rev() {
+            self.lines.remove(*idx);
+        }
+        !remove.is_empty()
+    }
+
+    /// Entries without the `disabled` token
+    fn entries(&self) -> Vec<LoaderEntry> {
+        self.stanzas()
+            .iter()
+            .filter(|stanza| {
+                !stanza
+                    .body
+                    .iter()
+                    .any(|idx| token(&self.lines[*idx]).is_some_and(|(key, _)| key == "disabled"))
+            })
+            .map(|stanza| self.loader_entry(stanza))
+            .collect()
+    }
+
+    fn default_key(&self) -> &'static str {
+        "default_selection"
+    }
+
+    fn default_value(&self, entry: &LoaderEntry) -> Option<String> {
+        Some(format!("\"{}\"", entry.title))
+    }
+
+    /// `default_selection` is either the position of the entry in the menu
+    /// or a part of its title or loader path
+    fn selected(&self) -> Option<LoaderEntry> {
+        let entries = self.entries();
+        let Some(default) = self.default_entry() else {
+            return entries.into_iter().next();
+        };
+
+        let selection = first_value(&default);
+        if selection == PREVIOUS_SELECTION {
+            return None;
+        }
+        if let Ok(position) = selection.parse::<usize>() {
+            return entries.into_iter().nth(position.checked_sub(1)?);
+        }
+
+        entries.into_iter().find(|entry| {
+            entry.title.contains(selection)
+                || entry
+                    .kernel
+                    .as_deref()
+                    .is_some_and(|kernel| kernel.contains(selection))
+        })
+    }
+
+    fn set_entry_cmdline(&mut self, id: &str, cmdline: &str) -> DResult<()> {
+        let stanza = self
+            .stanzas()
+            .into_iter()
+            .find(|stanza| stanza.title == id)
+            .ok_or_else(|| DError::generic(dctx!(), format!("Boot entry '{id}' is not found")))?;
+
+        let indent = stanza
+            .body
+            .first()
+            .map(|idx| {
+                let line = &self.lines[*idx];
+                line[..line.len() - line.trim_start().len()].to_string()
+            })
+            .unwrap_or_else(|| "    ".into());
+        let options = format!("{indent}options \"{cmdline}\"");
+
+        let existing = stanza
+            .body
+            .iter()
+            .find(|idx| token(&self.lines[**idx]).is_some_and(|(key, _)| key == "options"));
+        match existing {
+            Some(idx) => self.lines[*idx] = options,
+            None => self.lines.insert(stanza.end, options),
+        }
+        log::debug!("Changed options of rEFInd entry '{id}' to '{cmdline}'");
+        Ok(())
+    }
+
+    fn write(&self) -> DResult<()> {
+        write_file_atomic(REFIND_CONF_PATH, &self.as_string())?;
+        log::debug!("rEFInd config was written to {REFIND_CONF_PATH}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refind_parse() {
+        let refind = Refind::from_file("test_data/refind.conf").unwrap();
+        let values = refind.values();
+        assert_eq!(values.get("timeout").map(String::as_str), Some("20"));
+        assert_eq!(
+            values.get("default_selection").map(String::as_str),
+            Some("\"Arch Linux\"")
+        );
+        // tokens inside the stanzas are not global
+        assert!(!values.contains_key("loader"));
+
+        let entries = refind.entries();
+        let titles: Vec<&str> = entries.iter().map(|entry| entry.title.as_str()).collect();
+        assert_eq!(titles, vec!["Arch Linux", "Windows"]);
+        assert_eq!(
+            entries[0].kernel.as_deref(),
+            Some("\\EFI\\arch\\vmlinuz-6.12.5-arch1-1")
+        );
+        assert_eq!(entries[0].version.as_deref(), Some("6.12.5-arch1-1"));
+        assert_eq!(entries[0].initrds, vec!["\\EFI\\arch\\initramfs-linux.img"]);
+        assert_eq!(
+            entries[0].options.as_deref(),
+            Some("root=PARTUUID=1234 rw quiet")
+        );
+        assert_eq!(entries[1].options, None);
+
+        assert_eq!(
+            refind.selected().map(|entry| entry.id),
+            Some("Arch Linux".into())
+        );
+        assert_eq!(
+            refind.find("1").map(|entry| entry.id),
+            Some("Windows".into())
+        );
+        assert_eq!(refind.find("2"), None);
+    }
+
+    #[test]
+    fn test_refind_edit() {
+        let mut refind = Refind::from_file("test_data/refind.conf").unwrap();
+        refind.set_value("timeout", "5");
+        refind.set_value("default_selection", "2");
+        assert_eq!(
+            refind.selected().map(|entry| entry.id),
+            Some("Windows".into())
+        );
+        assert!(refind.remove_value("scanfor"));
+        assert!(!refind.remove_value("scanfor"));
+
+        refind
+            .set_entry_cmdline("Arch Linux", "root=PARTUUID=1234 ro")
+            .unwrap();
+        refind.set_entry_cmdline("Windows", "quiet").unwrap();
+        assert!(refind.set_entry_cmdline("Missing", "quiet").is_err());
+
+        assert_eq!(
+            refind.as_string(),
+            "timeout 5\n\
+             default_selection 2\n\
+             \n\
+             menuentry \"Arch Linux\" {\n    \
+                 icon /EFI/refind/icons/os_arch.png\n    \
+                 loader \\EFI\\arch\\vmlinuz-6.12.5-arch1-1\n    \
+                 initrd \\EFI\\arch\\initramfs-linux.img\n    \
+                 options \"root=PARTUUID=1234 ro\"\n    \
+                 submenuentry \"Fallback\" {\n        \
+                     initrd \\EFI\\arch\\initramfs-linux-fallback.img\n    \
+                 }\n\
+             }\n\
+             \n\
+             menuentry Windows {\n    \
+                 loader \\EFI\\Microsoft\\Boot\\bootmgfw.efi\n    \
+                 options \"quiet\"\n\
+             }\n\
+             \n\
+             menuentry Disabled {\n    \
+                 loader \\EFI\\old\\vmlinuz\n    \
+                 disabled\n\
+             }\n"
+        );
+
+        assert!(refind.validate("timeout", "-1").is_ok());
+        assert!(refind.validate("timeout", "-2").is_err());
+        assert!(refind.validate("default_selection", "\"\"").is_err());
+    }
+}
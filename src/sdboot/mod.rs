@@ -0,0 +1,487 @@
+use std::{cmp::Ordering, collections::BTreeMap, fs::read_to_string, path::Path};
+
+use crate::{
+    bootloader::{Bootloader, LoaderEntry},
+    config::{BLS_ENTRIES_PATH, SDBOOT_LOADER_CONF_PATH},
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::{bls::BlsEntry, version::compare_versions},
+    system::{
+        efivars::{read_var, utf16_string},
+        process, write_file_atomic,
+    },
+};
+
+/// Vendor GUID of the variables systemd-boot uses
+const LOADER_GUID: &str = "4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+/// Default entry set with `bootctl set-default`, overrides the loader.conf default
+pub const ENTRY_DEFAULT_VAR: &str = "LoaderEntryDefault";
+/// Entry booted once on the next boot, set with `bootctl set-oneshot`
+pub const ENTRY_ONESHOT_VAR: &str = "LoaderEntryOneShot";
+
+/// Special `timeout` values systemd-boot understands in addition to seconds
+pub const TIMEOUT_VALUES: &[&str] = &["menu-force", "menu-hidden", "menu-disabled"];
+
+/// systemd-boot `loader.conf`.
+///
+/// Same as with BLS entries, the raw lines are kept so comments and the keys
+/// we don't know about are written back as they were.
+#[derive(Debug, Clone, Default)]
+pub struct LoaderConf {
+    lines: Vec<String>,
+}
+
+impl LoaderConf {
+    pub fn parse(contents: &str) -> Self {
+        Self {
+            lines: contents.split('\n').map(str::to_string).collect(),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> DResult<Self> {
+        let path = path.as_ref();
+        let contents =
+            read_to_string(path).ctx(dctx!(), format!("Cannot read loader config {path:?}"))?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Key of the line and its value, none for comments and empty lines
+    fn key_value(line: &str) -> Option<(&str, &str)> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        Some(
+            line.split_once(char::is_whitespace)
+                .map_or((line, ""), |(key, value)| (key, value.trim())),
+        )
+    }
+
+    /// Value of `key`. The last line wins if the key is defined multiple times
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| Self::key_value(line))
+            .filter(|(line_key, _)| *line_key == key)
+            .map(|(_, value)| value)
+            .next_back()
+    }
+
+    /// All the keys with their effective values
+    pub fn values(&self) -> BTreeMap<String, String> {
+        self.lines
+            .iter()
+            .filter_map(|line| Self::key_value(line))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Pattern of the default entry id
+    pub fn default_entry(&self) -> Option<&str> {
+        self.value("default")
+    }
+
+    fn is_key(line: &str, key: &str) -> bool {
+        Self::key_value(line).is_some_and(|(line_key, _)| line_key == key)
+    }
+
+    /// Replace the first definition of `key` and remove the rest,
+    /// or add it to the end of the file if it's not defined
+    pub fn set(&mut self, key: &str, value: &str) {
+        let mut replaced = false;
+        self.lines.retain_mut(|line| {
+            if !Self::is_key(line, key) {
+                return true;
+            }
+
+            if replaced {
+                return false;
+            }
+
+            replaced = true;
+            *line = format!("{key} {value}");
+            true
+        });
+
+        if !replaced {
+            // keep the trailing empty line at the end of the file
+            let idx = self
+                .lines
+                .iter()
+                .rposition(|line| !line.trim().is_empty())
+                .map_or(0, |idx| idx + 1);
+            self.lines.insert(idx, format!("{key} {value}"));
+        }
+    }
+
+    /// Remove all the definitions of `key`. Returns false if it wasn't defined
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.lines.len();
+        self.lines.retain(|line| !Self::is_key(line, key));
+        len != self.lines.len()
+    }
+
+    pub fn as_string(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> DResult<()> {
+        let path = path.as_ref();
+        write_file_atomic(path, &self.as_string())?;
+        log::debug!("Loader config was written to {path:?}");
+        Ok(())
+    }
+}
+
+/// String value of a systemd-boot EFI variable, none if it's not set
+pub fn efi_var(name: &str) -> Option<String> {
+    read_var(name, LOADER_GUID).and_then(|data| utf16_string(&data))
+}
+
+/// Set the default or one-shot entry with bootctl, empty `entry` removes it.
+/// bootctl takes care of the efivarfs immutable flag and the variable attributes
+pub async fn bootctl_set_entry(command: &str, entry: &str) -> DResult<()> {
+    process::run("bootctl", &[command, entry]).await?.check()
+}
+
+/// Check that `timeout` is a number of seconds or one of the menu values
+pub fn validate_timeout(value: &str) -> Result<(), String> {
+    if value.parse::<u32>().is_ok() || TIMEOUT_VALUES.contains(&value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "'{value}' is not a number of seconds or any of {}",
+            TIMEOUT_VALUES.join(", ")
+        ))
+    }
+}
+
+/// Match `value` against a loader.conf glob pattern with `*` and `?`
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    // position of the last `*` in the pattern and the value it was matched against
+    let mut star = None;
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, v));
+                p += 1;
+            }
+            Some(&ch) if ch == '?' || ch == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match star {
+                Some((star_p, star_v)) => {
+                    p = star_p + 1;
+                    v = star_v + 1;
+                    star = Some((star_p, star_v + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|ch| *ch == '*')
+}
+
+/// Boot entries systemd-boot shows in its menu
+#[derive(Debug, Clone)]
+pub struct SdBootEntries {
+    entries: Vec<BlsEntry>,
+}
+
+impl SdBootEntries {
+    /// Entries in the same order as systemd-boot shows them: entries with a
+    /// `sort-key` first, then the rest with the newest version first
+    pub fn new(mut entries: Vec<BlsEntry>) -> Self {
+        entries.sort_by(|a, b| match (a.sort_key(), b.sort_key()) {
+            (Some(key_a), Some(key_b)) => key_a.cmp(key_b).then_with(|| {
+                compare_versions(b.version().unwrap_or(b.id()), a.version().unwrap_or(a.id()))
+            }),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => compare_versions(b.id(), a.id()),
+        });
+        Self { entries }
+    }
+
+    pub fn from_dir<P: AsRef<Path>>(path: P) -> DResult<Self> {
+        Ok(Self::new(BlsEntry::from_dir(path)?))
+    }
+
+    pub fn entries(&self) -> &[BlsEntry] {
+        &self.entries
+    }
+
+    /// Entry by its id, with or without the `.conf` suffix, or title
+    pub fn find(&self, entry: &str) -> Option<&BlsEntry> {
+        let id = entry.strip_suffix(".conf").unwrap_or(entry);
+        self.entries
+            .iter()
+            .find(|bls| bls.id() == id || bls.title() == entry)
+    }
+
+    /// Entry the `default` pattern of loader.conf selects. systemd-boot picks the first
+    /// entry in the menu if there's no default.
+    /// `@saved` depends on the EFI variables so it's not resolved here
+    pub fn selected(&self, default: Option<&str>) -> Option<&BlsEntry> {
+        let Some(pattern) = default else {
+            return self.entries.first();
+        };
+
+        self.entries.iter().find(|entry| {
+            glob_match(pattern, entry.id()) || glob_match(pattern, &format!("{}.conf", entry.id()))
+        })
+    }
+
+    /// Check that `default` selects an entry, either by matching one or being `@saved`
+    pub fn validate_default(&self, default: &str) -> Result<(), String> {
+        if default == "@saved" || self.selected(Some(default)).is_some() {
+            return Ok(());
+        }
+
+        Err(format!("'{default}' doesn't match any boot entry"))
+    }
+}
+
+impl From<&BlsEntry> for LoaderEntry {
+    fn from(entry: &BlsEntry) -> Self {
+        let options = entry.options();
+        Self {
+            id: entry.id().to_string(),
+            title: entry.title().to_string(),
+            kernel: entry.linux().map(str::to_string),
+            initrds: entry.initrds(),
+            options: (!options.is_empty()).then_some(options),
+            version: entry.version().map(str::to_string),
+            devicetree: entry.devicetree().map(str::to_string),
+            devicetree_dir: None,
+            classes: entry.classes(),
+        }
+    }
+}
+
+/// systemd-boot with loader.conf and the BLS entries
+#[derive(Debug, Clone)]
+pub struct SdBoot {
+    conf: LoaderConf,
+    entries: SdBootEntries,
+    /// Ids of the entries whose options were changed
+    changed: Vec<String>,
+}
+
+impl SdBoot {
+    pub fn read() -> DResult<Self> {
+        Ok(Self {
+            conf: LoaderConf::from_file(SDBOOT_LOADER_CONF_PATH)?,
+            entries: SdBootEntries::from_dir(BLS_ENTRIES_PATH)
+                .ctx(dctx!(), "Couldn't read boot entries")?,
+            changed: Vec::new(),
+        })
+    }
+
+    /// Entry file name bootctl expects for `entry`, or an empty string for no entry.
+    /// `entry` is an id with or without `.conf` or a title
+    pub fn entry_file(&self, entry: Option<&str>) -> DResult<String> {
+        let Some(entry) = entry else {
+            return Ok(String::new());
+        };
+
+        self.entries
+            .find(entry)
+            .map(|found| format!("{}.conf", found.id()))
+            .ok_or_else(|| DError::generic(dctx!(), format!("Boot entry '{entry}' is not found")))
+    }
+}
+
+impl Bootloader for SdBoot {
+    fn name(&self) -> &'static str {
+        "systemd-boot"
+    }
+
+    fn config_path(&self) -> &str {
+        SDBOOT_LOADER_CONF_PATH
+    }
+
+    fn values(&self) -> BTreeMap<String, String> {
+        self.conf.values()
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.conf.lines().to_vec()
+    }
+
+    fn validate(&self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "timeout" => validate_timeout(value),
+            "default" => self.entries.validate_default(value),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_value(&mut self, key: &str, value: &str) {
+        self.conf.set(key, value);
+    }
+
+    fn remove_value(&mut self, key: &str) -> bool {
+        self.conf.remove(key)
+    }
+
+    fn entries(&self) -> Vec<LoaderEntry> {
+        self.entries
+            .entries()
+            .iter()
+            .map(LoaderEntry::from)
+            .collect()
+    }
+
+    fn default_key(&self) -> &'static str {
+        "default"
+    }
+
+    /// LoaderEntryDefault set by bootctl overrides the default of loader.conf
+    fn default_entry(&self) -> Option<String> {
+        efi_var(ENTRY_DEFAULT_VAR).or_else(|| self.conf.default_entry().map(str::to_string))
+    }
+
+    fn default_value(&self, entry: &LoaderEntry) -> Option<String> {
+        Some(format!("{}.conf", entry.id))
+    }
+
+    fn selected(&self) -> Option<LoaderEntry> {
+        let default = self.default_entry();
+        self.entries
+            .selected(default.as_deref())
+            .map(LoaderEntry::from)
+    }
+
+    fn next_entry(&self) -> Option<String> {
+        efi_var(ENTRY_ONESHOT_VAR)
+    }
+
+    fn set_entry_cmdline(&mut self, id: &str, cmdline: &str) -> DResult<()> {
+        let entry = self
+            .entries
+            .entries
+            .iter_mut()
+            .find(|entry| entry.id() == id)
+            .ok_or_else(|| DError::generic(dctx!(), format!("Boot entry '{id}' is not found")))?;
+
+        log::debug!(
+            "Changing options of BLS entry '{id}' from '{}' to '{cmdline}'",
+            entry.options()
+        );
+        entry.set_options(cmdline);
+        self.changed.push(id.to_string());
+        Ok(())
+    }
+
+    fn write(&self) -> DResult<()> {
+        self.conf.write(SDBOOT_LOADER_CONF_PATH)?;
+        for entry in self.entries.entries() {
+            if self.changed.iter().any(|id| id == entry.id()) {
+                entry.write(BLS_ENTRIES_PATH)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loader_conf() {
+        let mut conf = LoaderConf::from_file("test_data/efi/loader/loader.conf").unwrap();
+        assert_eq!(
+            conf.default_entry(),
+            Some("4c1d5b8ad1e94d1c8f3b1e2a3d4c5b6a-*")
+        );
+        assert_eq!(conf.value("timeout"), Some("3"));
+        assert_eq!(conf.value("console-mode"), Some("keep"));
+        assert_eq!(conf.value("editor"), None);
+
+        conf.set("timeout", "menu-force");
+        conf.set("editor", "no");
+        assert!(conf.remove("console-mode"));
+        assert!(!conf.remove("console-mode"));
+        assert_eq!(
+            conf.as_string(),
+            "# systemd-boot configuration\n\
+             default 4c1d5b8ad1e94d1c8f3b1e2a3d4c5b6a-*\n\
+             timeout menu-force\n\
+             editor no\n"
+        );
+        assert_eq!(conf.values().len(), 3);
+
+        assert!(validate_timeout("0").is_ok());
+        assert!(validate_timeout("menu-hidden").is_ok());
+        assert!(validate_timeout("soon").is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("fedora-*", "fedora-6.12.5"));
+        assert!(glob_match("fedora-?.12*", "fedora-6.12.5"));
+        assert!(glob_match("*.conf", "fedora.conf"));
+        assert!(!glob_match("fedora-*", "arch-6.12.5"));
+        assert!(!glob_match("fedora", "fedora-6.12.5"));
+    }
+
+    #[test]
+    fn test_sdboot_entries() {
+        let entries = SdBootEntries::from_dir("test_data/loader/entries").unwrap();
+        let ids: Vec<&str> = entries.entries().iter().map(BlsEntry::id).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "4c1d5b8ad1e94d1c8f3b1e2a3d4c5b6a-6.12.5-200.fc41.x86_64",
+                "4c1d5b8ad1e94d1c8f3b1e2a3d4c5b6a-6.11.4-301.fc41.x86_64",
+            ]
+        );
+
+        let selected = entries.selected(Some("*6.11.4*")).map(BlsEntry::id);
+        assert_eq!(selected, Some(ids[1]));
+        let selected = entries.selected(None).map(BlsEntry::id);
+        assert_eq!(selected, Some(ids[0]));
+        assert!(entries
+            .find("4c1d5b8ad1e94d1c8f3b1e2a3d4c5b6a-6.11.4-301.fc41.x86_64.conf")
+            .is_some());
+
+        assert!(entries.validate_default("@saved").is_ok());
+        assert!(entries.validate_default("*6.12*").is_ok());
+        assert!(entries.validate_default("arch-*").is_err());
+    }
+
+    #[test]
+    fn test_entry_file() {
+        let sdboot = SdBoot {
+            conf: LoaderConf::from_file("test_data/efi/loader/loader.conf").unwrap(),
+            entries: SdBootEntries::from_dir("test_data/loader/entries").unwrap(),
+            changed: Vec::new(),
+        };
+        let file = "4c1d5b8ad1e94d1c8f3b1e2a3d4c5b6a-6.11.4-301.fc41.x86_64.conf";
+        assert_eq!(sdboot.entry_file(Some(file)).unwrap(), file);
+        assert_eq!(
+            sdboot
+                .entry_file(Some(file.strip_suffix(".conf").unwrap()))
+                .unwrap(),
+            file
+        );
+        assert_eq!(sdboot.entry_file(None).unwrap(), "");
+        // ids are not menu positions
+        assert!(sdboot.entry_file(Some("1")).is_err());
+        assert!(sdboot.entry_file(Some("missing.conf")).is_err());
+    }
+}
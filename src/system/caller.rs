@@ -0,0 +1,129 @@
+use std::{fmt::Display, fs::read_to_string, future::Future};
+
+use serde::Serialize;
+
+tokio::task_local! {
+    /// Caller of the D-Bus method that is being handled
+    static CALLER: Caller;
+}
+
+/// Process that called a D-Bus method. It's recorded with the grub2 snapshots and
+/// the grub2-mkconfig runs of the changes it made. Writes of the other boot
+/// loaders aren't stored anywhere, so for those the caller is only logged
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Caller {
+    pub uid: Option<u32>,
+    pub pid: Option<u32>,
+    /// Name of the process from /proc/PID/comm
+    pub process: Option<String>,
+    /// systemd unit the process runs in, like `session-2.scope` or `packagekit.service`
+    pub unit: Option<String>,
+}
+
+impl Caller {
+    /// Caller with the process name and the unit read from /proc
+    pub fn new(uid: Option<u32>, pid: Option<u32>) -> Self {
+        let proc_file =
+            |name| pid.and_then(|pid| read_to_string(format!("/proc/{pid}/{name}")).ok());
+        Self {
+            uid,
+            pid,
+            process: proc_file("comm").map(|comm| comm.trim().to_string()),
+            unit: proc_file("cgroup").and_then(|cgroup| unit_from_cgroup(&cgroup)),
+        }
+    }
+
+    /// Run `future` with this as the [`Caller::current`] caller
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CALLER.scope(self, future).await
+    }
+
+    /// Caller of the D-Bus method that is being handled, none outside of method calls
+    pub fn current() -> Option<Caller> {
+        CALLER.try_with(Clone::clone).ok()
+    }
+}
+
+impl Display for Caller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(uid) = self.uid {
+            parts.push(format!("uid {uid}"));
+        }
+        match (self.pid, &self.process) {
+            (Some(pid), Some(process)) => parts.push(format!("pid {pid} ({process})")),
+            (Some(pid), None) => parts.push(format!("pid {pid}")),
+            _ => {}
+        }
+        if let Some(unit) = &self.unit {
+            parts.push(format!("unit {unit}"));
+        }
+
+        if parts.is_empty() {
+            write!(f, "unknown caller")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+/// Innermost systemd unit in /proc/PID/cgroup, e.g. `session-2.scope` from
+/// `0::/user.slice/user-1000.slice/session-2.scope`
+fn unit_from_cgroup(cgroup: &str) -> Option<String> {
+    // cgroup v2 has a single line with the 0:: hierarchy, v1 has one per controller
+    let line = cgroup
+        .lines()
+        .find(|line| line.starts_with("0::"))
+        .or_else(|| cgroup.lines().find(|line| line.contains(":name=systemd:")))?;
+    let (_, path) = line.rsplit_once(':')?;
+    path.split('/')
+        .rev()
+        .find(|unit| unit.ends_with(".service") || unit.ends_with(".scope"))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_from_cgroup() {
+        assert_eq!(
+            unit_from_cgroup("0::/user.slice/user-1000.slice/session-2.scope\n").as_deref(),
+            Some("session-2.scope")
+        );
+        assert_eq!(
+            unit_from_cgroup(
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-gnome-foo-123.scope"
+            )
+            .as_deref(),
+            Some("app-gnome-foo-123.scope")
+        );
+        assert_eq!(
+            unit_from_cgroup("12:cpu:/\n1:name=systemd:/system.slice/packagekit.service\n")
+                .as_deref(),
+            Some("packagekit.service")
+        );
+        assert_eq!(unit_from_cgroup("0::/\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_caller_scope() {
+        assert_eq!(Caller::current(), None);
+
+        let caller = Caller {
+            uid: Some(1000),
+            pid: Some(4242),
+            process: Some("bootkit-cli".into()),
+            unit: Some("session-2.scope".into()),
+        };
+        assert_eq!(
+            caller.to_string(),
+            "uid 1000, pid 4242 (bootkit-cli), unit session-2.scope"
+        );
+        let current = caller.clone().scope(async { Caller::current() }).await;
+        assert_eq!(current, Some(caller));
+
+        assert_eq!(Caller::default().to_string(), "unknown caller");
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    fs::{create_dir_all, read, read_dir, read_to_string, remove_file, write},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{BOOT_NEXT_MARKER_PATH, EFIVARS_PATH},
+    dctx,
+    errors::{DRes, DResult},
+    system::process,
+};
+
+/// Vendor GUID of the variables defined by the UEFI specification, like `BootOrder`
+pub const EFI_GLOBAL_GUID: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
+/// `LOAD_OPTION_ACTIVE` attribute of EFI_LOAD_OPTION
+const LOAD_OPTION_ACTIVE: u32 = 0x1;
+/// Device path node types and subtypes that are decoded
+const MEDIA_DEVICE_PATH: u8 = 0x04;
+const MEDIA_HARDDRIVE: u8 = 0x01;
+const MEDIA_FILEPATH: u8 = 0x04;
+const END_DEVICE_PATH: u8 = 0x7f;
+
+/// Contents of an efivarfs variable without the 4 bytes of attributes in front
+pub fn read_var(name: &str, guid: &str) -> Option<Vec<u8>> {
+    let path = Path::new(EFIVARS_PATH).join(format!("{name}-{guid}"));
+    let data = read(path).ok()?;
+    data.get(4..).map(<[u8]>::to_vec)
+}
+
+/// Decode a NUL terminated UTF-16LE string. Decoding stops at the end of the data
+/// if there's no NUL
+pub fn utf16_string(data: &[u8]) -> Option<String> {
+    let chars: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|ch| *ch != 0)
+        .collect();
+    let value = String::from_utf16(&chars).ok()?;
+    (!value.is_empty()).then_some(value)
+}
+
+/// Format a GUID stored in the mixed endian EFI layout
+pub fn guid_string(data: &[u8]) -> Option<String> {
+    let data: &[u8; 16] = data.get(..16)?.try_into().ok()?;
+    Some(format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{}",
+        u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+        u16::from_le_bytes([data[4], data[5]]),
+        u16::from_le_bytes([data[6], data[7]]),
+        data[8],
+        data[9],
+        data[10..]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>()
+    ))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+/// `Boot####` entry of the firmware boot manager
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FirmwareEntry {
+    /// Number of the entry, the `####` in `Boot####`
+    pub number: u16,
+    pub description: String,
+    /// Inactive entries are skipped by the firmware
+    pub active: bool,
+    /// Unique partition GUID of the disk the entry boots from
+    pub partition_uuid: Option<String>,
+    /// EFI binary the entry loads, like `\EFI\fedora\shimx64.efi`
+    pub file_path: Option<String>,
+}
+
+impl FirmwareEntry {
+    /// Decode an EFI_LOAD_OPTION. Only the hard drive and file path nodes of the
+    /// device path are decoded since those identify the entries on disks
+    pub fn parse(number: u16, data: &[u8]) -> Option<Self> {
+        let attributes = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        let path_list_len = read_u16(data, 4)? as usize;
+
+        // description is a NUL terminated UTF-16 string before the device paths
+        let description_len = data[6..].chunks_exact(2).position(|pair| pair == [0, 0])?;
+        let description = utf16_string(&data[6..6 + description_len * 2]).unwrap_or_default();
+        let path_start = 6 + (description_len + 1) * 2;
+        let path_list = data.get(path_start..path_start + path_list_len)?;
+
+        let mut entry = Self {
+            number,
+            description,
+            active: attributes & LOAD_OPTION_ACTIVE != 0,
+            partition_uuid: None,
+            file_path: None,
+        };
+
+        let mut offset = 0;
+        while let (Some(&node_type), Some(&subtype), Some(len)) = (
+            path_list.get(offset),
+            path_list.get(offset + 1),
+            read_u16(path_list, offset + 2),
+        ) {
+            let len = len as usize;
+            if node_type == END_DEVICE_PATH || len < 4 {
+                break;
+            }
+            let node = path_list.get(offset + 4..offset + len)?;
+
+            match (node_type, subtype) {
+                // partition number, start, size, signature, format and signature type
+                (MEDIA_DEVICE_PATH, MEDIA_HARDDRIVE) => {
+                    entry.partition_uuid = node.get(20..36).and_then(guid_string);
+                }
+                (MEDIA_DEVICE_PATH, MEDIA_FILEPATH) => {
+                    entry.file_path = utf16_string(node);
+                }
+                _ => {}
+            }
+            offset += len;
+        }
+
+        Some(entry)
+    }
+}
+
+/// Firmware boot manager state from the EFI variables
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FirmwareBoot {
+    /// Entry the current boot was started from
+    pub current: Option<u16>,
+    /// Entry that is booted once on the next boot
+    pub next: Option<u16>,
+    /// BootNext is the entry that was booted and bootkit didn't set it again during
+    /// this boot, so the firmware didn't remove it and boots the entry every time
+    pub next_not_removed: bool,
+    /// Order the firmware tries the entries in
+    pub order: Vec<u16>,
+    /// All the `Boot####` entries sorted by their number
+    pub entries: Vec<FirmwareEntry>,
+}
+
+/// Number of the entry from a `Boot####-<guid>` variable name
+fn boot_entry_number(file_name: &str) -> Option<u16> {
+    let number = file_name
+        .strip_prefix("Boot")?
+        .strip_suffix(EFI_GLOBAL_GUID)?
+        .strip_suffix('-')?;
+    if number.len() != 4 {
+        return None;
+    }
+    u16::from_str_radix(number, 16).ok()
+}
+
+impl FirmwareBoot {
+    /// Read the boot manager variables. Fails if the system wasn't booted with UEFI
+    pub fn read() -> DResult<Self> {
+        let dir = read_dir(EFIVARS_PATH).ctx(
+            dctx!(),
+            format!("Cannot read {EFIVARS_PATH}, the system is not booted with UEFI"),
+        )?;
+
+        let mut entries = Vec::new();
+        for file in dir {
+            let file = file.ctx(dctx!(), format!("Cannot read {EFIVARS_PATH}"))?;
+            let name = file.file_name().to_string_lossy().to_string();
+            let Some(number) = boot_entry_number(&name) else {
+                continue;
+            };
+
+            let data = read_var(&format!("Boot{number:04X}"), EFI_GLOBAL_GUID);
+            match data.and_then(|data| FirmwareEntry::parse(number, &data)) {
+                Some(entry) => entries.push(entry),
+                None => log::warn!("Cannot decode firmware boot entry {name}"),
+            }
+        }
+        entries.sort_by_key(|entry| entry.number);
+
+        let number = |name| read_var(name, EFI_GLOBAL_GUID).and_then(|data| read_u16(&data, 0));
+        let order = read_var("BootOrder", EFI_GLOBAL_GUID)
+            .map(|data| boot_order(&data))
+            .unwrap_or_default();
+
+        let current = number("BootCurrent");
+        let next = number("BootNext");
+        Ok(Self {
+            current,
+            next,
+            next_not_removed: next_not_removed(current, next, boot_next_set_this_boot()),
+            order,
+            entries,
+        })
+    }
+
+    pub fn find(&self, number: u16) -> Option<&FirmwareEntry> {
+        self.entries.iter().find(|entry| entry.number == number)
+    }
+}
+
+/// Entry numbers of a `BootOrder` variable, a trailing odd byte is ignored
+fn boot_order(data: &[u8]) -> Vec<u16> {
+    (0..data.len() / 2)
+        .filter_map(|idx| read_u16(data, idx * 2))
+        .collect()
+}
+
+/// The firmware removes BootNext when it boots the entry, so BootNext pointing to
+/// the current entry means it wasn't removed. Unless it was set again to boot the
+/// same entry once more, which `set_this_boot` tells
+fn next_not_removed(current: Option<u16>, next: Option<u16>, set_this_boot: Option<u16>) -> bool {
+    next.is_some() && next == current && next != set_this_boot
+}
+
+/// BootNext that [`set_boot_next`] set during this boot
+fn boot_next_set_this_boot() -> Option<u16> {
+    let number = read_to_string(BOOT_NEXT_MARKER_PATH).ok()?;
+    u16::from_str_radix(number.trim(), 16).ok()
+}
+
+/// efibootmgr arguments that change BootNext from `current` to `number`, none if
+/// nothing has to be done. Deleting a missing BootNext fails in efibootmgr
+fn boot_next_args(number: Option<u16>, current: Option<u16>) -> Option<Vec<String>> {
+    match number {
+        Some(number) => Some(vec!["--bootnext".into(), format!("{number:04X}")]),
+        None if current.is_some() => Some(vec!["--delete-bootnext".into()]),
+        None => None,
+    }
+}
+
+/// Set `BootNext` to boot `number` once on the next boot, or remove it. Removing
+/// BootNext that isn't set does nothing. efibootmgr takes care of the efivarfs
+/// immutable flag and the variable attributes
+pub async fn set_boot_next(number: Option<u16>) -> DResult<()> {
+    let current = read_var("BootNext", EFI_GLOBAL_GUID).and_then(|data| read_u16(&data, 0));
+    let Some(args) = boot_next_args(number, current) else {
+        log::debug!("BootNext is not set, nothing to remove");
+        return Ok(());
+    };
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    process::run("efibootmgr", &args).await?.check()?;
+    remember_boot_next(number);
+    Ok(())
+}
+
+/// Keep the BootNext that was set in /run so it isn't mistaken for one that the
+/// firmware didn't remove
+fn remember_boot_next(number: Option<u16>) {
+    let path = Path::new(BOOT_NEXT_MARKER_PATH);
+    let result = match number {
+        Some(number) => path
+            .parent()
+            .map_or(Ok(()), create_dir_all)
+            .and_then(|_| write(path, format!("{number:04X}\n"))),
+        None => remove_file(path).or_else(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(err),
+        }),
+    };
+    if let Err(err) = result {
+        log::warn!("Cannot record BootNext in {BOOT_NEXT_MARKER_PATH}: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(value: &str) -> Vec<u8> {
+        value
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    #[test]
+    fn test_utf16_string() {
+        assert_eq!(
+            utf16_string(&utf16("fedora.conf")),
+            Some("fedora.conf".into())
+        );
+        assert_eq!(utf16_string(&[0, 0]), None);
+        assert_eq!(utf16_string(&[]), None);
+    }
+
+    #[test]
+    fn test_boot_entry_number() {
+        let name = format!("Boot000A-{EFI_GLOBAL_GUID}");
+        assert_eq!(boot_entry_number(&name), Some(10));
+        assert_eq!(
+            boot_entry_number(&format!("BootOrder-{EFI_GLOBAL_GUID}")),
+            None
+        );
+        assert_eq!(boot_entry_number("Boot0001-0000"), None);
+    }
+
+    #[test]
+    fn test_firmware_entry_parse() {
+        let mut harddrive = vec![MEDIA_DEVICE_PATH, MEDIA_HARDDRIVE, 42, 0];
+        // partition number, start and size
+        harddrive.extend([1, 0, 0, 0]);
+        harddrive.extend([0; 16]);
+        harddrive.extend([
+            0x67, 0x45, 0x23, 0x01, 0xab, 0x89, 0xef, 0xcd, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+            0xcd, 0xef,
+        ]);
+        // GPT format and signature type
+        harddrive.extend([2, 2]);
+
+        let path = utf16("\\EFI\\fedora\\shimx64.efi");
+        let mut file = vec![MEDIA_DEVICE_PATH, MEDIA_FILEPATH];
+        file.extend(((path.len() + 4) as u16).to_le_bytes());
+        file.extend(path);
+
+        let mut path_list = harddrive;
+        path_list.extend(file);
+        path_list.extend([END_DEVICE_PATH, 0xff, 4, 0]);
+
+        let mut data = LOAD_OPTION_ACTIVE.to_le_bytes().to_vec();
+        data.extend((path_list.len() as u16).to_le_bytes());
+        data.extend(utf16("Fedora"));
+        data.extend(path_list);
+
+        let entry = FirmwareEntry::parse(1, &data).unwrap();
+        assert_eq!(
+            entry,
+            FirmwareEntry {
+                number: 1,
+                description: "Fedora".into(),
+                active: true,
+                partition_uuid: Some("01234567-89ab-cdef-0123-456789abcdef".into()),
+                file_path: Some("\\EFI\\fedora\\shimx64.efi".into()),
+            }
+        );
+
+        assert_eq!(FirmwareEntry::parse(1, &data[..10]), None);
+    }
+
+    #[test]
+    fn test_boot_order() {
+        assert_eq!(
+            boot_order(&[0x01, 0x00, 0x0a, 0x00, 0x00, 0x20]),
+            [1, 10, 0x2000]
+        );
+        assert_eq!(boot_order(&[0x03, 0x00, 0x01]), [3]);
+        assert!(boot_order(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_next_not_removed() {
+        assert!(!next_not_removed(Some(1), None, None));
+        assert!(!next_not_removed(Some(1), Some(2), None));
+        assert!(next_not_removed(Some(1), Some(1), None));
+        // stale marker of another entry
+        assert!(next_not_removed(Some(1), Some(1), Some(2)));
+        // set again to boot the current entry once more
+        assert!(!next_not_removed(Some(1), Some(1), Some(1)));
+        assert!(!next_not_removed(None, None, None));
+    }
+
+    #[test]
+    fn test_boot_next_args() {
+        assert_eq!(
+            boot_next_args(Some(10), None),
+            Some(vec!["--bootnext".to_string(), "000A".into()])
+        );
+        assert_eq!(
+            boot_next_args(Some(1), Some(2)),
+            Some(vec!["--bootnext".to_string(), "0001".into()])
+        );
+        assert_eq!(
+            boot_next_args(None, Some(2)),
+            Some(vec!["--delete-bootnext".to_string()])
+        );
+        // clearing BootNext that isn't set is a no-op
+        assert_eq!(boot_next_args(None, None), None);
+    }
+}
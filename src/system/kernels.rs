@@ -0,0 +1,318 @@
+use std::{
+    fs::{read_dir, read_to_string},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{BOOT_PATH, MODULES_PATH},
+    dctx,
+    errors::{DError, DRes, DResult},
+    grub2::{kernel_version, version::compare_versions},
+    system::process,
+};
+
+const RPM_PATH: &str = "/usr/bin/rpm";
+const DPKG_QUERY_PATH: &str = "/usr/bin/dpkg-query";
+/// Release of the running kernel, same as `uname -r`
+pub const OSRELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+
+/// Initrd names of the distributions, `{}` is the kernel version
+const INITRD_NAMES: &[&str] = &[
+    "initrd-{}",
+    "initrd-{}.img",
+    "initramfs-{}.img",
+    "initramfs-{}-fallback.img",
+    "initrd.img-{}",
+];
+
+/// Kernel image found in /boot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InstalledKernel {
+    pub version: String,
+    pub image: String,
+    /// Empty if the kernel has no initrd
+    pub initrds: Vec<String>,
+    /// Does /usr/lib/modules have the modules of the kernel
+    pub has_modules: bool,
+    pub running: bool,
+    /// Ids of the boot entries that boot the kernel, empty for orphaned kernels
+    pub entries: Vec<String>,
+}
+
+impl InstalledKernel {
+    /// Does a boot entry with the `kernel` image and `version` boot this kernel.
+    /// Entries have the path relative to their boot partition, possibly with a grub
+    /// device or in EFI notation, and systemd-boot boots copies of the image on the
+    /// ESP, so the version is compared when the entry has one and the file names
+    /// otherwise
+    pub fn is_booted_by(&self, kernel: &str, version: Option<&str>) -> bool {
+        if version.is_some_and(|version| version == self.version) {
+            return true;
+        }
+        let name = |path: &str| {
+            path.rsplit(['/', '\\', ':', ')'])
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        };
+        !kernel.is_empty() && name(kernel) == name(&self.image)
+    }
+
+    /// Image and initrds of the kernel
+    pub fn files(&self) -> Vec<String> {
+        std::iter::once(self.image.clone())
+            .chain(self.initrds.iter().cloned())
+            .collect()
+    }
+}
+
+/// Kernels that are safe to remove: not running, not booted by any entry and
+/// not one of the `keep` newest ones. `kernels` are newest first
+pub fn removable_kernels(kernels: &[InstalledKernel], keep: usize) -> Vec<&InstalledKernel> {
+    kernels
+        .iter()
+        .skip(keep)
+        .filter(|kernel| !kernel.running && kernel.entries.is_empty())
+        .collect()
+}
+
+/// Package that owns the file at `path`, none if no package does. Fails if neither
+/// rpm nor dpkg can tell, the file is then left alone
+pub async fn package_owner(path: &str) -> DResult<Option<String>> {
+    if Path::new(RPM_PATH).exists() {
+        let output = process::run(
+            RPM_PATH,
+            &[
+                "-qf",
+                "--queryformat",
+                "%{NAME}-%{VERSION}-%{RELEASE}\\n",
+                path,
+            ],
+        )
+        .await?;
+        if output.stdout.contains("is not owned by any package") {
+            return Ok(None);
+        }
+        output.check()?;
+        return Ok(output.stdout.lines().next().map(str::to_string));
+    }
+
+    if Path::new(DPKG_QUERY_PATH).exists() {
+        let output = process::run(DPKG_QUERY_PATH, &["-S", path]).await?;
+        if output.stderr.contains("no path found") {
+            return Ok(None);
+        }
+        output.check()?;
+        return Ok(dpkg_owner(&output.stdout));
+    }
+
+    Err(DError::generic(
+        dctx!(),
+        format!("Cannot find out which package owns {path}, neither rpm nor dpkg is installed"),
+    ))
+}
+
+/// Package of `dpkg-query -S` output like `linux-image-6.1.0-9-amd64: /boot/vmlinuz-6.1.0-9-amd64`
+fn dpkg_owner(stdout: &str) -> Option<String> {
+    let (packages, _) = stdout.lines().next()?.split_once(": ")?;
+    // files shared by packages list all of them separated by commas
+    packages
+        .split(", ")
+        .next()
+        .map(str::to_string)
+        .filter(|package| !package.is_empty())
+}
+
+/// Kernels in /boot, newest first. The entries are left empty for the caller
+/// to fill in
+pub fn installed_kernels() -> DResult<Vec<InstalledKernel>> {
+    let files = dir_names(BOOT_PATH).ctx(dctx!(), format!("Cannot read {BOOT_PATH}"))?;
+    // missing modules directory just means that no kernel has modules
+    let modules = dir_names(MODULES_PATH).unwrap_or_default();
+    let running = read_to_string(OSRELEASE_PATH).ok();
+
+    Ok(find_kernels(
+        Path::new(BOOT_PATH),
+        &files,
+        &modules,
+        running.as_deref().map(str::trim),
+    ))
+}
+
+fn dir_names(path: &str) -> std::io::Result<Vec<String>> {
+    Ok(read_dir(path)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect())
+}
+
+/// Kernel images among `files` of `boot_dir` with their initrds
+fn find_kernels(
+    boot_dir: &Path,
+    files: &[String],
+    modules: &[String],
+    running: Option<&str>,
+) -> Vec<InstalledKernel> {
+    let path = |name: &str| boot_dir.join(name).to_string_lossy().to_string();
+    let mut kernels: Vec<InstalledKernel> = files
+        .iter()
+        // vmlinux-VERSION.gz is the uncompressed kernel kept for debugging
+        .filter(|name| name.starts_with("vmlinuz-") || name.starts_with("Image-"))
+        .filter(|name| !name.ends_with(".hmac"))
+        .filter_map(|name| {
+            let version = kernel_version(name)?;
+            let initrds = INITRD_NAMES
+                .iter()
+                .map(|pattern| pattern.replace("{}", &version))
+                .filter(|initrd| files.contains(initrd))
+                .map(|initrd| path(&initrd))
+                .collect();
+            Some(InstalledKernel {
+                image: path(name),
+                initrds,
+                has_modules: modules.contains(&version),
+                running: running == Some(version.as_str()),
+                entries: Vec::new(),
+                version,
+            })
+        })
+        .collect();
+    kernels.sort_by(|a, b| compare_versions(&b.version, &a.version));
+    kernels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_kernels() {
+        let files: Vec<String> = [
+            "vmlinuz",
+            "vmlinuz-6.17.5-1-default",
+            "initrd-6.17.5-1-default",
+            "vmlinuz-6.18.1-1-default",
+            "vmlinux-6.18.1-1-default.gz",
+            "vmlinuz-6.9.0-1-default",
+            "initrd-6.9.0-1-default",
+            "grub2",
+        ]
+        .map(String::from)
+        .to_vec();
+        let modules = vec!["6.17.5-1-default".to_string(), "6.18.1-1-default".into()];
+
+        let kernels = find_kernels(
+            Path::new("/boot"),
+            &files,
+            &modules,
+            Some("6.17.5-1-default"),
+        );
+        let versions: Vec<&str> = kernels.iter().map(|k| k.version.as_str()).collect();
+        assert_eq!(
+            versions,
+            vec!["6.18.1-1-default", "6.17.5-1-default", "6.9.0-1-default"]
+        );
+
+        // no initrd
+        assert!(kernels[0].initrds.is_empty());
+        assert!(kernels[0].has_modules);
+        assert!(!kernels[0].running);
+        assert!(kernels[1].running);
+        assert_eq!(kernels[1].initrds, vec!["/boot/initrd-6.17.5-1-default"]);
+        // modules removed with the package
+        assert!(!kernels[2].has_modules);
+        assert_eq!(kernels[2].image, "/boot/vmlinuz-6.9.0-1-default");
+
+        let fedora = [
+            "vmlinuz-6.11.4-301.fc41.x86_64",
+            "initramfs-6.11.4-301.fc41.x86_64.img",
+        ]
+        .map(String::from);
+        let kernels = find_kernels(Path::new("/boot"), &fedora, &[], None);
+        assert_eq!(
+            kernels[0].initrds,
+            vec!["/boot/initramfs-6.11.4-301.fc41.x86_64.img"]
+        );
+    }
+
+    #[test]
+    fn test_removable_kernels() {
+        let files = [
+            "vmlinuz-6.18.1-1-default",
+            "vmlinuz-6.17.5-1-default",
+            "vmlinuz-6.12.0-1-default",
+            "initrd-6.12.0-1-default",
+            "vmlinuz-6.9.0-1-default",
+            "vmlinuz-6.8.0-1-default",
+        ]
+        .map(String::from);
+        let mut kernels = find_kernels(Path::new("/boot"), &files, &[], Some("6.9.0-1-default"));
+        kernels[2]
+            .entries
+            .push("gnulinux-6.12.0-1-default-advanced".into());
+
+        let versions = |keep| -> Vec<String> {
+            removable_kernels(&kernels, keep)
+                .iter()
+                .map(|kernel| kernel.version.clone())
+                .collect()
+        };
+        // the running and the referenced kernels are never removed
+        assert_eq!(
+            versions(0),
+            vec!["6.18.1-1-default", "6.17.5-1-default", "6.8.0-1-default"]
+        );
+        assert_eq!(versions(2), vec!["6.8.0-1-default"]);
+        assert!(versions(5).is_empty());
+
+        assert_eq!(
+            kernels[2].files(),
+            vec![
+                "/boot/vmlinuz-6.12.0-1-default",
+                "/boot/initrd-6.12.0-1-default"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_booted_by() {
+        let kernel = find_kernels(
+            Path::new("/boot"),
+            &["vmlinuz-6.12.5-arch1-1".to_string()],
+            &[],
+            None,
+        )
+        .remove(0);
+        assert!(kernel.is_booted_by("/vmlinuz-6.12.5-arch1-1", None));
+        assert!(kernel.is_booted_by("(hd0,gpt2)/boot/vmlinuz-6.12.5-arch1-1", None));
+        assert!(kernel.is_booted_by("\\EFI\\arch\\vmlinuz-6.12.5-arch1-1", None));
+        assert!(kernel.is_booted_by("boot():/vmlinuz-6.12.5-arch1-1", None));
+        assert!(!kernel.is_booted_by("/vmlinuz-6.12.4-arch1-1", None));
+        assert!(!kernel.is_booted_by("", None));
+        // systemd-boot boots a copy of the image on the ESP
+        assert!(kernel.is_booted_by(
+            "/0123456789abcdef/6.12.5-arch1-1/linux-1a2b3c",
+            Some("6.12.5-arch1-1")
+        ));
+        assert!(kernel.is_booted_by("", Some("6.12.5-arch1-1")));
+        assert!(!kernel.is_booted_by(
+            "/0123456789abcdef/6.12.4-arch1-1/linux-1a2b3c",
+            Some("6.12.4-arch1-1")
+        ));
+    }
+
+    #[test]
+    fn test_dpkg_owner() {
+        assert_eq!(
+            dpkg_owner("linux-image-6.1.0-9-amd64: /boot/vmlinuz-6.1.0-9-amd64\n").as_deref(),
+            Some("linux-image-6.1.0-9-amd64")
+        );
+        assert_eq!(
+            dpkg_owner("linux-image-6.1.0-9-amd64, linux-signed: /boot/vmlinuz\n").as_deref(),
+            Some("linux-image-6.1.0-9-amd64")
+        );
+        assert_eq!(dpkg_owner(""), None);
+    }
+}
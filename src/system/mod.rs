@@ -0,0 +1,223 @@
+use std::{
+    fs::{canonicalize, metadata, read_to_string, rename, set_permissions, File},
+    io::Write,
+    path::Path,
+};
+
+use chrono::{DateTime, NaiveDateTime};
+
+pub mod caller;
+pub mod efivars;
+pub mod kernels;
+pub mod mok;
+pub mod notify;
+pub mod ostree;
+pub mod process;
+pub mod snapper;
+pub mod swap;
+
+use crate::{
+    config::FIRMWARE_EFI_PATH,
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+/// Same boot id that journald uses to separate boots
+pub const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+pub const KERNEL_CMDLINE_PATH: &str = "/proc/cmdline";
+pub const PROC_STAT_PATH: &str = "/proc/stat";
+pub const MOUNTS_PATH: &str = "/proc/self/mounts";
+pub const HOSTNAME_PATH: &str = "/proc/sys/kernel/hostname";
+
+/// Write `contents` to `path` through a temporary file that is renamed over it, so
+/// a crash never leaves a half written file behind. The permissions of the old
+/// file are kept, they are only set when they differ since the EFI system
+/// partition doesn't support changing them
+pub fn write_file_atomic<P: AsRef<Path>>(path: P, contents: &str) -> DResult<()> {
+    let path = path.as_ref();
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push(".new");
+    let new_path = Path::new(&new_path);
+
+    let mut file = File::create(new_path).ctx(dctx!(), format!("Failed to create {new_path:?}"))?;
+    write!(file, "{contents}").ctx(dctx!(), format!("Failed to write {new_path:?}"))?;
+    file.sync_all()
+        .ctx(dctx!(), format!("Failed to sync {new_path:?}"))?;
+    if let (Ok(previous), Ok(new)) = (metadata(path), file.metadata()) {
+        if previous.permissions() != new.permissions() {
+            set_permissions(new_path, previous.permissions()).ctx(
+                dctx!(),
+                format!("Failed to set the permissions of {new_path:?}"),
+            )?;
+        }
+    }
+    rename(new_path, path).ctx(dctx!(), format!("Failed to move {new_path:?} to {path:?}"))?;
+    Ok(())
+}
+
+/// Name of the machine, none if it can't be read
+pub fn hostname() -> Option<String> {
+    read_to_string(HOSTNAME_PATH)
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Firmware the machine was booted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareMode {
+    Uefi,
+    Bios,
+}
+
+impl FirmwareMode {
+    pub fn detect() -> Self {
+        if Path::new(FIRMWARE_EFI_PATH).exists() {
+            Self::Uefi
+        } else {
+            Self::Bios
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FirmwareMode::Uefi => "uefi",
+            FirmwareMode::Bios => "bios",
+        }
+    }
+}
+
+/// Information about the currently running boot
+#[derive(Debug, Clone)]
+pub struct CurrentBoot {
+    pub boot_id: String,
+    /// Kernel image grub booted, from BOOT_IMAGE in the kernel command line
+    pub boot_image: Option<String>,
+    pub boot_time: NaiveDateTime,
+}
+
+impl CurrentBoot {
+    pub fn read() -> DResult<Self> {
+        let boot_id =
+            read_to_string(BOOT_ID_PATH).ctx(dctx!(), format!("Cannot read {BOOT_ID_PATH}"))?;
+        let cmdline = read_to_string(KERNEL_CMDLINE_PATH)
+            .ctx(dctx!(), format!("Cannot read {KERNEL_CMDLINE_PATH}"))?;
+        let stat =
+            read_to_string(PROC_STAT_PATH).ctx(dctx!(), format!("Cannot read {PROC_STAT_PATH}"))?;
+
+        Ok(Self {
+            boot_id: boot_id.trim().into(),
+            boot_image: boot_image(&cmdline).map(str::to_string),
+            boot_time: boot_time(&stat)?,
+        })
+    }
+
+    /// Kernel version parsed from the boot image, e.g. `/boot/vmlinuz-6.17.5-1-default`
+    pub fn kernel_version(&self) -> Option<&str> {
+        let image = self.boot_image.as_deref()?;
+        let file = image.rsplit('/').next()?;
+        file.split_once('-').map(|(_, version)| version)
+    }
+}
+
+/// Filesystem type of the mount that contains `path`, like `btrfs`
+pub fn filesystem_type<P: AsRef<Path>>(path: P) -> Option<String> {
+    let path = canonicalize(path).ok()?;
+    let mounts = read_to_string(MOUNTS_PATH).ok()?;
+    mount_fs_type(&mounts, &path).map(str::to_string)
+}
+
+/// Device of the mount that contains `path`, like `/dev/vda2`
+pub fn mount_device<P: AsRef<Path>>(path: P) -> Option<String> {
+    let path = canonicalize(path).ok()?;
+    let mounts = read_to_string(MOUNTS_PATH).ok()?;
+    mount_entry(&mounts, &path).map(|(device, _)| device.to_string())
+}
+
+fn mount_fs_type<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mount_entry(mounts, path).map(|(_, fs_type)| fs_type)
+}
+
+/// Device and filesystem type of the mount that contains `path`
+fn mount_entry<'a>(mounts: &'a str, path: &Path) -> Option<(&'a str, &'a str)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?, fields.next()?, fields.next()?))
+        })
+        .filter(|(_, mount_point, _)| path.starts_with(mount_point))
+        // the longest mount point is the closest, later mounts hide the earlier ones
+        .max_by_key(|(_, mount_point, _)| mount_point.len())
+        .map(|(device, _, fs_type)| (device, fs_type))
+}
+
+fn boot_image(cmdline: &str) -> Option<&str> {
+    cmdline
+        .split_whitespace()
+        .find_map(|param| param.strip_prefix("BOOT_IMAGE="))
+        // BOOT_IMAGE can include the grub device, like (hd0,gpt2)/boot/vmlinuz
+        .map(|image| image.rsplit_once(')').map_or(image, |(_, path)| path))
+}
+
+fn boot_time(stat: &str) -> DResult<NaiveDateTime> {
+    let btime = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse::<i64>().ok())
+        .ok_or_else(|| DError::generic(dctx!(), format!("No btime in {PROC_STAT_PATH}")))?;
+
+    DateTime::from_timestamp(btime, 0)
+        .map(|time| time.naive_utc())
+        .ok_or_else(|| DError::generic(dctx!(), format!("Invalid btime '{btime}'")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_image() {
+        let cmdline = "BOOT_IMAGE=/boot/vmlinuz-6.17.5-1-default root=UUID=0abc quiet";
+        assert_eq!(boot_image(cmdline), Some("/boot/vmlinuz-6.17.5-1-default"));
+
+        let cmdline = "BOOT_IMAGE=(hd0,gpt2)/vmlinuz-6.11.4-301.fc41.x86_64 ro";
+        assert_eq!(boot_image(cmdline), Some("/vmlinuz-6.11.4-301.fc41.x86_64"));
+
+        assert_eq!(boot_image("root=/dev/sda1 quiet"), None);
+    }
+
+    #[test]
+    fn test_kernel_version() {
+        let boot = CurrentBoot {
+            boot_id: "id".into(),
+            boot_image: Some("/boot/vmlinuz-6.17.5-1-default".into()),
+            boot_time: NaiveDateTime::default(),
+        };
+        assert_eq!(boot.kernel_version(), Some("6.17.5-1-default"));
+    }
+
+    #[test]
+    fn test_boot_time() {
+        let stat = "cpu  1 2 3\nbtime 1700000000\nprocesses 10\n";
+        let time = boot_time(stat).unwrap();
+        assert_eq!(time.to_string(), "2023-11-14 22:13:20");
+        assert!(boot_time("cpu 1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn test_mount_fs_type() {
+        let mounts = "/dev/vda2 / btrfs rw,relatime 0 0\n\
+                      proc /proc proc rw 0 0\n\
+                      /dev/vda1 /boot/efi vfat rw 0 0\n\
+                      /dev/vda3 /boot ext4 rw 0 0\n";
+        let fs_type = |path: &str| mount_fs_type(mounts, Path::new(path));
+        assert_eq!(fs_type("/boot/grub2/grubenv"), Some("ext4"));
+        assert_eq!(fs_type("/boot/efi/EFI/fedora/grubenv"), Some("vfat"));
+        assert_eq!(fs_type("/bootloader"), Some("btrfs"));
+        assert_eq!(
+            mount_entry(mounts, Path::new("/swapfile")),
+            Some(("/dev/vda2", "btrfs"))
+        );
+    }
+}
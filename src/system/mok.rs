@@ -0,0 +1,207 @@
+use serde::Serialize;
+
+use crate::system::efivars::{guid_string, read_var};
+
+/// Vendor GUID of the variables shim and MokManager use
+const SHIM_LOCK_GUID: &str = "605dab50-e046-4300-abb6-3dd810dd8b23";
+/// Signature types of EFI_SIGNATURE_LIST that MOK lists contain
+const CERT_X509_GUID: &str = "a5c059a1-94e4-4aa7-87b5-ab155c2bf072";
+const CERT_SHA256_GUID: &str = "c1c41626-504c-4092-aca9-41f936934328";
+/// DER encoded object identifier of the X.509 common name attribute
+const COMMON_NAME_OID: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+
+/// Key or hash in a MOK list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MokKey {
+    /// `x509`, `sha256` or the signature type GUID for other types
+    pub kind: String,
+    /// GUID of the owner of the key
+    pub owner: String,
+    /// Common name of the certificate subject, none for hashes
+    pub subject: Option<String>,
+}
+
+/// Enrolled machine owner keys and the requests MokManager handles on the next boot
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MokStatus {
+    pub enrolled: Vec<MokKey>,
+    /// Keys waiting to be enrolled, from `mokutil --import`
+    pub pending_enroll: Vec<MokKey>,
+    /// Keys waiting to be deleted, from `mokutil --delete`
+    pub pending_delete: Vec<MokKey>,
+    /// MOK password change is waiting, from `mokutil --password`
+    pub pending_password: bool,
+    /// Shim validation change is waiting, from `mokutil --enable-validation`
+    /// or `--disable-validation`
+    pub pending_validation: bool,
+    /// MokManager screen is shown on the next boot and needs user input
+    pub manager_on_next_boot: bool,
+}
+
+impl MokStatus {
+    /// Read the MOK variables. Missing variables mean that nothing is enrolled
+    /// or pending, which is also the case on systems without shim
+    pub fn read() -> Self {
+        let keys = |name| {
+            read_var(name, SHIM_LOCK_GUID)
+                .map(|data| parse_signature_lists(&data))
+                .unwrap_or_default()
+        };
+        let pending = |name| read_var(name, SHIM_LOCK_GUID).is_some();
+
+        let mut status = Self {
+            enrolled: keys("MokListRT"),
+            pending_enroll: keys("MokNew"),
+            pending_delete: keys("MokDel"),
+            pending_password: pending("MokPW"),
+            pending_validation: pending("MokSB"),
+            manager_on_next_boot: false,
+        };
+        status.manager_on_next_boot = !status.pending_enroll.is_empty()
+            || !status.pending_delete.is_empty()
+            || status.pending_password
+            || status.pending_validation;
+        status
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(u32::from_le_bytes(bytes) as usize)
+}
+
+/// Decode the EFI_SIGNATURE_LIST structures of a MOK list. Decoding stops at
+/// the first malformed list
+fn parse_signature_lists(data: &[u8]) -> Vec<MokKey> {
+    let mut keys = Vec::new();
+    let mut offset = 0;
+
+    // type GUID, list size, header size and signature size
+    while let (Some(kind), Some(list_size), Some(header_size), Some(signature_size)) = (
+        data.get(offset..offset + 16).and_then(guid_string),
+        read_u32(data, offset + 16),
+        read_u32(data, offset + 20),
+        read_u32(data, offset + 24),
+    ) {
+        let Some(list) = data.get(offset..offset + list_size) else {
+            break;
+        };
+        // each signature starts with the owner GUID
+        if signature_size <= 16 || list_size < 28 + header_size {
+            break;
+        }
+
+        let kind = match kind.as_str() {
+            CERT_X509_GUID => "x509".to_string(),
+            CERT_SHA256_GUID => "sha256".to_string(),
+            _ => kind,
+        };
+        for signature in list[28 + header_size..].chunks_exact(signature_size) {
+            keys.push(MokKey {
+                kind: kind.clone(),
+                owner: guid_string(&signature[..16]).unwrap_or_default(),
+                subject: (kind == "x509")
+                    .then(|| common_name(&signature[16..]))
+                    .flatten(),
+            });
+        }
+
+        offset += list_size;
+    }
+
+    keys
+}
+
+/// Common name of the subject of a DER encoded certificate. The subject comes
+/// after the issuer so the last common name before the public key is used
+fn common_name(cert: &[u8]) -> Option<String> {
+    let mut name = None;
+    let mut offset = 0;
+    while let Some(pos) = cert[offset..]
+        .windows(COMMON_NAME_OID.len())
+        .position(|window| window == COMMON_NAME_OID)
+    {
+        let value_start = offset + pos + COMMON_NAME_OID.len();
+        // string tag and a short form length
+        let len = *cert.get(value_start + 1)? as usize;
+        if len < 0x80 {
+            if let Some(value) = cert.get(value_start + 2..value_start + 2 + len) {
+                name = Some(String::from_utf8_lossy(value).to_string());
+            }
+        }
+        offset = value_start;
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GUID bytes in the EFI layout from the string form
+    fn guid_bytes(guid: &str) -> Vec<u8> {
+        let hex: Vec<u8> = guid
+            .replace('-', "")
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect();
+        let mut bytes = Vec::new();
+        bytes.extend(hex[..4].iter().rev());
+        bytes.extend(hex[4..6].iter().rev());
+        bytes.extend(hex[6..8].iter().rev());
+        bytes.extend(&hex[8..]);
+        bytes
+    }
+
+    fn name(common_name: &str) -> Vec<u8> {
+        let mut name = COMMON_NAME_OID.to_vec();
+        name.extend([0x0c, common_name.len() as u8]);
+        name.extend(common_name.as_bytes());
+        name
+    }
+
+    #[test]
+    fn test_parse_signature_lists() {
+        // issuer comes before the subject
+        let mut cert = name("Fedora Secure Boot CA");
+        cert.extend([0x30, 0x00]);
+        cert.extend(name("fedora-signer"));
+
+        let owner = "11111111-2222-3333-4444-555555555555";
+        let mut signature = guid_bytes(owner);
+        signature.extend(&cert);
+
+        let mut data = guid_bytes(CERT_X509_GUID);
+        data.extend(((28 + signature.len()) as u32).to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend((signature.len() as u32).to_le_bytes());
+        data.extend(&signature);
+
+        // two hashes in one list
+        let mut hash = guid_bytes(owner);
+        hash.extend([0xaa; 32]);
+        data.extend(guid_bytes(CERT_SHA256_GUID));
+        data.extend(((28 + hash.len() * 2) as u32).to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend((hash.len() as u32).to_le_bytes());
+        data.extend(&hash);
+        data.extend(&hash);
+
+        let keys = parse_signature_lists(&data);
+        assert_eq!(keys.len(), 3);
+        assert_eq!(
+            keys[0],
+            MokKey {
+                kind: "x509".into(),
+                owner: owner.into(),
+                subject: Some("fedora-signer".into()),
+            }
+        );
+        assert_eq!(keys[1].kind, "sha256");
+        assert_eq!(keys[2].subject, None);
+
+        // truncated list is ignored
+        assert_eq!(parse_signature_lists(&data[..40]), Vec::new());
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    env,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    time::{Duration, Instant},
+};
+
+/// Socket of systemd for the state of `Type=notify` services
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+/// `WatchdogSec` of the unit in microseconds
+const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+/// Process the watchdog is meant for, unset means the main process
+const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+
+/// Send `state`, like `READY=1`, to systemd. Does nothing if the program wasn't
+/// started by systemd
+pub fn notify(state: &str) {
+    let Ok(path) = env::var(NOTIFY_SOCKET_ENV) else {
+        return;
+    };
+    if let Err(err) = send(&path, state) {
+        log::warn!("Cannot send {state} to systemd: {err}");
+    }
+}
+
+fn send(path: &str, state: &str) -> std::io::Result<()> {
+    // socket names starting with @ are in the abstract namespace
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Keeps the systemd watchdog from restarting the program by pinging it twice
+/// per `WatchdogSec`
+pub struct Watchdog {
+    interval: Option<Duration>,
+    last_ping: Instant,
+}
+
+impl Watchdog {
+    pub fn from_env() -> Self {
+        let interval = ping_interval(
+            env::var(WATCHDOG_USEC_ENV).ok().as_deref(),
+            env::var(WATCHDOG_PID_ENV).ok().as_deref(),
+            process::id(),
+        );
+        if let Some(interval) = interval {
+            log::debug!("Pinging the systemd watchdog every {interval:?}");
+        }
+        Self {
+            interval,
+            last_ping: Instant::now(),
+        }
+    }
+
+    /// Send WATCHDOG=1 if it's time to. Cheap enough to call on every loop iteration
+    pub fn ping(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        if self.last_ping.elapsed() >= interval {
+            notify("WATCHDOG=1");
+            self.last_ping = Instant::now();
+        }
+    }
+}
+
+/// Half of the watchdog timeout, none if the watchdog isn't enabled for `pid`
+fn ping_interval(usec: Option<&str>, watchdog_pid: Option<&str>, pid: u32) -> Option<Duration> {
+    if watchdog_pid.is_some_and(|watchdog_pid| watchdog_pid.parse() != Ok(pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_interval() {
+        assert_eq!(
+            ping_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            ping_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        // watchdog of another process
+        assert_eq!(ping_interval(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(ping_interval(Some("0"), None, 42), None);
+        assert_eq!(ping_interval(Some("abc"), None, 42), None);
+        assert_eq!(ping_interval(None, None, 42), None);
+    }
+}
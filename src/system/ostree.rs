@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::{
+    config::BLS_ENTRIES_PATH,
+    dctx,
+    errors::{DError, DResult},
+    grub2::cmdline::CmdLine,
+    sdboot::SdBootEntries,
+    system::process,
+};
+
+/// Created by ostree-prepare-root when the system is booted from an ostree deployment
+pub const OSTREE_BOOTED_PATH: &str = "/run/ostree-booted";
+const RPM_OSTREE_PATH: &str = "/usr/bin/rpm-ostree";
+/// ostree names the BLS entries of the deployments `ostree-<index>-<os>.conf`
+const OSTREE_ENTRY_PREFIX: &str = "ostree-";
+
+/// How kernel arguments are changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum KargsBackend {
+    /// Use rpm-ostree or ostree on systems booted from an ostree deployment,
+    /// otherwise edit the grub config
+    #[default]
+    Auto,
+    /// Edit GRUB_CMDLINE_* in the grub config and regenerate grub.cfg
+    Grub,
+    /// `rpm-ostree kargs`, the change is staged in a new deployment
+    RpmOstree,
+    /// `ostree admin kargs edit-in-place`, the current deployment is changed
+    Ostree,
+}
+
+impl KargsBackend {
+    /// Resolve `Auto` to the backend of the system
+    pub fn detect(self) -> Self {
+        if self != Self::Auto {
+            return self;
+        }
+
+        if !Path::new(OSTREE_BOOTED_PATH).exists() {
+            return Self::Grub;
+        }
+
+        let backend = if Path::new(RPM_OSTREE_PATH).is_file() {
+            Self::RpmOstree
+        } else {
+            Self::Ostree
+        };
+        log::info!(
+            "System is booted from an ostree deployment, using {backend:?} for kernel arguments"
+        );
+        backend
+    }
+
+    /// /boot is managed by ostree and the grub config can't be edited directly
+    pub fn is_ostree(self) -> bool {
+        matches!(self, Self::RpmOstree | Self::Ostree)
+    }
+}
+
+/// Kernel arguments of the default deployment, from its BLS entry. The entries
+/// are sorted so that the deployment booted by default is first
+pub fn deployment_kargs() -> DResult<CmdLine> {
+    let entries = SdBootEntries::from_dir(BLS_ENTRIES_PATH)?;
+    let entry = entries
+        .entries()
+        .iter()
+        .find(|entry| entry.id().starts_with(OSTREE_ENTRY_PREFIX))
+        .ok_or_else(|| {
+            DError::generic(
+                dctx!(),
+                format!("No ostree deployment entries in {BLS_ENTRIES_PATH}"),
+            )
+        })?;
+    Ok(CmdLine::new(&entry.options()))
+}
+
+/// Parameters to delete and append to turn `current` into `new`
+fn karg_changes(current: &CmdLine, new: &CmdLine) -> (Vec<String>, Vec<String>) {
+    let current: Vec<String> = current
+        .params()
+        .iter()
+        .map(|param| param.to_string())
+        .collect();
+    let new: Vec<String> = new.params().iter().map(|param| param.to_string()).collect();
+
+    let delete = current
+        .iter()
+        .filter(|param| !new.contains(param))
+        .cloned()
+        .collect();
+    let append = new
+        .iter()
+        .filter(|param| !current.contains(param))
+        .cloned()
+        .collect();
+    (delete, append)
+}
+
+/// Change the kernel arguments of the default deployment from `current` to `new`.
+/// Only the changed parameters are passed to the tool so the arguments ostree
+/// manages itself, like `ostree=`, are kept as they are
+pub async fn set_kargs(backend: KargsBackend, current: &CmdLine, new: &CmdLine) -> DResult<()> {
+    let (delete, append) = karg_changes(current, new);
+    if delete.is_empty() && append.is_empty() {
+        log::debug!("Kernel arguments are not changed");
+        return Ok(());
+    }
+
+    let (program, command): (&str, &[&str]) = match backend {
+        KargsBackend::RpmOstree => ("rpm-ostree", &["kargs"]),
+        KargsBackend::Ostree => ("ostree", &["admin", "kargs", "edit-in-place"]),
+        KargsBackend::Auto | KargsBackend::Grub => {
+            return Err(DError::generic(
+                dctx!(),
+                "Kernel arguments are not managed by ostree",
+            ))
+        }
+    };
+    let options: Vec<String> = delete
+        .iter()
+        .map(|param| format!("--delete-if-present={param}"))
+        .chain(
+            append
+                .iter()
+                .map(|param| format!("--append-if-missing={param}")),
+        )
+        .collect();
+    let args: Vec<&str> = command
+        .iter()
+        .copied()
+        .chain(options.iter().map(String::as_str))
+        .collect();
+    process::run(program, &args).await?.check()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_karg_changes() {
+        let current = CmdLine::new("rw ostree=/ostree/boot.1/fedora/abc/0 quiet console=tty0");
+        let mut new = current.clone();
+        new.remove_param("quiet");
+        new.set_param("console", Some("ttyS0,115200"));
+        new.add_param("mitigations", Some("off"));
+
+        let (delete, append) = karg_changes(&current, &new);
+        assert_eq!(delete, vec!["quiet", "console=tty0"]);
+        assert_eq!(append, vec!["console=ttyS0,115200", "mitigations=off"]);
+
+        assert_eq!(karg_changes(&current, &current), (Vec::new(), Vec::new()));
+    }
+}
@@ -0,0 +1,205 @@
+use std::process::Stdio;
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::Command,
+    sync::mpsc::UnboundedSender,
+};
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+};
+
+/// Output of a command that was run to completion
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandOutput {
+    /// Command line that was run
+    pub command: String,
+    /// Exit code, none if the process was killed by a signal
+    pub exit_status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_status == Some(0)
+    }
+
+    /// Fail with the exit status and stderr of the command unless it succeeded
+    pub fn check(&self) -> DResult<()> {
+        if self.success() {
+            return Ok(());
+        }
+        Err(DError::generic(
+            dctx!(),
+            format!(
+                "{} failed with exit status {:?}: {}",
+                self.command,
+                self.exit_status,
+                self.stderr.trim()
+            ),
+        ))
+    }
+}
+
+/// Run `program` without blocking the async runtime and capture its output
+pub async fn run(program: &str, args: &[&str]) -> DResult<CommandOutput> {
+    run_command(program, args, None, None, true).await
+}
+
+/// Same as [`run`] but writes `input` to the stdin of the program.
+/// Neither the input nor the output is logged since they can contain
+/// passwords and their hashes
+pub async fn run_with_input(
+    program: &str,
+    args: &[&str],
+    input: Option<&str>,
+) -> DResult<CommandOutput> {
+    run_command(program, args, input, None, false).await
+}
+
+/// Same as [`run`] but sends every line the program writes to stderr to `progress`
+/// as soon as it's written, grub2-mkconfig for example reports the kernels it finds there
+pub async fn run_with_progress(
+    program: &str,
+    args: &[&str],
+    progress: UnboundedSender<String>,
+) -> DResult<CommandOutput> {
+    run_command(program, args, None, Some(progress), true).await
+}
+
+async fn run_command(
+    program: &str,
+    args: &[&str],
+    input: Option<&str>,
+    progress: Option<UnboundedSender<String>>,
+    log_output: bool,
+) -> DResult<CommandOutput> {
+    let command = std::iter::once(program)
+        .chain(args.iter().copied())
+        .collect::<Vec<_>>()
+        .join(" ");
+    log::debug!("Calling {command}");
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ctx(dctx!(), format!("Failed to run {command}"))?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin
+            .write_all(input.as_bytes())
+            .await
+            .ctx(dctx!(), format!("Failed to write the input of {command}"))?;
+        // stdin is closed when dropped so the program sees the end of the input
+    }
+
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let read_stdout = async {
+        let mut buf = Vec::new();
+        if let Some(mut stdout) = stdout {
+            stdout.read_to_end(&mut buf).await?;
+        }
+        Ok(buf)
+    };
+    let read_stderr = async {
+        let mut buf = Vec::new();
+        if let Some(stderr) = stderr {
+            let mut reader = BufReader::new(stderr);
+            loop {
+                let start = buf.len();
+                if reader.read_until(b'\n', &mut buf).await? == 0 {
+                    break;
+                }
+                if let Some(progress) = &progress {
+                    let line = String::from_utf8_lossy(&buf[start..]);
+                    // nobody listening to the progress anymore is not an error
+                    let _ = progress.send(line.trim_end().to_string());
+                }
+            }
+        }
+        Ok(buf)
+    };
+
+    let (stdout, stderr, status) = tokio::try_join!(read_stdout, read_stderr, child.wait())
+        .ctx(dctx!(), format!("Failed to run {command}"))?;
+
+    let output = CommandOutput {
+        command,
+        exit_status: status.code(),
+        stdout: String::from_utf8_lossy(&stdout).to_string(),
+        stderr: String::from_utf8_lossy(&stderr).to_string(),
+    };
+
+    if log_output {
+        log::debug!("{} stdout: {}", output.command, output.stdout);
+        log::debug!("{} stderr: {}", output.command, output.stderr);
+    }
+    log::debug!(
+        "Calling {} done with status {:?}",
+        output.command,
+        output.exit_status
+    );
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_process_run() {
+        let output = run("sh", &["-c", "echo out; echo err >&2; exit 3"])
+            .await
+            .unwrap();
+        assert_eq!(output.command, "sh -c echo out; echo err >&2; exit 3");
+        assert_eq!(output.exit_status, Some(3));
+        assert!(!output.success());
+        let error = output.check().unwrap_err();
+        assert!(error
+            .error()
+            .as_string()
+            .contains("sh -c echo out; echo err >&2; exit 3 failed with exit status Some(3): err"));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+
+        assert!(run("bootkit-missing-command", &[]).await.is_err());
+
+        let output = run_with_input("sh", &["-c", "read line; echo got $line"], Some("secret\n"))
+            .await
+            .unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, "got secret\n");
+    }
+
+    #[tokio::test]
+    async fn test_process_run_with_progress() {
+        let (progress, mut lines) = tokio::sync::mpsc::unbounded_channel();
+        let output = run_with_progress(
+            "sh",
+            &["-c", "echo out; echo one >&2; printf two >&2"],
+            progress,
+        )
+        .await
+        .unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "one\ntwo");
+
+        assert_eq!(lines.recv().await.as_deref(), Some("one"));
+        assert_eq!(lines.recv().await.as_deref(), Some("two"));
+        assert_eq!(lines.recv().await, None);
+    }
+}
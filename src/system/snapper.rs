@@ -0,0 +1,49 @@
+use std::{fs::read_to_string, path::Path};
+
+/// snapper config of the root filesystem, the root is only looked up from the
+/// snapper snapshots if it exists
+pub const SNAPPER_ROOT_CONFIG: &str = "/etc/snapper/configs/root";
+pub const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// Number of the snapper snapshot the root filesystem is mounted from, none if
+/// snapper isn't used or the root isn't a snapshot
+pub fn root_snapshot() -> Option<i64> {
+    if !Path::new(SNAPPER_ROOT_CONFIG).exists() {
+        return None;
+    }
+    let mountinfo = read_to_string(MOUNTINFO_PATH).ok()?;
+    mountinfo_root_snapshot(&mountinfo)
+}
+
+/// The root is mounted from a subvolume like `/@/.snapshots/42/snapshot`
+fn mountinfo_root_snapshot(mountinfo: &str) -> Option<i64> {
+    // later mounts hide the earlier ones
+    let root = mountinfo.lines().rev().find_map(|line| {
+        // id, parent id and major:minor come before the root and the mount point
+        let mut fields = line.split_whitespace().skip(3);
+        let root = fields.next()?;
+        (fields.next()? == "/").then_some(root)
+    })?;
+    let (_, number) = root
+        .strip_suffix("/snapshot")?
+        .rsplit_once("/.snapshots/")?;
+    number.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mountinfo_root_snapshot() {
+        let mountinfo = "22 1 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:5 - proc proc rw\n\
+                         59 1 0:35 /@/.snapshots/42/snapshot / ro,relatime shared:1 - btrfs /dev/vda2 rw,subvol=/@/.snapshots/42/snapshot\n\
+                         60 59 0:35 /@/home /home rw,relatime shared:28 - btrfs /dev/vda2 rw,subvol=/@/home\n";
+        assert_eq!(mountinfo_root_snapshot(mountinfo), Some(42));
+
+        let plain = "59 1 253:0 / / rw,relatime shared:1 - ext4 /dev/vda2 rw\n";
+        assert_eq!(mountinfo_root_snapshot(plain), None);
+        let subvol = "59 1 0:35 /@ / rw,relatime shared:1 - btrfs /dev/vda2 rw,subvol=/@\n";
+        assert_eq!(mountinfo_root_snapshot(subvol), None);
+    }
+}
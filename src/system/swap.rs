@@ -0,0 +1,207 @@
+use std::{
+    fs::{canonicalize, read_dir, read_to_string},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    dctx,
+    errors::{DError, DRes, DResult},
+    system::{filesystem_type, mount_device, process},
+};
+
+/// Active swap areas
+pub const SWAPS_PATH: &str = "/proc/swaps";
+pub const MEMINFO_PATH: &str = "/proc/meminfo";
+/// Symlinks named by filesystem UUID that point to the block devices
+pub const DISK_BY_UUID_PATH: &str = "/dev/disk/by-uuid";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwapKind {
+    Partition,
+    File,
+}
+
+/// Active swap area from /proc/swaps
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Swap {
+    pub path: String,
+    pub kind: SwapKind,
+    pub size_kib: u64,
+    pub priority: i64,
+}
+
+/// Kernel parameters needed to resume from hibernation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResumeParams {
+    /// Value of `resume=`, the swap partition or the filesystem of the swap file
+    pub resume: String,
+    /// Value of `resume_offset=`, page offset of the swap file in the filesystem
+    pub resume_offset: Option<u64>,
+    pub swap: Swap,
+}
+
+fn parse_swaps(swaps: &str) -> Vec<Swap> {
+    swaps
+        .lines()
+        // first line is the header
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let path = fields.next()?;
+            let kind = match fields.next()? {
+                "partition" => SwapKind::Partition,
+                "file" => SwapKind::File,
+                // zram and other virtual devices can't be used for hibernation
+                _ => return None,
+            };
+            Some(Swap {
+                // spaces in paths are escaped as \040
+                path: path.replace("\\040", " "),
+                kind,
+                size_kib: fields.next()?.parse().ok()?,
+                priority: fields.nth(1)?.parse().ok()?,
+            })
+        })
+        .filter(|swap| !swap.path.starts_with("/dev/zram"))
+        .collect()
+}
+
+/// Swap areas that can hold a hibernation image
+pub fn swaps() -> DResult<Vec<Swap>> {
+    let swaps = read_to_string(SWAPS_PATH).ctx(dctx!(), format!("Cannot read {SWAPS_PATH}"))?;
+    Ok(parse_swaps(&swaps))
+}
+
+/// Total memory in KiB, the hibernation image can be this large
+pub fn memory_kib() -> Option<u64> {
+    read_to_string(MEMINFO_PATH)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| value.split_whitespace().next()?.parse().ok())
+}
+
+/// Filesystem UUID of `device`, found from the /dev/disk/by-uuid symlinks
+fn device_uuid<P: AsRef<Path>>(device: &str, by_uuid_dir: P) -> Option<String> {
+    let device = canonicalize(device).ok()?;
+    read_dir(by_uuid_dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|link| canonicalize(link.path()).is_ok_and(|target| target == device))
+        .map(|link| link.file_name().to_string_lossy().to_string())
+}
+
+/// `resume=` value of `device`. UUID is preferred since device names can change
+fn resume_device(device: &str) -> String {
+    match device_uuid(device, DISK_BY_UUID_PATH) {
+        Some(uuid) => format!("UUID={uuid}"),
+        None => device.to_string(),
+    }
+}
+
+/// Physical offset of the first extent from `filefrag -v` output
+fn parse_filefrag(output: &str) -> Option<u64> {
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.trim().split(':').collect();
+        if fields.first()?.trim() != "0" {
+            return None;
+        }
+        fields.get(2)?.split("..").next()?.trim().parse().ok()
+    })
+}
+
+/// Page offset of the swap file. Btrfs doesn't report physical offsets with
+/// filefrag so it has its own tool
+async fn resume_offset(file: &str) -> DResult<u64> {
+    let output = if filesystem_type(file).as_deref() == Some("btrfs") {
+        let output =
+            process::run("btrfs", &["inspect-internal", "map-swapfile", "-r", file]).await?;
+        output
+            .success()
+            .then(|| output.stdout.trim().parse().ok())
+            .flatten()
+    } else {
+        let output = process::run("filefrag", &["-v", file]).await?;
+        output
+            .success()
+            .then(|| parse_filefrag(&output.stdout))
+            .flatten()
+    };
+
+    output.ok_or_else(|| {
+        DError::generic(
+            dctx!(),
+            format!("Failed to find the physical offset of swap file {file}"),
+        )
+    })
+}
+
+/// Kernel parameters for resuming from the largest swap area
+pub async fn resume_params() -> DResult<ResumeParams> {
+    let swap = swaps()?
+        .into_iter()
+        .max_by_key(|swap| swap.size_kib)
+        .ok_or_else(|| DError::generic(dctx!(), "No swap partition or swap file is active"))?;
+
+    match swap.kind {
+        SwapKind::Partition => Ok(ResumeParams {
+            resume: resume_device(&swap.path),
+            resume_offset: None,
+            swap,
+        }),
+        SwapKind::File => {
+            let device = mount_device(&swap.path).ok_or_else(|| {
+                DError::generic(
+                    dctx!(),
+                    format!("Cannot find the filesystem of swap file {}", swap.path),
+                )
+            })?;
+            Ok(ResumeParams {
+                resume: resume_device(&device),
+                resume_offset: Some(resume_offset(&swap.path).await?),
+                swap,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_swaps() {
+        let swaps = "Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n\
+                     /dev/nvme0n1p3                          partition\t8388604\t\t0\t\t-2\n\
+                     /swap/my\\040swapfile                    file\t\t4194300\t\t0\t\t-3\n\
+                     /dev/zram0                              partition\t4194300\t\t0\t\t100\n";
+        assert_eq!(
+            parse_swaps(swaps),
+            vec![
+                Swap {
+                    path: "/dev/nvme0n1p3".into(),
+                    kind: SwapKind::Partition,
+                    size_kib: 8388604,
+                    priority: -2,
+                },
+                Swap {
+                    path: "/swap/my swapfile".into(),
+                    kind: SwapKind::File,
+                    size_kib: 4194300,
+                    priority: -3,
+                },
+            ]
+        );
+
+        let filefrag = "Filesystem type is: ef53\n\
+                        File size of /swapfile is 2147483648 (524288 blocks of 4096 bytes)\n \
+                        ext:     logical_offset:        physical_offset: length:   expected: flags:\n   \
+                        0:        0..   32767:      34816..     67583:  32768:\n   \
+                        1:    32768..   65535:      69632..    102399:  32768:      67584:\n";
+        assert_eq!(parse_filefrag(filefrag), Some(34816));
+        assert_eq!(parse_filefrag("File size of /swapfile is 0\n"), None);
+    }
+}